@@ -0,0 +1,42 @@
+struct Params {
+    index: u32,
+    pad: vec3<u32>,
+}
+
+@group(0) @binding(0) var chunk: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var<uniform> params: Params;
+@group(0) @binding(2) var<storage, read_write> checksums: array<atomic<u32>>;
+
+var<workgroup> sum: atomic<u32>;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash2(x: i32, y: i32, s: u32) -> u32 {
+    return pcg(pcg(pcg(s) ^ bitcast<u32>(x)) ^ bitcast<u32>(y));
+}
+
+// Same sum as `chunk_checksum`, so the order the pixels are added in doesn't
+// matter.
+@compute @workgroup_size(8, 8, 1)
+fn main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    if local == 0u {
+        atomicStore(&sum, 0u);
+    }
+    workgroupBarrier();
+    let size = textureDimensions(chunk);
+    if all(id.xy < size) {
+        let color = pack4x8unorm(textureLoad(chunk, id.xy));
+        atomicAdd(&sum, hash2(i32(id.x), i32(id.y), color));
+    }
+    workgroupBarrier();
+    if local == 0u {
+        atomicAdd(&checksums[params.index], atomicLoad(&sum));
+    }
+}
@@ -0,0 +1,84 @@
+// Per chunk dirty bounds in texture coordinates: min.x, min.y, max.x, max.y (inclusive),
+// followed by the number of pixel writes. `changed` covers the current tick and
+// `frame_changed` every tick of the frame, for frames that simulate several.
+@group(0) @binding(0) var<storage, read_write> changed: array<atomic<i32>, 5>;
+@group(0) @binding(1) var<storage, read_write> previous: array<i32, 4>;
+@group(0) @binding(2) var<storage, read_write> dispatch_args: array<u32, 3>;
+@group(0) @binding(3) var<storage, read_write> dispatch_offset: vec2<u32>;
+@group(0) @binding(4) var<uniform> params: DirtyParams;
+@group(0) @binding(5) var<storage, read_write> frame_changed: array<i32, 5>;
+
+struct DirtyParams {
+    size: vec2<u32>,
+    margin: u32,
+}
+
+const EMPTY_MIN: i32 = 2147483647;
+const EMPTY_MAX: i32 = -2147483647 - 1;
+
+fn begin_tick() {
+    for (var i = 0; i < 4; i++) {
+        previous[i] = atomicLoad(&changed[i]);
+    }
+    atomicStore(&changed[0], EMPTY_MIN);
+    atomicStore(&changed[1], EMPTY_MIN);
+    atomicStore(&changed[2], EMPTY_MAX);
+    atomicStore(&changed[3], EMPTY_MAX);
+    atomicStore(&changed[4], 0);
+}
+
+fn merge_tick() {
+    frame_changed[0] = min(frame_changed[0], atomicLoad(&changed[0]));
+    frame_changed[1] = min(frame_changed[1], atomicLoad(&changed[1]));
+    frame_changed[2] = max(frame_changed[2], atomicLoad(&changed[2]));
+    frame_changed[3] = max(frame_changed[3], atomicLoad(&changed[3]));
+    frame_changed[4] += atomicLoad(&changed[4]);
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn begin_frame() {
+    begin_tick();
+    frame_changed[0] = EMPTY_MIN;
+    frame_changed[1] = EMPTY_MIN;
+    frame_changed[2] = EMPTY_MAX;
+    frame_changed[3] = EMPTY_MAX;
+    frame_changed[4] = 0;
+}
+
+// Between the ticks of a frame, like between frames.
+@compute @workgroup_size(1, 1, 1)
+fn next_tick() {
+    merge_tick();
+    begin_tick();
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn end_frame() {
+    merge_tick();
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn prepare_dispatch() {
+    let lo = min(
+        vec2<i32>(atomicLoad(&changed[0]), atomicLoad(&changed[1])),
+        vec2<i32>(previous[0], previous[1]),
+    );
+    let hi = max(
+        vec2<i32>(atomicLoad(&changed[2]), atomicLoad(&changed[3])),
+        vec2<i32>(previous[2], previous[3]),
+    );
+    if lo.x > hi.x || lo.y > hi.y {
+        dispatch_args[0] = 0u;
+        dispatch_args[1] = 0u;
+        dispatch_args[2] = 0u;
+        return;
+    }
+    let margin = i32(params.margin);
+    let last = vec2<i32>(params.size) - vec2<i32>(1);
+    let start = clamp(lo - vec2<i32>(margin), vec2<i32>(0), last);
+    let end = clamp(hi + vec2<i32>(margin), vec2<i32>(0), last);
+    dispatch_offset = vec2<u32>(start);
+    dispatch_args[0] = u32(end.x - start.x) / 8u + 1u;
+    dispatch_args[1] = u32(end.y - start.y) / 8u + 1u;
+    dispatch_args[2] = 1u;
+}
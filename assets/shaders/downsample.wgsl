@@ -0,0 +1,20 @@
+@group(0) @binding(0) var source_texture: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var target_texture: texture_storage_2d<rgba8unorm, write>;
+// x, y: texel of the target the downsampled source starts at, z: downsampling factor
+@group(0) @binding(2) var<uniform> params: vec4<u32>;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let factor = params.z;
+    let size = textureDimensions(source_texture) / factor;
+    if invocation_id.x >= size.x || invocation_id.y >= size.y {
+        return;
+    }
+    var sum = vec4<f32>(0.0);
+    for (var y = 0u; y < factor; y++) {
+        for (var x = 0u; x < factor; x++) {
+            sum += textureLoad(source_texture, invocation_id.xy * factor + vec2<u32>(x, y));
+        }
+    }
+    textureStore(target_texture, params.xy + invocation_id.xy, sum / f32(factor * factor));
+}
@@ -0,0 +1,100 @@
+struct Params {
+    origin: vec2<i32>,
+    center: vec2<i32>,
+    rect_min: vec2<u32>,
+    rect_max: vec2<u32>,
+    radius: f32,
+    noise: f32,
+    scorch_width: f32,
+    scorch_strength: f32,
+    strength: f32,
+    capacity: u32,
+    seed: u32,
+    scorch_color: u32,
+    phases: vec3<f32>,
+    chunk_height: u32,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    color: u32,
+    flying: u32,
+    pad: vec2<u32>,
+}
+
+@group(0) @binding(0) var chunk: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(1) var<uniform> params: Params;
+@group(0) @binding(2) var<storage, read_write> dirty_bounds: array<atomic<i32>, 5>;
+@group(0) @binding(3) var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(4) var<storage, read_write> particle_count: atomic<u32>;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash2(x: i32, y: i32, s: u32) -> u32 {
+    return pcg(pcg(pcg(s) ^ bitcast<u32>(x)) ^ bitcast<u32>(y));
+}
+
+fn unit(h: u32) -> f32 {
+    return f32(h >> 8u) / 16777216.0;
+}
+
+fn edge(angle: f32) -> f32 {
+    let wave = sin(3.0 * angle + params.phases.x)
+        + 0.5 * sin(5.0 * angle + params.phases.y)
+        + 0.25 * sin(9.0 * angle + params.phases.z);
+    return params.radius * (1.0 + params.noise * wave / 1.75);
+}
+
+fn mark_dirty(coords: vec2<i32>) {
+    atomicMin(&dirty_bounds[0], coords.x);
+    atomicMin(&dirty_bounds[1], coords.y);
+    atomicMax(&dirty_bounds[2], coords.x);
+    atomicMax(&dirty_bounds[3], coords.y);
+    atomicAdd(&dirty_bounds[4], 1);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let texel = invocation_id.xy + params.rect_min;
+    if texel.x >= params.rect_max.x || texel.y >= params.rect_max.y {
+        return;
+    }
+    let coords = vec2<i32>(texel);
+    let color = textureLoad(chunk, coords);
+    if color.a == 0.0 {
+        return;
+    }
+    let world = params.origin + vec2<i32>(coords.x, i32(params.chunk_height) - 1 - coords.y);
+    let offset = vec2<f32>(world - params.center);
+    let length_ = length(offset);
+    let hole = edge(atan2(offset.y, offset.x));
+    if length_ < hole {
+        let index = atomicAdd(&particle_count, 1u);
+        if index < params.capacity {
+            var direction = vec2<f32>(0.0, 1.0);
+            if length_ > 0.0 {
+                direction = offset / length_;
+            }
+            let speed = params.strength * (0.5 + 0.5 * unit(hash2(world.x, world.y, params.seed)));
+            particles[index] = Particle(
+                vec2<f32>(world) + 0.5,
+                direction * speed,
+                pack4x8unorm(color),
+                1u,
+                vec2<u32>(0u),
+            );
+        }
+        textureStore(chunk, coords, vec4<f32>(0.0));
+        mark_dirty(coords);
+    } else if length_ < hole + params.scorch_width {
+        let mix_ = params.scorch_strength * (1.0 - (length_ - hole) / params.scorch_width);
+        let scorch = unpack4x8unorm(params.scorch_color);
+        textureStore(chunk, coords, vec4<f32>(mix(color.rgb, scorch.rgb, mix_), color.a));
+        mark_dirty(coords);
+    }
+}
@@ -0,0 +1,21 @@
+// Moves a chunk between its rgba8unorm texture, which is drawn and edited as
+// floats, and the rgba8uint texture integer simulation shaders run on. Bytes
+// survive both ways exactly.
+@group(0) @binding(0) var chunk: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(1) var simulation: texture_storage_2d<rgba8uint, read_write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn load(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(chunk)) {
+        return;
+    }
+    textureStore(simulation, id.xy, vec4<u32>(round(textureLoad(chunk, id.xy) * 255.0)));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn store(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(chunk)) {
+        return;
+    }
+    textureStore(chunk, id.xy, vec4<f32>(textureLoad(simulation, id.xy)) / 255.0);
+}
@@ -0,0 +1,38 @@
+// Seeds are packed as x | y << 16, 0xffffffff where there is none.
+@group(0) @binding(0) var source_seeds: texture_storage_2d<r32uint, read>;
+@group(0) @binding(1) var target_seeds: texture_storage_2d<r32uint, write>;
+// x: step width
+@group(0) @binding(2) var<uniform> params: vec4<u32>;
+
+const NO_SEED: u32 = 0xffffffffu;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(source_seeds));
+    let texel = vec2<i32>(invocation_id.xy);
+    if texel.x >= size.x || texel.y >= size.y {
+        return;
+    }
+    let step = i32(params.x);
+    var best = NO_SEED;
+    var best_distance = 0xffffffffu;
+    for (var offset_y = -1; offset_y <= 1; offset_y++) {
+        for (var offset_x = -1; offset_x <= 1; offset_x++) {
+            let other = texel + vec2<i32>(offset_x, offset_y) * step;
+            if any(other < vec2<i32>(0)) || any(other >= size) {
+                continue;
+            }
+            let seed = textureLoad(source_seeds, other).x;
+            if seed == NO_SEED {
+                continue;
+            }
+            let delta = vec2<i32>(i32(seed & 0xffffu), i32(seed >> 16u)) - texel;
+            let distance = u32(delta.x * delta.x + delta.y * delta.y);
+            if distance < best_distance {
+                best = seed;
+                best_distance = distance;
+            }
+        }
+    }
+    textureStore(target_seeds, texel, vec4<u32>(best, 0u, 0u, 0u));
+}
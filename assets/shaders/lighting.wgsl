@@ -0,0 +1,148 @@
+struct Light {
+    // xy: position in texels, z: radius in texels
+    position: vec4<f32>,
+    // rgb: color times intensity
+    color: vec4<f32>,
+}
+
+struct Emissive {
+    // x: color packed as rgba8
+    color: vec4<u32>,
+    // rgb: light
+    light: vec4<f32>,
+}
+
+struct LightingParams {
+    ambient: vec4<f32>,
+    // x: lights, y: emissive colors, z: emissive rays
+    counts: vec4<u32>,
+    // x: emissive range in texels, y: shadow hardness
+    shape: vec4<f32>,
+}
+
+@group(0) @binding(0) var occupancy: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var seeds: texture_storage_2d<r32uint, read>;
+@group(0) @binding(2) var light_map: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(3) var<storage, read> lights: array<Light>;
+@group(0) @binding(4) var<storage, read> emissive: array<Emissive>;
+@group(0) @binding(5) var<uniform> params: LightingParams;
+
+const NO_SEED: u32 = 0xffffffffu;
+const EMISSIVE_TOLERANCE: i32 = 8;
+const TAU: f32 = 6.28318530718;
+
+fn in_bounds(texel: vec2<i32>) -> bool {
+    return all(texel >= vec2<i32>(0)) && all(texel < vec2<i32>(textureDimensions(occupancy)));
+}
+
+fn is_solid(texel: vec2<i32>) -> bool {
+    return textureLoad(occupancy, texel).a >= 0.5;
+}
+
+// Distance from position to the center of the nearest solid texel.
+fn distance_at(position: vec2<f32>) -> f32 {
+    let texel = vec2<i32>(floor(position));
+    if !in_bounds(texel) {
+        return 3.4e38;
+    }
+    let seed = textureLoad(seeds, texel).x;
+    if seed == NO_SEED {
+        return 3.4e38;
+    }
+    let nearest = vec2<f32>(f32(seed & 0xffffu), f32(seed >> 16u)) + 0.5;
+    return distance(nearest, position);
+}
+
+fn emission(texel: vec2<i32>) -> vec4<f32> {
+    let color = vec4<i32>(round(textureLoad(occupancy, texel) * 255.0));
+    for (var i = 0u; i < params.counts.y; i++) {
+        let target_color = vec4<i32>(round(unpack4x8unorm(emissive[i].color.x) * 255.0));
+        if all(abs(color - target_color) <= vec4<i32>(EMISSIVE_TOLERANCE)) {
+            return vec4<f32>(emissive[i].light.rgb, 1.0);
+        }
+    }
+    return vec4<f32>(0.0);
+}
+
+// How much of a light length away along direction reaches origin.
+fn shadow(origin: vec2<f32>, direction: vec2<f32>, length: f32, start: f32) -> f32 {
+    var t = start;
+    var shade = 1.0;
+    while t < length {
+        let position = origin + direction * t;
+        let texel = vec2<i32>(floor(position));
+        if in_bounds(texel) && is_solid(texel) {
+            return 0.0;
+        }
+        let clearance = max(distance_at(position) - 0.71, 0.0);
+        shade = min(shade, params.shape.y * clearance / t);
+        t += max(clearance, 0.5);
+    }
+    return clamp(shade, 0.0, 1.0);
+}
+
+// Light of the first emissive texel hit along direction.
+fn gather(origin: vec2<f32>, direction: vec2<f32>, range: f32, start: f32) -> vec3<f32> {
+    var t = start;
+    while t < range {
+        let position = origin + direction * t;
+        let texel = vec2<i32>(floor(position));
+        if !in_bounds(texel) {
+            break;
+        }
+        if is_solid(texel) {
+            return emission(texel).rgb * (1.0 - t / range);
+        }
+        t += max(distance_at(position) - 0.71, 0.5);
+    }
+    return vec3<f32>(0.0);
+}
+
+fn texel_hash(texel: vec2<u32>) -> u32 {
+    var hash = (texel.x * 73856093u) ^ (texel.y * 19349663u);
+    hash ^= hash >> 13u;
+    hash *= 0x5bd1e995u;
+    return hash ^ (hash >> 15u);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(occupancy);
+    if invocation_id.x >= size.x || invocation_id.y >= size.y {
+        return;
+    }
+    let texel = vec2<i32>(invocation_id.xy);
+    let origin = vec2<f32>(texel) + 0.5;
+    let solid = is_solid(texel);
+    // Solid texels are lit from the outside of their surface.
+    var start = 1.0;
+    var light = params.ambient.rgb;
+    if solid {
+        start = 1.5;
+        light += emission(texel).rgb;
+    }
+    for (var i = 0u; i < params.counts.x; i++) {
+        let source = lights[i];
+        let to_light = source.position.xy - origin;
+        let length = length(to_light);
+        let radius = source.position.z;
+        if length >= radius {
+            continue;
+        }
+        let falloff = pow(1.0 - length / radius, 2.0);
+        let direction = to_light / max(length, 1.1920929e-7);
+        light += source.color.rgb * falloff * shadow(origin, direction, length, start);
+    }
+    let range = params.shape.x;
+    let rays = params.counts.z;
+    if params.counts.y > 0u && range > 0.0 {
+        let jitter = f32(texel_hash(invocation_id.xy) & 0xffffu) / 65536.0;
+        var gathered = vec3<f32>(0.0);
+        for (var ray = 0u; ray < rays; ray++) {
+            let angle = (f32(ray) + jitter) * TAU / f32(rays);
+            gathered += gather(origin, vec2<f32>(cos(angle), sin(angle)), range, start);
+        }
+        light += gathered / f32(rays);
+    }
+    textureStore(light_map, texel, vec4<f32>(clamp(light, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0));
+}
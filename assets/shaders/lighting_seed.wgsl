@@ -0,0 +1,19 @@
+// Solid texels of the occupancy seed the jump flood of the lighting.
+@group(0) @binding(0) var occupancy: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var seeds: texture_storage_2d<r32uint, write>;
+
+const NO_SEED: u32 = 0xffffffffu;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(occupancy);
+    let texel = invocation_id.xy;
+    if texel.x >= size.x || texel.y >= size.y {
+        return;
+    }
+    var value = NO_SEED;
+    if textureLoad(occupancy, texel).a >= 0.5 {
+        value = texel.x | texel.y << 16u;
+    }
+    textureStore(seeds, texel, vec4<u32>(value, 0u, 0u, 0u));
+}
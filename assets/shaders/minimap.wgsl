@@ -0,0 +1,51 @@
+struct MinimapParams {
+    // xy: world origin of the chunk, zw: min of the minimap rect
+    origins: vec4<i32>,
+    // xy: max of the minimap rect, zw: end of the minimap pixels to draw
+    bounds: vec4<i32>,
+    // xy: first minimap pixel to draw, z: downscale, w: palette mode
+    pixels: vec4<u32>,
+    // x: solid color, y: empty color, packed as rgba8
+    colors: vec4<u32>,
+}
+
+@group(0) @binding(0) var chunk_texture: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var minimap_texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(2) var<uniform> params: MinimapParams;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel = params.pixels.xy + invocation_id.xy;
+    if pixel.x >= u32(params.bounds.z) || pixel.y >= u32(params.bounds.w) {
+        return;
+    }
+    let image_size = vec2<u32>(params.bounds.xy - params.origins.zw);
+    let downscale = params.pixels.z;
+    let block_min = pixel * downscale;
+    let block_max = min(block_min + downscale, image_size);
+    let chunk_size = vec2<i32>(textureDimensions(chunk_texture));
+    var sum = vec4<f32>(0.0);
+    var count = 0.0;
+    for (var y = block_min.y; y < block_max.y; y++) {
+        for (var x = block_min.x; x < block_max.x; x++) {
+            let world = vec2<i32>(params.origins.z + i32(x), params.bounds.y - 1 - i32(y));
+            let texel = vec2<i32>(
+                world.x - params.origins.x,
+                params.origins.y + chunk_size.y - 1 - world.y,
+            );
+            if all(texel >= vec2<i32>(0)) && all(texel < chunk_size) {
+                sum += textureLoad(chunk_texture, texel);
+                count += 1.0;
+            }
+        }
+    }
+    var color = sum / max(count, 1.0);
+    if params.pixels.w == 1u {
+        if color.a >= 0.5 {
+            color = unpack4x8unorm(params.colors.x);
+        } else {
+            color = unpack4x8unorm(params.colors.y);
+        }
+    }
+    textureStore(minimap_texture, vec2<i32>(pixel), color);
+}
@@ -0,0 +1,47 @@
+struct Params {
+    corner: vec2<i32>,
+    step: f32,
+    gravity: f32,
+    last: u32,
+    capacity: u32,
+    pad: vec2<u32>,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    color: u32,
+    flying: u32,
+    pad: vec2<u32>,
+}
+
+@group(0) @binding(0) var overlay: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(1) var<storage, read> particles: array<Particle>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+@compute @workgroup_size(8, 8, 1)
+fn clear(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(overlay);
+    if invocation_id.x >= size.x || invocation_id.y >= size.y {
+        return;
+    }
+    textureStore(overlay, vec2<i32>(invocation_id.xy), vec4<f32>(0.0));
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn draw(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if invocation_id.x >= params.capacity {
+        return;
+    }
+    let particle = particles[invocation_id.x];
+    if particle.flying == 0u {
+        return;
+    }
+    let pixel = vec2<i32>(floor(particle.position));
+    let texel = vec2<i32>(pixel.x - params.corner.x, params.corner.y - 1 - pixel.y);
+    let size = vec2<i32>(textureDimensions(overlay));
+    if any(texel < vec2<i32>(0)) || any(texel >= size) {
+        return;
+    }
+    textureStore(overlay, texel, unpack4x8unorm(particle.color));
+}
@@ -0,0 +1,96 @@
+struct Params {
+    // World pixel of the left edge and one past the top edge of the atlas.
+    corner: vec2<i32>,
+    step: f32,
+    gravity: f32,
+    last: u32,
+    capacity: u32,
+    pad: vec2<u32>,
+}
+
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    color: u32,
+    flying: u32,
+    pad: vec2<u32>,
+}
+
+@group(0) @binding(0) var atlas: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(1) var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+const SETTLE_STEPS: u32 = 64u;
+
+fn texel_at(position: vec2<f32>) -> vec2<i32> {
+    let pixel = vec2<i32>(floor(position));
+    return vec2<i32>(pixel.x - params.corner.x, params.corner.y - 1 - pixel.y);
+}
+
+fn blocked(texel: vec2<i32>) -> bool {
+    let size = vec2<i32>(textureDimensions(atlas));
+    if any(texel < vec2<i32>(0)) || any(texel >= size) {
+        return true;
+    }
+    return textureLoad(atlas, texel).a > 0.0;
+}
+
+// Slides down like sand and leaves the color where it stops, climbing out of
+// pixels that settled there first.
+fn settle(start: vec2<i32>, color: u32, index: u32) {
+    var side = 1;
+    if index % 2u == 1u {
+        side = -1;
+    }
+    var texel = start;
+    for (var i = 0u; i < SETTLE_STEPS; i++) {
+        if !blocked(texel) || texel.y <= 0 {
+            break;
+        }
+        texel.y -= 1;
+    }
+    for (var i = 0u; i < SETTLE_STEPS; i++) {
+        if !blocked(texel + vec2<i32>(0, 1)) {
+            texel += vec2<i32>(0, 1);
+        } else if !blocked(texel + vec2<i32>(-side, 1)) {
+            texel += vec2<i32>(-side, 1);
+        } else if !blocked(texel + vec2<i32>(side, 1)) {
+            texel += vec2<i32>(side, 1);
+        } else {
+            break;
+        }
+    }
+    if !blocked(texel) {
+        textureStore(atlas, texel, unpack4x8unorm(color));
+    }
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    if index >= params.capacity {
+        return;
+    }
+    var particle = particles[index];
+    if particle.flying == 0u {
+        return;
+    }
+    particle.velocity.y -= params.gravity * params.step;
+    let travel = particle.velocity * params.step;
+    let steps = max(ceil(max(abs(travel.x), abs(travel.y))), 1.0);
+    let delta = travel / steps;
+    var landed = params.last != 0u;
+    for (var i = 0u; i < u32(steps); i++) {
+        let next = particle.position + delta;
+        if blocked(texel_at(next)) {
+            landed = true;
+            break;
+        }
+        particle.position = next;
+    }
+    if landed {
+        particle.flying = 0u;
+        settle(texel_at(particle.position), particle.color, index);
+    }
+    particles[index] = particle;
+}
@@ -0,0 +1,32 @@
+@group(0) @binding(0) var padded: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var outside_seeds: texture_storage_2d<r32uint, read>;
+@group(0) @binding(2) var inside_seeds: texture_storage_2d<r32uint, read>;
+@group(0) @binding(3) var field: texture_storage_2d<r32float, write>;
+// x: halo around the chunk in the padded textures, y: max distance as f32 bits
+@group(0) @binding(4) var<uniform> params: vec4<u32>;
+
+const NO_SEED: u32 = 0xffffffffu;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(field);
+    if invocation_id.x >= size.x || invocation_id.y >= size.y {
+        return;
+    }
+    let max_distance = bitcast<f32>(params.y);
+    let texel = invocation_id.xy + params.x;
+    let solid = textureLoad(padded, texel).a >= 0.5;
+    var seed = textureLoad(outside_seeds, texel).x;
+    if solid {
+        seed = textureLoad(inside_seeds, texel).x;
+    }
+    var value = max_distance;
+    if seed != NO_SEED {
+        let nearest = vec2<f32>(f32(seed & 0xffffu), f32(seed >> 16u));
+        value = min(distance(nearest, vec2<f32>(texel)) - 0.5, max_distance);
+    }
+    if solid {
+        value = -value;
+    }
+    textureStore(field, invocation_id.xy, vec4<f32>(value, 0.0, 0.0, 0.0));
+}
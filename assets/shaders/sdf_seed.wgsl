@@ -0,0 +1,25 @@
+// Empty texels seed the flood of the distances outside of solid pixels, solid
+// ones the flood of the distances inside.
+@group(0) @binding(0) var padded: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var outside_seeds: texture_storage_2d<r32uint, write>;
+@group(0) @binding(2) var inside_seeds: texture_storage_2d<r32uint, write>;
+
+const NO_SEED: u32 = 0xffffffffu;
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(padded);
+    let texel = invocation_id.xy;
+    if texel.x >= size.x || texel.y >= size.y {
+        return;
+    }
+    let seed = texel.x | texel.y << 16u;
+    var outside = seed;
+    var inside = NO_SEED;
+    if textureLoad(padded, texel).a >= 0.5 {
+        outside = NO_SEED;
+        inside = seed;
+    }
+    textureStore(outside_seeds, texel, vec4<u32>(outside, 0u, 0u, 0u));
+    textureStore(inside_seeds, texel, vec4<u32>(inside, 0u, 0u, 0u));
+}
@@ -0,0 +1,55 @@
+struct Params {
+    origin: vec2<i32>,
+    rect_min: vec2<u32>,
+    rect_max: vec2<u32>,
+    chunk_height: u32,
+    mode: u32,
+    color: u32,
+    tolerance: u32,
+    palette_len: u32,
+    palette_tolerance: u32,
+}
+
+@group(0) @binding(0) var chunk: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1) var<uniform> params: Params;
+@group(0) @binding(2) var<storage, read> palette: array<u32>;
+// count, min x, min y, max x, max y, then the histogram.
+@group(0) @binding(3) var<storage, read_write> result: array<atomic<i32>>;
+
+fn bytes(color: vec4<f32>) -> vec4<u32> {
+    return vec4<u32>(round(color * 255.0));
+}
+
+fn within(color: vec4<u32>, target_: u32, tolerance: u32) -> bool {
+    let other = bytes(unpack4x8unorm(target_));
+    let diff = max(color, other) - min(color, other);
+    return all(diff <= vec4<u32>(tolerance));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let coords = params.rect_min + id.xy;
+    if any(coords >= params.rect_max) {
+        return;
+    }
+    let color = bytes(textureLoad(chunk, coords));
+    for (var i = 0u; i < params.palette_len; i++) {
+        if within(color, palette[i], params.palette_tolerance) {
+            atomicAdd(&result[5u + i], 1);
+            break;
+        }
+    }
+    var matches = color.a > 0u;
+    if params.mode == 1u {
+        matches = within(color, params.color, params.tolerance);
+    }
+    if !matches {
+        return;
+    }
+    let world = params.origin + vec2<i32>(i32(coords.x), i32(params.chunk_height - 1u - coords.y));
+    atomicAdd(&result[0], 1);
+    atomicMin(&result[1], world.x);
+    atomicMin(&result[2], world.y);
+    atomicMax(&result[3], world.x);
+    atomicMax(&result[4], world.y);
+}
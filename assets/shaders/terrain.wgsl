@@ -0,0 +1,183 @@
+@group(0) @binding(0) var input_texture: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(1) var<uniform> input_texture_pos: vec2<i32>;
+@group(0) @binding(2) var<uniform> input_texture_size: vec2<u32>;
+@group(0) @binding(3) var<uniform> seed: u32;
+@group(0) @binding(4) var<storage, read> params: TerrainParams;
+
+const MAX_NOISE_LAYERS: u32 = 8u;
+const MAX_MATERIAL_BANDS: u32 = 16u;
+
+struct NoiseLayer {
+    kind: u32,
+    octaves: u32,
+    frequency: f32,
+    amplitude: f32,
+}
+
+struct MaterialBand {
+    depth: f32,
+    color: u32,
+}
+
+struct TerrainParams {
+    surface_height: f32,
+    cave_threshold: f32,
+    cave_min_depth: f32,
+    sky_color: u32,
+    surface_layer_count: u32,
+    cave_layer_count: u32,
+    band_count: u32,
+    surface_layers: array<NoiseLayer, MAX_NOISE_LAYERS>,
+    cave_layers: array<NoiseLayer, MAX_NOISE_LAYERS>,
+    bands: array<MaterialBand, MAX_MATERIAL_BANDS>,
+}
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash2(x: i32, y: i32, s: u32) -> u32 {
+    return pcg(pcg(pcg(s) ^ bitcast<u32>(x)) ^ bitcast<u32>(y));
+}
+
+fn unit(h: u32) -> f32 {
+    return f32(h >> 8u) / 16777216.0;
+}
+
+fn grad(h: u32, d: vec2<f32>) -> f32 {
+    switch h & 7u {
+        case 0u: { return d.x + d.y; }
+        case 1u: { return -d.x + d.y; }
+        case 2u: { return d.x - d.y; }
+        case 3u: { return -d.x - d.y; }
+        case 4u: { return d.x; }
+        case 5u: { return -d.x; }
+        case 6u: { return d.y; }
+        default: { return -d.y; }
+    }
+}
+
+fn perlin(p: vec2<f32>, s: u32) -> f32 {
+    let i = floor(p);
+    let f = p - i;
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let ix = i32(i.x);
+    let iy = i32(i.y);
+    let n00 = grad(hash2(ix, iy, s), f);
+    let n10 = grad(hash2(ix + 1, iy, s), f - vec2<f32>(1.0, 0.0));
+    let n01 = grad(hash2(ix, iy + 1, s), f - vec2<f32>(0.0, 1.0));
+    let n11 = grad(hash2(ix + 1, iy + 1, s), f - vec2<f32>(1.0, 1.0));
+    return mix(mix(n00, n10, u.x), mix(n01, n11, u.x), u.y);
+}
+
+fn simplex_corner(x: vec2<f32>, ix: i32, iy: i32, s: u32) -> f32 {
+    let t = 0.5 - dot(x, x);
+    if t <= 0.0 {
+        return 0.0;
+    }
+    return t * t * t * t * grad(hash2(ix, iy, s), x);
+}
+
+fn simplex(p: vec2<f32>, s: u32) -> f32 {
+    let f2 = 0.36602542;
+    let g2 = 0.21132487;
+    let skew = (p.x + p.y) * f2;
+    let i = floor(p + vec2<f32>(skew));
+    let unskew = (i.x + i.y) * g2;
+    let x0 = p - (i - vec2<f32>(unskew));
+    var o = vec2<f32>(0.0, 1.0);
+    if x0.x > x0.y {
+        o = vec2<f32>(1.0, 0.0);
+    }
+    let x1 = x0 - o + vec2<f32>(g2);
+    let x2 = x0 - vec2<f32>(1.0) + vec2<f32>(2.0 * g2);
+    let ix = i32(i.x);
+    let iy = i32(i.y);
+    let n = simplex_corner(x0, ix, iy, s)
+        + simplex_corner(x1, ix + i32(o.x), iy + i32(o.y), s)
+        + simplex_corner(x2, ix + 1, iy + 1, s);
+    return 70.0 * n;
+}
+
+fn worley(p: vec2<f32>, s: u32) -> f32 {
+    let i = floor(p);
+    let f = p - i;
+    let ix = i32(i.x);
+    let iy = i32(i.y);
+    var min_d = 8.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let h = hash2(ix + dx, iy + dy, s);
+            let point = vec2<f32>(f32(dx), f32(dy)) + vec2<f32>(unit(h), unit(pcg(h)));
+            min_d = min(min_d, length(point - f));
+        }
+    }
+    return min(min_d, 1.0);
+}
+
+fn noise(kind: u32, p: vec2<f32>, s: u32) -> f32 {
+    switch kind {
+        case 0u: { return perlin(p, s); }
+        case 1u: { return simplex(p, s); }
+        default: { return worley(p, s); }
+    }
+}
+
+fn layer(l: NoiseLayer, index: u32, p: vec2<f32>) -> f32 {
+    let layer_seed = pcg(seed + index);
+    var sum = 0.0;
+    var frequency = l.frequency;
+    var amplitude = l.amplitude;
+    for (var octave = 0u; octave < l.octaves; octave++) {
+        sum += amplitude * noise(l.kind, p * frequency, pcg(layer_seed + octave));
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+    return sum;
+}
+
+fn surface_height(x: f32) -> f32 {
+    var height = params.surface_height;
+    for (var i = 0u; i < min(params.surface_layer_count, MAX_NOISE_LAYERS); i++) {
+        height += layer(params.surface_layers[i], i, vec2<f32>(x, 0.5));
+    }
+    return height;
+}
+
+fn cave_density(p: vec2<f32>) -> f32 {
+    var density = 0.0;
+    for (var i = 0u; i < min(params.cave_layer_count, MAX_NOISE_LAYERS); i++) {
+        density += layer(params.cave_layers[i], MAX_NOISE_LAYERS + i, p);
+    }
+    return density;
+}
+
+fn sample(world: vec2<i32>) -> u32 {
+    let p = vec2<f32>(world);
+    let depth = surface_height(p.x) - p.y;
+    if depth < 0.0 {
+        return params.sky_color;
+    }
+    if params.cave_layer_count > 0u && depth >= params.cave_min_depth && cave_density(p) > params.cave_threshold {
+        return params.sky_color;
+    }
+    var color = params.sky_color;
+    for (var i = 0u; i < min(params.band_count, MAX_MATERIAL_BANDS); i++) {
+        if i == 0u || params.bands[i].depth <= depth {
+            color = params.bands[i].color;
+        }
+    }
+    return color;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if any(invocation_id.xy >= input_texture_size) {
+        return;
+    }
+    let coords = vec2<i32>(invocation_id.xy);
+    let world = input_texture_pos + vec2<i32>(coords.x, i32(input_texture_size.y) - 1 - coords.y);
+    textureStore(input_texture, coords, unpack4x8unorm(sample(world)));
+}
@@ -0,0 +1,19 @@
+@group(0) @binding(0) var input_texture: texture_storage_2d<rgba8unorm, read_write>;
+// x: texture coordinates packed as x | y << 16, y: color packed as rgba8
+@group(0) @binding(1) var<storage, read> writes: array<vec2<u32>>;
+@group(0) @binding(2) var<storage, read_write> dirty_bounds: array<atomic<i32>, 5>;
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if invocation_id.x >= arrayLength(&writes) {
+        return;
+    }
+    let write = writes[invocation_id.x];
+    let coords = vec2<i32>(i32(write.x & 0xffffu), i32(write.x >> 16u));
+    textureStore(input_texture, coords, unpack4x8unorm(write.y));
+    atomicMin(&dirty_bounds[0], coords.x);
+    atomicMin(&dirty_bounds[1], coords.y);
+    atomicMax(&dirty_bounds[2], coords.x);
+    atomicMax(&dirty_bounds[3], coords.y);
+    atomicAdd(&dirty_bounds[4], 1);
+}
@@ -1,13 +1,31 @@
 @group(0) @binding(0) var input_texture: texture_storage_2d<rgba8unorm, read_write>;
 @group(0) @binding(1) var<uniform> input_texture_pos: vec2<i32>;
 @group(0) @binding(2) var<uniform> input_texture_size: vec2<u32>;
+@group(0) @binding(3) var<uniform> dispatch_offset: vec2<u32>;
+@group(0) @binding(4) var<storage, read_write> dirty_bounds: array<atomic<i32>, 5>;
+
+fn mark_dirty(coords: vec2<i32>) {
+    atomicMin(&dirty_bounds[0], coords.x);
+    atomicMin(&dirty_bounds[1], coords.y);
+    atomicMax(&dirty_bounds[2], coords.x);
+    atomicMax(&dirty_bounds[3], coords.y);
+    atomicAdd(&dirty_bounds[4], 1);
+}
+
+fn move_pixel(old_coords: vec2<i32>, new_coords: vec2<i32>, pixel: vec4<f32>) {
+    textureStore(input_texture, new_coords, pixel);
+    textureStore(input_texture, old_coords, vec4<f32>(0.0));
+    mark_dirty(old_coords);
+    mark_dirty(new_coords);
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let coords = vec2<i32>(invocation_id.xy);
-    let current_pixel: vec4<f32> = textureLoad(input_texture, coords);
-    if coords.x == 0 || coords.y == 0 || coords.x == i32(input_texture_size.x) - 1 || coords.y == i32(input_texture_size.y) - 1 {
+    let coords = vec2<i32>(invocation_id.xy + dispatch_offset);
+    if coords.x <= 0 || coords.y <= 0 || coords.x >= i32(input_texture_size.x) - 1 || coords.y >= i32(input_texture_size.y) - 1 {
         return;
     }
+    let current_pixel: vec4<f32> = textureLoad(input_texture, coords);
     if ( current_pixel.r == 1.0 && current_pixel.a == 1.0) {
         return;
     }
@@ -18,8 +36,7 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
             return;
         }
         if (below_pixel.a == 0.0) { 
-            textureStore(input_texture, below_coords, current_pixel);
-            textureStore(input_texture, coords, vec4<f32>(0.0)); 
+            move_pixel(coords, below_coords, current_pixel);
         } else { 
             let left_coords = coords + vec2<i32>(-1, -1);
             let right_coords = coords + vec2<i32>(1, -1); 
@@ -32,12 +49,10 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                 return;
             }
             if (left_pixel.a == 0.0) {
-                textureStore(input_texture, left_coords, current_pixel);
-                textureStore(input_texture, coords, vec4<f32>(0.0)); 
+                move_pixel(coords, left_coords, current_pixel);
             } else if (right_pixel.a == 0.0) { 
-                textureStore(input_texture, right_coords, current_pixel);
-                textureStore(input_texture, coords, vec4<f32>(0.0));
+                move_pixel(coords, right_coords, current_pixel);
             }
         }
     }
-}
\ No newline at end of file
+}
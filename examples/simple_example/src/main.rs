@@ -41,14 +41,11 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn(Transform::default())
         .insert(Visibility::Visible)
         .id();
-    commands.entity(id).insert(PixelMap::new(
-        UVec2 { x: 1000, y: 1000 },
-        id,
-        None,
-        None,
-        None,
-        vec!["shaders/sand_sim.wgsl".into()],
-    ));
+    commands.entity(id).insert(
+        PixelMap::builder(UVec2 { x: 1000, y: 1000 }, id)
+            .with_simulation_shader("shaders/sand_sim.wgsl")
+            .build(),
+    );
 
     commands.insert_resource(Imgs(vec![
         asset_server.load("images/1.png"),
@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    CommandEncoder, Extent3d, ImageCopyTexture, Origin3d, Texture, TextureAspect,
+};
+
+use crate::export::{copy_rows, filled_image, finish_export, ExportRequest};
+use crate::import::RegionWrite;
+use crate::{get_chunk_outer_i, PixelMap, PixelPositionedTexture, RegionExported};
+
+/// Label of [`PixelMap::copy_region`] images whose pixels are only on the
+/// GPU so far.
+const REGION_COPY_LABEL: &str = "pixel map region copy";
+
+/// Whether the main world data of `image` is a placeholder for a region copy
+/// made on the GPU.
+pub(crate) fn is_gpu_only(image: &Handle<Image>, images: &Assets<Image>) -> bool {
+    images
+        .get(image)
+        .is_some_and(|image| image.texture_descriptor.label == Some(REGION_COPY_LABEL))
+}
+
+/// Copy of a world pixel rect into an image, done before this frame's edits.
+#[derive(Clone, Debug)]
+pub(crate) struct RegionCopy {
+    pub image: Handle<Image>,
+    pub rect: IRect,
+    pub export: Option<ExportRequest>,
+}
+
+/// The part of `rect` inside a chunk: its texture rect in the chunk and the
+/// texel it starts at in an image covering `rect`, first row at the top.
+pub(crate) fn chunk_copy_rect(
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    rect: IRect,
+) -> Option<(URect, UVec2)> {
+    let origin = chunk_pos * chunk_size.as_ivec2();
+    let overlap = rect.intersect(IRect::from_corners(origin, origin + chunk_size.as_ivec2()));
+    if overlap.is_empty() {
+        return None;
+    }
+    let min = IVec2::new(
+        overlap.min.x - origin.x,
+        origin.y + chunk_size.y as i32 - overlap.max.y,
+    )
+    .as_uvec2();
+    let target = IVec2::new(overlap.min.x - rect.min.x, rect.max.y - overlap.max.y).as_uvec2();
+    Some((
+        URect::from_corners(min, min + overlap.size().as_uvec2()),
+        target,
+    ))
+}
+
+/// Chunk positions `rect` overlaps.
+pub(crate) fn chunks_in_rect(rect: IRect, chunk_size: UVec2) -> impl Iterator<Item = IVec2> {
+    let min = get_chunk_outer_i(rect.min, chunk_size);
+    let max = get_chunk_outer_i(rect.max - IVec2::ONE, chunk_size);
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+}
+
+impl PixelMap {
+    /// Copies the world pixels of `rect` into a new image. The copy happens
+    /// on the GPU before this frame's edits, so the image data in the main
+    /// world stays transparent unless the CPU backend is used. Stamp the
+    /// result with [`PixelMap::set_pixels_gpu`] or [`PixelMap::paste`], on
+    /// this map or another one. Missing chunks copy as transparent.
+    pub fn copy_region(
+        &mut self,
+        rect: IRect,
+        images: &mut ResMut<Assets<Image>>,
+    ) -> Option<PixelPositionedTexture> {
+        if rect.is_empty() {
+            return None;
+        }
+        let size = rect.size().as_uvec2();
+        let mut image = filled_image(size, [0; 4]);
+        image.texture_descriptor.label = Some(REGION_COPY_LABEL);
+        let image = images.add(image);
+        self.queue_region_copy(image.clone(), rect, None);
+        Some(PixelPositionedTexture {
+            position: rect.min,
+            image,
+            size,
+        })
+    }
+
+    pub(crate) fn queue_region_copy(
+        &mut self,
+        image: Handle<Image>,
+        rect: IRect,
+        export: Option<ExportRequest>,
+    ) {
+        self.region_copies.push(RegionCopy {
+            image,
+            rect,
+            export,
+        });
+    }
+
+    /// Copies `rect` like [`PixelMap::copy_region`], then clears it to
+    /// transparent with one region write per chunk.
+    pub fn cut_region(
+        &mut self,
+        rect: IRect,
+        images: &mut ResMut<Assets<Image>>,
+    ) -> Option<PixelPositionedTexture> {
+        let clip = self.copy_region(rect, images)?;
+        for chunk_pos in chunks_in_rect(rect, self.chunk_size) {
+            if !self.positions.contains_key(&chunk_pos) {
+                continue;
+            }
+            let Some((texture_rect, _)) = chunk_copy_rect(chunk_pos, self.chunk_size, rect) else {
+                continue;
+            };
+            self.queue_region_write(RegionWrite {
+                chunk_pos,
+                rect: texture_rect,
+                data: vec![0; texture_rect.size().element_product() as usize * 4].into(),
+            });
+        }
+        Some(clip)
+    }
+
+    /// Stamps a copied region with its bottom left corner at `position`.
+    /// Transparent pixels of the copy leave the map as it is.
+    pub fn paste(
+        &mut self,
+        clip: &PixelPositionedTexture,
+        position: IVec2,
+        images: &mut ResMut<Assets<Image>>,
+    ) {
+        self.set_pixels_gpu(
+            vec![PixelPositionedTexture {
+                position,
+                ..clip.clone()
+            }],
+            images,
+        );
+    }
+}
+
+/// A texture to texture copy of one chunk's part of a [`RegionCopy`].
+pub(crate) struct TextureCopy {
+    pub source: Texture,
+    pub target: Texture,
+    pub source_rect: URect,
+    pub target_origin: UVec2,
+}
+
+impl TextureCopy {
+    pub(crate) fn encode(&self, encoder: &mut CommandEncoder) {
+        let size = self.source_rect.size();
+        encoder.copy_texture_to_texture(
+            ImageCopyTexture {
+                texture: &self.source,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: self.source_rect.min.x,
+                    y: self.source_rect.min.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyTexture {
+                texture: &self.target,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: self.target_origin.x,
+                    y: self.target_origin.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+/// The CPU backend's copies, straight between the image assets. Returns the
+/// finished exports.
+pub(crate) fn copy_regions_cpu(
+    map: Entity,
+    pixel_map: &mut PixelMap,
+    images: &mut Assets<Image>,
+) -> Vec<RegionExported> {
+    let chunk_size = pixel_map.chunk_size;
+    let mut exports = Vec::new();
+    for copy in std::mem::take(&mut pixel_map.region_copies) {
+        let Some(mut target) = images
+            .get_mut(&copy.image)
+            .map(|image| std::mem::take(&mut image.data))
+        else {
+            continue;
+        };
+        for chunk_pos in chunks_in_rect(copy.rect, chunk_size) {
+            let Some(source) = pixel_map
+                .positions
+                .get(&chunk_pos)
+                .and_then(|&index| images.get(&pixel_map.image_data[index]))
+            else {
+                continue;
+            };
+            let Some((rect, target_origin)) = chunk_copy_rect(chunk_pos, chunk_size, copy.rect)
+            else {
+                continue;
+            };
+            copy_rows(
+                &source.data,
+                chunk_size.x,
+                rect,
+                &mut target,
+                copy.rect.width() as u32,
+                target_origin,
+            );
+        }
+        if let Some(request) = copy.export {
+            let size = copy.rect.size().as_uvec2();
+            exports.push(finish_export(map, request, size, target.clone()));
+        }
+        if let Some(image) = images.get_mut(&copy.image) {
+            image.data = target;
+            image.texture_descriptor.label = None;
+        }
+    }
+    exports
+}
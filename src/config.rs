@@ -0,0 +1,341 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::image::ImageSampler;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ChunkGenerator, CpuSimulation, GpuChunkGenerator, PixelMap};
+
+/// Serializable description of a [`PixelMap`].
+///
+/// Insert it as a component (for example from a scene) or load it as a
+/// `.pixelmap.ron` asset through [`PixelMapConfigHandle`], and a [`PixelMap`]
+/// rooted at the same entity is created from it.
+#[derive(Asset, Component, Reflect, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[reflect(Component, Serialize, Deserialize)]
+pub struct PixelMapConfig {
+    pub chunk_size: UVec2,
+    #[serde(default)]
+    pub default_chunk_color: [u8; 4],
+    #[serde(default)]
+    pub simulation_shaders: Vec<String>,
+    #[serde(default = "default_simulation_margin")]
+    pub simulation_margin: u32,
+    #[serde(default = "default_sleep_after")]
+    pub sleep_after: u32,
+    /// Bytes of undo history to keep, see [`PixelMap::enable_history`]. No
+    /// history is recorded when 0.
+    #[serde(default)]
+    pub history_budget: usize,
+    /// Downsampled levels drawn when zoomed out, see [`PixelMap::enable_lod`].
+    #[serde(default)]
+    pub lod_levels: u32,
+    /// Pixels a signed distance field reaches, see [`PixelMap::enable_sdf`].
+    /// No field is kept when 0.
+    #[serde(default)]
+    pub sdf_distance: u32,
+}
+
+fn default_simulation_margin() -> u32 {
+    2
+}
+
+fn default_sleep_after() -> u32 {
+    30
+}
+
+/// Spawns a [`PixelMap`] on this entity once the referenced config has loaded,
+/// and keeps it up to date when the config is reloaded.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub struct PixelMapConfigHandle(pub Handle<PixelMapConfig>);
+
+pub struct PixelMapBuilder {
+    chunk_size: UVec2,
+    root_entity: Entity,
+    empty_texture: Option<Image>,
+    sampler: Option<ImageSampler>,
+    default_chunk_color: Option<[u8; 4]>,
+    simulation_shaders: Vec<String>,
+    simulation_margin: Option<u32>,
+    sleep_after: Option<u32>,
+    history_budget: Option<usize>,
+    lod_levels: Option<u32>,
+    sdf_distance: Option<u32>,
+    generator: Option<Arc<dyn ChunkGenerator>>,
+    gpu_generator: Option<GpuChunkGenerator>,
+    cpu_simulations: Vec<Arc<dyn CpuSimulation>>,
+}
+
+impl PixelMapBuilder {
+    pub fn new(chunk_size: UVec2, root_entity: Entity) -> Self {
+        PixelMapBuilder {
+            chunk_size,
+            root_entity,
+            empty_texture: None,
+            sampler: None,
+            default_chunk_color: None,
+            simulation_shaders: Vec::new(),
+            simulation_margin: None,
+            sleep_after: None,
+            history_budget: None,
+            lod_levels: None,
+            sdf_distance: None,
+            generator: None,
+            gpu_generator: None,
+            cpu_simulations: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &PixelMapConfig, root_entity: Entity) -> Self {
+        let mut builder = PixelMapBuilder::new(config.chunk_size, root_entity)
+            .with_default_chunk_color(config.default_chunk_color)
+            .with_simulation_shaders(config.simulation_shaders.clone())
+            .with_simulation_margin(config.simulation_margin)
+            .with_sleep_after(config.sleep_after);
+        if config.history_budget > 0 {
+            builder = builder.with_history(config.history_budget);
+        }
+        if config.lod_levels > 0 {
+            builder = builder.with_lod(config.lod_levels);
+        }
+        if config.sdf_distance > 0 {
+            builder = builder.with_sdf(config.sdf_distance);
+        }
+        builder
+    }
+
+    pub fn with_empty_texture(mut self, empty_texture: Image) -> Self {
+        self.empty_texture = Some(empty_texture);
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: ImageSampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    pub fn with_default_chunk_color(mut self, color: [u8; 4]) -> Self {
+        self.default_chunk_color = Some(color);
+        self
+    }
+
+    pub fn with_simulation_shader(mut self, shader: impl Into<String>) -> Self {
+        self.simulation_shaders.push(shader.into());
+        self
+    }
+
+    pub fn with_simulation_shaders(mut self, shaders: Vec<String>) -> Self {
+        self.simulation_shaders.extend(shaders);
+        self
+    }
+
+    /// Pixels around the dirty rectangle of a chunk that simulation shaders
+    /// are still dispatched over.
+    pub fn with_simulation_margin(mut self, margin: u32) -> Self {
+        self.simulation_margin = Some(margin);
+        self
+    }
+
+    /// Ticks without a pixel write before a chunk stops being simulated.
+    pub fn with_sleep_after(mut self, ticks: u32) -> Self {
+        self.sleep_after = Some(ticks);
+        self
+    }
+
+    /// Records edits for [`PixelMap::undo`], keeping at most `budget` bytes
+    /// of snapshots.
+    pub fn with_history(mut self, budget: usize) -> Self {
+        self.history_budget = Some(budget);
+        self
+    }
+
+    /// Draws downsampled tiles in place of the chunks when zoomed out, see
+    /// [`PixelMap::enable_lod`].
+    pub fn with_lod(mut self, levels: u32) -> Self {
+        self.lod_levels = Some(levels);
+        self
+    }
+
+    /// Keeps a signed distance field of the chunks, see
+    /// [`PixelMap::enable_sdf`].
+    pub fn with_sdf(mut self, max_distance: u32) -> Self {
+        self.sdf_distance = Some(max_distance);
+        self
+    }
+
+    pub fn with_generator(mut self, generator: impl ChunkGenerator) -> Self {
+        self.generator = Some(Arc::new(generator));
+        self
+    }
+
+    pub fn with_gpu_generator(mut self, generator: GpuChunkGenerator) -> Self {
+        self.gpu_generator = Some(generator);
+        self
+    }
+
+    /// Simulation step run by [`PixelMapCpuPlugin`](crate::PixelMapCpuPlugin)
+    /// in place of the simulation shaders.
+    pub fn with_cpu_simulation(mut self, simulation: impl CpuSimulation) -> Self {
+        self.cpu_simulations.push(Arc::new(simulation));
+        self
+    }
+
+    pub fn build(self) -> PixelMap {
+        let mut pixel_map = PixelMap::new(
+            self.chunk_size,
+            self.root_entity,
+            self.empty_texture,
+            self.sampler,
+            self.default_chunk_color,
+            self.simulation_shaders,
+        );
+        if let Some(margin) = self.simulation_margin {
+            pixel_map.simulation_margin = margin;
+        }
+        if let Some(ticks) = self.sleep_after {
+            pixel_map.sleep_after = ticks;
+        }
+        if let Some(budget) = self.history_budget {
+            pixel_map.enable_history(budget);
+        }
+        if let Some(levels) = self.lod_levels {
+            pixel_map.enable_lod(levels);
+        }
+        if let Some(max_distance) = self.sdf_distance {
+            pixel_map.enable_sdf(max_distance);
+        }
+        pixel_map.generator = self.generator;
+        pixel_map.gpu_generator = self.gpu_generator;
+        pixel_map.cpu_simulations = self.cpu_simulations;
+        pixel_map
+    }
+}
+
+impl PixelMap {
+    /// Takes the settings of `config` that can change on a live map: the
+    /// simulation shaders and margin, sleep ticks, history budget, levels of
+    /// detail and distance field. The chunk size and color stay as built.
+    pub fn apply_config(&mut self, config: &PixelMapConfig) {
+        if config.chunk_size != self.chunk_size {
+            warn!(
+                "can't change the chunk size of a pixel map from {} to {}",
+                self.chunk_size, config.chunk_size
+            );
+        }
+        self.simulation_shaders = config.simulation_shaders.clone();
+        self.simulation_margin = config.simulation_margin;
+        self.sleep_after = config.sleep_after;
+        match config.history_budget {
+            0 => self.disable_history(),
+            budget => self.enable_history(budget),
+        }
+        match config.lod_levels {
+            0 => self.disable_lod(),
+            levels => self.enable_lod(levels),
+        }
+        match config.sdf_distance {
+            0 => self.disable_sdf(),
+            distance if self.config().sdf_distance != distance => self.enable_sdf(distance),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PixelMapConfigLoader;
+
+#[derive(Debug)]
+pub enum PixelMapConfigLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl std::fmt::Display for PixelMapConfigLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PixelMapConfigLoaderError::Io(err) => {
+                write!(f, "could not read pixel map config: {err}")
+            }
+            PixelMapConfigLoaderError::Ron(err) => {
+                write!(f, "could not parse pixel map config: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PixelMapConfigLoaderError {}
+
+impl AssetLoader for PixelMapConfigLoader {
+    type Asset = PixelMapConfig;
+    type Settings = ();
+    type Error = PixelMapConfigLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(PixelMapConfigLoaderError::Io)?;
+        ron::de::from_bytes(&bytes).map_err(PixelMapConfigLoaderError::Ron)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["pixelmap.ron"]
+    }
+}
+
+pub(crate) fn spawn_configured_pixel_maps(
+    mut commands: Commands,
+    configs: Res<Assets<PixelMapConfig>>,
+    inline_query: Query<(Entity, &PixelMapConfig), Without<PixelMap>>,
+    handle_query: Query<(Entity, &PixelMapConfigHandle), Without<PixelMap>>,
+) {
+    for (entity, config) in inline_query.iter() {
+        commands
+            .entity(entity)
+            .insert(PixelMapBuilder::from_config(config, entity).build());
+    }
+    for (entity, handle) in handle_query.iter() {
+        if let Some(config) = configs.get(&handle.0) {
+            commands
+                .entity(entity)
+                .insert(PixelMapBuilder::from_config(config, entity).build());
+        }
+    }
+}
+
+/// Pushes reloaded config assets and changed config components into the maps
+/// built from them.
+pub(crate) fn reload_pixel_map_configs(
+    mut events: EventReader<AssetEvent<PixelMapConfig>>,
+    configs: Res<Assets<PixelMapConfig>>,
+    mut inline_query: Query<(Ref<PixelMapConfig>, &mut PixelMap)>,
+    mut handle_query: Query<(&PixelMapConfigHandle, &mut PixelMap), Without<PixelMapConfig>>,
+) {
+    for (config, mut pixel_map) in inline_query.iter_mut() {
+        if config.is_changed() {
+            pixel_map.apply_config(&config);
+        }
+    }
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        let Some(config) = configs.get(*id) else {
+            continue;
+        };
+        for (handle, mut pixel_map) in handle_query.iter_mut() {
+            if handle.0.id() == *id {
+                pixel_map.apply_config(config);
+            }
+        }
+    }
+}
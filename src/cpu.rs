@@ -0,0 +1,382 @@
+use bevy::prelude::*;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+
+use crate::clipboard::{chunks_in_rect, copy_regions_cpu};
+use crate::deterministic::{apply_checksums_cpu, sorted_chunks};
+use crate::dirty::{
+    dispatch_rect, full_chunk_bounds, mark_dirty, merge_bounds, stamp_texel_origin,
+    stamp_texture_rect, DIRTY_WORDS, EMPTY_BOUNDS,
+};
+use crate::explosion::carve_cpu;
+use crate::history::{copy_region, paste_region, record_history};
+use crate::lighting::apply_lighting_cpu;
+use crate::lod::{apply_lod_cpu, update_lod};
+use crate::minimap::{apply_minimaps_cpu, update_minimaps};
+use crate::particles::{apply_particles_cpu, Particle, PixelParticle};
+use crate::sdf::{apply_sdf_cpu, update_sdf};
+use crate::stats::apply_stats_cpu;
+use crate::terrain::hash2;
+use crate::{
+    add_main_world_systems, ChunkChecksums, ChunkDirty, PixelMap, PixelPositionedTexture,
+    RegionExported, RegionStats,
+};
+
+/// Runs pixel maps on the CPU instead of the GPU, for headless apps and tests.
+///
+/// Stamps and [`PixelMap::set_pixels_cpu`] writes are applied to the chunk
+/// images in [`Assets<Image>`] during [`PostUpdate`], followed by the
+/// [`CpuSimulation`]s of the map. Simulation shaders and [`GpuChunkGenerator`](crate::GpuChunkGenerator)s
+/// are ignored. Needs the [`AssetPlugin`], so `(MinimalPlugins, AssetPlugin::default())`
+/// is enough to run it.
+pub struct PixelMapCpuPlugin;
+
+impl Plugin for PixelMapCpuPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<Assets<Image>>() {
+            app.init_asset::<Image>();
+        }
+        add_main_world_systems(app);
+        app.init_resource::<CpuDirtyState>().add_systems(
+            PostUpdate,
+            apply_ops_cpu
+                .after(record_history)
+                .after(update_lod)
+                .after(update_minimaps)
+                .after(update_sdf),
+        );
+    }
+}
+
+/// A simulation step over one chunk, the CPU counterpart of a simulation shader.
+pub trait CpuSimulation: Send + Sync + 'static {
+    fn step(&self, chunk: &mut CpuChunk);
+}
+
+impl<F> CpuSimulation for F
+where
+    F: Fn(&mut CpuChunk) + Send + Sync + 'static,
+{
+    fn step(&self, chunk: &mut CpuChunk) {
+        self(chunk)
+    }
+}
+
+/// Pixels of a chunk in texture coordinates, row 0 at the top like in the
+/// shaders.
+pub struct CpuChunk<'a> {
+    pub position: IVec2,
+    pub size: UVec2,
+    /// The part of the chunk to simulate: what changed this tick or the last,
+    /// grown by the simulation margin.
+    pub dispatch: URect,
+    /// [`PixelMap::simulation_tick`] of this step.
+    pub tick: u64,
+    /// Seeds [`CpuChunk::random`], different for every chunk and tick and
+    /// the same between runs of a deterministic map.
+    pub seed: u32,
+    pixels: &'a mut [u8],
+    dirty: &'a mut [i32; DIRTY_WORDS],
+    ejected: &'a mut Vec<PixelParticle>,
+    eject_capacity: usize,
+}
+
+impl CpuChunk<'_> {
+    pub fn contains(&self, coords: IVec2) -> bool {
+        coords.cmpge(IVec2::ZERO).all() && coords.cmplt(self.size.as_ivec2()).all()
+    }
+
+    /// Transparent outside of the chunk.
+    pub fn get(&self, coords: IVec2) -> [u8; 4] {
+        if !self.contains(coords) {
+            return [0; 4];
+        }
+        let ind = self.index(coords);
+        self.pixels[ind..ind + 4].try_into().unwrap()
+    }
+
+    /// Writes a pixel and marks it dirty, ignoring coordinates outside of the chunk.
+    pub fn set(&mut self, coords: IVec2, color: [u8; 4]) {
+        if !self.contains(coords) {
+            return;
+        }
+        let ind = self.index(coords);
+        self.pixels[ind..ind + 4].copy_from_slice(&color);
+        mark_dirty(self.dirty, coords);
+    }
+
+    /// Clears a pixel and throws it over the map as a particle, moving
+    /// `velocity` world pixels per second, like [`PixelMap::emit_particles`].
+    /// Ignored outside of the chunk, for transparent pixels and once the
+    /// `eject_capacity` of the map is used up this frame.
+    pub fn eject(&mut self, coords: IVec2, velocity: Vec2) {
+        let color = self.get(coords);
+        if color[3] == 0 || self.ejected.len() >= self.eject_capacity {
+            return;
+        }
+        self.ejected.push(PixelParticle {
+            position: self.world_position(coords).as_vec2() + 0.5,
+            velocity,
+            color,
+        });
+        self.set(coords, [0; 4]);
+    }
+
+    /// A random number per pixel and tick, `hash2(coords.x, coords.y, seed)`
+    /// like in `explode.wgsl`.
+    pub fn random(&self, coords: IVec2) -> u32 {
+        hash2(coords.x, coords.y, self.seed)
+    }
+
+    pub fn world_position(&self, coords: IVec2) -> IVec2 {
+        self.position * self.size.as_ivec2()
+            + IVec2::new(coords.x, self.size.y as i32 - 1 - coords.y)
+    }
+
+    fn index(&self, coords: IVec2) -> usize {
+        (coords.y as usize * self.size.x as usize + coords.x as usize) * 4
+    }
+}
+
+#[derive(Resource, Default)]
+struct CpuDirtyState {
+    bounds: HashMap<AssetId<Image>, ([i32; DIRTY_WORDS], [i32; 4])>,
+    frame: u64,
+}
+
+fn apply_ops_cpu(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut images: ResMut<Assets<Image>>,
+    mut state: ResMut<CpuDirtyState>,
+    mut dirty_events: EventWriter<ChunkDirty>,
+    mut export_events: EventWriter<RegionExported>,
+    mut stats_events: EventWriter<RegionStats>,
+    mut checksum_events: EventWriter<ChunkChecksums>,
+) {
+    let state = state.as_mut();
+    state.frame += 1;
+    state.bounds.retain(|image, _| images.contains(*image));
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        let chunk_size = pixel_map.chunk_size;
+        export_events.send_batch(copy_regions_cpu(map, &mut pixel_map, &mut images));
+        let pixel_writes = std::mem::take(&mut pixel_map.pixel_writes);
+        let woken_chunks = std::mem::take(&mut pixel_map.woken_chunks);
+        let (snapshot_requests, mut region_writes) = pixel_map
+            .history
+            .as_mut()
+            .map(|history| {
+                (
+                    std::mem::take(&mut history.snapshot_requests),
+                    std::mem::take(&mut history.restores),
+                )
+            })
+            .unwrap_or_default();
+        region_writes.append(&mut pixel_map.region_writes);
+        let mut snapshots = vec![None; snapshot_requests.len()];
+        let simulations = if pixel_map.simulation_paused {
+            Vec::new()
+        } else {
+            pixel_map.cpu_simulations.clone()
+        };
+        let ticks = if simulations.is_empty() {
+            pixel_map.simulation_tick..pixel_map.simulation_tick + 1
+        } else {
+            pixel_map.simulated_ticks()
+        };
+        let mut chunks: HashSet<IVec2> = pixel_map
+            .texture_to_chunk_posses
+            .keys()
+            .chain(pixel_writes.keys())
+            .copied()
+            .collect();
+        if !simulations.is_empty() {
+            chunks.extend(pixel_map.awake_chunks());
+        }
+        chunks.extend(snapshot_requests.iter().map(|request| request.chunk_pos));
+        chunks.extend(region_writes.iter().map(|write| write.chunk_pos));
+        let explosions: Vec<_> = pixel_map
+            .particles
+            .batches
+            .iter_mut()
+            .enumerate()
+            .flat_map(|(index, batch)| {
+                std::mem::take(&mut batch.explosions)
+                    .into_iter()
+                    .map(move |explosion| (index, explosion))
+            })
+            .collect();
+        chunks.extend(
+            explosions
+                .iter()
+                .flat_map(|(_, explosion)| chunks_in_rect(explosion.rect(), chunk_size)),
+        );
+        let eject_capacity = pixel_map.particles.settings.eject_capacity as usize;
+        let mut ejected = Vec::new();
+        let mut thrown: Vec<Vec<Particle>> = vec![Vec::new(); pixel_map.particles.batches.len()];
+
+        let chunks = if pixel_map.deterministic.is_some() {
+            sorted_chunks(chunks)
+        } else {
+            chunks.into_iter().collect()
+        };
+        let mut results = Vec::with_capacity(chunks.len());
+        for chunk_pos in chunks {
+            let Some(&index) = pixel_map.positions.get(&chunk_pos) else {
+                continue;
+            };
+            let image = pixel_map.image_data[index].id();
+            let Some(mut pixels) = images
+                .get_mut(image)
+                .map(|img| std::mem::take(&mut img.data))
+            else {
+                continue;
+            };
+            let (changed, previous) = state
+                .bounds
+                .entry(image)
+                .or_insert((EMPTY_BOUNDS, [i32::MAX, i32::MAX, i32::MIN, i32::MIN]));
+            for (request, snapshot) in snapshot_requests.iter().zip(snapshots.iter_mut()) {
+                if request.chunk_pos == chunk_pos {
+                    *snapshot = Some(copy_region(&pixels, chunk_size, request.rect));
+                }
+            }
+            for write in region_writes
+                .iter()
+                .filter(|write| write.chunk_pos == chunk_pos)
+            {
+                paste_region(&mut pixels, chunk_size, write.rect, &write.data);
+            }
+
+            if woken_chunks.contains(&chunk_pos) {
+                *changed = full_chunk_bounds(chunk_size);
+            }
+            previous.copy_from_slice(&changed[..4]);
+            *changed = EMPTY_BOUNDS;
+
+            if let Some(writes) = pixel_writes.get(&chunk_pos) {
+                for (&coords, &color) in writes.iter() {
+                    let coords = IVec2::new((coords & 0xffff) as i32, (coords >> 16) as i32);
+                    let ind = (coords.y as usize * chunk_size.x as usize + coords.x as usize) * 4;
+                    pixels[ind..ind + 4].copy_from_slice(&color.to_le_bytes());
+                    mark_dirty(changed, coords);
+                }
+            }
+
+            let chunk_texes = pixel_map
+                .texture_to_chunk_posses
+                .get(&chunk_pos)
+                .map_or(&[][..], Vec::as_slice);
+            for tex in chunk_texes {
+                if let Some(source) = images.get(tex.image.id()) {
+                    stamp(&mut pixels, changed, chunk_pos, chunk_size, tex, source);
+                }
+            }
+            for (index, explosion) in explosions.iter() {
+                let capacity = pixel_map.particles.batches[*index].capacity;
+                carve_cpu(
+                    &mut pixels,
+                    changed,
+                    chunk_pos,
+                    chunk_size,
+                    explosion,
+                    &mut thrown[*index],
+                    capacity,
+                );
+            }
+
+            let mut frame_changed = EMPTY_BOUNDS;
+            for (step, tick) in ticks.clone().enumerate() {
+                // Later ticks of the frame start like a new frame would.
+                if step > 0 {
+                    merge_bounds(&mut frame_changed, changed);
+                    previous.copy_from_slice(&changed[..4]);
+                    *changed = EMPTY_BOUNDS;
+                }
+                let passes = if step == 0 {
+                    chunk_texes.len().max(1)
+                } else {
+                    1
+                };
+                for _ in 0..passes {
+                    for simulation in simulations.iter() {
+                        let Some(dispatch) = dispatch_rect(
+                            changed,
+                            previous,
+                            chunk_size,
+                            pixel_map.simulation_margin,
+                        ) else {
+                            continue;
+                        };
+                        simulation.step(&mut CpuChunk {
+                            position: chunk_pos,
+                            size: chunk_size,
+                            dispatch,
+                            tick,
+                            seed: pixel_map.tick_seed(chunk_pos, tick),
+                            pixels: &mut pixels,
+                            dirty: changed,
+                            ejected: &mut ejected,
+                            eject_capacity,
+                        });
+                    }
+                }
+            }
+            merge_bounds(&mut frame_changed, changed);
+
+            results.push((chunk_pos, frame_changed, !simulations.is_empty()));
+            if let Some(img) = images.get_mut(image) {
+                img.data = pixels;
+            }
+        }
+        if let Some(history) = pixel_map.history.as_mut() {
+            for (request, snapshot) in snapshot_requests.into_iter().zip(snapshots) {
+                history.complete(request, snapshot);
+            }
+        }
+        for (batch, particles) in pixel_map.particles.batches.iter_mut().zip(thrown) {
+            batch.particles.extend(particles);
+        }
+        apply_particles_cpu(&mut pixel_map, &mut images);
+        pixel_map.emit_particles(ejected);
+        stats_events.send_batch(apply_stats_cpu(map, &mut pixel_map, &images));
+        checksum_events.send_batch(apply_checksums_cpu(map, &mut pixel_map, &images));
+        apply_lod_cpu(&mut pixel_map, &mut images);
+        apply_minimaps_cpu(&mut pixel_map, &mut images);
+        apply_lighting_cpu(&mut pixel_map, &mut images);
+        apply_sdf_cpu(&mut pixel_map, &mut images);
+        dirty_events.send_batch(pixel_map.apply_dirty_state(map, state.frame, results));
+    }
+}
+
+/// Same lookup as `place_tex.wgsl`, both from [`stamp_texel_origin`], with reads
+/// outside of the source transparent.
+fn stamp(
+    pixels: &mut [u8],
+    changed: &mut [i32; DIRTY_WORDS],
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    tex: &PixelPositionedTexture,
+    source: &Image,
+) {
+    let Some(rect) = stamp_texture_rect(chunk_pos, chunk_size, tex) else {
+        return;
+    };
+    let texel_origin = stamp_texel_origin(chunk_pos, chunk_size, tex);
+    let source_size = source.size().as_ivec2();
+    for y in rect.min.y as i32..rect.max.y as i32 {
+        for x in rect.min.x as i32..rect.max.x as i32 {
+            let source_coords = IVec2::new(x, y) - texel_origin;
+            if source_coords.cmplt(IVec2::ZERO).any() || source_coords.cmpge(source_size).any() {
+                continue;
+            }
+            let source_ind = (source_coords.y * source_size.x + source_coords.x) as usize * 4;
+            let Some(color) = source.data.get(source_ind..source_ind + 4) else {
+                continue;
+            };
+            if color[3] > 0 {
+                let ind = (y as usize * chunk_size.x as usize + x as usize) * 4;
+                pixels[ind..ind + 4].copy_from_slice(color);
+                mark_dirty(changed, IVec2::new(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+
+use crate::export::filled_image;
+use crate::{
+    get_chunk_inner_i, get_chunk_outer_i, PixelEdit, PixelMap, PixelPositionedTexture,
+    RegionExported,
+};
+
+/// Which groups of pixels [`PixelMap::detach_debris`] turns into debris.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebrisSelection {
+    /// Groups touching neither the edge of the rect, where they may hold on
+    /// to the terrain around it, nor a pixel of an anchor color.
+    #[default]
+    Disconnected,
+    /// Every group within the rect, cut off at its edge.
+    All,
+}
+
+/// How debris of a map is picked and when it settles back into the map.
+#[derive(Reflect, Clone, Debug, PartialEq)]
+pub struct DebrisSettings {
+    /// Colors of pixels that hold the groups they are in in place.
+    pub anchors: Vec<[u8; 4]>,
+    /// Groups with fewer pixels stay in the map.
+    pub min_pixels: usize,
+    /// Debris moving slower than this, in pixels or radians per second, is
+    /// at rest.
+    pub rest_speed: f32,
+    /// Seconds debris has to be at rest before it is stamped back.
+    pub rest_seconds: f32,
+}
+
+impl Default for DebrisSettings {
+    fn default() -> Self {
+        DebrisSettings {
+            anchors: Vec::new(),
+            min_pixels: 4,
+            rest_speed: 1.0,
+            rest_seconds: 1.0,
+        }
+    }
+}
+
+/// A [`PixelMap::detach_debris`] waiting for the pixels of its rect.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DebrisRequest {
+    pub export: u64,
+    pub rect: IRect,
+    pub selection: DebrisSelection,
+}
+
+/// Pixels cut out of a map into a sprite of their own, a child of the map
+/// root. Move it around with its [`Transform`], usually through a physics
+/// engine given its [`DebrisCollider`]. Once it has been at rest for
+/// [`DebrisSettings::rest_seconds`] its pixels are stamped back into the map
+/// where it lies and the entity is despawned.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, from_reflect = false)]
+pub struct PixelDebris {
+    pub map: Entity,
+    pub image: Handle<Image>,
+    pub size: UVec2,
+    pub pixel_count: usize,
+    #[reflect(ignore)]
+    last: Option<Transform>,
+    #[reflect(ignore)]
+    resting: f32,
+}
+
+/// Shape of a [`PixelDebris`] as rectangles covering its pixels, relative to
+/// the center of its sprite.
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Component)]
+pub struct DebrisCollider {
+    pub rects: Vec<Rect>,
+}
+
+impl PixelMap {
+    /// Looks for debris in `rect` once its pixels are read back, as they are
+    /// before this frame's edits. Pixels that aren't fully transparent make
+    /// up groups with their horizontal and vertical neighbors; the groups
+    /// picked by `selection` are cleared from the map and spawned as
+    /// [`PixelDebris`].
+    pub fn detach_debris(
+        &mut self,
+        rect: IRect,
+        selection: DebrisSelection,
+        images: &mut ResMut<Assets<Image>>,
+    ) {
+        let Some(export) = self.request_export(rect, 1, images) else {
+            return;
+        };
+        self.debris_requests.push(DebrisRequest {
+            export,
+            rect,
+            selection,
+        });
+    }
+
+    pub fn debris_settings(&self) -> &DebrisSettings {
+        &self.debris_settings
+    }
+
+    pub fn debris_settings_mut(&mut self) -> &mut DebrisSettings {
+        &mut self.debris_settings
+    }
+
+    /// Clears world pixels to transparent where their chunk exists, and
+    /// records them for replication.
+    fn clear_pixels(&mut self, positions: impl IntoIterator<Item = IVec2>) {
+        let mut cleared = Vec::new();
+        for position in positions {
+            let chunk_pos = get_chunk_outer_i(position, self.chunk_size);
+            if !self.positions.contains_key(&chunk_pos) {
+                continue;
+            }
+            let simulated = self.has_simulation() && self.is_chunk_sleeping(chunk_pos);
+            let inner = get_chunk_inner_i(position, self.chunk_size);
+            let coords = UVec2::new(inner.x, self.chunk_size.y - inner.y - 1);
+            self.queue_pixel_write(chunk_pos, coords, [0; 4], simulated);
+            cleared.push((position, [0; 4]));
+        }
+        if !cleared.is_empty() && (self.replication.is_some() || self.recorder.is_some()) {
+            self.record_edit(PixelEdit::Pixels(cleared));
+        }
+    }
+}
+
+/// Groups of non transparent texels of an rgba8 image, connected
+/// horizontally and vertically.
+fn pixel_groups(data: &[u8], size: UVec2) -> Vec<Vec<UVec2>> {
+    let filled = |texel: UVec2| data[(texel.y * size.x + texel.x) as usize * 4 + 3] > 0;
+    let mut seen = vec![false; size.element_product() as usize];
+    let mut groups = Vec::new();
+    for start in (0..size.y).flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y))) {
+        let ind = (start.y * size.x + start.x) as usize;
+        if seen[ind] || !filled(start) {
+            continue;
+        }
+        seen[ind] = true;
+        let mut group = Vec::new();
+        let mut stack = vec![start];
+        while let Some(texel) = stack.pop() {
+            group.push(texel);
+            let neighbors = [
+                texel.x.checked_sub(1).map(|x| UVec2::new(x, texel.y)),
+                (texel.x + 1 < size.x).then(|| UVec2::new(texel.x + 1, texel.y)),
+                texel.y.checked_sub(1).map(|y| UVec2::new(texel.x, y)),
+                (texel.y + 1 < size.y).then(|| UVec2::new(texel.x, texel.y + 1)),
+            ];
+            for neighbor in neighbors.into_iter().flatten() {
+                let ind = (neighbor.y * size.x + neighbor.x) as usize;
+                if !seen[ind] && filled(neighbor) {
+                    seen[ind] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Rectangles covering the non transparent pixels of an rgba8 image, merging
+/// equal runs of consecutive rows, centered on the image.
+fn collider_rects(data: &[u8], size: UVec2) -> Vec<Rect> {
+    let half = size.as_vec2() / 2.;
+    let mut rects = Vec::new();
+    // Runs of the previous row still growing downwards: start, end, top row.
+    let mut open: Vec<(u32, u32, u32)> = Vec::new();
+    for y in 0..=size.y {
+        let mut runs = Vec::new();
+        if y < size.y {
+            let mut x = 0;
+            while x < size.x {
+                if data[(y * size.x + x) as usize * 4 + 3] == 0 {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < size.x && data[(y * size.x + x) as usize * 4 + 3] > 0 {
+                    x += 1;
+                }
+                runs.push((start, x));
+            }
+        }
+        let mut next = Vec::with_capacity(runs.len());
+        for (start, end) in runs {
+            let top = open
+                .iter()
+                .position(|&(open_start, open_end, _)| (open_start, open_end) == (start, end))
+                .map_or(y, |index| open.swap_remove(index).2);
+            next.push((start, end, top));
+        }
+        // Texture rows go down while the collider's y goes up.
+        rects.extend(open.drain(..).map(|(start, end, top)| {
+            Rect::new(
+                start as f32 - half.x,
+                half.y - y as f32,
+                end as f32 - half.x,
+                half.y - top as f32,
+            )
+        }));
+        open = next;
+    }
+    rects
+}
+
+pub(crate) fn spawn_debris(
+    mut commands: Commands,
+    mut exports: EventReader<RegionExported>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for export in exports.read() {
+        let Ok(mut pixel_map) = pixel_map_query.get_mut(export.map) else {
+            continue;
+        };
+        let Some(index) = pixel_map
+            .debris_requests
+            .iter()
+            .position(|request| request.export == export.id)
+        else {
+            continue;
+        };
+        let request = pixel_map.debris_requests.swap_remove(index);
+        let size = export.image.size();
+        let data = &export.image.data;
+        let settings = pixel_map.debris_settings.clone();
+        for group in pixel_groups(data, size) {
+            if group.len() < settings.min_pixels.max(1) {
+                continue;
+            }
+            let color = |texel: UVec2| -> [u8; 4] {
+                let ind = (texel.y * size.x + texel.x) as usize * 4;
+                data[ind..ind + 4].try_into().unwrap()
+            };
+            if request.selection == DebrisSelection::Disconnected {
+                let anchored = group.iter().any(|&texel| {
+                    texel.x == 0
+                        || texel.y == 0
+                        || texel.x + 1 == size.x
+                        || texel.y + 1 == size.y
+                        || settings.anchors.contains(&color(texel))
+                });
+                if anchored {
+                    continue;
+                }
+            }
+
+            let min = group.iter().fold(UVec2::MAX, |min, &texel| min.min(texel));
+            let max = group.iter().fold(UVec2::ZERO, |max, &texel| max.max(texel)) + 1;
+            let debris_size = max - min;
+            let mut image = filled_image(debris_size, [0; 4]);
+            for &texel in group.iter() {
+                let local = texel - min;
+                let ind = (local.y * debris_size.x + local.x) as usize * 4;
+                image.data[ind..ind + 4].copy_from_slice(&color(texel));
+            }
+            pixel_map.clear_pixels(group.iter().map(|texel| {
+                IVec2::new(
+                    request.rect.min.x + texel.x as i32,
+                    request.rect.max.y - 1 - texel.y as i32,
+                )
+            }));
+            let collider = DebrisCollider {
+                rects: collider_rects(&image.data, debris_size),
+            };
+            image.sampler = pixel_map.empty_texture.sampler.clone();
+            let image = images.add(image);
+
+            // Chunk sprites are centered on the origin of their chunk.
+            let world_min = IVec2::new(
+                request.rect.min.x + min.x as i32,
+                request.rect.max.y - max.y as i32,
+            );
+            let center = world_min.as_vec2() + debris_size.as_vec2() / 2.
+                - pixel_map.chunk_size.as_vec2() / 2.;
+            let entity = commands
+                .spawn((
+                    Sprite {
+                        image: image.clone(),
+                        custom_size: Some(debris_size.as_vec2()),
+                        ..default()
+                    },
+                    Transform::from_xyz(center.x, center.y, 0.0),
+                    PixelDebris {
+                        map: export.map,
+                        image,
+                        size: debris_size,
+                        pixel_count: group.len(),
+                        last: None,
+                        resting: 0.0,
+                    },
+                    collider,
+                ))
+                .id();
+            commands.entity(pixel_map.root_entity).add_child(entity);
+        }
+    }
+}
+
+/// The pixels of an rgba8 image turned by `angle` around its center, in the
+/// smallest image holding them.
+fn rotate_image(data: &[u8], size: UVec2, angle: f32) -> (Vec<u8>, UVec2) {
+    let (sin, cos) = angle.sin_cos();
+    let extent = size.as_vec2();
+    // Shaved a little so quarter turns don't grow by a pixel.
+    let rotated = Vec2::new(
+        (extent.x * cos.abs() + extent.y * sin.abs() - 1e-3).ceil(),
+        (extent.x * sin.abs() + extent.y * cos.abs() - 1e-3).ceil(),
+    )
+    .as_uvec2()
+    .max(UVec2::ONE);
+    let mut target = vec![0; rotated.element_product() as usize * 4];
+    for y in 0..rotated.y {
+        for x in 0..rotated.x {
+            let offset = Vec2::new(
+                x as f32 + 0.5 - rotated.x as f32 / 2.,
+                rotated.y as f32 / 2. - y as f32 - 0.5,
+            );
+            let source = Vec2::new(
+                offset.x * cos + offset.y * sin,
+                offset.y * cos - offset.x * sin,
+            );
+            let texel = Vec2::new(source.x + extent.x / 2., extent.y / 2. - source.y).floor();
+            if texel.cmplt(Vec2::ZERO).any() || texel.cmpge(extent).any() {
+                continue;
+            }
+            let from = (texel.y as u32 * size.x + texel.x as u32) as usize * 4;
+            let to = (y * rotated.x + x) as usize * 4;
+            target[to..to + 4].copy_from_slice(&data[from..from + 4]);
+        }
+    }
+    (target, rotated)
+}
+
+fn z_angle(rotation: Quat) -> f32 {
+    rotation.to_euler(EulerRot::ZYX).0
+}
+
+pub(crate) fn settle_debris(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut debris_query: Query<(Entity, &mut PixelDebris, &Transform)>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let delta = time.delta_secs();
+    for (entity, mut debris, transform) in debris_query.iter_mut() {
+        let Ok(mut pixel_map) = pixel_map_query.get_mut(debris.map) else {
+            continue;
+        };
+        let settings = &pixel_map.debris_settings;
+        let moving = debris.last.is_none_or(|last| {
+            let speed = last.translation.distance(transform.translation);
+            let turn = z_angle(transform.rotation) - z_angle(last.rotation);
+            let spin = (turn + PI).rem_euclid(TAU) - PI;
+            speed.max(spin.abs()) > settings.rest_speed * delta
+        });
+        debris.last = Some(*transform);
+        if moving {
+            debris.resting = 0.0;
+            continue;
+        }
+        debris.resting += delta;
+        if debris.resting < settings.rest_seconds {
+            continue;
+        }
+
+        let Some(image) = images.get(&debris.image) else {
+            continue;
+        };
+        let (data, size) = rotate_image(&image.data, debris.size, z_angle(transform.rotation));
+        let mut stamp = filled_image(size, [0; 4]);
+        stamp.data = data;
+        let center = transform.translation.truncate() + pixel_map.chunk_size.as_vec2() / 2.;
+        let position = (center - size.as_vec2() / 2.).round().as_ivec2();
+        let image = images.add(stamp);
+        pixel_map.set_pixels_gpu(
+            vec![PixelPositionedTexture {
+                position,
+                image,
+                size,
+            }],
+            &mut images,
+        );
+        commands.entity(entity).despawn_recursive();
+    }
+}
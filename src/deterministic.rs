@@ -0,0 +1,411 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType, Buffer,
+    BufferBindingType, BufferInitDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor,
+    ComputePipeline, Extent3d, IntoBinding, ShaderStages, StorageTextureAccess, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::texture::GpuImage;
+use bevy::utils::hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::readback::PendingReadback;
+use crate::terrain::{hash2, pcg};
+use crate::{ChunkChecksums, PixelMap};
+
+/// Settings of [`PixelMap::enable_deterministic_simulation`].
+#[derive(Reflect, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeterministicSettings {
+    /// Seeds the per tick random numbers of the simulation.
+    pub seed: u32,
+    /// Each simulation shader is dispatched once per phase, and should only
+    /// update pixels whose coordinates modulo `phases` equal the phase. With
+    /// the default of 3 by 3, pixels updated by one dispatch are far enough
+    /// apart to touch their direct neighbors without racing each other.
+    pub phases: UVec2,
+    /// Simulation shaders bind an `rgba8uint` copy of each chunk instead of
+    /// the chunk itself, so they load and store whole bytes. Off by default,
+    /// since shaders have to be written for it.
+    #[serde(default)]
+    pub integer_storage: bool,
+}
+
+impl Default for DeterministicSettings {
+    fn default() -> Self {
+        DeterministicSettings {
+            seed: 0,
+            phases: UVec2::splat(3),
+            integer_storage: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeterministicSimulation {
+    pub settings: DeterministicSettings,
+    checksums: HashMap<IVec2, u32>,
+    checksum_tick: Option<u64>,
+}
+
+impl PixelMap {
+    /// Makes the simulation bit identical between runs given the same edits
+    /// on the same tick: chunks are simulated in a fixed order, never sleep,
+    /// and simulation shaders are dispatched in phases with a seeded
+    /// `SimulationTick` uniform. After each tick a checksum of every
+    /// chunk is computed on the GPU and sent in a [`ChunkChecksums`] event
+    /// to detect desyncs.
+    ///
+    /// Chunk textures are `rgba8unorm`, and float math on colors isn't bit
+    /// identical across GPUs. With [`DeterministicSettings::integer_storage`]
+    /// each simulated chunk is copied into an `rgba8uint` texture before its
+    /// first tick and back after its last, so simulation shaders only see
+    /// integers while sprites, LOD and lighting keep sampling floats.
+    pub fn enable_deterministic_simulation(&mut self, settings: DeterministicSettings) {
+        let deterministic = self.deterministic.get_or_insert_with(Default::default);
+        deterministic.settings = DeterministicSettings {
+            phases: settings.phases.max(UVec2::ONE),
+            ..settings
+        };
+        let awake: Vec<IVec2> = self.positions.keys().copied().collect();
+        for chunk_pos in awake {
+            self.wake_chunk(chunk_pos);
+        }
+    }
+
+    pub fn disable_deterministic_simulation(&mut self) {
+        self.deterministic = None;
+    }
+
+    pub fn deterministic_settings(&self) -> Option<&DeterministicSettings> {
+        self.deterministic
+            .as_ref()
+            .map(|deterministic| &deterministic.settings)
+    }
+
+    /// Whether simulation shaders are dispatched on `rgba8uint` copies.
+    pub(crate) fn integer_storage(&self) -> bool {
+        self.deterministic_settings()
+            .is_some_and(|settings| settings.integer_storage)
+    }
+
+    /// Frames the map has been updated for, counting the current one.
+    pub fn simulation_tick(&self) -> u64 {
+        self.simulation_tick
+    }
+
+    /// The latest checksum of a chunk read back and its tick.
+    pub fn chunk_checksum(&self, chunk_position: IVec2) -> Option<(u64, u32)> {
+        let deterministic = self.deterministic.as_ref()?;
+        Some((
+            deterministic.checksum_tick?,
+            *deterministic.checksums.get(&chunk_position)?,
+        ))
+    }
+
+    /// Ticks simulated this frame, one unless a replay is catching up.
+    pub(crate) fn simulated_ticks(&self) -> Range<u64> {
+        self.simulation_tick..self.simulation_tick + self.simulation_steps as u64
+    }
+
+    /// Seed of the random numbers of a chunk on `tick`.
+    pub(crate) fn tick_seed(&self, chunk_pos: IVec2, tick: u64) -> u32 {
+        let seed = self
+            .deterministic
+            .as_ref()
+            .map_or(0, |deterministic| deterministic.settings.seed);
+        hash2(
+            chunk_pos.x,
+            chunk_pos.y,
+            seed ^ pcg(tick as u32) ^ pcg((tick >> 32) as u32),
+        )
+    }
+
+    /// The `SimulationTick` uniform of every dispatch of a chunk, one per
+    /// phase: tick, seed, phase, phases and padding.
+    pub(crate) fn tick_uniforms(&self, chunk_pos: IVec2, tick: u64) -> Vec<[u32; 8]> {
+        let phases = self
+            .deterministic
+            .as_ref()
+            .map_or(UVec2::ONE, |deterministic| deterministic.settings.phases);
+        let seed = self.tick_seed(chunk_pos, tick);
+        (0..phases.y)
+            .flat_map(|y| (0..phases.x).map(move |x| UVec2::new(x, y)))
+            .map(|phase| {
+                [
+                    tick as u32,
+                    seed,
+                    phase.x,
+                    phase.y,
+                    phases.x,
+                    phases.y,
+                    0,
+                    0,
+                ]
+            })
+            .collect()
+    }
+
+    fn apply_checksums(&mut self, tick: u64, checksums: &[(IVec2, u32)]) {
+        let Some(deterministic) = self.deterministic.as_mut() else {
+            return;
+        };
+        if deterministic
+            .checksum_tick
+            .is_some_and(|latest| latest > tick)
+        {
+            return;
+        }
+        deterministic.checksum_tick = Some(tick);
+        deterministic.checksums = checksums.iter().copied().collect();
+    }
+}
+
+/// Order independent, so the GPU can add it up in any order.
+pub(crate) fn chunk_checksum(pixels: &[u8], chunk_size: UVec2) -> u32 {
+    pixels
+        .chunks_exact(4)
+        .enumerate()
+        .fold(0u32, |sum, (index, pixel)| {
+            let x = index as u32 % chunk_size.x;
+            let y = index as u32 / chunk_size.x;
+            let color = u32::from_le_bytes(pixel.try_into().unwrap());
+            sum.wrapping_add(hash2(x as i32, y as i32, color))
+        })
+}
+
+pub(crate) fn advance_simulation_ticks(mut pixel_map_query: Query<&mut PixelMap>) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        if !pixel_map.simulation_paused {
+            pixel_map.simulation_tick += pixel_map.simulation_steps as u64;
+        }
+        if pixel_map.simulation_steps != 1 {
+            pixel_map.simulation_steps = 1;
+        }
+    }
+}
+
+/// Chunks of a deterministic map in the order they're simulated.
+pub(crate) fn sorted_chunks(chunks: impl IntoIterator<Item = IVec2>) -> Vec<IVec2> {
+    let mut chunks: Vec<IVec2> = chunks.into_iter().collect();
+    chunks.sort_by_key(|pos| (pos.y, pos.x));
+    chunks
+}
+
+pub(crate) fn apply_checksums_cpu(
+    map: Entity,
+    pixel_map: &mut PixelMap,
+    images: &Assets<Image>,
+) -> Option<ChunkChecksums> {
+    pixel_map.deterministic.as_ref()?;
+    let chunk_size = pixel_map.chunk_size;
+    let checksums: Vec<(IVec2, u32)> = sorted_chunks(pixel_map.positions.keys().copied())
+        .into_iter()
+        .filter_map(|chunk_pos| {
+            let image = images.get(&pixel_map.image_data[pixel_map.positions[&chunk_pos]])?;
+            Some((chunk_pos, chunk_checksum(&image.data, chunk_size)))
+        })
+        .collect();
+    let tick = pixel_map.simulated_ticks().end - 1;
+    pixel_map.apply_checksums(tick, &checksums);
+    Some(ChunkChecksums {
+        map,
+        tick,
+        checksums,
+    })
+}
+
+pub(crate) fn checksum_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let buffer = |binding, ty| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map checksum Bind Group Layout"),
+        &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            buffer(1, BufferBindingType::Uniform),
+            buffer(2, BufferBindingType::Storage { read_only: false }),
+        ],
+    )
+}
+
+/// Binds a chunk and its `rgba8uint` copy for `integer_storage.wgsl`.
+pub(crate) fn integer_storage_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let texture = |binding, format| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access: StorageTextureAccess::ReadWrite,
+            format,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map integer storage Bind Group Layout"),
+        &[
+            texture(0, TextureFormat::Rgba8Unorm),
+            texture(1, TextureFormat::Rgba8Uint),
+        ],
+    )
+}
+
+/// The `rgba8uint` copy of a chunk integer simulation shaders run on.
+pub(crate) fn integer_texture(device: &RenderDevice, chunk_size: UVec2) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("integer_storage_texture"),
+        size: Extent3d {
+            width: chunk_size.x,
+            height: chunk_size.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Uint,
+        usage: TextureUsages::STORAGE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// Checksums of every chunk of a deterministic map after a tick, one word
+/// per chunk of `result`.
+pub(crate) struct ChecksumDispatch {
+    map: Entity,
+    tick: u64,
+    result: Buffer,
+    chunks: Vec<(IVec2, BindGroup)>,
+    workgroups: UVec2,
+}
+
+pub(crate) fn prepare_checksums(
+    device: &RenderDevice,
+    layout: &BindGroupLayout,
+    gpu_images: &RenderAssets<GpuImage>,
+    pixel_map: &PixelMap,
+    map: Entity,
+) -> Option<ChecksumDispatch> {
+    pixel_map.deterministic.as_ref()?;
+    let chunk_positions: Vec<IVec2> = sorted_chunks(pixel_map.positions.keys().copied())
+        .into_iter()
+        .filter(|chunk_pos| {
+            gpu_images
+                .get(&pixel_map.image_data[pixel_map.positions[chunk_pos]])
+                .is_some()
+        })
+        .collect();
+    if chunk_positions.is_empty() {
+        return None;
+    }
+    let result = device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("checksum_result_buffer"),
+        contents: bytemuck::cast_slice(&vec![0u32; chunk_positions.len()]),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+    });
+    let chunks = chunk_positions
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk_pos)| {
+            let chunk = gpu_images
+                .get(&pixel_map.image_data[pixel_map.positions[&chunk_pos]])
+                .expect("filtered");
+            let params = device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("checksum_params_buffer"),
+                contents: bytemuck::cast_slice(&[index as u32, 0, 0, 0]),
+                usage: BufferUsages::UNIFORM,
+            });
+            let binds = device.create_bind_group(
+                "pixel map checksum bind group",
+                layout,
+                &BindGroupEntries::sequential((
+                    chunk.texture_view.into_binding(),
+                    params.as_entire_binding(),
+                    result.as_entire_binding(),
+                )),
+            );
+            (chunk_pos, binds)
+        })
+        .collect();
+    Some(ChecksumDispatch {
+        map,
+        tick: pixel_map.simulated_ticks().end - 1,
+        result,
+        chunks,
+        workgroups: (pixel_map.chunk_size + UVec2::splat(7)) / 8,
+    })
+}
+
+/// Adds up the checksums after the simulation and reads them back.
+pub(crate) fn encode_checksums(
+    render_device: &RenderDevice,
+    encoder: &mut CommandEncoder,
+    pipeline: &ComputePipeline,
+    dispatches: Vec<ChecksumDispatch>,
+) -> Vec<PendingReadback> {
+    if dispatches.is_empty() {
+        return Vec::new();
+    }
+    {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(pipeline);
+        for dispatch in dispatches.iter() {
+            for (_, binds) in dispatch.chunks.iter() {
+                pass.set_bind_group(0, binds, &[]);
+                pass.dispatch_workgroups(dispatch.workgroups.x, dispatch.workgroups.y, 1);
+            }
+        }
+    }
+    dispatches
+        .into_iter()
+        .map(|dispatch| {
+            let ChecksumDispatch {
+                map,
+                tick,
+                result,
+                chunks,
+                ..
+            } = dispatch;
+            let positions: Vec<IVec2> = chunks.into_iter().map(|(pos, _)| pos).collect();
+            PendingReadback::buffers(
+                render_device,
+                encoder,
+                &[&result],
+                positions.len() as u64 * 4,
+                move |data| {
+                    let words: &[u32] = bytemuck::cast_slice(&data);
+                    let checksums: Vec<(IVec2, u32)> =
+                        positions.into_iter().zip(words.iter().copied()).collect();
+                    Box::new(move |world| {
+                        if let Some(mut pixel_map) = world.get_mut::<PixelMap>(map) {
+                            pixel_map.apply_checksums(tick, &checksums);
+                        }
+                        world.send_event(ChunkChecksums {
+                            map,
+                            tick,
+                            checksums,
+                        });
+                    })
+                },
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType, Buffer,
+    BufferBindingType, BufferInitDescriptor, BufferUsages, ShaderStages,
+};
+use bevy::render::renderer::RenderDevice;
+
+use crate::{ChunkDirty, PixelMap, PixelPositionedTexture};
+
+/// Dirty bounds followed by the number of pixel writes, see `dirty_rect.wgsl`.
+pub(crate) const DIRTY_WORDS: usize = 5;
+pub(crate) const EMPTY_BOUNDS: [i32; DIRTY_WORDS] = [i32::MAX, i32::MAX, i32::MIN, i32::MIN, 0];
+
+/// GPU side dirty state of one chunk, see `dirty_rect.wgsl`.
+pub(crate) struct DirtyBuffers {
+    pub changed: Buffer,
+    pub previous: Buffer,
+    /// Everything changed over the ticks of the frame, read back.
+    pub frame: Buffer,
+    pub args: Buffer,
+    pub offset: Buffer,
+    pub params: Buffer,
+    margin: u32,
+}
+
+impl DirtyBuffers {
+    pub(crate) fn new(render_device: &RenderDevice, chunk_size: UVec2, margin: u32) -> Self {
+        let bounds = |label| {
+            render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&EMPTY_BOUNDS),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            })
+        };
+        DirtyBuffers {
+            changed: bounds("dirty_changed_buffer"),
+            previous: bounds("dirty_previous_buffer"),
+            frame: bounds("dirty_frame_buffer"),
+            args: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("dirty_dispatch_args_buffer"),
+                contents: bytemuck::cast_slice(&[0u32; 3]),
+                usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            }),
+            offset: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("dirty_dispatch_offset_buffer"),
+                contents: bytemuck::cast_slice(&[0u32; 2]),
+                usage: BufferUsages::STORAGE | BufferUsages::UNIFORM,
+            }),
+            params: params_buffer(render_device, chunk_size, margin),
+            margin,
+        }
+    }
+
+    /// Follows a simulation margin changed since the buffers were created.
+    pub(crate) fn set_margin(
+        &mut self,
+        render_device: &RenderDevice,
+        chunk_size: UVec2,
+        margin: u32,
+    ) {
+        if self.margin != margin {
+            self.params = params_buffer(render_device, chunk_size, margin);
+            self.margin = margin;
+        }
+    }
+
+    pub(crate) fn bind_group(
+        &self,
+        render_device: &RenderDevice,
+        layout: &BindGroupLayout,
+    ) -> BindGroup {
+        render_device.create_bind_group(
+            "pixel map dirty rect bind group",
+            layout,
+            &BindGroupEntries::sequential((
+                self.changed.as_entire_binding(),
+                self.previous.as_entire_binding(),
+                self.args.as_entire_binding(),
+                self.offset.as_entire_binding(),
+                self.params.as_entire_binding(),
+                self.frame.as_entire_binding(),
+            )),
+        )
+    }
+}
+
+fn params_buffer(render_device: &RenderDevice, chunk_size: UVec2, margin: u32) -> Buffer {
+    render_device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("dirty_params_buffer"),
+        contents: bytemuck::cast_slice(&[chunk_size.x, chunk_size.y, margin, 0]),
+        usage: BufferUsages::UNIFORM,
+    })
+}
+
+/// Dirty state that makes the next simulation pass cover the whole chunk.
+pub(crate) fn full_chunk_bounds(chunk_size: UVec2) -> [i32; DIRTY_WORDS] {
+    [0, 0, chunk_size.x as i32 - 1, chunk_size.y as i32 - 1, 0]
+}
+
+pub(crate) fn dirty_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let storage = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map dirty rect Bind Group Layout"),
+        &[
+            storage(0),
+            storage(1),
+            storage(2),
+            storage(3),
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            storage(5),
+        ],
+    )
+}
+
+/// Chunk texture coordinates of the first texel of `tex`. Both rows top down,
+/// so `place_tex.wgsl` and the CPU backend read texel `coords - origin`.
+pub(crate) fn stamp_texel_origin(
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    tex: &PixelPositionedTexture,
+) -> IVec2 {
+    let origin = chunk_pos * chunk_size.as_ivec2();
+    IVec2::new(
+        tex.position.x - origin.x,
+        origin.y - tex.position.y + chunk_size.y as i32 - tex.size.y as i32,
+    )
+}
+
+/// Texture coordinates of a chunk that `place_tex.wgsl` can write for `tex`,
+/// with an exclusive max.
+pub(crate) fn stamp_texture_rect(
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    tex: &PixelPositionedTexture,
+) -> Option<URect> {
+    let size = chunk_size.as_ivec2();
+    let min = stamp_texel_origin(chunk_pos, chunk_size, tex);
+    let max = min + tex.size.as_ivec2();
+    let min = min.clamp(IVec2::ZERO, size);
+    let max = max.clamp(IVec2::ZERO, size);
+    (min.x < max.x && min.y < max.y).then(|| URect {
+        min: min.as_uvec2(),
+        max: max.as_uvec2(),
+    })
+}
+
+/// World pixel rect (exclusive max) of inclusive texture coordinate bounds.
+pub(crate) fn texture_bounds_to_world(
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    bounds: [i32; DIRTY_WORDS],
+) -> Option<IRect> {
+    if bounds[0] > bounds[2] || bounds[1] > bounds[3] {
+        return None;
+    }
+    let origin = chunk_pos * chunk_size.as_ivec2();
+    let height = chunk_size.y as i32;
+    Some(IRect {
+        min: origin + IVec2::new(bounds[0], height - 1 - bounds[3]),
+        max: origin + IVec2::new(bounds[2] + 1, height - bounds[1]),
+    })
+}
+
+/// Texture coordinates the simulation is dispatched over, the CPU mirror of
+/// `prepare_dispatch` in `dirty_rect.wgsl`.
+pub(crate) fn dispatch_rect(
+    changed: &[i32; DIRTY_WORDS],
+    previous: &[i32; 4],
+    chunk_size: UVec2,
+    margin: u32,
+) -> Option<URect> {
+    let lo = IVec2::new(changed[0], changed[1]).min(IVec2::new(previous[0], previous[1]));
+    let hi = IVec2::new(changed[2], changed[3]).max(IVec2::new(previous[2], previous[3]));
+    if lo.x > hi.x || lo.y > hi.y {
+        return None;
+    }
+    let margin = IVec2::splat(margin as i32);
+    let last = chunk_size.as_ivec2() - IVec2::ONE;
+    Some(URect {
+        min: (lo - margin).clamp(IVec2::ZERO, last).as_uvec2(),
+        max: ((hi + margin).clamp(IVec2::ZERO, last) + IVec2::ONE).as_uvec2(),
+    })
+}
+
+/// Adds the dirty state of a tick to that of the frame, like `merge_tick` in
+/// `dirty_rect.wgsl`.
+pub(crate) fn merge_bounds(frame: &mut [i32; DIRTY_WORDS], tick: &[i32; DIRTY_WORDS]) {
+    frame[0] = frame[0].min(tick[0]);
+    frame[1] = frame[1].min(tick[1]);
+    frame[2] = frame[2].max(tick[2]);
+    frame[3] = frame[3].max(tick[3]);
+    frame[4] += tick[4];
+}
+
+/// Marks a pixel write in texture coordinates, like `mark_dirty` in the shaders.
+pub(crate) fn mark_dirty(bounds: &mut [i32; DIRTY_WORDS], coords: IVec2) {
+    bounds[0] = bounds[0].min(coords.x);
+    bounds[1] = bounds[1].min(coords.y);
+    bounds[2] = bounds[2].max(coords.x);
+    bounds[3] = bounds[3].max(coords.y);
+    bounds[4] += 1;
+}
+
+/// Applies the dirty state read back for render frame `frame` of a map,
+/// unless a later frame already arrived.
+pub(crate) fn apply_dirty_bounds(
+    world: &mut World,
+    map: Entity,
+    frame: u64,
+    bounds: Vec<(IVec2, [i32; DIRTY_WORDS], bool)>,
+) {
+    let Some(mut pixel_map) = world.get_mut::<PixelMap>(map) else {
+        return;
+    };
+    let events = pixel_map.apply_dirty_state(map, frame, bounds);
+    world.send_event_batch(events);
+}
+
+impl PixelMap {
+    pub(crate) fn apply_dirty_state(
+        &mut self,
+        map: Entity,
+        frame: u64,
+        bounds: Vec<(IVec2, [i32; DIRTY_WORDS], bool)>,
+    ) -> Vec<ChunkDirty> {
+        if self.dirty_frame > frame {
+            return Vec::new();
+        }
+        self.dirty_frame = frame;
+        self.dirty_rects.clear();
+        let mut events = Vec::new();
+        for (chunk_pos, bounds, simulated) in bounds {
+            let rect = texture_bounds_to_world(chunk_pos, self.chunk_size, bounds);
+            if let Some(rect) = rect {
+                self.dirty_rects.insert(chunk_pos, rect);
+                events.push(ChunkDirty {
+                    map,
+                    chunk_pos,
+                    rect,
+                });
+            }
+            self.record_activity(chunk_pos, bounds[4].max(0) as u32, rect, simulated);
+        }
+        events
+    }
+}
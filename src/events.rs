@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+
+use crate::{PixelMap, PixelMapRecording, PixelMapSnapshot, PixelStats, UnrecordableEdit};
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkCreated {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+    pub entity: Entity,
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkRemoved {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+    pub entity: Entity,
+}
+
+/// Sent when pixels of a chunk were written, by a stamp or from the CPU.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkModified {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+}
+
+/// Sent when a chunk was queued for the simulation shaders of its map.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkSimulated {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+}
+
+/// Sent when a chunk stops being simulated, see [`ChunkActivity`](crate::ChunkActivity).
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkSlept {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkWoke {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+}
+
+/// Sent once the GPU reports which pixels of a chunk changed in a frame.
+/// `rect` is in world pixels with an exclusive max, see [`PixelMap::dirty_rect`].
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkDirty {
+    pub map: Entity,
+    pub chunk_pos: IVec2,
+    pub rect: IRect,
+}
+
+/// The image of a [`PixelMap::request_export`].
+#[derive(Event, Clone, Debug)]
+pub struct RegionExported {
+    pub map: Entity,
+    pub id: u64,
+    pub image: Image,
+}
+
+/// The result of a [`PixelMap::request_stats`].
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub struct RegionStats {
+    pub map: Entity,
+    pub id: u64,
+    pub stats: PixelStats,
+}
+
+/// Checksums of every chunk of a deterministic map after a simulation tick,
+/// see [`PixelMap::enable_deterministic_simulation`]. Maps in sync have the
+/// same checksums on the same tick.
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkChecksums {
+    pub map: Entity,
+    pub tick: u64,
+    pub checksums: Vec<(IVec2, u32)>,
+}
+
+/// The snapshot of a [`PixelMap::request_replication_snapshot`].
+#[derive(Event, Clone, Debug)]
+pub struct PixelMapSnapshotReady {
+    pub map: Entity,
+    pub id: u64,
+    pub snapshot: PixelMapSnapshot,
+}
+
+/// The recording of a [`PixelMap::stop_recording`](crate::PixelMap::stop_recording).
+#[derive(Event, Clone, Debug)]
+pub struct PixelMapRecordingReady {
+    pub map: Entity,
+    pub recording: PixelMapRecording,
+}
+
+/// Sent instead of a [`PixelMapRecordingReady`] when a recording stops at an
+/// edit it can't hold.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelMapRecordingFailed {
+    pub map: Entity,
+    pub edit: UnrecordableEdit,
+}
+
+/// Sent when a [`PixelMapReplay`](crate::PixelMapReplay) reaches the end of
+/// its recording, with the chunks whose pixels differ from the recorded
+/// ones. Empty if the replay matches.
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub struct PixelMapReplayVerified {
+    pub map: Entity,
+    pub mismatched_chunks: Vec<IVec2>,
+}
+
+/// Sent when at least `threshold` pixels of a [`PixelTrigger`](crate::PixelTrigger)
+/// match its predicate.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelTriggerEntered {
+    pub trigger: Entity,
+    pub map: Entity,
+    pub count: u32,
+}
+
+/// Sent when fewer than `threshold` pixels of an entered
+/// [`PixelTrigger`](crate::PixelTrigger) match its predicate again.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelTriggerExited {
+    pub trigger: Entity,
+    pub map: Entity,
+    pub count: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ChunkEvent {
+    Created { chunk_pos: IVec2, entity: Entity },
+    Removed { chunk_pos: IVec2, entity: Entity },
+    Modified { chunk_pos: IVec2 },
+    Simulated { chunk_pos: IVec2 },
+    Slept { chunk_pos: IVec2 },
+    Woke { chunk_pos: IVec2 },
+}
+
+pub(crate) fn send_chunk_events(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut created: EventWriter<ChunkCreated>,
+    mut removed: EventWriter<ChunkRemoved>,
+    mut modified: EventWriter<ChunkModified>,
+    mut simulated: EventWriter<ChunkSimulated>,
+    mut slept: EventWriter<ChunkSlept>,
+    mut woke: EventWriter<ChunkWoke>,
+) {
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        if pixel_map.chunk_events.is_empty() {
+            continue;
+        }
+        for event in pixel_map.chunk_events.drain(..) {
+            match event {
+                ChunkEvent::Created { chunk_pos, entity } => {
+                    created.send(ChunkCreated {
+                        map,
+                        chunk_pos,
+                        entity,
+                    });
+                }
+                ChunkEvent::Removed { chunk_pos, entity } => {
+                    removed.send(ChunkRemoved {
+                        map,
+                        chunk_pos,
+                        entity,
+                    });
+                }
+                ChunkEvent::Modified { chunk_pos } => {
+                    modified.send(ChunkModified { map, chunk_pos });
+                }
+                ChunkEvent::Simulated { chunk_pos } => {
+                    simulated.send(ChunkSimulated { map, chunk_pos });
+                }
+                ChunkEvent::Slept { chunk_pos } => {
+                    slept.send(ChunkSlept { map, chunk_pos });
+                }
+                ChunkEvent::Woke { chunk_pos } => {
+                    woke.send(ChunkWoke { map, chunk_pos });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    BindGroupLayout, BindGroupLayoutEntry, BindingType, BufferBindingType, ShaderStages,
+    StorageTextureAccess, TextureFormat, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+
+use crate::clipboard::chunk_copy_rect;
+use crate::dirty::{mark_dirty, DIRTY_WORDS};
+use crate::particles::Particle;
+use crate::terrain::{hash2, unit};
+use crate::PixelMap;
+
+/// How explosions of a map carve and scorch it.
+#[derive(Reflect, Clone, Debug, PartialEq)]
+pub struct ExplosionSettings {
+    /// How far the edge of the hole strays from the radius, as a fraction
+    /// of it.
+    pub noise: f32,
+    /// Pixels past the edge of the hole that get scorched.
+    pub scorch_width: f32,
+    pub scorch_color: [u8; 4],
+    /// How much of the scorch color is mixed into pixels at the edge,
+    /// fading out over the scorch width.
+    pub scorch_strength: f32,
+}
+
+impl Default for ExplosionSettings {
+    fn default() -> Self {
+        ExplosionSettings {
+            noise: 0.25,
+            scorch_width: 3.0,
+            scorch_color: [32, 24, 20, 255],
+            scorch_strength: 0.6,
+        }
+    }
+}
+
+/// A [`PixelMap::explode`] with the settings of the map at the time.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Explosion {
+    pub center: IVec2,
+    pub radius: f32,
+    pub strength: f32,
+    pub seed: u32,
+    pub settings: [f32; 3],
+    pub scorch_color: [u8; 4],
+}
+
+impl Explosion {
+    fn noise(&self) -> f32 {
+        self.settings[0]
+    }
+
+    fn phases(&self) -> [f32; 3] {
+        std::array::from_fn(|i| unit(hash2(i as i32, 0, self.seed)) * TAU)
+    }
+
+    /// Distance from the center to the edge of the hole at `angle`, like
+    /// `explode.wgsl`.
+    fn edge(&self, angle: f32, phases: [f32; 3]) -> f32 {
+        let wave = (3.0 * angle + phases[0]).sin()
+            + 0.5 * (5.0 * angle + phases[1]).sin()
+            + 0.25 * (9.0 * angle + phases[2]).sin();
+        self.radius * (1.0 + self.noise() * wave / 1.75)
+    }
+
+    /// Furthest pixel the explosion changes, from its center.
+    pub fn extent(&self) -> f32 {
+        self.radius * (1.0 + self.noise()) + self.settings[1]
+    }
+
+    /// World pixels the explosion may change, with an exclusive max.
+    pub fn rect(&self) -> IRect {
+        let extent = self.extent().ceil() as i32 + 1;
+        IRect::from_center_half_size(self.center, IVec2::splat(extent))
+    }
+
+    /// Most pixels the explosion can throw.
+    pub fn capacity(&self) -> u32 {
+        let side = 2 * (self.radius * (1.0 + self.noise())).ceil() as u32 + 1;
+        side * side
+    }
+}
+
+impl PixelMap {
+    /// Blows a hole of about `radius` world pixels into the map around
+    /// `center`, with a noisy edge and scorched pixels around it. The pixels
+    /// of the hole fly out as particles, `strength` pixels per second fast at
+    /// most, and settle back into the map where they land, piling up like
+    /// sand. Happens on the GPU, or on the CPU with the
+    /// [`PixelMapCpuPlugin`](crate::PixelMapCpuPlugin).
+    pub fn explode(&mut self, center: IVec2, radius: f32, strength: f32) {
+        if radius <= 0.0 {
+            return;
+        }
+        let settings = &self.explosion_settings;
+        let explosion = Explosion {
+            center,
+            radius,
+            strength,
+            seed: hash2(center.x, center.y, self.particles.next_id as u32)
+                ^ self.particles.pending.len() as u32,
+            settings: [
+                settings.noise.max(0.0),
+                settings.scorch_width.max(0.0),
+                settings.scorch_strength.clamp(0.0, 1.0),
+            ],
+            scorch_color: settings.scorch_color,
+        };
+        self.particles.pending.push(explosion);
+    }
+
+    pub fn explosion_settings(&self) -> &ExplosionSettings {
+        &self.explosion_settings
+    }
+
+    pub fn explosion_settings_mut(&mut self) -> &mut ExplosionSettings {
+        &mut self.explosion_settings
+    }
+}
+
+/// Same carve as `explode.wgsl`, throwing the removed pixels into
+/// `particles` until it holds `capacity` of them.
+pub(crate) fn carve_cpu(
+    pixels: &mut [u8],
+    changed: &mut [i32; DIRTY_WORDS],
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    explosion: &Explosion,
+    particles: &mut Vec<Particle>,
+    capacity: u32,
+) {
+    let Some((rect, _)) = chunk_copy_rect(chunk_pos, chunk_size, explosion.rect()) else {
+        return;
+    };
+    let phases = explosion.phases();
+    let [_, scorch_width, scorch_strength] = explosion.settings;
+    let origin = chunk_pos * chunk_size.as_ivec2();
+    for y in rect.min.y..rect.max.y {
+        for x in rect.min.x..rect.max.x {
+            let ind = (y * chunk_size.x + x) as usize * 4;
+            if pixels[ind + 3] == 0 {
+                continue;
+            }
+            let world = IVec2::new(
+                origin.x + x as i32,
+                origin.y + (chunk_size.y - 1 - y) as i32,
+            );
+            let offset = (world - explosion.center).as_vec2();
+            let length = offset.length();
+            let edge = explosion.edge(offset.y.atan2(offset.x), phases);
+            let coords = UVec2::new(x, y).as_ivec2();
+            if length < edge {
+                if (particles.len() as u32) < capacity {
+                    let direction = if length > 0.0 {
+                        offset / length
+                    } else {
+                        Vec2::Y
+                    };
+                    let speed = explosion.strength
+                        * (0.5 + 0.5 * unit(hash2(world.x, world.y, explosion.seed)));
+                    particles.push(Particle {
+                        position: world.as_vec2() + 0.5,
+                        velocity: direction * speed,
+                        color: pixels[ind..ind + 4].try_into().unwrap(),
+                        flying: true,
+                    });
+                }
+                pixels[ind..ind + 4].fill(0);
+                mark_dirty(changed, coords);
+            } else if length < edge + scorch_width {
+                let mix = scorch_strength * (1.0 - (length - edge) / scorch_width);
+                for channel in 0..3 {
+                    let color = pixels[ind + channel] as f32;
+                    let scorch = explosion.scorch_color[channel] as f32;
+                    pixels[ind + channel] = (color + (scorch - color) * mix).round() as u8;
+                }
+                mark_dirty(changed, coords);
+            }
+        }
+    }
+}
+
+pub(crate) fn explode_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let buffer = |binding, ty| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let storage = |binding| buffer(binding, BufferBindingType::Storage { read_only: false });
+    device.create_bind_group_layout(
+        Some("pixel map explode Bind Group Layout"),
+        &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            buffer(1, BufferBindingType::Uniform),
+            storage(2),
+            storage(3),
+            storage(4),
+        ],
+    )
+}
+
+/// Uniform of `explode.wgsl` for the carve of an explosion in one chunk,
+/// with the workgroups to dispatch.
+pub(crate) fn explode_params(
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    explosion: &Explosion,
+    capacity: u32,
+) -> Option<([u32; 20], UVec2)> {
+    let (rect, _) = chunk_copy_rect(chunk_pos, chunk_size, explosion.rect())?;
+    let origin = chunk_pos * chunk_size.as_ivec2();
+    let phases = explosion.phases();
+    let [noise, scorch_width, scorch_strength] = explosion.settings;
+    let params = [
+        origin.x as u32,
+        origin.y as u32,
+        explosion.center.x as u32,
+        explosion.center.y as u32,
+        rect.min.x,
+        rect.min.y,
+        rect.max.x,
+        rect.max.y,
+        explosion.radius.to_bits(),
+        noise.to_bits(),
+        scorch_width.to_bits(),
+        scorch_strength.to_bits(),
+        explosion.strength.to_bits(),
+        capacity,
+        explosion.seed,
+        u32::from_le_bytes(explosion.scorch_color),
+        phases[0].to_bits(),
+        phases[1].to_bits(),
+        phases[2].to_bits(),
+        chunk_size.y,
+    ];
+    Some((params, (rect.size() + UVec2::splat(7)) / 8))
+}
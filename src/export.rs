@@ -0,0 +1,225 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+
+use crate::clipboard::{chunk_copy_rect, chunks_in_rect};
+use crate::{PixelMap, RegionExported};
+
+/// A [`PixelMap::request_export`] waiting for its region copy.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ExportRequest {
+    pub id: u64,
+    pub downscale: u32,
+}
+
+#[derive(Debug)]
+pub enum PixelMapExportError {
+    UnsupportedFormat(TextureFormat),
+    Image(image::ImageError),
+}
+
+impl std::fmt::Display for PixelMapExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PixelMapExportError::UnsupportedFormat(format) => {
+                write!(f, "can only export rgba8 images, not {format:?}")
+            }
+            PixelMapExportError::Image(err) => write!(f, "could not write image: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for PixelMapExportError {}
+
+impl PixelMap {
+    /// Stitches the chunk images overlapping `rect` into one image, first row
+    /// at the top, with missing chunks in the default chunk color.
+    ///
+    /// Reads the main world images, which don't see stamps, writes or
+    /// simulation on the GPU. Use [`PixelMap::request_export`] there.
+    pub fn export_region(&self, rect: IRect, textures: &Assets<Image>) -> Option<Image> {
+        if rect.is_empty() {
+            return None;
+        }
+        let mut export = filled_image(rect.size().as_uvec2(), self.default_chunk_color);
+        for chunk_pos in chunks_in_rect(rect, self.chunk_size) {
+            let Some(source) = self
+                .positions
+                .get(&chunk_pos)
+                .and_then(|&index| textures.get(&self.image_data[index]))
+            else {
+                continue;
+            };
+            if let Some((source_rect, target_origin)) =
+                chunk_copy_rect(chunk_pos, self.chunk_size, rect)
+            {
+                copy_rows(
+                    &source.data,
+                    self.chunk_size.x,
+                    source_rect,
+                    &mut export.data,
+                    rect.width() as u32,
+                    target_origin,
+                );
+            }
+        }
+        Some(export)
+    }
+
+    /// Exports `rect` as it is on the GPU before this frame's edits, so it
+    /// includes the simulation. A [`RegionExported`] event with the returned
+    /// id carries the image once it has been read back. Each `downscale` by
+    /// `downscale` block of pixels is averaged into one.
+    pub fn request_export(
+        &mut self,
+        rect: IRect,
+        downscale: u32,
+        images: &mut ResMut<Assets<Image>>,
+    ) -> Option<u64> {
+        if rect.is_empty() {
+            return None;
+        }
+        self.export_count += 1;
+        let id = self.export_count;
+        let image = images.add(filled_image(
+            rect.size().as_uvec2(),
+            self.default_chunk_color,
+        ));
+        self.queue_region_copy(
+            image,
+            rect,
+            Some(ExportRequest {
+                id,
+                downscale: downscale.max(1),
+            }),
+        );
+        Some(id)
+    }
+}
+
+/// An image that region copies can be made into on the GPU.
+pub(crate) fn filled_image(size: UVec2, color: [u8; 4]) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &color,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::all(),
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::TEXTURE_BINDING
+        | TextureUsages::STORAGE_BINDING;
+    image
+}
+
+/// Copies `source_rect` of an rgba8 image `source_width` wide into another
+/// one at `target_origin`.
+pub(crate) fn copy_rows(
+    source: &[u8],
+    source_width: u32,
+    source_rect: URect,
+    target: &mut [u8],
+    target_width: u32,
+    target_origin: UVec2,
+) {
+    let row = source_rect.width() as usize * 4;
+    for y in 0..source_rect.height() {
+        let from = ((source_rect.min.y + y) * source_width + source_rect.min.x) as usize * 4;
+        let to = ((target_origin.y + y) * target_width + target_origin.x) as usize * 4;
+        target[to..to + row].copy_from_slice(&source[from..from + row]);
+    }
+}
+
+/// Averages each `factor` by `factor` block of an rgba8 image into one pixel.
+/// Blocks at the right and bottom edges may be smaller.
+pub fn downscale_image(image: &Image, factor: u32) -> Image {
+    let size = image.size();
+    let factor = factor.max(1);
+    let scaled = (size + UVec2::splat(factor - 1)) / factor;
+    let mut data = Vec::with_capacity(scaled.element_product() as usize * 4);
+    for by in 0..scaled.y {
+        for bx in 0..scaled.x {
+            let min = UVec2::new(bx, by) * factor;
+            let max = (min + UVec2::splat(factor)).min(size);
+            let mut sum = [0u32; 4];
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let ind = (y * size.x + x) as usize * 4;
+                    for (total, &channel) in sum.iter_mut().zip(&image.data[ind..ind + 4]) {
+                        *total += channel as u32;
+                    }
+                }
+            }
+            let count = (max - min).element_product();
+            data.extend(sum.map(|total| ((total + count / 2) / count) as u8));
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: scaled.x,
+            height: scaled.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        image.texture_descriptor.format,
+        RenderAssetUsages::all(),
+    )
+}
+
+/// Writes an rgba8 image, like the ones exported from a map, as a PNG file.
+pub fn save_png(image: &Image, path: impl AsRef<Path>) -> Result<(), PixelMapExportError> {
+    let format = image.texture_descriptor.format;
+    if !matches!(
+        format,
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+    ) {
+        return Err(PixelMapExportError::UnsupportedFormat(format));
+    }
+    let size = image.size();
+    image::save_buffer_with_format(
+        path,
+        &image.data,
+        size.x,
+        size.y,
+        image::ExtendedColorType::Rgba8,
+        image::ImageFormat::Png,
+    )
+    .map_err(PixelMapExportError::Image)
+}
+
+/// Turns the pixels read back for an export into its event.
+pub(crate) fn finish_export(
+    map: Entity,
+    request: ExportRequest,
+    size: UVec2,
+    data: Vec<u8>,
+) -> RegionExported {
+    let image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::all(),
+    );
+    RegionExported {
+        map,
+        id: request.id,
+        image: if request.downscale > 1 {
+            downscale_image(&image, request.downscale)
+        } else {
+            image
+        },
+    }
+}
@@ -0,0 +1,278 @@
+use std::borrow::Cow;
+use std::iter::once;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType,
+    BufferBindingType, BufferInitDescriptor, BufferUsages, CachedComputePipelineId,
+    CachedPipelineState, CommandEncoderDescriptor, ComputePassDescriptor,
+    ComputePipelineDescriptor, IntoBinding, PipelineCache, ShaderStages, StorageTextureAccess,
+    TextureFormat, TextureId, TextureViewDimension,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::texture::GpuImage;
+use bevy::utils::hashbrown::HashMap;
+
+use crate::{get_chunk_index_i, PixelMap};
+
+/// Fills the pixels of a chunk when [`PixelMap::add_chunk`] creates it.
+///
+/// `pixels` is the `Rgba8Unorm` data of the chunk texture, rows ordered from
+/// the top of the chunk to the bottom. [`PixelGenerator`] hides that layout
+/// behind a function of the world pixel position.
+pub trait ChunkGenerator: Send + Sync + 'static {
+    fn generate(&self, chunk_position: IVec2, chunk_size: UVec2, pixels: &mut [u8]);
+}
+
+impl<F> ChunkGenerator for F
+where
+    F: Fn(IVec2, UVec2, &mut [u8]) + Send + Sync + 'static,
+{
+    fn generate(&self, chunk_position: IVec2, chunk_size: UVec2, pixels: &mut [u8]) {
+        self(chunk_position, chunk_size, pixels)
+    }
+}
+
+/// A [`ChunkGenerator`] that asks a function for the color of every world pixel.
+pub struct PixelGenerator<F>(pub F);
+
+impl<F> ChunkGenerator for PixelGenerator<F>
+where
+    F: Fn(IVec2) -> [u8; 4] + Send + Sync + 'static,
+{
+    fn generate(&self, chunk_position: IVec2, chunk_size: UVec2, pixels: &mut [u8]) {
+        fill_chunk(chunk_position, chunk_size, pixels, &self.0);
+    }
+}
+
+pub(crate) fn fill_chunk(
+    chunk_position: IVec2,
+    chunk_size: UVec2,
+    pixels: &mut [u8],
+    color_at: impl Fn(IVec2) -> [u8; 4],
+) {
+    let origin = chunk_position * chunk_size.as_ivec2();
+    for y in 0..chunk_size.y as i32 {
+        for x in 0..chunk_size.x as i32 {
+            let world = origin + IVec2::new(x, y);
+            let ind = get_chunk_index_i(world, chunk_size) * 4;
+            pixels[ind..ind + 4].copy_from_slice(&color_at(world));
+        }
+    }
+}
+
+/// Runs a compute shader over every new chunk of a map.
+///
+/// The shader gets the chunk texture, its world pixel position and its size at
+/// bindings 0 to 2 (the same as a simulation shader), `seed` as a `u32`
+/// uniform at binding 3 and `params` as a read-only storage buffer at binding 4.
+/// It is dispatched with `@workgroup_size(8, 8, 1)` over the whole chunk, after
+/// any CPU [`ChunkGenerator`] has run. Chunk sizes that aren't a multiple of 8
+/// get a partial workgroup at the edges, so skip invocations outside the chunk.
+/// Edits and simulation of the chunk wait until the shader has run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpuChunkGenerator {
+    pub shader: String,
+    pub seed: u32,
+    pub params: Vec<u8>,
+}
+
+impl GpuChunkGenerator {
+    pub fn new(shader: impl Into<String>, seed: u32) -> Self {
+        GpuChunkGenerator {
+            shader: shader.into(),
+            seed,
+            params: Vec::new(),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct GenerationRenderData {
+    pending: Vec<(AssetId<Image>, IVec2, UVec2, GpuChunkGenerator)>,
+    pipelines: HashMap<String, CachedComputePipelineId>,
+    binds: Vec<(BindGroup, TextureId, UVec2, CachedComputePipelineId)>,
+}
+
+impl GenerationRenderData {
+    /// Whether the chunk texture still waits for its generator shader.
+    pub(crate) fn is_generating(&self, texture: TextureId) -> bool {
+        self.binds.iter().any(|(_, id, ..)| *id == texture)
+    }
+}
+
+pub(crate) fn clear_generation_queue(mut pixel_map_query: Query<&mut PixelMap>) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        if !pixel_map.generation_queue.is_empty() {
+            pixel_map.generation_queue.clear();
+        }
+    }
+}
+
+pub(crate) fn prepare_generation_binds(
+    pixel_map_query: Query<&PixelMap>,
+    render_device: Res<RenderDevice>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    mut render_data: ResMut<GenerationRenderData>,
+    pipeline_cache: Res<PipelineCache>,
+    asset_server: Res<AssetServer>,
+) {
+    for pixel_map in pixel_map_query.iter() {
+        let Some(generator) = &pixel_map.gpu_generator else {
+            continue;
+        };
+        for chunk_pos in pixel_map.generation_queue.iter() {
+            if let Some(&index) = pixel_map.positions.get(chunk_pos) {
+                render_data.pending.push((
+                    pixel_map.image_data[index].id(),
+                    *chunk_pos,
+                    pixel_map.chunk_size,
+                    generator.clone(),
+                ));
+            }
+        }
+    }
+    if render_data.pending.is_empty() {
+        return;
+    }
+
+    let layout = generator_bind_group_layout(&render_device);
+    let render_data = render_data.as_mut();
+    render_data
+        .pending
+        .retain(|(image, chunk_pos, chunk_size, generator)| {
+            let Some(gpu_image) = gpu_images.get(*image) else {
+                return true;
+            };
+            let pipeline = *render_data
+                .pipelines
+                .entry(generator.shader.clone())
+                .or_insert_with(|| {
+                    pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                        label: Some("pixel map generator pipeline".into()),
+                        layout: vec![layout.clone()],
+                        push_constant_ranges: Vec::new(),
+                        shader: asset_server.load(generator.shader.clone()),
+                        shader_defs: vec![],
+                        entry_point: Cow::from("main"),
+                        zero_initialize_workgroup_memory: true,
+                    })
+                });
+            let pos_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("generator_texture_pos_buffer"),
+                contents: bytemuck::cast_slice(&[
+                    chunk_pos.x * chunk_size.x as i32,
+                    chunk_pos.y * chunk_size.y as i32,
+                ]),
+                usage: BufferUsages::UNIFORM,
+            });
+            let size_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("generator_texture_size_buffer"),
+                contents: bytemuck::cast_slice(&[chunk_size.x, chunk_size.y]),
+                usage: BufferUsages::UNIFORM,
+            });
+            let seed_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("generator_seed_buffer"),
+                contents: bytemuck::cast_slice(&[generator.seed]),
+                usage: BufferUsages::UNIFORM,
+            });
+            let params: &[u8] = if generator.params.is_empty() {
+                &[0; 16]
+            } else {
+                &generator.params
+            };
+            let params_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("generator_params_buffer"),
+                contents: params,
+                usage: BufferUsages::STORAGE,
+            });
+            let binds = render_device.create_bind_group(
+                "pixel map generator bind group",
+                &layout,
+                &BindGroupEntries::sequential((
+                    gpu_image.texture_view.into_binding(),
+                    pos_buffer.as_entire_binding(),
+                    size_buffer.as_entire_binding(),
+                    seed_buffer.as_entire_binding(),
+                    params_buffer.as_entire_binding(),
+                )),
+            );
+            render_data
+                .binds
+                .push((binds, gpu_image.texture.id(), *chunk_size, pipeline));
+            false
+        });
+}
+
+pub(crate) fn apply_generation(
+    mut render_data: ResMut<GenerationRenderData>,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    render_queue: Res<RenderQueue>,
+) {
+    if render_data.binds.is_empty() {
+        return;
+    }
+    let mut command_encoder =
+        render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+    render_data
+        .binds
+        .retain(|(binds, _, chunk_size, pipeline_id)| {
+            if let CachedPipelineState::Err(_) =
+                pipeline_cache.get_compute_pipeline_state(*pipeline_id)
+            {
+                return false;
+            }
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(*pipeline_id) else {
+                return true;
+            };
+            let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, binds, &[]);
+            pass.dispatch_workgroups(chunk_size.x.div_ceil(8), chunk_size.y.div_ceil(8), 1);
+            false
+        });
+    render_queue.submit(once(command_encoder.finish()));
+}
+
+fn generator_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let uniform = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map generator Bind Group Layout"),
+        &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            uniform(1),
+            uniform(2),
+            uniform(3),
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    )
+}
@@ -0,0 +1,417 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    BufferInitDescriptor, BufferUsages, CommandEncoder, Extent3d, ImageCopyBuffer,
+    ImageCopyTexture, ImageDataLayout, Origin3d, Texture, TextureAspect,
+};
+use bevy::render::renderer::RenderDevice;
+
+use crate::dirty::stamp_texture_rect;
+use crate::import::RegionWrite;
+use crate::PixelMap;
+
+/// Undo and redo stacks of the pixel edits of a map, see [`PixelMap::enable_history`].
+///
+/// Before the edits of a frame are applied, the chunk regions they touch are
+/// copied. On the GPU the copies arrive a few frames later, and an entry can
+/// only be undone once all of its copies have arrived.
+#[derive(Clone, Debug, Default)]
+pub struct PixelHistory {
+    budget: usize,
+    undo: VecDeque<HistoryEntry>,
+    redo: VecDeque<HistoryEntry>,
+    in_transaction: bool,
+    transaction_entry: Option<u64>,
+    next_id: u64,
+    pub(crate) snapshot_requests: Vec<SnapshotRequest>,
+    /// Snapshot contents to write back after this frame's snapshots were taken.
+    pub(crate) restores: Vec<RegionWrite>,
+}
+
+#[derive(Clone, Debug)]
+struct HistoryEntry {
+    id: u64,
+    snapshots: Vec<Snapshot>,
+}
+
+#[derive(Clone, Debug)]
+struct Snapshot {
+    chunk_pos: IVec2,
+    rect: URect,
+    data: Option<Arc<[u8]>>,
+}
+
+/// A chunk region, in texture coordinates with an exclusive max, to copy
+/// before this frame's edits.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SnapshotRequest {
+    pub entry: u64,
+    pub chunk_pos: IVec2,
+    pub rect: URect,
+}
+
+impl HistoryEntry {
+    fn is_complete(&self) -> bool {
+        self.snapshots
+            .iter()
+            .all(|snapshot| snapshot.data.is_some())
+    }
+
+    fn size(&self) -> usize {
+        self.snapshots
+            .iter()
+            .map(|snapshot| snapshot.rect.size().element_product() as usize * 4)
+            .sum()
+    }
+}
+
+impl PixelHistory {
+    /// Bytes the snapshots may take before the oldest entries are dropped.
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Bytes taken by the snapshots of both stacks, including the ones still
+    /// being read back.
+    pub fn memory_usage(&self) -> usize {
+        self.undo
+            .iter()
+            .chain(self.redo.iter())
+            .map(HistoryEntry::size)
+            .sum()
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    fn new_entry(&mut self) -> HistoryEntry {
+        self.next_id += 1;
+        HistoryEntry {
+            id: self.next_id,
+            snapshots: Vec::new(),
+        }
+    }
+
+    fn request(&mut self, entry: &mut HistoryEntry, chunk_pos: IVec2, rect: URect) {
+        entry.snapshots.push(Snapshot {
+            chunk_pos,
+            rect,
+            data: None,
+        });
+        self.snapshot_requests.push(SnapshotRequest {
+            entry: entry.id,
+            chunk_pos,
+            rect,
+        });
+    }
+
+    fn trim(&mut self) {
+        let mut usage = self.memory_usage();
+        while usage > self.budget {
+            let Some(entry) = self.undo.pop_front().or_else(|| self.redo.pop_front()) else {
+                break;
+            };
+            if self.transaction_entry == Some(entry.id) {
+                self.transaction_entry = None;
+            }
+            usage -= entry.size();
+        }
+    }
+
+    /// Stores the contents read for a snapshot, or drops the snapshot if its
+    /// chunk is gone.
+    pub(crate) fn complete(&mut self, request: SnapshotRequest, data: Option<Vec<u8>>) {
+        let Some(entry) = self
+            .undo
+            .iter_mut()
+            .chain(self.redo.iter_mut())
+            .find(|entry| entry.id == request.entry)
+        else {
+            return;
+        };
+        let Some(index) = entry.snapshots.iter().position(|snapshot| {
+            snapshot.data.is_none()
+                && snapshot.chunk_pos == request.chunk_pos
+                && snapshot.rect == request.rect
+        }) else {
+            return;
+        };
+        match data {
+            Some(data) => entry.snapshots[index].data = Some(data.into()),
+            None => {
+                entry.snapshots.remove(index);
+            }
+        }
+    }
+}
+
+impl PixelMap {
+    /// Records the previous contents of every region edited through
+    /// [`PixelMap::set_pixels_gpu`] or [`PixelMap::set_pixels_cpu`] so it
+    /// can be undone, keeping at most `budget` bytes of snapshots.
+    pub fn enable_history(&mut self, budget: usize) {
+        let history = self.history.get_or_insert_with(PixelHistory::default);
+        history.budget = budget;
+        history.trim();
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history(&self) -> Option<&PixelHistory> {
+        self.history.as_ref()
+    }
+
+    /// Groups the edits of the following frames into one history entry until
+    /// [`PixelMap::end_transaction`]. Without a transaction, each frame of
+    /// edits is an entry of its own.
+    pub fn begin_transaction(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            history.in_transaction = true;
+            history.transaction_entry = None;
+        }
+    }
+
+    pub fn end_transaction(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            history.in_transaction = false;
+            history.transaction_entry = None;
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history
+            .as_ref()
+            .and_then(|history| history.undo.back())
+            .is_some_and(HistoryEntry::is_complete)
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history
+            .as_ref()
+            .and_then(|history| history.redo.back())
+            .is_some_and(HistoryEntry::is_complete)
+    }
+
+    /// Restores the regions of the latest entry as they were before its
+    /// edits, this frame. Returns `false` if there is nothing to undo or the
+    /// snapshots of the latest entry haven't arrived yet.
+    pub fn undo(&mut self) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+        let mut history = self.history.take().expect("can undo");
+        let entry = history.undo.pop_back().expect("can undo");
+        if history.transaction_entry == Some(entry.id) {
+            history.transaction_entry = None;
+        }
+        let redo = self.restore(&mut history, entry);
+        history.redo.push_back(redo);
+        history.trim();
+        self.history = Some(history);
+        true
+    }
+
+    /// Reapplies the latest undone entry, this frame. Returns `false` like
+    /// [`PixelMap::undo`].
+    pub fn redo(&mut self) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+        let mut history = self.history.take().expect("can redo");
+        let entry = history.redo.pop_back().expect("can redo");
+        let undo = self.restore(&mut history, entry);
+        history.undo.push_back(undo);
+        history.trim();
+        self.history = Some(history);
+        true
+    }
+
+    /// Queues the snapshots of `entry` to be written back, latest first, and
+    /// returns the entry that reverts it.
+    fn restore(&mut self, history: &mut PixelHistory, entry: HistoryEntry) -> HistoryEntry {
+        let mut reverse = history.new_entry();
+        for snapshot in entry.snapshots.into_iter().rev() {
+            let Some(data) = snapshot.data else {
+                continue;
+            };
+            if !self.positions.contains_key(&snapshot.chunk_pos) {
+                continue;
+            }
+            history.request(&mut reverse, snapshot.chunk_pos, snapshot.rect);
+            let restore = RegionWrite {
+                chunk_pos: snapshot.chunk_pos,
+                rect: snapshot.rect,
+                data,
+            };
+            self.record_region_write(&restore);
+            history.restores.push(restore);
+            self.mark_region_written(snapshot.chunk_pos);
+        }
+        reverse
+    }
+
+    /// Requests snapshots of the regions this frame's writes, stamps and
+    /// imports touch.
+    fn record_edits(&mut self) {
+        let Some(mut history) = self.history.take() else {
+            return;
+        };
+        let mut rects: Vec<(IVec2, URect)> = Vec::new();
+        let mut add = |chunk_pos: IVec2, rect: URect| match rects
+            .iter_mut()
+            .find(|(pos, _)| *pos == chunk_pos)
+        {
+            Some((_, existing)) => *existing = existing.union(rect),
+            None => rects.push((chunk_pos, rect)),
+        };
+        for (&chunk_pos, writes) in self.pixel_writes.iter() {
+            for &coords in writes.keys() {
+                let coords = UVec2::new(coords & 0xffff, coords >> 16);
+                add(chunk_pos, URect::from_corners(coords, coords + UVec2::ONE));
+            }
+        }
+        for write in self.region_writes.iter() {
+            add(write.chunk_pos, write.rect);
+        }
+        for (&chunk_pos, texes) in self.texture_to_chunk_posses.iter() {
+            for tex in texes {
+                if let Some(rect) = stamp_texture_rect(chunk_pos, self.chunk_size, tex) {
+                    add(chunk_pos, rect);
+                }
+            }
+        }
+        if !rects.is_empty() {
+            let mut entry = match history.transaction_entry {
+                Some(id) if history.undo.back().is_some_and(|entry| entry.id == id) => {
+                    history.undo.pop_back().expect("checked")
+                }
+                _ => history.new_entry(),
+            };
+            if history.in_transaction {
+                history.transaction_entry = Some(entry.id);
+            }
+            for (chunk_pos, rect) in rects {
+                history.request(&mut entry, chunk_pos, rect);
+            }
+            history.undo.push_back(entry);
+            history.redo.clear();
+            history.trim();
+        }
+        self.history = Some(history);
+    }
+}
+
+pub(crate) fn record_history(mut pixel_map_query: Query<&mut PixelMap>) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        if pixel_map.history.is_some() {
+            pixel_map.record_edits();
+        }
+    }
+}
+
+/// Completes a snapshot from the chunk image in the main world, for chunks
+/// that aren't on the GPU yet and so still match it.
+pub(crate) fn snapshot_from_image(world: &mut World, map: Entity, request: SnapshotRequest) {
+    let data = world.get::<PixelMap>(map).and_then(|pixel_map| {
+        let &index = pixel_map.positions.get(&request.chunk_pos)?;
+        let image = world
+            .resource::<Assets<Image>>()
+            .get(&pixel_map.image_data[index])?;
+        Some(copy_region(&image.data, pixel_map.chunk_size, request.rect))
+    });
+    complete_snapshot(world, map, request, data);
+}
+
+pub(crate) fn complete_snapshot(
+    world: &mut World,
+    map: Entity,
+    request: SnapshotRequest,
+    data: Option<Vec<u8>>,
+) {
+    if let Some(history) = world
+        .get_mut::<PixelMap>(map)
+        .and_then(|pixel_map| pixel_map.into_inner().history.as_mut())
+    {
+        history.complete(request, data);
+    }
+}
+
+pub(crate) fn copy_region(pixels: &[u8], chunk_size: UVec2, rect: URect) -> Vec<u8> {
+    let mut data = Vec::with_capacity(rect.size().element_product() as usize * 4);
+    for y in rect.min.y..rect.max.y {
+        let start = (y * chunk_size.x + rect.min.x) as usize * 4;
+        data.extend_from_slice(&pixels[start..start + rect.width() as usize * 4]);
+    }
+    data
+}
+
+pub(crate) fn paste_region(pixels: &mut [u8], chunk_size: UVec2, rect: URect, data: &[u8]) {
+    let row = rect.width() as usize * 4;
+    for (y, source) in (rect.min.y..rect.max.y).zip(data.chunks_exact(row)) {
+        let start = (y * chunk_size.x + rect.min.x) as usize * 4;
+        pixels[start..start + row].copy_from_slice(source);
+    }
+}
+
+/// Records a copy of `data`, tightly packed rgba8 rows, into a region of
+/// `texture`.
+pub(crate) fn restore_texture_region(
+    render_device: &RenderDevice,
+    encoder: &mut CommandEncoder,
+    texture: &Texture,
+    rect: URect,
+    data: &[u8],
+) {
+    let row = rect.width() as usize * 4;
+    let padded_row = RenderDevice::align_copy_bytes_per_row(row);
+    let mut padded = vec![0; padded_row * rect.height() as usize];
+    for (target, source) in padded
+        .chunks_exact_mut(padded_row)
+        .zip(data.chunks_exact(row))
+    {
+        target[..row].copy_from_slice(source);
+    }
+    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("pixel map restore buffer"),
+        contents: &padded,
+        usage: BufferUsages::COPY_SRC,
+    });
+    encoder.copy_buffer_to_texture(
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row as u32),
+                rows_per_image: None,
+            },
+        },
+        ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: Origin3d {
+                x: rect.min.x,
+                y: rect.min.y,
+                z: 0,
+            },
+            aspect: TextureAspect::All,
+        },
+        Extent3d {
+            width: rect.width(),
+            height: rect.height(),
+            depth_or_array_layers: 1,
+        },
+    );
+}
@@ -0,0 +1,397 @@
+use std::borrow::Cow;
+use std::io::Read;
+use std::sync::Arc;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+use bevy::tasks::futures_lite::io::BlockOn;
+use bevy::utils::hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{get_chunk_outer_i, ChunkEvent, PixelEdit, PixelMap};
+
+/// Pieces of imported images written into chunks per frame.
+const IMPORT_PIECES_PER_FRAME: usize = 64;
+
+/// Pixels to write over a region of a chunk, tightly packed rgba8 rows in
+/// texture coordinates.
+#[derive(Clone, Debug)]
+pub(crate) struct RegionWrite {
+    pub chunk_pos: IVec2,
+    pub rect: URect,
+    pub data: Arc<[u8]>,
+}
+
+/// An image split into chunk sized pieces, so it can be far bigger than a
+/// texture. Build one from images or PNG files, placed anywhere in the world,
+/// and write it into a map with [`PixelMap::import`].
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct ChunkedImage {
+    chunk_size: UVec2,
+    /// Shared with the maps importing it rather than copied.
+    pieces: Arc<Vec<RegionWrite>>,
+}
+
+/// Pieces of a [`ChunkedImage`] still to be written, from `next` on.
+#[derive(Clone, Debug)]
+pub(crate) struct QueuedImport {
+    pieces: Arc<Vec<RegionWrite>>,
+    next: usize,
+}
+
+#[derive(Debug)]
+pub enum PixelMapImportError {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    UnsupportedFormat(String),
+    ChunkSizeMismatch { map: UVec2, image: UVec2 },
+}
+
+impl std::fmt::Display for PixelMapImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PixelMapImportError::Io(err) => write!(f, "could not read image: {err}"),
+            PixelMapImportError::Png(err) => write!(f, "could not decode png: {err}"),
+            PixelMapImportError::UnsupportedFormat(format) => {
+                write!(f, "can't import {format} images")
+            }
+            PixelMapImportError::ChunkSizeMismatch { map, image } => write!(
+                f,
+                "image was split into {image} chunks but the map has {map} chunks"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PixelMapImportError {}
+
+impl ChunkedImage {
+    pub fn new(chunk_size: UVec2) -> Self {
+        ChunkedImage {
+            chunk_size,
+            pieces: Arc::new(Vec::new()),
+        }
+    }
+
+    pub fn chunk_size(&self) -> UVec2 {
+        self.chunk_size
+    }
+
+    pub fn chunk_positions(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.pieces.iter().map(|piece| piece.chunk_pos)
+    }
+
+    /// Adds an rgba8 image with its bottom left corner at `position`.
+    pub fn add_image(&mut self, image: &Image, position: IVec2) -> Result<(), PixelMapImportError> {
+        let format = image.texture_descriptor.format;
+        if !matches!(
+            format,
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+        ) {
+            return Err(PixelMapImportError::UnsupportedFormat(format!(
+                "{format:?}"
+            )));
+        }
+        let size = image.size();
+        let rows = image.data.chunks_exact(size.x as usize * 4).map(Ok);
+        self.add_rows(position, size, rows)
+    }
+
+    /// Decodes a PNG row by row into pieces, with its bottom left corner at
+    /// `position`. Call it once per tile to assemble a level from tiles.
+    pub fn add_png(
+        &mut self,
+        reader: impl Read,
+        position: IVec2,
+    ) -> Result<(), PixelMapImportError> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(PixelMapImportError::Png)?;
+        let info = reader.info();
+        let size = UVec2::new(info.width, info.height);
+        let (color_type, _) = reader.output_color_type();
+        if info.interlaced {
+            // Interlaced rows come in passes, so these have to be decoded whole.
+            let mut data = vec![0; reader.output_buffer_size()];
+            reader
+                .next_frame(&mut data)
+                .map_err(PixelMapImportError::Png)?;
+            let line = reader.output_line_size(size.x);
+            let mut rows = data.chunks_exact(line).map(|row| rgba_row(row, color_type));
+            return self.add_owned_rows(position, size, &mut rows);
+        }
+        let mut rows = std::iter::from_fn(|| {
+            let row = match reader.next_row() {
+                Ok(Some(row)) => row,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(PixelMapImportError::Png(err))),
+            };
+            Some(rgba_row(row.data(), color_type).map(|row| Cow::Owned(row.into_owned())))
+        });
+        self.add_owned_rows(position, size, &mut rows)
+    }
+
+    pub fn from_png(
+        reader: impl Read,
+        chunk_size: UVec2,
+        position: IVec2,
+    ) -> Result<Self, PixelMapImportError> {
+        let mut image = ChunkedImage::new(chunk_size);
+        image.add_png(reader, position)?;
+        Ok(image)
+    }
+
+    fn add_rows<'a>(
+        &mut self,
+        position: IVec2,
+        size: UVec2,
+        rows: impl Iterator<Item = Result<&'a [u8], PixelMapImportError>>,
+    ) -> Result<(), PixelMapImportError> {
+        let mut owned = rows.map(|row| row.map(Cow::Borrowed));
+        self.add_owned_rows(position, size, &mut owned)
+    }
+
+    /// Splits rows of an image, first row at the top, along chunk borders.
+    fn add_owned_rows<'a>(
+        &mut self,
+        position: IVec2,
+        size: UVec2,
+        rows: &mut dyn Iterator<Item = Result<Cow<'a, [u8]>, PixelMapImportError>>,
+    ) -> Result<(), PixelMapImportError> {
+        if size.x == 0 || size.y == 0 {
+            return Ok(());
+        }
+        let chunk_size = self.chunk_size.as_ivec2();
+        let first_column = get_chunk_outer_i(position, self.chunk_size).x;
+        let last_column =
+            get_chunk_outer_i(position + IVec2::new(size.x as i32 - 1, 0), self.chunk_size).x;
+        let mut band: HashMap<i32, (URect, Vec<u8>)> = HashMap::new();
+        let mut band_row = None;
+        for (i, row) in rows.take(size.y as usize).enumerate() {
+            let row = row?;
+            let y = position.y + size.y as i32 - 1 - i as i32;
+            let chunk_row = y.div_euclid(chunk_size.y);
+            if band_row != Some(chunk_row) {
+                self.flush_band(&mut band, band_row);
+                band_row = Some(chunk_row);
+            }
+            let texture_y = (chunk_size.y - 1 - y.rem_euclid(chunk_size.y)) as u32;
+            for column in first_column..=last_column {
+                let min_x = (column * chunk_size.x).max(position.x);
+                let max_x = ((column + 1) * chunk_size.x).min(position.x + size.x as i32);
+                let texture_x = (min_x - column * chunk_size.x) as u32;
+                let width = (max_x - min_x) as u32;
+                let start = (min_x - position.x) as usize * 4;
+                let (rect, data) = band.entry(column).or_insert_with(|| {
+                    (
+                        URect::new(texture_x, texture_y, texture_x + width, texture_y),
+                        Vec::new(),
+                    )
+                });
+                rect.max.y = texture_y + 1;
+                data.extend_from_slice(&row[start..start + width as usize * 4]);
+            }
+        }
+        self.flush_band(&mut band, band_row);
+        Ok(())
+    }
+
+    fn flush_band(&mut self, band: &mut HashMap<i32, (URect, Vec<u8>)>, chunk_row: Option<i32>) {
+        let Some(chunk_row) = chunk_row else {
+            return;
+        };
+        let mut columns: Vec<_> = band.drain().collect();
+        columns.sort_by_key(|(column, _)| *column);
+        Arc::make_mut(&mut self.pieces).extend(columns.into_iter().map(
+            |(column, (rect, data))| RegionWrite {
+                chunk_pos: IVec2::new(column, chunk_row),
+                rect,
+                data: data.into(),
+            },
+        ));
+    }
+}
+
+fn rgba_row(row: &[u8], color_type: png::ColorType) -> Result<Cow<'_, [u8]>, PixelMapImportError> {
+    Ok(match color_type {
+        png::ColorType::Rgba => Cow::Borrowed(row),
+        png::ColorType::Rgb => Cow::Owned(
+            row.chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+        ),
+        png::ColorType::GrayscaleAlpha => Cow::Owned(
+            row.chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+        ),
+        png::ColorType::Grayscale => Cow::Owned(row.iter().flat_map(|&g| [g, g, g, 255]).collect()),
+        png::ColorType::Indexed => {
+            return Err(PixelMapImportError::UnsupportedFormat(
+                "indexed png".to_string(),
+            ))
+        }
+    })
+}
+
+impl PixelMap {
+    /// Queues the pieces of `image` to be written over the map, creating the
+    /// chunks they land on. A few pieces are written per frame, see
+    /// [`PixelMap::pending_import_pieces`].
+    pub fn import(&mut self, image: &ChunkedImage) -> Result<(), PixelMapImportError> {
+        if image.chunk_size != self.chunk_size {
+            return Err(PixelMapImportError::ChunkSizeMismatch {
+                map: self.chunk_size,
+                image: image.chunk_size,
+            });
+        }
+        if !image.pieces.is_empty() {
+            self.import_queue.push_back(QueuedImport {
+                pieces: image.pieces.clone(),
+                next: 0,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn pending_import_pieces(&self) -> usize {
+        self.import_queue
+            .iter()
+            .map(|import| import.pieces.len() - import.next)
+            .sum()
+    }
+
+    /// Wakes a chunk whose pixels are replaced outside of the dirty tracking,
+    /// so its next simulation pass covers all of it.
+    pub(crate) fn mark_region_written(&mut self, chunk_pos: IVec2) {
+        self.wake_chunk(chunk_pos);
+        if !self.woken_chunks.contains(&chunk_pos) {
+            self.woken_chunks.push(chunk_pos);
+        }
+        self.chunk_events.push(ChunkEvent::Modified { chunk_pos });
+    }
+
+    /// Writes `write` over its chunk this frame, recording it for
+    /// replication.
+    pub(crate) fn queue_region_write(&mut self, write: RegionWrite) {
+        self.record_region_write(&write);
+        self.mark_region_written(write.chunk_pos);
+        self.region_writes.push(write);
+    }
+
+    pub(crate) fn record_region_write(&mut self, write: &RegionWrite) {
+        if self.replication.is_some() || self.recorder.is_some() {
+            self.record_edit(PixelEdit::Region {
+                chunk_pos: write.chunk_pos,
+                rect: write.rect,
+                data: write.data.to_vec(),
+            });
+        }
+    }
+
+    /// History restores first, then this frame's imported pieces.
+    pub(crate) fn queued_region_writes(&self) -> impl Iterator<Item = &RegionWrite> {
+        self.history
+            .iter()
+            .flat_map(|history| history.restores.iter())
+            .chain(self.region_writes.iter())
+    }
+}
+
+pub(crate) fn drain_imports(
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut commands: Commands,
+    mut textures: ResMut<Assets<Image>>,
+) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        for _ in 0..IMPORT_PIECES_PER_FRAME {
+            let Some(import) = pixel_map.import_queue.front_mut() else {
+                break;
+            };
+            let piece = import.pieces[import.next].clone();
+            import.next += 1;
+            // The image is let go of as soon as its last piece is written.
+            if import.next == import.pieces.len() {
+                pixel_map.import_queue.pop_front();
+            }
+            pixel_map.add_chunk(piece.chunk_pos, &mut commands, &mut textures);
+            pixel_map.queue_region_write(piece);
+        }
+    }
+}
+
+/// Where a [`ChunkedImage`] loaded from a PNG is split and placed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ChunkedImageSettings {
+    pub chunk_size: UVec2,
+    /// World position of the bottom left corner of the image.
+    pub position: IVec2,
+}
+
+impl Default for ChunkedImageSettings {
+    fn default() -> Self {
+        ChunkedImageSettings {
+            chunk_size: UVec2::splat(256),
+            position: IVec2::ZERO,
+        }
+    }
+}
+
+/// Loads `.chunked.png` files as [`ChunkedImage`]s, never holding them as
+/// one texture. Plain `.png` files are left to Bevy's image loader.
+#[derive(Default)]
+pub struct ChunkedImageLoader;
+
+impl AssetLoader for ChunkedImageLoader {
+    type Asset = ChunkedImage;
+    type Settings = ChunkedImageSettings;
+    type Error = PixelMapImportError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &ChunkedImageSettings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        // Rows are split into pieces as they are read, the file is never
+        // held whole.
+        ChunkedImage::from_png(BlockOn::new(reader), settings.chunk_size, settings.position)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["chunked.png"]
+    }
+}
+
+/// Imports a loaded [`ChunkedImage`] into the [`PixelMap`] on this entity,
+/// creating a map with the image's chunk size if there is none.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub struct PixelMapImageHandle(pub Handle<ChunkedImage>);
+
+pub(crate) fn import_loaded_images(
+    mut commands: Commands,
+    images: Res<Assets<ChunkedImage>>,
+    mut query: Query<(Entity, &PixelMapImageHandle, Option<&mut PixelMap>)>,
+) {
+    for (entity, handle, pixel_map) in query.iter_mut() {
+        let Some(image) = images.get(&handle.0) else {
+            continue;
+        };
+        commands.entity(entity).remove::<PixelMapImageHandle>();
+        let result = match pixel_map {
+            Some(mut pixel_map) => pixel_map.import(image),
+            None => {
+                let mut pixel_map = PixelMap::builder(image.chunk_size, entity).build();
+                let result = pixel_map.import(image);
+                commands.entity(entity).insert(pixel_map);
+                result
+            }
+        };
+        if let Err(err) = result {
+            warn!("could not import image into pixel map: {err}");
+        }
+    }
+}
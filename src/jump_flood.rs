@@ -0,0 +1,129 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType,
+    BufferBindingType, BufferInitDescriptor, BufferUsages, IntoBinding, ShaderStages,
+    StorageTextureAccess, TextureFormat, TextureView, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+
+/// Seed texel of a jump flood, packed as `x | y << 16`.
+pub(crate) const NO_SEED: u32 = u32::MAX;
+
+pub(crate) fn pack_seed(texel: UVec2) -> u32 {
+    texel.x | texel.y << 16
+}
+
+pub(crate) fn unpack_seed(seed: u32) -> UVec2 {
+    UVec2::new(seed & 0xffff, seed >> 16)
+}
+
+/// Step widths of a jump flood over `size`, halving down to 1 with one more
+/// pass of 1 to fix the remaining errors.
+pub(crate) fn jump_flood_steps(size: UVec2) -> Vec<u32> {
+    let mut step = size.max_element().next_power_of_two() / 2;
+    let mut steps = Vec::new();
+    while step > 0 {
+        steps.push(step);
+        step /= 2;
+    }
+    steps.push(1);
+    steps
+}
+
+/// Replaces each seed with the nearest one found around it at the step
+/// widths, like `jump_flood.wgsl`.
+pub(crate) fn jump_flood_cpu(mut seeds: Vec<u32>, size: UVec2) -> Vec<u32> {
+    let mut next = seeds.clone();
+    for step in jump_flood_steps(size) {
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let texel = IVec2::new(x as i32, y as i32);
+                let mut best = NO_SEED;
+                let mut best_distance = u32::MAX;
+                for offset_y in [-1, 0, 1] {
+                    for offset_x in [-1, 0, 1] {
+                        let other = texel + IVec2::new(offset_x, offset_y) * step as i32;
+                        if other.cmplt(IVec2::ZERO).any() || other.cmpge(size.as_ivec2()).any() {
+                            continue;
+                        }
+                        let seed = seeds[(other.y as u32 * size.x + other.x as u32) as usize];
+                        if seed == NO_SEED {
+                            continue;
+                        }
+                        let distance = unpack_seed(seed).as_ivec2().distance_squared(texel) as u32;
+                        if distance < best_distance {
+                            best = seed;
+                            best_distance = distance;
+                        }
+                    }
+                }
+                next[(y * size.x + x) as usize] = best;
+            }
+        }
+        std::mem::swap(&mut seeds, &mut next);
+    }
+    seeds
+}
+
+pub(crate) fn jump_flood_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let texture = |binding, access| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access,
+            format: TextureFormat::R32Uint,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map jump flood Bind Group Layout"),
+        &[
+            texture(0, StorageTextureAccess::ReadOnly),
+            texture(1, StorageTextureAccess::WriteOnly),
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    )
+}
+
+/// Bind groups of the passes of a jump flood, ping-ponging between `seeds`
+/// starting from the first one. Returns them with the index of the texture
+/// that ends up with the result.
+pub(crate) fn jump_flood_bind_groups(
+    device: &RenderDevice,
+    layout: &BindGroupLayout,
+    seeds: [&TextureView; 2],
+    size: UVec2,
+) -> (Vec<BindGroup>, usize) {
+    let steps = jump_flood_steps(size);
+    let binds = steps
+        .iter()
+        .enumerate()
+        .map(|(pass, &step)| {
+            let params = device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("jump_flood_step_buffer"),
+                contents: bytemuck::cast_slice(&[step, 0, 0, 0]),
+                usage: BufferUsages::UNIFORM,
+            });
+            device.create_bind_group(
+                "pixel map jump flood bind group",
+                layout,
+                &BindGroupEntries::sequential((
+                    seeds[pass % 2].into_binding(),
+                    seeds[(pass + 1) % 2].into_binding(),
+                    params.as_entire_binding(),
+                )),
+            )
+        })
+        .collect();
+    (binds, steps.len() % 2)
+}
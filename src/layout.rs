@@ -0,0 +1,237 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Shader, Source};
+use bevy::utils::hashbrown::HashSet;
+
+use crate::PixelMap;
+
+/// The kind of every group 0 binding, how it's declared, and whether a shader
+/// has to declare it to work.
+type Bindings = [(BindingKind, &'static str, bool); 7];
+
+/// The group 0 bindings a simulation shader is dispatched with.
+const SIMULATION_BINDINGS: Bindings = [
+    (
+        BindingKind::StorageTexture,
+        "var input_texture: texture_storage_2d<rgba8unorm, read_write>",
+        true,
+    ),
+    (
+        BindingKind::Uniform,
+        "var<uniform> input_texture_pos: vec2<i32>",
+        true,
+    ),
+    (
+        BindingKind::Uniform,
+        "var<uniform> input_texture_size: vec2<u32>",
+        true,
+    ),
+    (
+        BindingKind::Uniform,
+        "var<uniform> dispatch_offset: vec2<u32>",
+        true,
+    ),
+    (
+        BindingKind::Storage,
+        "var<storage, read_write> dirty_bounds: array<atomic<i32>, 5>",
+        true,
+    ),
+    (
+        BindingKind::Storage,
+        "var<storage, read_write> ejected: EjectedParticles",
+        false,
+    ),
+    (
+        BindingKind::Uniform,
+        "var<uniform> simulation_tick: SimulationTick",
+        false,
+    ),
+];
+
+/// The bindings of a map with [`DeterministicSettings::integer_storage`],
+/// which binds the `rgba8uint` copy of each chunk instead.
+///
+/// [`DeterministicSettings::integer_storage`]: crate::DeterministicSettings::integer_storage
+const INTEGER_SIMULATION_BINDINGS: Bindings = {
+    let mut bindings = SIMULATION_BINDINGS;
+    bindings[0] = (
+        BindingKind::IntegerStorageTexture,
+        "var input_texture: texture_storage_2d<rgba8uint, read_write>",
+        true,
+    );
+    bindings
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BindingKind {
+    StorageTexture,
+    IntegerStorageTexture,
+    Uniform,
+    Storage,
+}
+
+/// A simulation shader binding that doesn't match what the map binds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SimulationLayoutError {
+    /// A binding every simulation shader needs isn't declared.
+    Missing {
+        binding: u32,
+        expected: &'static str,
+    },
+    /// A binding is declared with the wrong kind of resource.
+    Mismatch {
+        binding: u32,
+        expected: &'static str,
+        found: String,
+    },
+    /// A binding the map doesn't provide is declared.
+    Unknown { binding: u32, found: String },
+}
+
+impl std::fmt::Display for SimulationLayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationLayoutError::Missing { binding, expected } => {
+                write!(f, "binding {binding} is missing, expected `{expected}`")
+            }
+            SimulationLayoutError::Mismatch {
+                binding,
+                expected,
+                found,
+            } => write!(
+                f,
+                "binding {binding} should be `{expected}`, found `{found}`"
+            ),
+            SimulationLayoutError::Unknown { binding, found } => write!(
+                f,
+                "binding {binding} (`{found}`) isn't provided, the layout ends at binding {}",
+                SIMULATION_BINDINGS.len() - 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SimulationLayoutError {}
+
+/// Checks the group 0 bindings of a WGSL simulation shader against the layout
+/// the map dispatches it with.
+///
+/// Only the kind of each resource is compared, so the names and struct types
+/// are up to the shader.
+pub fn check_simulation_shader(source: &str) -> Result<(), SimulationLayoutError> {
+    check_bindings(source, &SIMULATION_BINDINGS)
+}
+
+/// Like [`check_simulation_shader`], for maps with
+/// [`DeterministicSettings::integer_storage`], whose shaders bind an
+/// `rgba8uint` texture.
+///
+/// [`DeterministicSettings::integer_storage`]: crate::DeterministicSettings::integer_storage
+pub fn check_integer_simulation_shader(source: &str) -> Result<(), SimulationLayoutError> {
+    check_bindings(source, &INTEGER_SIMULATION_BINDINGS)
+}
+
+fn check_bindings(source: &str, bindings: &Bindings) -> Result<(), SimulationLayoutError> {
+    let source: String = source
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut declared = [false; SIMULATION_BINDINGS.len()];
+    for (binding, declaration) in group_zero_bindings(&source) {
+        let found = declaration.split_whitespace().collect::<Vec<_>>().join(" ");
+        let Some((kind, expected, _)) = bindings.get(binding as usize) else {
+            return Err(SimulationLayoutError::Unknown { binding, found });
+        };
+        if binding_kind(declaration) != Some(*kind) {
+            return Err(SimulationLayoutError::Mismatch {
+                binding,
+                expected,
+                found,
+            });
+        }
+        declared[binding as usize] = true;
+    }
+    match bindings
+        .iter()
+        .zip(declared)
+        .position(|((_, _, required), declared)| *required && !declared)
+    {
+        Some(binding) => Err(SimulationLayoutError::Missing {
+            binding: binding as u32,
+            expected: bindings[binding].1,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The binding index and `var` declaration of every `@group(0)` global.
+fn group_zero_bindings(source: &str) -> impl Iterator<Item = (u32, &str)> {
+    source.split([';', '{', '}']).filter_map(|statement| {
+        let statement = statement.trim();
+        let var = statement.find("var")?;
+        let attributes = &statement[..var];
+        if !attributes.contains("@group(0)") {
+            return None;
+        }
+        let binding = attributes.split("@binding(").nth(1)?;
+        let binding = binding[..binding.find(')')?].trim().parse().ok()?;
+        Some((binding, &statement[var..]))
+    })
+}
+
+fn binding_kind(declaration: &str) -> Option<BindingKind> {
+    let rest = declaration.strip_prefix("var")?.trim_start();
+    if let Some(space) = rest.strip_prefix('<') {
+        let space = space[..space.find('>')?].split(',').next()?.trim();
+        return match space {
+            "uniform" => Some(BindingKind::Uniform),
+            "storage" => Some(BindingKind::Storage),
+            _ => None,
+        };
+    }
+    let ty = rest.split(':').nth(1)?.trim_start();
+    if ty.starts_with("texture_storage_2d<rgba8uint") {
+        return Some(BindingKind::IntegerStorageTexture);
+    }
+    ty.starts_with("texture_storage_2d<rgba8unorm")
+        .then_some(BindingKind::StorageTexture)
+}
+
+/// Logs an error for every simulation shader of a map that is loaded with a
+/// layout the map can't dispatch it with.
+pub(crate) fn check_simulation_shaders(
+    mut events: EventReader<AssetEvent<Shader>>,
+    shaders: Res<Assets<Shader>>,
+    pixel_map_query: Query<&PixelMap>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        let Some(shader) = shaders.get(*id) else {
+            continue;
+        };
+        let Source::Wgsl(source) = &shader.source else {
+            continue;
+        };
+        let integer_storage: HashSet<bool> = pixel_map_query
+            .iter()
+            .filter(|pixel_map| pixel_map.simulation_shaders.contains(&shader.path))
+            .map(PixelMap::integer_storage)
+            .collect();
+        for integer_storage in integer_storage {
+            let (check, layout): (fn(&str) -> _, _) = if integer_storage {
+                (check_integer_simulation_shader, "integer storage layout")
+            } else {
+                (check_simulation_shader, "layout")
+            };
+            if let Err(err) = check(source) {
+                error!(
+                    "simulation shader {} doesn't match the pixel map {layout}: {err}",
+                    shader.path
+                );
+            }
+        }
+    }
+}
@@ -1,12 +1,20 @@
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::sync::Arc;
 
+use bevy::asset::AssetPath;
+use bevy::image::ImageSampler;
 use bevy::render::render_resource::{
-    CachedComputePipelineId, CachedPipelineState, CommandEncoderDescriptor, ComputePassDescriptor,
-    ComputePipelineDescriptor, IntoBinding, PipelineCache,
+    Buffer, CachedComputePipelineId, CachedPipelineState, CommandEncoderDescriptor,
+    ComputePassDescriptor, ComputePipelineDescriptor, IntoBinding, Maintain, PipelineCache,
+    Texture, TextureViewDescriptor,
 };
 use bevy::render::renderer::RenderQueue;
-use bevy::utils::hashbrown::HashMap;
+use bevy::render::sync_world::MainEntity;
+use bevy::sprite::Material2dPlugin;
+use bevy::transform::TransformSystem;
+use bevy::utils::hashbrown::{HashMap, HashSet};
 use bevy::{
     prelude::*,
     render::{
@@ -19,7 +27,7 @@ use bevy::{
             TextureViewDimension,
         },
         renderer::RenderDevice,
-        texture::{GpuImage, ImageSampler},
+        texture::GpuImage,
         Render, RenderApp, RenderSet,
     },
 };
@@ -27,6 +35,102 @@ use lazy_static::lazy_static;
 use std::iter::once;
 use std::path::Path;
 
+mod clipboard;
+mod config;
+mod cpu;
+mod debris;
+mod deterministic;
+mod dirty;
+mod events;
+mod explosion;
+mod export;
+mod generation;
+mod history;
+mod import;
+mod jump_flood;
+mod layout;
+mod lighting;
+mod lod;
+mod minimap;
+mod navigation;
+mod particles;
+mod readback;
+mod replay;
+mod replication;
+mod sdf;
+mod sleep;
+mod stats;
+mod terrain;
+mod trigger;
+
+pub use config::*;
+pub use cpu::*;
+pub use debris::{DebrisCollider, DebrisSelection, DebrisSettings, PixelDebris};
+pub use deterministic::DeterministicSettings;
+pub use events::*;
+pub use explosion::ExplosionSettings;
+pub use export::{downscale_image, save_png, PixelMapExportError};
+pub use generation::*;
+pub use history::PixelHistory;
+pub use import::{
+    ChunkedImage, ChunkedImageLoader, ChunkedImageSettings, PixelMapImageHandle,
+    PixelMapImportError,
+};
+pub use layout::{check_integer_simulation_shader, check_simulation_shader, SimulationLayoutError};
+pub use lighting::{EmissiveColor, LightCompositeMaterial, PixelLight2d, PixelMapLighting};
+pub use lod::{PixelLodTile, PixelMapLod};
+pub use minimap::{MinimapPalette, PixelMapMinimap};
+pub use navigation::{NavMode, PixelFlowField, PixelNavGrid};
+pub use particles::{ParticleSettings, PixelParticle};
+pub use replay::{PixelMapRecording, PixelMapReplay, RecordedFrame, UnrecordableEdit};
+pub use replication::{PixelEdit, PixelEditBatch, PixelMapSnapshot};
+pub use sdf::PixelMapSdf;
+pub use sleep::*;
+pub use stats::{PixelPredicate, PixelStats, PixelStatsQuery};
+pub use terrain::*;
+pub use trigger::PixelTrigger;
+
+use clipboard::{chunk_copy_rect, chunks_in_rect, RegionCopy, TextureCopy};
+use debris::{settle_debris, spawn_debris, DebrisRequest};
+use deterministic::{
+    advance_simulation_ticks, checksum_bind_group_layout, encode_checksums,
+    integer_storage_bind_group_layout, integer_texture, prepare_checksums, sorted_chunks,
+    ChecksumDispatch, DeterministicSimulation,
+};
+use dirty::{
+    apply_dirty_bounds, dirty_bind_group_layout, full_chunk_bounds, stamp_texel_origin,
+    stamp_texture_rect, DirtyBuffers, DIRTY_WORDS,
+};
+use explosion::{explode_bind_group_layout, explode_params};
+use export::{finish_export, ExportRequest};
+use history::{
+    complete_snapshot, record_history, restore_texture_region, snapshot_from_image, SnapshotRequest,
+};
+use import::{drain_imports, import_loaded_images, QueuedImport, RegionWrite};
+use jump_flood::jump_flood_bind_group_layout;
+use layout::check_simulation_shaders;
+use lighting::{
+    composite_lighting, encode_lighting, lighting_bind_group_layouts, prepare_lighting,
+    update_lighting, LightingDispatch, LightingPass,
+};
+use lod::{downsample_bind_group_layout, encode_downsamples, update_lod};
+use minimap::{update_minimaps, MinimapUpdate};
+use navigation::{invalidate_nav_grids, read_nav_grids};
+use particles::{
+    eject_buffer, encode_particles, particle_bind_group_layouts, prepare_particles, read_ejected,
+    update_particles, GpuParticleBatch, ParticleDispatch, PixelParticles,
+};
+use readback::{apply_readbacks, readback_channel, PendingReadback, ReadbackSender};
+use replay::{collect_recordings, flush_recordings, play_replay, verify_replay, PixelRecorder};
+use replication::{
+    apply_incoming_edits, collect_replication_readbacks, collect_replication_snapshots,
+    flush_replication, forget_modified_stamp_textures, request_replication_readbacks,
+    PixelReplication,
+};
+use sdf::{encode_sdf, prepare_sdf, sdf_bind_group_layouts, update_sdf, SdfDispatch};
+use stats::{encode_stats, prepare_stats, stats_bind_group_layout, StatsDispatch, StatsRequest};
+use trigger::update_pixel_triggers;
+
 lazy_static! {
     static ref ASSETS_PATH: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR"))
         .join("assets")
@@ -42,7 +146,7 @@ fn get_chunk_inner_i(position: IVec2, chunk_size: UVec2) -> UVec2 {
 
 fn get_chunk_index_i(position: IVec2, chunk_size: UVec2) -> usize {
     let inner = get_chunk_inner_i(position, chunk_size);
-    return (((chunk_size.y - inner.y - 1) * chunk_size.x) + inner.x) as usize;
+    (((chunk_size.y - inner.y - 1) * chunk_size.x) + inner.x) as usize
 }
 
 fn get_chunk_outer_i(position: IVec2, chunk_size: UVec2) -> IVec2 {
@@ -52,22 +156,94 @@ fn get_chunk_outer_i(position: IVec2, chunk_size: UVec2) -> IVec2 {
     }
 }
 
-#[derive(Component)]
-pub struct PixelChunk;
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct PixelChunk {
+    pub position: IVec2,
+}
 
-#[derive(Component, ExtractComponent, Clone)]
+#[derive(Component, ExtractComponent, Clone, Reflect)]
+#[reflect(Component, from_reflect = false)]
 pub struct PixelMap {
     chunk_size: UVec2,
+    #[reflect(ignore)]
     image_data: Vec<Handle<Image>>,
+    #[reflect(ignore)]
+    chunk_entities: Vec<Entity>,
+    #[reflect(ignore)]
     positions: HashMap<IVec2, usize>,
     simulation_shaders: Vec<String>,
+    #[reflect(ignore)]
     empty_texture: Image,
+    #[reflect(ignore)]
     root_entity: Entity,
     default_chunk_color: [u8; 4],
+    #[reflect(ignore)]
     texture_queue: Vec<PixelPositionedTexture>,
+    #[reflect(ignore)]
     texture_to_chunk_posses: HashMap<IVec2, Vec<PixelPositionedTexture>>,
+    #[reflect(ignore)]
+    chunk_events: Vec<ChunkEvent>,
+    #[reflect(ignore)]
+    generator: Option<Arc<dyn ChunkGenerator>>,
+    #[reflect(ignore)]
+    gpu_generator: Option<GpuChunkGenerator>,
+    #[reflect(ignore)]
+    generation_queue: Vec<IVec2>,
+    #[reflect(ignore)]
+    pixel_writes: HashMap<IVec2, HashMap<u32, u32>>,
+    simulation_margin: u32,
+    #[reflect(ignore)]
+    dirty_rects: HashMap<IVec2, IRect>,
+    #[reflect(ignore)]
+    dirty_frame: u64,
+    simulation_tick: u64,
+    simulation_paused: bool,
+    /// Ticks simulated this frame, more than one while a replay catches up.
+    simulation_steps: u32,
+    #[reflect(ignore)]
+    deterministic: Option<DeterministicSimulation>,
+    sleep_after: u32,
+    #[reflect(ignore)]
+    chunk_activity: HashMap<IVec2, ChunkActivity>,
+    #[reflect(ignore)]
+    woken_chunks: Vec<IVec2>,
+    #[reflect(ignore)]
+    cpu_simulations: Vec<Arc<dyn CpuSimulation>>,
+    #[reflect(ignore)]
+    history: Option<PixelHistory>,
+    #[reflect(ignore)]
+    region_copies: Vec<RegionCopy>,
+    #[reflect(ignore)]
+    export_count: u64,
+    #[reflect(ignore)]
+    stats_requests: Vec<StatsRequest>,
+    #[reflect(ignore)]
+    import_queue: VecDeque<QueuedImport>,
+    #[reflect(ignore)]
+    region_writes: Vec<RegionWrite>,
+    #[reflect(ignore)]
+    lod: Option<PixelMapLod>,
+    #[reflect(ignore)]
+    minimap_updates: Vec<MinimapUpdate>,
+    #[reflect(ignore)]
+    lighting_passes: Vec<LightingPass>,
+    #[reflect(ignore)]
+    sdf: Option<PixelMapSdf>,
+    #[reflect(ignore)]
+    debris_requests: Vec<DebrisRequest>,
+    debris_settings: DebrisSettings,
+    #[reflect(ignore)]
+    particles: PixelParticles,
+    explosion_settings: ExplosionSettings,
+    #[reflect(ignore)]
+    replication: Option<PixelReplication>,
+    #[reflect(ignore)]
+    recorder: Option<PixelRecorder>,
 }
 
+/// Covers the world pixels `position..position + size`, with the first row of
+/// the image at the top.
 #[derive(Clone, Debug)]
 pub struct PixelPositionedTexture {
     pub position: IVec2,
@@ -99,22 +275,73 @@ impl PixelMap {
             )
         });
         empty.texture_descriptor.usage = TextureUsages::COPY_DST
+            | TextureUsages::COPY_SRC
             | TextureUsages::TEXTURE_BINDING
             | TextureUsages::STORAGE_BINDING;
         empty.sampler = sampler.unwrap_or_else(ImageSampler::nearest);
         PixelMap {
             chunk_size,
             image_data: Vec::new(),
+            chunk_entities: Vec::new(),
             positions: HashMap::new(),
             empty_texture: empty,
             root_entity,
             default_chunk_color: color,
             texture_queue: vec![],
             texture_to_chunk_posses: HashMap::new(),
+            chunk_events: vec![],
+            generator: None,
+            gpu_generator: None,
+            generation_queue: vec![],
+            pixel_writes: HashMap::new(),
+            simulation_margin: 2,
+            dirty_rects: HashMap::new(),
+            dirty_frame: 0,
+            simulation_tick: 0,
+            simulation_paused: false,
+            simulation_steps: 1,
+            deterministic: None,
+            sleep_after: 30,
+            chunk_activity: HashMap::new(),
+            woken_chunks: vec![],
+            cpu_simulations: vec![],
+            history: None,
+            region_copies: vec![],
+            export_count: 0,
+            stats_requests: Vec::new(),
+            import_queue: VecDeque::new(),
+            region_writes: vec![],
+            lod: None,
+            minimap_updates: vec![],
+            lighting_passes: vec![],
+            sdf: None,
+            debris_requests: vec![],
+            debris_settings: DebrisSettings::default(),
+            particles: PixelParticles::default(),
+            explosion_settings: ExplosionSettings::default(),
+            replication: None,
+            recorder: None,
             simulation_shaders,
         }
     }
 
+    pub fn builder(chunk_size: UVec2, root_entity: Entity) -> PixelMapBuilder {
+        PixelMapBuilder::new(chunk_size, root_entity)
+    }
+
+    pub fn config(&self) -> PixelMapConfig {
+        PixelMapConfig {
+            chunk_size: self.chunk_size,
+            default_chunk_color: self.default_chunk_color,
+            simulation_shaders: self.simulation_shaders.clone(),
+            simulation_margin: self.simulation_margin,
+            sleep_after: self.sleep_after,
+            history_budget: self.history.as_ref().map_or(0, PixelHistory::budget),
+            lod_levels: self.lod().map_or(0, PixelMapLod::levels),
+            sdf_distance: self.sdf().map_or(0, PixelMapSdf::max_distance),
+        }
+    }
+
     pub fn get_pixels_cpu(
         &self,
         world_positions: &[IVec2],
@@ -135,7 +362,7 @@ impl PixelMap {
             .map(|&x| {
                 let outer = get_chunk_outer_i(x, self.chunk_size);
                 resources[&outer].map_or(self.default_chunk_color, |true_res| {
-                    let ind = get_chunk_index_i(x, self.chunk_size);
+                    let ind = get_chunk_index_i(x, self.chunk_size) * 4;
                     true_res[ind..ind + 4]
                         .try_into()
                         .unwrap_or(self.default_chunk_color)
@@ -144,6 +371,97 @@ impl PixelMap {
             .collect()
     }
 
+    /// Writes single pixels on the GPU this frame, creating missing chunks.
+    /// Later writes to the same pixel win.
+    pub fn set_pixels_cpu(
+        &mut self,
+        pixels: &[(IVec2, [u8; 4])],
+        commands: &mut Commands,
+        textures: &mut ResMut<Assets<Image>>,
+    ) {
+        if self.replication.is_some() || self.recorder.is_some() {
+            self.record_edit(PixelEdit::Pixels(pixels.to_vec()));
+        }
+        for &(position, color) in pixels {
+            let chunk_pos = get_chunk_outer_i(position, self.chunk_size);
+            let simulated = self.has_simulation()
+                && (self.is_chunk_sleeping(chunk_pos) || !self.positions.contains_key(&chunk_pos));
+            self.add_chunk(chunk_pos, commands, textures);
+            let inner = get_chunk_inner_i(position, self.chunk_size);
+            let coords = UVec2::new(inner.x, self.chunk_size.y - inner.y - 1);
+            self.queue_pixel_write(chunk_pos, coords, color, simulated);
+        }
+    }
+
+    /// Queues a write in texture coordinates of an existing chunk.
+    fn queue_pixel_write(
+        &mut self,
+        chunk_pos: IVec2,
+        coords: UVec2,
+        color: [u8; 4],
+        simulated: bool,
+    ) {
+        self.wake_chunk(chunk_pos);
+        let writes = self.pixel_writes.entry(chunk_pos).or_insert_with(|| {
+            self.chunk_events.push(ChunkEvent::Modified { chunk_pos });
+            if simulated {
+                self.chunk_events.push(ChunkEvent::Simulated { chunk_pos });
+            }
+            HashMap::new()
+        });
+        writes.insert(coords.x | coords.y << 16, u32::from_le_bytes(color));
+    }
+
+    /// Chunks whose pixels may change this frame.
+    pub(crate) fn changed_chunks(&self) -> HashSet<IVec2> {
+        let mut changed: HashSet<IVec2> = self
+            .texture_to_chunk_posses
+            .keys()
+            .chain(self.pixel_writes.keys())
+            .copied()
+            .collect();
+        changed.extend(self.queued_region_writes().map(|write| write.chunk_pos));
+        if self.has_simulation() {
+            changed.extend(self.awake_chunks());
+        }
+        changed.extend(self.particle_chunks());
+        changed
+    }
+
+    fn has_simulation(&self) -> bool {
+        !self.simulation_paused
+            && (!self.simulation_shaders.is_empty() || !self.cpu_simulations.is_empty())
+    }
+
+    pub fn simulation_margin(&self) -> u32 {
+        self.simulation_margin
+    }
+
+    /// Stops simulation shaders, CPU simulations and the simulation tick
+    /// until [`PixelMap::resume_simulation`]. Edits still apply, and chunks
+    /// don't fall asleep meanwhile.
+    pub fn pause_simulation(&mut self) {
+        self.simulation_paused = true;
+    }
+
+    pub fn resume_simulation(&mut self) {
+        self.simulation_paused = false;
+    }
+
+    pub fn is_simulation_paused(&self) -> bool {
+        self.simulation_paused
+    }
+
+    /// World pixels of a chunk changed in the latest frame read back from the
+    /// GPU, with an exclusive max. `None` if nothing changed.
+    pub fn dirty_rect(&self, chunk_position: IVec2) -> Option<IRect> {
+        self.dirty_rects.get(&chunk_position).copied()
+    }
+
+    pub fn dirty_rects(&self) -> impl Iterator<Item = (IVec2, IRect)> + '_ {
+        self.dirty_rects.iter().map(|(&pos, &rect)| (pos, rect))
+    }
+
     pub fn add_chunk(
         &mut self,
         chunk_position: IVec2,
@@ -154,21 +472,91 @@ impl PixelMap {
             return;
         }
         let computed_position = (chunk_position * self.chunk_size.as_ivec2()).as_vec2();
-        let tex_handle = textures.add(self.empty_texture.clone());
+        let mut chunk_texture = self.empty_texture.clone();
+        if let Some(generator) = &self.generator {
+            generator.generate(chunk_position, self.chunk_size, &mut chunk_texture.data);
+        }
+        if self.gpu_generator.is_some() {
+            self.generation_queue.push(chunk_position);
+        }
+        let tex_handle = textures.add(chunk_texture);
         let id = commands
-            .spawn(SpriteBundle {
-                sprite: Sprite {
+            .spawn((
+                Sprite {
                     image: tex_handle.clone(),
                     ..default()
                 },
-                transform: Transform::from_xyz(computed_position.x, computed_position.y, 0.0),
-                ..Default::default()
-            })
-            .insert(PixelChunk)
+                Transform::from_xyz(computed_position.x, computed_position.y, 0.0),
+                PixelChunk {
+                    position: chunk_position,
+                },
+            ))
             .id();
         commands.entity(self.root_entity).add_child(id);
         self.positions.insert(chunk_position, self.positions.len());
         self.image_data.push(tex_handle);
+        self.chunk_entities.push(id);
+        self.chunk_activity
+            .insert(chunk_position, ChunkActivity::AWAKE);
+        self.woken_chunks.push(chunk_position);
+        self.chunk_events.push(ChunkEvent::Created {
+            chunk_pos: chunk_position,
+            entity: id,
+        });
+        self.record_edit(PixelEdit::AddChunk(chunk_position));
+    }
+
+    pub fn remove_chunk(
+        &mut self,
+        chunk_position: IVec2,
+        commands: &mut Commands,
+        textures: &mut ResMut<Assets<Image>>,
+    ) {
+        let Some(index) = self.positions.remove(&chunk_position) else {
+            return;
+        };
+        let tex_handle = self.image_data.swap_remove(index);
+        let id = self.chunk_entities.swap_remove(index);
+        if let Some(moved) = self
+            .positions
+            .values_mut()
+            .find(|i| **i == self.image_data.len())
+        {
+            *moved = index;
+        }
+        textures.remove(tex_handle.id());
+        commands.entity(id).despawn_recursive();
+        self.texture_to_chunk_posses.remove(&chunk_position);
+        self.pixel_writes.remove(&chunk_position);
+        self.dirty_rects.remove(&chunk_position);
+        self.chunk_activity.remove(&chunk_position);
+        self.chunk_events.push(ChunkEvent::Removed {
+            chunk_pos: chunk_position,
+            entity: id,
+        });
+        self.record_edit(PixelEdit::RemoveChunk(chunk_position));
+    }
+
+    pub fn set_generator(&mut self, generator: impl ChunkGenerator) {
+        self.generator = Some(Arc::new(generator));
+    }
+
+    pub fn add_cpu_simulation(&mut self, simulation: impl CpuSimulation) {
+        self.cpu_simulations.push(Arc::new(simulation));
+    }
+
+    pub fn set_gpu_generator(&mut self, generator: GpuChunkGenerator) {
+        self.gpu_generator = Some(generator);
+    }
+
+    pub fn chunk_entity(&self, chunk_position: IVec2) -> Option<Entity> {
+        self.positions
+            .get(&chunk_position)
+            .map(|&i| self.chunk_entities[i])
+    }
+
+    pub fn chunk_positions(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.positions.keys().copied()
     }
 
     pub fn set_pixels_gpu(
@@ -176,49 +564,241 @@ impl PixelMap {
         textures: Vec<PixelPositionedTexture>,
         images: &mut ResMut<Assets<Image>>,
     ) {
+        let usage = TextureUsages::COPY_DST
+            | TextureUsages::TEXTURE_BINDING
+            | TextureUsages::STORAGE_BINDING;
         textures.iter().for_each(|positioned_image| {
             let hi = positioned_image.image.id();
-            images
-                .get_mut(hi)
-                .expect("expect loaded")
-                .texture_descriptor
-                .format = TextureFormat::Rgba8Unorm;
-            images
+            let descriptor = &images.get(hi).expect("expect loaded").texture_descriptor;
+            // Changing the image uploads it again, which would undo GPU side
+            // writes like the ones of `copy_region`.
+            if descriptor.format == TextureFormat::Rgba8Unorm && descriptor.usage.contains(usage) {
+                return;
+            }
+            let descriptor = &mut images
                 .get_mut(hi)
                 .expect("expect loaded")
-                .texture_descriptor
-                .usage = TextureUsages::COPY_DST
-                | TextureUsages::TEXTURE_BINDING
-                | TextureUsages::STORAGE_BINDING;
+                .texture_descriptor;
+            descriptor.format = TextureFormat::Rgba8Unorm;
+            descriptor.usage = usage;
         });
+        self.record_stamps(&textures, images);
         self.texture_queue.extend(textures);
     }
 }
 
 pub struct PixelMapGpuComputePlugin;
 
-#[derive(Resource)]
+struct ChunkOps {
+    map: Entity,
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+    wake: bool,
+    texture: Texture,
+    snapshots: Vec<SnapshotRequest>,
+    region_writes: Vec<RegionWrite>,
+    dirty: BindGroup,
+    changed: Buffer,
+    frame_changed: Buffer,
+    args: Buffer,
+    writes: Option<(BindGroup, u32)>,
+    stamps: Vec<(BindGroup, UVec2)>,
+    explosions: Vec<(BindGroup, UVec2)>,
+    /// Per simulated tick, the bind group of every phase and how many
+    /// passes to run.
+    simulation: Option<Vec<(Vec<BindGroup>, usize)>>,
+    simulation_pipelines: Vec<CachedComputePipelineId>,
+    /// Binds the chunk and the `rgba8uint` copy it's simulated on, when the
+    /// map has integer storage.
+    integer: Option<BindGroup>,
+}
+
+impl ChunkOps {
+    /// Whether the op changes or reads the chunk, beyond simulating it.
+    fn has_edits(&self) -> bool {
+        self.wake
+            || self.writes.is_some()
+            || !self.stamps.is_empty()
+            || !self.explosions.is_empty()
+            || !self.snapshots.is_empty()
+            || !self.region_writes.is_empty()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct CorePipelines {
+    stamp: CachedComputePipelineId,
+    write: CachedComputePipelineId,
+    begin_frame: CachedComputePipelineId,
+    next_tick: CachedComputePipelineId,
+    end_frame: CachedComputePipelineId,
+    prepare_dispatch: CachedComputePipelineId,
+    downsample: CachedComputePipelineId,
+    minimap: CachedComputePipelineId,
+    lighting_seed: CachedComputePipelineId,
+    jump_flood: CachedComputePipelineId,
+    lighting: CachedComputePipelineId,
+    sdf_seed: CachedComputePipelineId,
+    sdf: CachedComputePipelineId,
+    explode: CachedComputePipelineId,
+    particles: CachedComputePipelineId,
+    particle_clear: CachedComputePipelineId,
+    particle_draw: CachedComputePipelineId,
+    stats: CachedComputePipelineId,
+    checksum: CachedComputePipelineId,
+    integer_load: CachedComputePipelineId,
+    integer_store: CachedComputePipelineId,
+}
+
+#[derive(Resource, Default)]
 struct RenderData {
-    bind_map: HashMap<IVec2, Vec<(BindGroup, UVec2)>>,
-    shader_map: HashMap<IVec2, CachedComputePipelineId>,
-    bind_map_2: HashMap<IVec2, Vec<(BindGroup, UVec2)>>,
-    shader_map_2: HashMap<IVec2, Vec<CachedComputePipelineId>>,
+    ops: Vec<ChunkOps>,
+    held_ops: Vec<ChunkOps>,
+    copies: Vec<TextureCopy>,
+    exports: Vec<(Entity, ExportRequest, Texture, UVec2)>,
+    downsamples: Vec<(BindGroup, UVec2)>,
+    minimap_draws: Vec<(BindGroup, UVec2)>,
+    lighting: Vec<LightingDispatch>,
+    sdf: Vec<SdfDispatch>,
+    particles: Vec<ParticleDispatch>,
+    stats: Vec<StatsDispatch>,
+    checksums: Vec<ChecksumDispatch>,
+    particle_batches: HashMap<(Entity, u64), GpuParticleBatch>,
+    ejects: Vec<(Entity, Buffer)>,
+    eject_buffers: HashMap<Entity, (Buffer, u32)>,
+    fills: Vec<(Texture, URect, Vec<u8>)>,
+    maps: Vec<Entity>,
+    frame: u64,
+    core_pipelines: Option<CorePipelines>,
+    simulation_pipelines: HashMap<(String, bool), CachedComputePipelineId>,
+    dirty: HashMap<AssetId<Image>, DirtyBuffers>,
+    integer_textures: HashMap<AssetId<Image>, Texture>,
+}
+
+fn add_main_world_systems(app: &mut App) {
+    app.register_type::<PixelMap>()
+        .register_type::<PixelMapConfig>()
+        .register_type::<PixelMapConfigHandle>()
+        .init_asset::<PixelMapConfig>()
+        .init_asset_loader::<PixelMapConfigLoader>()
+        .register_type::<PixelChunk>()
+        .register_type::<PixelLodTile>()
+        .register_type::<PixelMapMinimap>()
+        .register_type::<PixelMapLighting>()
+        .register_type::<PixelLight2d>()
+        .register_type::<PixelDebris>()
+        .register_type::<DebrisCollider>()
+        .register_type::<PixelNavGrid>()
+        .register_type::<PixelTrigger>()
+        .register_type::<PixelMapImageHandle>()
+        .init_asset::<ChunkedImage>()
+        .init_asset_loader::<ChunkedImageLoader>()
+        .add_event::<ChunkCreated>()
+        .add_event::<ChunkRemoved>()
+        .add_event::<ChunkModified>()
+        .add_event::<ChunkSimulated>()
+        .add_event::<ChunkDirty>()
+        .add_event::<ChunkSlept>()
+        .add_event::<ChunkWoke>()
+        .add_event::<RegionExported>()
+        .add_event::<RegionStats>()
+        .add_event::<PixelTriggerEntered>()
+        .add_event::<PixelTriggerExited>()
+        .add_event::<PixelMapSnapshotReady>()
+        .add_event::<ChunkChecksums>()
+        .add_event::<PixelMapRecordingReady>()
+        .add_event::<PixelMapRecordingFailed>()
+        .add_event::<PixelMapReplayVerified>()
+        .add_systems(
+            Update,
+            (
+                spawn_configured_pixel_maps,
+                reload_pixel_map_configs,
+                import_loaded_images,
+                drain_imports,
+                prepare_chunks,
+                send_chunk_events,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                spawn_debris,
+                settle_debris.before(prepare_chunks),
+                update_particles.before(prepare_chunks),
+                (invalidate_nav_grids, read_nav_grids)
+                    .chain()
+                    .after(send_chunk_events),
+                update_pixel_triggers,
+                collect_replication_snapshots,
+                (collect_replication_readbacks, request_replication_readbacks)
+                    .chain()
+                    .before(prepare_chunks),
+                apply_incoming_edits.before(prepare_chunks),
+                collect_recordings.after(update_particles),
+                (play_replay, verify_replay)
+                    .chain()
+                    .after(apply_incoming_edits)
+                    .before(prepare_chunks),
+            ),
+        )
+        .add_systems(
+            First,
+            (
+                clear_generation_queue,
+                advance_simulation_ticks,
+                forget_modified_stamp_textures,
+            ),
+        )
+        .add_systems(
+            PostUpdate,
+            (
+                record_history,
+                update_lod,
+                update_lighting
+                    .after(TransformSystem::TransformPropagate)
+                    .before(update_minimaps),
+                update_minimaps,
+                update_sdf,
+                flush_replication,
+                flush_recordings,
+            ),
+        );
 }
 
 impl Plugin for PixelMapGpuComputePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ExtractComponentPlugin::<PixelMap>::default())
-            .add_systems(Update, prepare_chunks);
+        let (readback_sender, readback_receiver) = readback_channel();
+        app.add_plugins(ExtractComponentPlugin::<PixelMap>::default());
+        add_main_world_systems(app);
+        app.add_plugins(Material2dPlugin::<LightCompositeMaterial>::default())
+            .add_systems(Update, check_simulation_shaders)
+            .add_systems(PostUpdate, composite_lighting.after(update_lighting));
+        app.insert_resource(readback_receiver).add_systems(
+            First,
+            (
+                clear_frame_queues,
+                apply_readbacks.after(clear_frame_queues),
+            ),
+        );
         let render_app = app.sub_app_mut(RenderApp);
         render_app
             .add_systems(Render, prepare_binds.in_set(RenderSet::PrepareBindGroups))
+            .add_systems(
+                Render,
+                prepare_generation_binds.in_set(RenderSet::PrepareBindGroups),
+            )
+            .add_systems(
+                Render,
+                apply_generation
+                    .after(RenderSet::PrepareBindGroups)
+                    .before(apply_ops),
+            )
             .add_systems(Render, apply_ops)
-            .insert_resource(RenderData {
-                bind_map: HashMap::default(),
-                shader_map: HashMap::default(),
-                bind_map_2: HashMap::default(),
-                shader_map_2: HashMap::default(),
-            });
+            .init_resource::<GenerationRenderData>()
+            .init_resource::<RenderData>()
+            .insert_resource(readback_sender);
     }
 }
 
@@ -269,148 +849,758 @@ fn prepare_chunks(
         }
         for (k, _v) in texture_to_chunk_posses.iter() {
             pixel_map.add_chunk(*k, &mut commands, &mut textures);
+            pixel_map.wake_chunk(*k);
+        }
+        let mut modified: HashSet<IVec2> = HashSet::new();
+        for tex in pixel_map.texture_queue.iter() {
+            let last = tex.position + tex.size.as_ivec2() - IVec2::ONE;
+            modified.extend(
+                IRect {
+                    min: get_chunk_outer_i(tex.position, pixel_map.chunk_size),
+                    max: get_chunk_outer_i(last, pixel_map.chunk_size) + IVec2::ONE,
+                }
+                .points(),
+            );
+        }
+        pixel_map.chunk_events.extend(
+            modified
+                .into_iter()
+                .map(|chunk_pos| ChunkEvent::Modified { chunk_pos }),
+        );
+        if pixel_map.has_simulation() {
+            let simulated: Vec<ChunkEvent> = pixel_map
+                .awake_chunks()
+                .map(|chunk_pos| ChunkEvent::Simulated { chunk_pos })
+                .collect();
+            pixel_map.chunk_events.extend(simulated);
         }
         pixel_map.texture_to_chunk_posses = texture_to_chunk_posses;
         pixel_map.texture_queue.clear();
     }
 }
 
+fn clear_frame_queues(mut pixel_map_query: Query<&mut PixelMap>) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        if !pixel_map.pixel_writes.is_empty() {
+            pixel_map.pixel_writes.clear();
+        }
+        if !pixel_map.woken_chunks.is_empty() {
+            pixel_map.woken_chunks.clear();
+        }
+        if !pixel_map.region_copies.is_empty() {
+            pixel_map.region_copies.clear();
+        }
+        if !pixel_map.region_writes.is_empty() {
+            pixel_map.region_writes.clear();
+        }
+        if let Some(history) = pixel_map.history.as_mut() {
+            history.snapshot_requests.clear();
+            history.restores.clear();
+        }
+        if let Some(lod) = pixel_map.lod.as_mut() {
+            lod.updates.clear();
+        }
+        if !pixel_map.minimap_updates.is_empty() {
+            pixel_map.minimap_updates.clear();
+        }
+        if !pixel_map.lighting_passes.is_empty() {
+            pixel_map.lighting_passes.clear();
+        }
+        if let Some(sdf) = pixel_map.sdf.as_mut() {
+            sdf.updates.clear();
+        }
+        for batch in pixel_map.particles.batches.iter_mut() {
+            batch.explosions.clear();
+            batch.particles.clear();
+        }
+        if !pixel_map.stats_requests.is_empty() {
+            pixel_map.stats_requests.clear();
+        }
+    }
+}
+
+fn queue_pipeline(
+    pipeline_cache: &PipelineCache,
+    asset_server: &AssetServer,
+    shader: impl Into<AssetPath<'static>>,
+    entry_point: &'static str,
+    layout: &BindGroupLayout,
+) -> CachedComputePipelineId {
+    pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: asset_server.load(shader),
+        zero_initialize_workgroup_memory: true,
+        shader_defs: vec![],
+        entry_point: Cow::from(entry_point),
+    })
+}
+
 fn prepare_binds(
-    pixel_map_query: Query<&PixelMap>,
+    pixel_map_query: Query<(&MainEntity, &PixelMap)>,
     render_device: Res<RenderDevice>,
     gpu_images: Res<RenderAssets<GpuImage>>,
     mut render_data: ResMut<RenderData>,
     pipeline_cache: Res<PipelineCache>,
     asset_server: Res<AssetServer>,
+    readback_sender: Res<ReadbackSender>,
 ) {
-    for pixel_map in pixel_map_query.iter() {
-        for (chunk_pos, chunk_texes) in pixel_map.texture_to_chunk_posses.iter() {
+    let layouts = PixelMapShaderLayoutInput::new(&render_device);
+    let render_data = render_data.as_mut();
+    render_data
+        .dirty
+        .retain(|image, _| gpu_images.get(*image).is_some());
+    render_data
+        .integer_textures
+        .retain(|image, _| gpu_images.get(*image).is_some());
+    render_data.core_pipelines.get_or_insert_with(|| {
+        let dirty_shader = ASSETS_PATH.join("dirty_rect.wgsl");
+        CorePipelines {
+            stamp: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("place_tex.wgsl"),
+                "main",
+                &layouts.bind_group_layout,
+            ),
+            write: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("write_pixels.wgsl"),
+                "main",
+                &layouts.write_layout,
+            ),
+            begin_frame: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                dirty_shader.clone(),
+                "begin_frame",
+                &layouts.dirty_layout,
+            ),
+            next_tick: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                dirty_shader.clone(),
+                "next_tick",
+                &layouts.dirty_layout,
+            ),
+            end_frame: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                dirty_shader.clone(),
+                "end_frame",
+                &layouts.dirty_layout,
+            ),
+            prepare_dispatch: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                dirty_shader,
+                "prepare_dispatch",
+                &layouts.dirty_layout,
+            ),
+            downsample: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("downsample.wgsl"),
+                "main",
+                &layouts.downsample_layout,
+            ),
+            minimap: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("minimap.wgsl"),
+                "main",
+                &layouts.downsample_layout,
+            ),
+            lighting_seed: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("lighting_seed.wgsl"),
+                "main",
+                &layouts.lighting_seed_layout,
+            ),
+            jump_flood: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("jump_flood.wgsl"),
+                "main",
+                &layouts.jump_flood_layout,
+            ),
+            lighting: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("lighting.wgsl"),
+                "main",
+                &layouts.lighting_layout,
+            ),
+            sdf_seed: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("sdf_seed.wgsl"),
+                "main",
+                &layouts.sdf_seed_layout,
+            ),
+            sdf: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("sdf.wgsl"),
+                "main",
+                &layouts.sdf_layout,
+            ),
+            explode: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("explode.wgsl"),
+                "main",
+                &layouts.explode_layout,
+            ),
+            particles: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("particles.wgsl"),
+                "main",
+                &layouts.particle_layout,
+            ),
+            particle_clear: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("particle_draw.wgsl"),
+                "clear",
+                &layouts.particle_draw_layout,
+            ),
+            particle_draw: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("particle_draw.wgsl"),
+                "draw",
+                &layouts.particle_draw_layout,
+            ),
+            stats: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("stats.wgsl"),
+                "main",
+                &layouts.stats_layout,
+            ),
+            checksum: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("checksum.wgsl"),
+                "main",
+                &layouts.checksum_layout,
+            ),
+            integer_load: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("integer_storage.wgsl"),
+                "load",
+                &layouts.integer_storage_layout,
+            ),
+            integer_store: queue_pipeline(
+                &pipeline_cache,
+                &asset_server,
+                ASSETS_PATH.join("integer_storage.wgsl"),
+                "store",
+                &layouts.integer_storage_layout,
+            ),
+        }
+    });
+
+    for (main_entity, pixel_map) in pixel_map_query.iter() {
+        let chunk_size = pixel_map.chunk_size;
+        render_data.maps.push(main_entity.id());
+        let integer_storage = pixel_map.integer_storage();
+        let simulation_layout = if integer_storage {
+            &layouts.integer_simulation_layout
+        } else {
+            &layouts.bind_group_layout_2
+        };
+        let simulation_pipelines: Vec<CachedComputePipelineId> = pixel_map
+            .simulation_shaders
+            .iter()
+            .filter(|_| pixel_map.has_simulation())
+            .map(|shader| {
+                *render_data
+                    .simulation_pipelines
+                    .entry((shader.clone(), integer_storage))
+                    .or_insert_with(|| {
+                        queue_pipeline(
+                            &pipeline_cache,
+                            &asset_server,
+                            shader.clone(),
+                            "main",
+                            simulation_layout,
+                        )
+                    })
+            })
+            .collect();
+        let mut chunks: HashSet<IVec2> = pixel_map
+            .texture_to_chunk_posses
+            .keys()
+            .chain(pixel_map.pixel_writes.keys())
+            .copied()
+            .collect();
+        if !simulation_pipelines.is_empty() {
+            chunks.extend(pixel_map.awake_chunks());
+        }
+        let snapshot_requests = pixel_map
+            .history
+            .as_ref()
+            .map_or(&[][..], |history| &history.snapshot_requests[..]);
+        chunks.extend(snapshot_requests.iter().map(|request| request.chunk_pos));
+        chunks.extend(
+            pixel_map
+                .queued_region_writes()
+                .map(|write| write.chunk_pos),
+        );
+        let eject = (!simulation_pipelines.is_empty()).then(|| {
+            let capacity = pixel_map.particles.settings.eject_capacity.max(1);
+            let (buffer, buffer_capacity) = render_data
+                .eject_buffers
+                .entry(main_entity.id())
+                .or_insert_with(|| (eject_buffer(&render_device, capacity), capacity));
+            if *buffer_capacity != capacity {
+                *buffer = eject_buffer(&render_device, capacity);
+                *buffer_capacity = capacity;
+            }
+            render_data.ejects.push((main_entity.id(), buffer.clone()));
+            buffer.clone()
+        });
+        let explosions: Vec<_> = pixel_map
+            .particles
+            .batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .explosions
+                    .iter()
+                    .map(move |explosion| (batch.id, batch.capacity, explosion))
+            })
+            .collect();
+        chunks.extend(
+            explosions
+                .iter()
+                .flat_map(|(_, _, explosion)| chunks_in_rect(explosion.rect(), chunk_size)),
+        );
+
+        for copy in pixel_map.region_copies.iter() {
+            let Some(target) = gpu_images.get(&copy.image) else {
+                continue;
+            };
+            if let Some(request) = copy.export {
+                render_data.exports.push((
+                    main_entity.id(),
+                    request,
+                    target.texture.clone(),
+                    copy.rect.size().as_uvec2(),
+                ));
+            }
+            for chunk_pos in chunks_in_rect(copy.rect, chunk_size) {
+                let Some(source) = pixel_map
+                    .positions
+                    .get(&chunk_pos)
+                    .and_then(|&index| gpu_images.get(&pixel_map.image_data[index]))
+                else {
+                    continue;
+                };
+                let Some((source_rect, target_origin)) =
+                    chunk_copy_rect(chunk_pos, chunk_size, copy.rect)
+                else {
+                    continue;
+                };
+                render_data.copies.push(TextureCopy {
+                    source: source.texture.clone(),
+                    target: target.texture.clone(),
+                    source_rect,
+                    target_origin,
+                });
+            }
+        }
+
+        let lod_updates = pixel_map
+            .lod
+            .as_ref()
+            .map_or(&[][..], |lod| &lod.updates[..]);
+        for update in lod_updates {
+            let Some(tile) = gpu_images.get(&update.tile) else {
+                continue;
+            };
+            let cell = chunk_size / update.factor;
+            let Some(source) = update.source.as_ref() else {
+                render_data.fills.push((
+                    tile.texture.clone(),
+                    URect::from_corners(update.origin, update.origin + cell),
+                    pixel_map
+                        .default_chunk_color
+                        .repeat(cell.element_product() as usize),
+                ));
+                continue;
+            };
+            let Some(source) = gpu_images.get(source) else {
+                continue;
+            };
+            let params = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("lod_params_buffer"),
+                contents: bytemuck::cast_slice(&[
+                    update.origin.x,
+                    update.origin.y,
+                    update.factor,
+                    0,
+                ]),
+                usage: BufferUsages::UNIFORM,
+            });
+            let binds = render_device.create_bind_group(
+                "pixel map lod bind group",
+                &layouts.downsample_layout,
+                &BindGroupEntries::sequential((
+                    source.texture_view.into_binding(),
+                    tile.texture_view.into_binding(),
+                    params.as_entire_binding(),
+                )),
+            );
+            render_data
+                .downsamples
+                .push((binds, (cell + UVec2::splat(7)) / 8));
+        }
+
+        for update in pixel_map.minimap_updates.iter() {
+            let Some(target) = gpu_images.get(&update.target) else {
+                continue;
+            };
+            let Some(source) = update.source.as_ref() else {
+                render_data.fills.push((
+                    target.texture.clone(),
+                    update.pixels,
+                    update
+                        .palette
+                        .apply(pixel_map.default_chunk_color)
+                        .repeat(update.pixels.size().element_product() as usize),
+                ));
+                continue;
+            };
+            let Some(source) = gpu_images.get(source) else {
+                continue;
+            };
+            let origin = update.chunk_pos * chunk_size.as_ivec2();
+            let [mode, solid, empty] = update.palette.shader_params();
+            let params = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("minimap_params_buffer"),
+                contents: bytemuck::cast_slice(&[
+                    origin.x as u32,
+                    origin.y as u32,
+                    update.rect.min.x as u32,
+                    update.rect.min.y as u32,
+                    update.rect.max.x as u32,
+                    update.rect.max.y as u32,
+                    update.pixels.max.x,
+                    update.pixels.max.y,
+                    update.pixels.min.x,
+                    update.pixels.min.y,
+                    update.downscale,
+                    mode,
+                    solid,
+                    empty,
+                    0,
+                    0,
+                ]),
+                usage: BufferUsages::UNIFORM,
+            });
+            let binds = render_device.create_bind_group(
+                "pixel map minimap bind group",
+                &layouts.downsample_layout,
+                &BindGroupEntries::sequential((
+                    source.texture_view.into_binding(),
+                    target.texture_view.into_binding(),
+                    params.as_entire_binding(),
+                )),
+            );
+            render_data
+                .minimap_draws
+                .push((binds, (update.pixels.size() + UVec2::splat(7)) / 8));
+        }
+
+        render_data.lighting.extend(
+            pixel_map
+                .lighting_passes
+                .iter()
+                .filter_map(|pass| prepare_lighting(&render_device, &layouts, &gpu_images, pass)),
+        );
+        let sdf_updates = pixel_map
+            .sdf
+            .as_ref()
+            .map_or(&[][..], |sdf| &sdf.updates[..]);
+        render_data
+            .sdf
+            .extend(sdf_updates.iter().filter_map(|update| {
+                prepare_sdf(
+                    &render_device,
+                    &layouts,
+                    &gpu_images,
+                    pixel_map,
+                    main_entity.id(),
+                    update,
+                )
+            }));
+        render_data.particles.extend(prepare_particles(
+            &render_device,
+            &layouts,
+            &gpu_images,
+            pixel_map,
+            main_entity.id(),
+            &mut render_data.particle_batches,
+        ));
+        render_data.stats.extend(prepare_stats(
+            &render_device,
+            &layouts.stats_layout,
+            &gpu_images,
+            pixel_map,
+            main_entity.id(),
+        ));
+        render_data.checksums.extend(prepare_checksums(
+            &render_device,
+            &layouts.checksum_layout,
+            &gpu_images,
+            pixel_map,
+            main_entity.id(),
+        ));
+
+        let chunks = if pixel_map.deterministic.is_some() {
+            sorted_chunks(chunks)
+        } else {
+            chunks.into_iter().collect()
+        };
+        for chunk_pos in chunks {
+            let snapshots: Vec<SnapshotRequest> = snapshot_requests
+                .iter()
+                .filter(|request| request.chunk_pos == chunk_pos)
+                .copied()
+                .collect();
+            let input_view = pixel_map
+                .positions
+                .get(&chunk_pos)
+                .map(|&index| pixel_map.image_data[index].id())
+                .and_then(|image| Some((image, gpu_images.get(image)?)));
+            let Some((image, input_view)) = input_view else {
+                let map = main_entity.id();
+                for request in snapshots {
+                    readback_sender.send(Box::new(move |world| {
+                        snapshot_from_image(world, map, request)
+                    }));
+                }
+                continue;
+            };
+            let dirty = render_data.dirty.entry(image).or_insert_with(|| {
+                DirtyBuffers::new(&render_device, chunk_size, pixel_map.simulation_margin)
+            });
+            dirty.set_margin(&render_device, chunk_size, pixel_map.simulation_margin);
             let input_texture_pos_buffer =
                 render_device.create_buffer_with_data(&BufferInitDescriptor {
                     label: Some("input_texture_pos_buffer"),
                     contents: bytemuck::cast_slice(&[
-                        chunk_pos.x * pixel_map.chunk_size.x as i32,
-                        chunk_pos.y * pixel_map.chunk_size.y as i32,
+                        chunk_pos.x * chunk_size.x as i32,
+                        chunk_pos.y * chunk_size.y as i32,
                     ]),
                     usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
                 });
-
             let input_texture_size_buffer =
                 render_device.create_buffer_with_data(&BufferInitDescriptor {
                     label: Some("input_texture_size_buffer"),
-                    contents: bytemuck::cast_slice(&[
-                        pixel_map.chunk_size.x,
-                        pixel_map.chunk_size.y,
-                    ]),
+                    contents: bytemuck::cast_slice(&[chunk_size.x, chunk_size.y]),
                     usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
                 });
 
-            for texes_chunk in chunk_texes.into_iter() {
-                let source_texture_pos_buffer =
-                    render_device.create_buffer_with_data(&BufferInitDescriptor {
-                        label: Some("source_texture_pos_buffer"),
-                        contents: bytemuck::cast_slice(&[
-                            texes_chunk.position.x,
-                            texes_chunk.position.y,
-                        ]),
-                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-                    });
-                let source_texture_size_buffer =
-                    render_device.create_buffer_with_data(&BufferInitDescriptor {
-                        label: Some("source_texture_size_buffer"),
-                        contents: bytemuck::cast_slice(&[texes_chunk.size.x, texes_chunk.size.y]),
-                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-                    });
-                let input_view = gpu_images
-                    .get(&pixel_map.image_data[pixel_map.positions[chunk_pos]])
-                    .unwrap();
-                let source_view = &gpu_images.get(texes_chunk.image.id()).unwrap().texture_view;
-
-                let layout = PixelMapShaderLayoutInput::new(&render_device).bind_group_layout;
+            let writes = pixel_map.pixel_writes.get(&chunk_pos).map(|writes| {
+                let words: Vec<u32> = writes
+                    .iter()
+                    .flat_map(|(&coords, &color)| [coords, color])
+                    .collect();
+                let writes_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("pixel_writes_buffer"),
+                    contents: bytemuck::cast_slice(&words),
+                    usage: BufferUsages::STORAGE,
+                });
                 let binds = render_device.create_bind_group(
-                    "pixel map bind group",
-                    &layout,
+                    "pixel map write bind group",
+                    &layouts.write_layout,
                     &BindGroupEntries::sequential((
                         input_view.texture_view.into_binding(),
-                        input_texture_pos_buffer.as_entire_binding(),
-                        input_texture_size_buffer.as_entire_binding(),
-                        source_texture_pos_buffer.as_entire_binding(),
-                        source_texture_size_buffer.as_entire_binding(),
-                        source_view.into_binding(),
+                        writes_buffer.as_entire_binding(),
+                        dirty.changed.as_entire_binding(),
                     )),
                 );
-                let layout_2 = PixelMapShaderLayoutInput::new(&render_device).bind_group_layout_2;
-                let binds_2 = render_device.create_bind_group(
-                    "pixel map bind group",
-                    &layout_2,
+                (binds, writes.len() as u32)
+            });
+
+            let chunk_texes = pixel_map
+                .texture_to_chunk_posses
+                .get(&chunk_pos)
+                .map_or(&[][..], Vec::as_slice);
+            let stamps = chunk_texes
+                .iter()
+                .filter_map(|texes_chunk| {
+                    let rect = stamp_texture_rect(chunk_pos, chunk_size, texes_chunk)?;
+                    let source_view = &gpu_images.get(texes_chunk.image.id())?.texture_view;
+                    let texel_origin = stamp_texel_origin(chunk_pos, chunk_size, texes_chunk);
+                    let texel_origin_buffer =
+                        render_device.create_buffer_with_data(&BufferInitDescriptor {
+                            label: Some("stamp_texel_origin_buffer"),
+                            contents: bytemuck::cast_slice(&[texel_origin.x, texel_origin.y]),
+                            usage: BufferUsages::UNIFORM,
+                        });
+                    let dispatch_offset_buffer =
+                        render_device.create_buffer_with_data(&BufferInitDescriptor {
+                            label: Some("stamp_dispatch_offset_buffer"),
+                            contents: bytemuck::cast_slice(&[rect.min.x, rect.min.y]),
+                            usage: BufferUsages::UNIFORM,
+                        });
+                    let binds = render_device.create_bind_group(
+                        "pixel map bind group",
+                        &layouts.bind_group_layout,
+                        &BindGroupEntries::sequential((
+                            input_view.texture_view.into_binding(),
+                            input_texture_pos_buffer.as_entire_binding(),
+                            input_texture_size_buffer.as_entire_binding(),
+                            texel_origin_buffer.as_entire_binding(),
+                            source_view.into_binding(),
+                            dispatch_offset_buffer.as_entire_binding(),
+                            dirty.changed.as_entire_binding(),
+                        )),
+                    );
+                    Some((binds, (rect.size() + UVec2::splat(7)) / 8))
+                })
+                .collect();
+
+            let explosions = explosions
+                .iter()
+                .filter_map(|&(id, capacity, explosion)| {
+                    let batch = render_data.particle_batches.get(&(main_entity.id(), id))?;
+                    let (params, workgroups) =
+                        explode_params(chunk_pos, chunk_size, explosion, capacity)?;
+                    let params = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                        label: Some("explode_params_buffer"),
+                        contents: bytemuck::cast_slice(&params),
+                        usage: BufferUsages::UNIFORM,
+                    });
+                    let binds = render_device.create_bind_group(
+                        "pixel map explode bind group",
+                        &layouts.explode_layout,
+                        &BindGroupEntries::sequential((
+                            input_view.texture_view.into_binding(),
+                            params.as_entire_binding(),
+                            dirty.changed.as_entire_binding(),
+                            batch.particles.as_entire_binding(),
+                            batch.count.as_entire_binding(),
+                        )),
+                    );
+                    Some((binds, workgroups))
+                })
+                .collect();
+
+            let integer_view = eject.as_ref().filter(|_| integer_storage).map(|_| {
+                render_data
+                    .integer_textures
+                    .entry(image)
+                    .or_insert_with(|| integer_texture(&render_device, chunk_size))
+                    .create_view(&TextureViewDescriptor::default())
+            });
+            let simulated_view = integer_view.as_ref().unwrap_or(&input_view.texture_view);
+            let simulation = eject.as_ref().map(|eject| {
+                pixel_map
+                    .simulated_ticks()
+                    .enumerate()
+                    .map(|(step, tick)| {
+                        let binds = pixel_map
+                            .tick_uniforms(chunk_pos, tick)
+                            .iter()
+                            .map(|tick| {
+                                let tick_buffer =
+                                    render_device.create_buffer_with_data(&BufferInitDescriptor {
+                                        label: Some("simulation_tick_buffer"),
+                                        contents: bytemuck::cast_slice(tick),
+                                        usage: BufferUsages::UNIFORM,
+                                    });
+                                render_device.create_bind_group(
+                                    "pixel map bind group",
+                                    simulation_layout,
+                                    &BindGroupEntries::sequential((
+                                        simulated_view.into_binding(),
+                                        input_texture_pos_buffer.as_entire_binding(),
+                                        input_texture_size_buffer.as_entire_binding(),
+                                        dirty.offset.as_entire_binding(),
+                                        dirty.changed.as_entire_binding(),
+                                        eject.as_entire_binding(),
+                                        tick_buffer.as_entire_binding(),
+                                    )),
+                                )
+                            })
+                            .collect();
+                        let passes = if step == 0 {
+                            chunk_texes.len().max(1)
+                        } else {
+                            1
+                        };
+                        (binds, passes)
+                    })
+                    .collect()
+            });
+            let integer = integer_view.map(|view| {
+                render_device.create_bind_group(
+                    "pixel map integer storage bind group",
+                    &layouts.integer_storage_layout,
                     &BindGroupEntries::sequential((
                         input_view.texture_view.into_binding(),
-                        input_texture_pos_buffer.as_entire_binding(),
-                        input_texture_size_buffer.as_entire_binding(),
+                        view.into_binding(),
                     )),
-                );
-                if !render_data.bind_map.contains_key(chunk_pos) {
-                    render_data
-                        .bind_map
-                        .insert(*chunk_pos, vec![(binds, pixel_map.chunk_size)]);
-                } else {
-                    render_data
-                        .bind_map
-                        .get_mut(chunk_pos)
-                        .unwrap()
-                        .push((binds, pixel_map.chunk_size));
-                }
-                if !render_data.shader_map.contains_key(chunk_pos) {
-                    let shader = asset_server.load(ASSETS_PATH.join("place_tex.wgsl"));
-                    let pipeline =
-                        pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-                            label: None,
-                            layout: vec![layout.clone()],
-                            push_constant_ranges: Vec::new(),
-                            shader: shader.clone(),
-                            zero_initialize_workgroup_memory: true,
-                            shader_defs: vec![],
-                            entry_point: Cow::from("main"),
-                        });
-                    assert!(render_data
-                        .shader_map
-                        .insert(*chunk_pos, pipeline)
-                        .is_none());
-                }
-                if !render_data.bind_map_2.contains_key(chunk_pos) {
-                    render_data
-                        .bind_map_2
-                        .insert(*chunk_pos, vec![(binds_2, pixel_map.chunk_size)]);
-                } else {
-                    render_data
-                        .bind_map_2
-                        .get_mut(chunk_pos)
-                        .unwrap()
-                        .push((binds_2, pixel_map.chunk_size));
-                }
-                if !render_data.shader_map_2.contains_key(chunk_pos) {
-                    for shader_id in pixel_map.simulation_shaders.iter() {
-                        let shader = asset_server.load(shader_id.clone());
-                        let pipeline =
-                            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-                                label: None,
-                                layout: vec![layout_2.clone()],
-                                push_constant_ranges: Vec::new(),
-                                zero_initialize_workgroup_memory: true,
-                                shader: shader.clone(),
-                                shader_defs: vec![],
-                                entry_point: Cow::from("main"),
-                            });
-                        if let Some(vec) = render_data.shader_map_2.get_mut(chunk_pos) {
-                            vec.push(pipeline);
-                        } else {
-                            render_data.shader_map_2.insert(*chunk_pos, vec![pipeline]);
-                        }
-                    }
-                }
-            }
+                )
+            });
+
+            let ops = ChunkOps {
+                map: main_entity.id(),
+                chunk_pos,
+                chunk_size,
+                wake: pixel_map.woken_chunks.contains(&chunk_pos),
+                texture: input_view.texture.clone(),
+                snapshots,
+                region_writes: pixel_map
+                    .queued_region_writes()
+                    .filter(|write| write.chunk_pos == chunk_pos)
+                    .cloned()
+                    .collect(),
+                dirty: dirty.bind_group(&render_device, &layouts.dirty_layout),
+                changed: dirty.changed.clone(),
+                frame_changed: dirty.frame.clone(),
+                args: dirty.args.clone(),
+                writes,
+                stamps,
+                explosions,
+                simulation,
+                simulation_pipelines: simulation_pipelines.clone(),
+                integer,
+            };
+            render_data.ops.push(ops);
         }
     }
+    let batches: HashSet<(Entity, u64)> = pixel_map_query
+        .iter()
+        .flat_map(|(main_entity, pixel_map)| {
+            pixel_map
+                .particles
+                .batches
+                .iter()
+                .map(|batch| (main_entity.id(), batch.id))
+        })
+        .collect();
+    render_data
+        .particle_batches
+        .retain(|key, _| batches.contains(key));
+    let maps: HashSet<Entity> = pixel_map_query
+        .iter()
+        .map(|(main_entity, _)| main_entity.id())
+        .collect();
+    render_data
+        .eject_buffers
+        .retain(|map, _| maps.contains(map));
 }
 
 fn apply_ops(
@@ -418,169 +1608,573 @@ fn apply_ops(
     render_device: Res<RenderDevice>,
     pipeline_cache: Res<PipelineCache>,
     render_queue: Res<RenderQueue>,
+    readback_sender: Res<ReadbackSender>,
+    generation: Res<GenerationRenderData>,
 ) {
-    // Layer 1 operations
-    for (chunk_pos, bind_groups) in render_data.bind_map.iter() {
-        let pipeline_id = *render_data.shader_map.get(chunk_pos).unwrap();
-        if let CachedPipelineState::Ok(_) = pipeline_cache.get_compute_pipeline_state(pipeline_id) {
-            let pipeline = pipeline_cache.get_compute_pipeline(pipeline_id).unwrap();
-            for binds in bind_groups.iter() {
-                let mut command_encoder =
-                    render_device.create_command_encoder(&CommandEncoderDescriptor::default());
-                {
-                    let mut pass =
-                        command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
-                    pass.set_pipeline(pipeline);
-                    pass.set_bind_group(0, &binds.0, &[]);
-                    pass.dispatch_workgroups(binds.1.x / 8, binds.1.y / 8, 1);
-                }
-
-                render_queue.submit(once(command_encoder.finish()));
-            }
+    render_device.poll(Maintain::Poll);
+    let pipeline = |id: CachedComputePipelineId| pipeline_cache.get_compute_pipeline(id);
+    let core = render_data.core_pipelines.and_then(|core| {
+        Some((
+            pipeline(core.stamp)?,
+            pipeline(core.write)?,
+            [
+                pipeline(core.begin_frame)?,
+                pipeline(core.next_tick)?,
+                pipeline(core.end_frame)?,
+                pipeline(core.prepare_dispatch)?,
+            ],
+        ))
+    });
+    let pipelines = |ids: [CachedComputePipelineId; 3]| {
+        let [first, second, third] = ids.map(pipeline);
+        Some([first?, second?, third?])
+    };
+    // Each feature only skips its own pass while its shader compiles.
+    let features = render_data.core_pipelines.map(|core| {
+        (
+            pipeline(core.downsample),
+            pipeline(core.minimap),
+            pipelines([core.lighting_seed, core.jump_flood, core.lighting]),
+            pipelines([core.sdf_seed, core.jump_flood, core.sdf]),
+            pipeline(core.explode),
+            pipelines([core.particles, core.particle_clear, core.particle_draw]),
+            pipeline(core.stats),
+            pipeline(core.checksum),
+            pipeline(core.integer_load).zip(pipeline(core.integer_store)),
+        )
+    });
+    let (
+        downsample,
+        minimap,
+        light,
+        field,
+        explode,
+        particle,
+        stats_pipeline,
+        checksum,
+        integer_storage,
+    ) = features.unwrap_or_default();
+    // Work held for a pipeline is dropped once it failed to compile.
+    let ids = render_data.core_pipelines;
+    let waiting = |pick: fn(CorePipelines) -> Vec<CachedComputePipelineId>| {
+        ids.is_none_or(|core| {
+            pick(core).into_iter().all(|id| {
+                !matches!(
+                    pipeline_cache.get_compute_pipeline_state(id),
+                    CachedPipelineState::Err(_)
+                )
+            })
+        })
+    };
+    let mut ops = std::mem::take(&mut render_data.held_ops);
+    ops.append(&mut render_data.ops);
+    let mut copies = std::mem::take(&mut render_data.copies);
+    let mut exports = std::mem::take(&mut render_data.exports);
+    if core.is_none() {
+        ops.retain_mut(|op| {
+            op.simulation = None;
+            op.integer = None;
+            op.has_edits()
+        });
+        // Edits, and the copies and readbacks that must see them, wait for
+        // the passes that apply them instead of being dropped.
+        if waiting(|core| {
+            vec![
+                core.stamp,
+                core.write,
+                core.begin_frame,
+                core.next_tick,
+                core.end_frame,
+                core.prepare_dispatch,
+            ]
+        }) {
+            render_data.held_ops = std::mem::take(&mut ops);
+            render_data.copies = std::mem::take(&mut copies);
+            render_data.exports = std::mem::take(&mut exports);
+        } else {
+            ops.clear();
+            copies.clear();
+            exports.clear();
         }
     }
+    // Chunks wait for their generator shader, or it would overwrite their edits.
+    let (ops, held_ops): (Vec<ChunkOps>, Vec<ChunkOps>) = ops.into_iter().partition(|op| {
+        !generation.is_generating(op.texture.id())
+            && (op.explosions.is_empty()
+                || explode.is_some()
+                || !waiting(|core| vec![core.explode]))
+    });
+    render_data.held_ops.extend(held_ops);
+    let downsamples = std::mem::take(&mut render_data.downsamples);
+    let fills = std::mem::take(&mut render_data.fills);
+    let minimap_draws = std::mem::take(&mut render_data.minimap_draws);
+    let lighting = std::mem::take(&mut render_data.lighting);
+    let sdf = std::mem::take(&mut render_data.sdf);
+    let particles = std::mem::take(&mut render_data.particles);
+    let stats = std::mem::take(&mut render_data.stats);
+    let checksums = std::mem::take(&mut render_data.checksums);
+    let ejects = std::mem::take(&mut render_data.ejects);
+    let maps = std::mem::take(&mut render_data.maps);
+    render_data.frame += 1;
+    let frame = render_data.frame;
+    let mut command_encoder =
+        render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+    for copy in copies.iter() {
+        copy.encode(&mut command_encoder);
+    }
+    let mut readbacks = Vec::new();
+    for (map, request, texture, size) in exports {
+        readbacks.push(PendingReadback::texture(
+            &render_device,
+            &mut command_encoder,
+            &texture,
+            URect::from_corners(UVec2::ZERO, size),
+            move |data| {
+                Box::new(move |world| {
+                    world.send_event(finish_export(map, request, size, data));
+                })
+            },
+        ));
+    }
+    for op in ops.iter() {
+        for &request in op.snapshots.iter() {
+            let map = op.map;
+            readbacks.push(PendingReadback::texture(
+                &render_device,
+                &mut command_encoder,
+                &op.texture,
+                request.rect,
+                move |data| {
+                    Box::new(move |world| complete_snapshot(world, map, request, Some(data)))
+                },
+            ));
+        }
+        for write in op.region_writes.iter() {
+            restore_texture_region(
+                &render_device,
+                &mut command_encoder,
+                &op.texture,
+                write.rect,
+                &write.data,
+            );
+        }
+    }
+    for (texture, rect, data) in fills.iter() {
+        restore_texture_region(&render_device, &mut command_encoder, texture, *rect, data);
+    }
 
-    for (chunk_pos, bind_groups) in render_data.bind_map_2.iter() {
-        for pipeline_id in (*render_data.shader_map_2.get(chunk_pos).unwrap()).iter() {
-            if let CachedPipelineState::Ok(_) =
-                pipeline_cache.get_compute_pipeline_state(pipeline_id.clone())
-            {
-                let pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline_id.clone())
-                    .unwrap();
-                for binds in bind_groups.iter() {
-                    let mut command_encoder =
-                        render_device.create_command_encoder(&CommandEncoderDescriptor::default());
-                    {
-                        let mut pass =
-                            command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
-                        pass.set_pipeline(pipeline);
-                        pass.set_bind_group(0, &binds.0, &[]);
-                        pass.dispatch_workgroups(binds.1.x / 8, binds.1.y / 8, 1);
-                    }
+    if let Some((stamp, write, [begin_frame, next_tick, end_frame, prepare_dispatch])) =
+        core.filter(|_| !ops.is_empty())
+    {
+        for (_, buffer) in ejects.iter() {
+            render_queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[0u32; 2]));
+        }
+        for op in ops.iter().filter(|op| op.wake) {
+            render_queue.write_buffer(
+                &op.changed,
+                0,
+                bytemuck::cast_slice(&full_chunk_bounds(op.chunk_size)),
+            );
+        }
+
+        let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        for op in ops.iter() {
+            pass.set_pipeline(begin_frame);
+            pass.set_bind_group(0, &op.dirty, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+
+            if let Some((binds, count)) = &op.writes {
+                pass.set_pipeline(write);
+                pass.set_bind_group(0, binds, &[]);
+                pass.dispatch_workgroups(count.div_ceil(64), 1, 1);
+            }
+
+            pass.set_pipeline(stamp);
+            for (binds, workgroups) in op.stamps.iter() {
+                pass.set_bind_group(0, binds, &[]);
+                pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+            }
 
-                    render_queue.submit(once(command_encoder.finish()));
+            if let Some(explode) = explode {
+                pass.set_pipeline(explode);
+                for (binds, workgroups) in op.explosions.iter() {
+                    pass.set_bind_group(0, binds, &[]);
+                    pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
                 }
             }
+
+            // Integer maps skip their simulation until the chunks can be
+            // copied to and from their `rgba8uint` textures.
+            let integer_workgroups = (op.chunk_size + UVec2::splat(7)) / 8;
+            let simulation = match (&op.integer, integer_storage) {
+                (Some(binds), Some((load, _))) => {
+                    pass.set_pipeline(load);
+                    pass.set_bind_group(0, binds, &[]);
+                    pass.dispatch_workgroups(integer_workgroups.x, integer_workgroups.y, 1);
+                    op.simulation.as_ref()
+                }
+                (Some(_), None) => None,
+                (None, _) => op.simulation.as_ref(),
+            };
+            for (step, (binds, passes)) in simulation.into_iter().flatten().enumerate() {
+                if step > 0 {
+                    pass.set_pipeline(next_tick);
+                    pass.set_bind_group(0, &op.dirty, &[]);
+                    pass.dispatch_workgroups(1, 1, 1);
+                }
+                for _ in 0..*passes {
+                    for pipeline_id in op.simulation_pipelines.iter() {
+                        let Some(pipeline) = pipeline(*pipeline_id) else {
+                            continue;
+                        };
+                        for phase in binds.iter() {
+                            pass.set_pipeline(prepare_dispatch);
+                            pass.set_bind_group(0, &op.dirty, &[]);
+                            pass.dispatch_workgroups(1, 1, 1);
+                            pass.set_pipeline(pipeline);
+                            pass.set_bind_group(0, phase, &[]);
+                            pass.dispatch_workgroups_indirect(&op.args, 0);
+                        }
+                    }
+                }
+            }
+            if let (Some(binds), Some((_, store))) = (&op.integer, integer_storage) {
+                pass.set_pipeline(store);
+                pass.set_bind_group(0, binds, &[]);
+                pass.dispatch_workgroups(integer_workgroups.x, integer_workgroups.y, 1);
+            }
+
+            pass.set_pipeline(end_frame);
+            pass.set_bind_group(0, &op.dirty, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
         }
+        drop(pass);
+
+        for (map, buffer) in ejects {
+            readbacks.push(PendingReadback::buffers(
+                &render_device,
+                &mut command_encoder,
+                &[&buffer],
+                buffer.size(),
+                move |data| {
+                    let particles = read_ejected(&data);
+                    Box::new(move |world| {
+                        if particles.is_empty() {
+                            return;
+                        }
+                        if let Some(mut pixel_map) = world.get_mut::<PixelMap>(map) {
+                            pixel_map.particles.emitted.push(particles);
+                        }
+                    })
+                },
+            ));
+        }
+    }
+    if let Some(particle) = particle {
+        encode_particles(&mut command_encoder, particle, &particles);
+    }
+    // Queries and refreshes wait for their own pipeline instead of being
+    // dropped. Lighting is redrawn every frame anyway.
+    match stats_pipeline {
+        Some(pipeline) => readbacks.extend(encode_stats(
+            &render_device,
+            &mut command_encoder,
+            pipeline,
+            stats,
+        )),
+        None if waiting(|core| vec![core.stats]) => render_data.stats = stats,
+        None => {}
+    }
+    match checksum {
+        Some(pipeline) => readbacks.extend(encode_checksums(
+            &render_device,
+            &mut command_encoder,
+            pipeline,
+            checksums,
+        )),
+        None if waiting(|core| vec![core.checksum]) => render_data.checksums = checksums,
+        None => {}
+    }
+    match downsample {
+        Some(pipeline) => encode_downsamples(&mut command_encoder, pipeline, &downsamples),
+        None if waiting(|core| vec![core.downsample]) => render_data.downsamples = downsamples,
+        None => {}
+    }
+    match minimap {
+        Some(pipeline) => encode_downsamples(&mut command_encoder, pipeline, &minimap_draws),
+        None if waiting(|core| vec![core.minimap]) => render_data.minimap_draws = minimap_draws,
+        None => {}
+    }
+    if let Some(pipelines) = light {
+        encode_lighting(&mut command_encoder, pipelines, &lighting);
+    }
+    match field {
+        Some(pipelines) => readbacks.extend(encode_sdf(
+            &render_device,
+            &mut command_encoder,
+            pipelines,
+            &sdf,
+        )),
+        None if waiting(|core| vec![core.sdf_seed, core.jump_flood, core.sdf]) => {
+            render_data.sdf = sdf
+        }
+        None => {}
     }
 
-    render_data.bind_map_2.clear();
-    render_data.bind_map.clear();
+    // Frames whose edits are held report their dirty rects once they run.
+    if core.is_some() && ops.is_empty() {
+        readback_sender.send(Box::new(move |world| {
+            for map in maps {
+                apply_dirty_bounds(world, map, frame, Vec::new());
+            }
+        }));
+    } else if core.is_some() {
+        // Mapped first so dirty rects arrive before the readbacks of the same frame.
+        let changed: Vec<&Buffer> = ops.iter().map(|op| &op.frame_changed).collect();
+        // Chunks only idle when their simulation ran, not while it compiles.
+        let chunks: Vec<(Entity, IVec2, bool)> = ops
+            .iter()
+            .map(|op| {
+                let simulated = op.simulation.is_some()
+                    && op
+                        .simulation_pipelines
+                        .iter()
+                        .any(|id| pipeline(*id).is_some());
+                (op.map, op.chunk_pos, simulated)
+            })
+            .collect();
+        readbacks.insert(
+            0,
+            PendingReadback::buffers(
+                &render_device,
+                &mut command_encoder,
+                &changed,
+                (DIRTY_WORDS * 4) as u64,
+                move |data| {
+                    let bounds: Vec<[i32; DIRTY_WORDS]> = data
+                        .chunks_exact(DIRTY_WORDS * 4)
+                        .map(|chunk| {
+                            std::array::from_fn(|i| {
+                                i32::from_le_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap())
+                            })
+                        })
+                        .collect();
+                    Box::new(move |world| {
+                        for map in maps {
+                            let map_bounds = chunks
+                                .iter()
+                                .zip(bounds.iter())
+                                .filter(|((chunk_map, ..), _)| *chunk_map == map)
+                                .map(|((_, chunk_pos, simulated), bounds)| {
+                                    (*chunk_pos, *bounds, *simulated)
+                                })
+                                .collect();
+                            apply_dirty_bounds(world, map, frame, map_bounds);
+                        }
+                    })
+                },
+            ),
+        );
+    }
+    render_queue.submit(once(command_encoder.finish()));
+    for readback in readbacks {
+        readback.map(&readback_sender);
+    }
 }
 
 struct PixelMapShaderLayoutInput {
     pub bind_group_layout: BindGroupLayout,
     pub bind_group_layout_2: BindGroupLayout,
+    pub write_layout: BindGroupLayout,
+    pub dirty_layout: BindGroupLayout,
+    pub downsample_layout: BindGroupLayout,
+    pub jump_flood_layout: BindGroupLayout,
+    pub lighting_seed_layout: BindGroupLayout,
+    pub lighting_layout: BindGroupLayout,
+    pub sdf_seed_layout: BindGroupLayout,
+    pub sdf_layout: BindGroupLayout,
+    pub explode_layout: BindGroupLayout,
+    pub particle_layout: BindGroupLayout,
+    pub particle_draw_layout: BindGroupLayout,
+    pub stats_layout: BindGroupLayout,
+    pub checksum_layout: BindGroupLayout,
+    pub integer_simulation_layout: BindGroupLayout,
+    pub integer_storage_layout: BindGroupLayout,
 }
 
 impl PixelMapShaderLayoutInput {
     pub fn new(device: &RenderDevice) -> Self {
+        let texture = |binding, access| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access,
+                format: TextureFormat::Rgba8Unorm,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let buffer = |binding, ty| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform = |binding| buffer(binding, BufferBindingType::Uniform);
+        let storage = |binding| buffer(binding, BufferBindingType::Storage { read_only: false });
+
         let bind_group_layout = device.create_bind_group_layout(
             Some("set_pixels_cpu Bind Group Layout"),
             &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadWrite,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
+                texture(0, StorageTextureAccess::ReadWrite),
+                uniform(1),
+                uniform(2),
+                uniform(3),
+                texture(4, StorageTextureAccess::ReadOnly),
+                uniform(5),
+                storage(6),
             ],
         );
 
         let bind_group_layout_2 = device.create_bind_group_layout(
             Some("set_pixels_cpu Bind Group Layout 2"),
+            &[
+                texture(0, StorageTextureAccess::ReadWrite),
+                uniform(1),
+                uniform(2),
+                uniform(3),
+                storage(4),
+                storage(5),
+                uniform(6),
+            ],
+        );
+
+        let integer_simulation_layout = device.create_bind_group_layout(
+            Some("pixel map integer simulation Bind Group Layout"),
             &[
                 BindGroupLayoutEntry {
                     binding: 0,
                     visibility: ShaderStages::COMPUTE,
                     ty: BindingType::StorageTexture {
                         access: StorageTextureAccess::ReadWrite,
-                        format: TextureFormat::Rgba8Unorm,
+                        format: TextureFormat::Rgba8Uint,
                         view_dimension: TextureViewDimension::D2,
                     },
                     count: None,
                 },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
+                uniform(1),
+                uniform(2),
+                uniform(3),
+                storage(4),
+                storage(5),
+                uniform(6),
             ],
         );
 
+        let write_layout = device.create_bind_group_layout(
+            Some("pixel map write Bind Group Layout"),
+            &[
+                texture(0, StorageTextureAccess::ReadWrite),
+                buffer(1, BufferBindingType::Storage { read_only: true }),
+                storage(2),
+            ],
+        );
+
+        let [lighting_seed_layout, lighting_layout] = lighting_bind_group_layouts(device);
+        let [sdf_seed_layout, sdf_layout] = sdf_bind_group_layouts(device);
+        let [particle_layout, particle_draw_layout] = particle_bind_group_layouts(device);
+
         Self {
             bind_group_layout,
             bind_group_layout_2,
+            write_layout,
+            dirty_layout: dirty_bind_group_layout(device),
+            downsample_layout: downsample_bind_group_layout(device),
+            jump_flood_layout: jump_flood_bind_group_layout(device),
+            lighting_seed_layout,
+            lighting_layout,
+            sdf_seed_layout,
+            sdf_layout,
+            explode_layout: explode_bind_group_layout(device),
+            particle_layout,
+            particle_draw_layout,
+            stats_layout: stats_bind_group_layout(device),
+            checksum_layout: checksum_bind_group_layout(device),
+            integer_simulation_layout,
+            integer_storage_layout: integer_storage_bind_group_layout(device),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dirty::texture_bounds_to_world;
+    use proptest::prelude::*;
+
+    fn chunk_sizes() -> impl Strategy<Value = UVec2> {
+        (1u32..64, 1u32..64).prop_map(|(x, y)| UVec2::new(x, y))
+    }
+
+    fn positions() -> impl Strategy<Value = IVec2> {
+        (-10_000i32..10_000, -10_000i32..10_000).prop_map(|(x, y)| IVec2::new(x, y))
+    }
+
+    proptest! {
+        #[test]
+        fn outer_and_inner_recompose_the_position(
+            position in positions(),
+            chunk_size in chunk_sizes(),
+        ) {
+            let outer = get_chunk_outer_i(position, chunk_size);
+            let inner = get_chunk_inner_i(position, chunk_size);
+            prop_assert_eq!(outer, position.div_euclid(chunk_size.as_ivec2()));
+            prop_assert!(inner.cmplt(chunk_size).all());
+            prop_assert_eq!(outer * chunk_size.as_ivec2() + inner.as_ivec2(), position);
+        }
+
+        #[test]
+        fn index_puts_the_top_row_first(
+            position in positions(),
+            chunk_size in chunk_sizes(),
+        ) {
+            let index = get_chunk_index_i(position, chunk_size);
+            prop_assert!(index < (chunk_size.x * chunk_size.y) as usize);
+            let above = position + IVec2::Y;
+            if get_chunk_outer_i(above, chunk_size) == get_chunk_outer_i(position, chunk_size) {
+                prop_assert_eq!(get_chunk_index_i(above, chunk_size) + chunk_size.x as usize, index);
+            }
+            let right = position + IVec2::X;
+            if get_chunk_outer_i(right, chunk_size) == get_chunk_outer_i(position, chunk_size) {
+                prop_assert_eq!(get_chunk_index_i(right, chunk_size), index + 1);
+            }
+        }
+
+        #[test]
+        fn stamp_rect_is_the_footprint_within_the_chunk(
+            position in (-200i32..200, -200i32..200).prop_map(|(x, y)| IVec2::new(x, y)),
+            size in (1u32..100, 1u32..100).prop_map(|(x, y)| UVec2::new(x, y)),
+            chunk_pos in (-4i32..4, -4i32..4).prop_map(|(x, y)| IVec2::new(x, y)),
+            chunk_size in chunk_sizes(),
+        ) {
+            let tex = PixelPositionedTexture {
+                position,
+                image: Handle::default(),
+                size,
+            };
+            let origin = chunk_pos * chunk_size.as_ivec2();
+            let footprint = IRect::from_corners(position, position + size.as_ivec2())
+                .intersect(IRect::from_corners(origin, origin + chunk_size.as_ivec2()));
+            let world = stamp_texture_rect(chunk_pos, chunk_size, &tex).and_then(|rect| {
+                let max = rect.max.as_ivec2() - IVec2::ONE;
+                let bounds = [rect.min.x as i32, rect.min.y as i32, max.x, max.y, 0];
+                texture_bounds_to_world(chunk_pos, chunk_size, bounds)
+            });
+            prop_assert_eq!(world, (!footprint.is_empty()).then_some(footprint));
         }
     }
 }
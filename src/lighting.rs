@@ -0,0 +1,701 @@
+use std::f32::consts::TAU;
+
+use bevy::asset::AssetPath;
+use bevy::image::ImageSampler;
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshVertexBufferLayoutRef;
+use bevy::render::render_asset::{RenderAssetUsages, RenderAssets};
+use bevy::render::render_resource::{
+    AsBindGroup, BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType,
+    BlendComponent, BlendFactor, BlendOperation, BlendState, BufferBindingType,
+    BufferInitDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor, ComputePipeline,
+    Extent3d, IntoBinding, RenderPipelineDescriptor, ShaderRef, ShaderStages,
+    SpecializedMeshPipelineError, StorageTextureAccess, TextureDimension, TextureFormat,
+    TextureUsages, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::texture::GpuImage;
+use bevy::sprite::{AlphaMode2d, Material2d, Material2dKey};
+
+use crate::export::filled_image;
+use crate::jump_flood::{jump_flood_bind_groups, jump_flood_cpu, pack_seed, unpack_seed, NO_SEED};
+use crate::{MinimapPalette, PixelMap, PixelMapMinimap, PixelMapShaderLayoutInput, ASSETS_PATH};
+
+/// Rays each texel casts to gather the light of emissive pixels.
+pub const EMISSIVE_RAYS: u32 = 16;
+/// How far, per channel, a light map texel may be from an [`EmissiveColor`]
+/// and still glow.
+pub const EMISSIVE_TOLERANCE: u8 = 8;
+
+/// A point light for [`PixelMapLighting`], placed by its [`GlobalTransform`]
+/// in the space the chunk sprites are drawn in.
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq)]
+#[reflect(Component)]
+pub struct PixelLight2d {
+    pub color: LinearRgba,
+    pub intensity: f32,
+    /// Map pixels the light reaches.
+    pub radius: f32,
+}
+
+impl Default for PixelLight2d {
+    fn default() -> Self {
+        PixelLight2d {
+            color: LinearRgba::WHITE,
+            intensity: 1.0,
+            radius: 64.0,
+        }
+    }
+}
+
+/// Pixels of this color glow and light up their surroundings.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+pub struct EmissiveColor {
+    pub color: [u8; 4],
+    pub light: LinearRgba,
+}
+
+/// Light map of `rect` of a [`PixelMap`], where pixels of the map occlude.
+///
+/// The rect is looked at through a [`PixelMapMinimap`] kept on the same
+/// entity: texels with at least half of their pixels opaque are solid. Every
+/// frame a jump flood builds the distance to the nearest solid texel, along
+/// which shadows of the [`PixelLight2d`]s are traced and the light of
+/// emissive texels is gathered. With [`PixelMapGpuComputePlugin`](crate::PixelMapGpuComputePlugin)
+/// the light map is multiplied over everything drawn below `composite_z`.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, from_reflect = false)]
+pub struct PixelMapLighting {
+    pub map: Entity,
+    pub rect: IRect,
+    /// Map pixels per light map texel along each axis.
+    pub downscale: u32,
+    pub ambient: LinearRgba,
+    pub emissive: Vec<EmissiveColor>,
+    /// Map pixels emissive texels reach.
+    pub emissive_range: f32,
+    /// Higher values give sharper shadow edges.
+    pub shadow_hardness: f32,
+    pub composite_z: f32,
+    pub light_map: Handle<Image>,
+    #[reflect(ignore)]
+    seeds: [Handle<Image>; 2],
+    #[reflect(ignore)]
+    size: UVec2,
+    #[reflect(ignore)]
+    pub(crate) composite: Option<Entity>,
+}
+
+/// One frame of lighting, with lights and colors laid out like
+/// `lighting.wgsl` reads them.
+#[derive(Clone, Debug)]
+pub(crate) struct LightingPass {
+    pub occupancy: Handle<Image>,
+    pub seeds: [Handle<Image>; 2],
+    pub light_map: Handle<Image>,
+    pub size: UVec2,
+    /// x, y and radius in texels, then the color times the intensity.
+    pub lights: Vec<[f32; 8]>,
+    /// The packed color, then the bits of the light.
+    pub emissive: Vec<[u32; 8]>,
+    /// Ambient light; light, emissive and ray counts; emissive range and
+    /// shadow hardness.
+    pub params: [u32; 12],
+}
+
+impl PixelMapLighting {
+    pub fn new(map: Entity, rect: IRect, downscale: u32, images: &mut Assets<Image>) -> Self {
+        PixelMapLighting {
+            map,
+            rect,
+            downscale,
+            ambient: LinearRgba::rgb(0.1, 0.1, 0.1),
+            emissive: Vec::new(),
+            emissive_range: 32.0,
+            shadow_hardness: 8.0,
+            composite_z: 10.0,
+            light_map: images.add(filled_image(UVec2::ONE, [255; 4])),
+            seeds: [
+                images.add(seed_image(UVec2::ONE)),
+                images.add(seed_image(UVec2::ONE)),
+            ],
+            size: UVec2::ZERO,
+            composite: None,
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: LinearRgba) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn with_emissive(mut self, color: [u8; 4], light: LinearRgba) -> Self {
+        self.emissive.push(EmissiveColor { color, light });
+        self
+    }
+
+    /// Size of the light map, one texel per started block of the rect.
+    pub fn size(&self) -> UVec2 {
+        let downscale = self.downscale.max(1);
+        (self.rect.size().as_uvec2() + UVec2::splat(downscale - 1)) / downscale
+    }
+
+    /// Where a point drawn at `local`, relative to the map root, lies on the
+    /// light map in texels.
+    fn texel_position(&self, local: Vec2, chunk_size: UVec2) -> Vec2 {
+        // Chunk sprites are centered on the origin of their chunk.
+        let pixel = local + chunk_size.as_vec2() / 2.;
+        Vec2::new(
+            pixel.x - self.rect.min.x as f32,
+            self.rect.max.y as f32 - pixel.y,
+        ) / self.downscale.max(1) as f32
+    }
+}
+
+fn seed_image(size: UVec2) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &NO_SEED.to_le_bytes(),
+        TextureFormat::R32Uint,
+        RenderAssetUsages::all(),
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
+    image
+}
+
+pub(crate) fn update_lighting(
+    mut commands: Commands,
+    mut lighting_query: Query<(Entity, &mut PixelMapLighting, Option<&mut PixelMapMinimap>)>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+    lights: Query<(&PixelLight2d, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (entity, mut lighting, minimap) in lighting_query.iter_mut() {
+        let Ok(mut pixel_map) = pixel_map_query.get_mut(lighting.map) else {
+            continue;
+        };
+        let downscale = lighting.downscale.max(1);
+        let palette = MinimapPalette::Colors;
+        let Some(mut minimap) = minimap else {
+            let minimap = PixelMapMinimap::new(lighting.map, lighting.rect, downscale, &mut images)
+                .with_palette(palette);
+            commands.entity(entity).insert(minimap);
+            continue;
+        };
+        if minimap.map != lighting.map
+            || minimap.rect != lighting.rect
+            || minimap.downscale != downscale
+            || minimap.palette != palette
+        {
+            minimap.map = lighting.map;
+            minimap.rect = lighting.rect;
+            minimap.downscale = downscale;
+            minimap.palette = palette;
+        }
+        if lighting.rect.is_empty() {
+            continue;
+        }
+
+        let size = lighting.size();
+        if lighting.size != size {
+            let lighting = lighting.as_mut();
+            let mut light_map = filled_image(size, [255; 4]);
+            light_map.sampler = ImageSampler::linear();
+            images.insert(&lighting.light_map, light_map);
+            for seeds in lighting.seeds.iter() {
+                images.insert(seeds, seed_image(size));
+            }
+            lighting.size = size;
+        }
+
+        let root = transforms
+            .get(pixel_map.root_entity)
+            .map_or(Affine3A::IDENTITY, |transform| transform.affine())
+            .inverse();
+        let scale = downscale as f32;
+        let lights: Vec<[f32; 8]> = lights
+            .iter()
+            .map(|(light, transform)| {
+                let local = root.transform_point3(transform.translation()).truncate();
+                let position = lighting.texel_position(local, pixel_map.chunk_size);
+                let color = light.color * light.intensity;
+                [
+                    position.x,
+                    position.y,
+                    light.radius / scale,
+                    0.,
+                    color.red,
+                    color.green,
+                    color.blue,
+                    0.,
+                ]
+            })
+            .collect();
+        let emissive = lighting
+            .emissive
+            .iter()
+            .map(|emissive| {
+                [
+                    u32::from_le_bytes(emissive.color),
+                    0,
+                    0,
+                    0,
+                    emissive.light.red.to_bits(),
+                    emissive.light.green.to_bits(),
+                    emissive.light.blue.to_bits(),
+                    0,
+                ]
+            })
+            .collect();
+        let ambient = lighting.ambient;
+        let light_count = lights.len() as u32;
+        pixel_map.lighting_passes.push(LightingPass {
+            occupancy: minimap.image.clone(),
+            seeds: lighting.seeds.clone(),
+            light_map: lighting.light_map.clone(),
+            size,
+            lights,
+            emissive,
+            params: [
+                ambient.red.to_bits(),
+                ambient.green.to_bits(),
+                ambient.blue.to_bits(),
+                0,
+                light_count,
+                lighting.emissive.len() as u32,
+                EMISSIVE_RAYS,
+                0,
+                (lighting.emissive_range / scale).to_bits(),
+                lighting.shadow_hardness.to_bits(),
+                0,
+                0,
+            ],
+        });
+    }
+}
+
+/// Solid texels seed the jump flood, like `lighting_seed.wgsl`.
+fn seed_cpu(occupancy: &[u8], size: UVec2) -> Vec<u32> {
+    (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y)))
+        .map(|texel| {
+            let ind = (texel.y * size.x + texel.x) as usize * 4;
+            if occupancy[ind + 3] >= 128 {
+                pack_seed(texel)
+            } else {
+                NO_SEED
+            }
+        })
+        .collect()
+}
+
+/// Distances and occupancy of a light map, as the light pass reads them.
+struct LightingField<'a> {
+    occupancy: &'a [u8],
+    seeds: &'a [u32],
+    size: UVec2,
+    emissive: &'a [[u32; 8]],
+}
+
+impl LightingField<'_> {
+    fn texel(&self, position: Vec2) -> Option<UVec2> {
+        let texel = position.floor();
+        (texel.cmpge(Vec2::ZERO).all() && texel.cmplt(self.size.as_vec2()).all())
+            .then(|| texel.as_uvec2())
+    }
+
+    fn color(&self, texel: UVec2) -> [u8; 4] {
+        let ind = (texel.y * self.size.x + texel.x) as usize * 4;
+        self.occupancy[ind..ind + 4].try_into().unwrap()
+    }
+
+    fn solid(&self, texel: UVec2) -> bool {
+        self.color(texel)[3] >= 128
+    }
+
+    /// Distance from `position` to the center of the nearest solid texel.
+    fn distance(&self, position: Vec2) -> f32 {
+        let Some(texel) = self.texel(position) else {
+            return f32::MAX;
+        };
+        let seed = self.seeds[(texel.y * self.size.x + texel.x) as usize];
+        if seed == NO_SEED {
+            return f32::MAX;
+        }
+        (unpack_seed(seed).as_vec2() + 0.5).distance(position)
+    }
+
+    fn emission(&self, texel: UVec2) -> Option<Vec3> {
+        let color = self.color(texel);
+        self.emissive.iter().find_map(|emissive| {
+            let matches = emissive[0]
+                .to_le_bytes()
+                .iter()
+                .zip(color)
+                .all(|(&a, b)| a.abs_diff(b) <= EMISSIVE_TOLERANCE);
+            matches.then(|| {
+                Vec3::new(
+                    f32::from_bits(emissive[4]),
+                    f32::from_bits(emissive[5]),
+                    f32::from_bits(emissive[6]),
+                )
+            })
+        })
+    }
+
+    /// How much of a light `length` away along `direction` reaches `origin`.
+    fn shadow(&self, origin: Vec2, direction: Vec2, length: f32, start: f32, hardness: f32) -> f32 {
+        let mut t = start;
+        let mut shade = 1f32;
+        while t < length {
+            let position = origin + direction * t;
+            if self.texel(position).is_some_and(|texel| self.solid(texel)) {
+                return 0.;
+            }
+            let clearance = (self.distance(position) - 0.71).max(0.);
+            shade = shade.min(hardness * clearance / t);
+            t += clearance.max(0.5);
+        }
+        shade.clamp(0., 1.)
+    }
+
+    /// Light of the first emissive texel hit along `direction`.
+    fn gather(&self, origin: Vec2, direction: Vec2, range: f32, start: f32) -> Vec3 {
+        let mut t = start;
+        while t < range {
+            let position = origin + direction * t;
+            let Some(texel) = self.texel(position) else {
+                break;
+            };
+            if self.solid(texel) {
+                return self
+                    .emission(texel)
+                    .map_or(Vec3::ZERO, |light| light * (1. - t / range));
+            }
+            t += (self.distance(position) - 0.71).max(0.5);
+        }
+        Vec3::ZERO
+    }
+}
+
+/// Same per texel hash as `lighting.wgsl`, to rotate the emissive rays.
+fn texel_hash(texel: UVec2) -> u32 {
+    let mut hash = texel.x.wrapping_mul(73856093) ^ texel.y.wrapping_mul(19349663);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1e995);
+    hash ^ hash >> 15
+}
+
+/// Same as `lighting.wgsl`.
+fn light_cpu(field: &LightingField, pass: &LightingPass) -> Vec<u8> {
+    let params = pass.params.map(f32::from_bits);
+    let ambient = Vec3::new(params[0], params[1], params[2]);
+    let rays = pass.params[6];
+    let range = params[8];
+    let hardness = params[9];
+    let mut data = Vec::with_capacity(field.size.element_product() as usize * 4);
+    for y in 0..field.size.y {
+        for x in 0..field.size.x {
+            let texel = UVec2::new(x, y);
+            let origin = texel.as_vec2() + 0.5;
+            let solid = field.solid(texel);
+            // Solid texels are lit from the outside of their surface.
+            let start = if solid { 1.5 } else { 1.0 };
+            let mut light = ambient;
+            if solid {
+                light += field.emission(texel).unwrap_or(Vec3::ZERO);
+            }
+            for source in pass.lights.iter() {
+                let to_light = Vec2::new(source[0], source[1]) - origin;
+                let length = to_light.length();
+                let radius = source[2];
+                if length >= radius {
+                    continue;
+                }
+                let falloff = (1. - length / radius).powi(2);
+                let direction = to_light / length.max(f32::EPSILON);
+                let shadow = field.shadow(origin, direction, length, start, hardness);
+                light += Vec3::new(source[4], source[5], source[6]) * falloff * shadow;
+            }
+            if !field.emissive.is_empty() && range > 0. {
+                let jitter = (texel_hash(texel) & 0xffff) as f32 / 65536.;
+                let mut gathered = Vec3::ZERO;
+                for ray in 0..rays {
+                    let angle = (ray as f32 + jitter) * TAU / rays as f32;
+                    let direction = Vec2::new(angle.cos(), angle.sin());
+                    gathered += field.gather(origin, direction, range, start);
+                }
+                light += gathered / rays as f32;
+            }
+            let light = light.clamp(Vec3::ZERO, Vec3::ONE) * 255.;
+            data.extend([light.x, light.y, light.z].map(|channel| channel.round() as u8));
+            data.push(255);
+        }
+    }
+    data
+}
+
+/// The CPU backend's lighting, after this frame's minimap updates.
+pub(crate) fn apply_lighting_cpu(pixel_map: &mut PixelMap, images: &mut Assets<Image>) {
+    for pass in std::mem::take(&mut pixel_map.lighting_passes) {
+        let Some(occupancy) = images.get(&pass.occupancy) else {
+            continue;
+        };
+        if occupancy.size() != pass.size {
+            continue;
+        }
+        let seeds = jump_flood_cpu(seed_cpu(&occupancy.data, pass.size), pass.size);
+        let field = LightingField {
+            occupancy: &occupancy.data,
+            seeds: &seeds,
+            size: pass.size,
+            emissive: &pass.emissive,
+        };
+        let data = light_cpu(&field, &pass);
+        if let Some(light_map) = images.get_mut(&pass.light_map) {
+            light_map.data = data;
+        }
+    }
+}
+
+/// Bind group layouts of the seed and light passes of `lighting.wgsl`.
+pub(crate) fn lighting_bind_group_layouts(device: &RenderDevice) -> [BindGroupLayout; 2] {
+    let texture = |binding, access, format| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    let buffer = |binding, ty| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let storage = BufferBindingType::Storage { read_only: true };
+    [
+        device.create_bind_group_layout(
+            Some("pixel map lighting seed Bind Group Layout"),
+            &[
+                texture(0, StorageTextureAccess::ReadOnly, TextureFormat::Rgba8Unorm),
+                texture(1, StorageTextureAccess::WriteOnly, TextureFormat::R32Uint),
+            ],
+        ),
+        device.create_bind_group_layout(
+            Some("pixel map lighting Bind Group Layout"),
+            &[
+                texture(0, StorageTextureAccess::ReadOnly, TextureFormat::Rgba8Unorm),
+                texture(1, StorageTextureAccess::ReadOnly, TextureFormat::R32Uint),
+                texture(
+                    2,
+                    StorageTextureAccess::WriteOnly,
+                    TextureFormat::Rgba8Unorm,
+                ),
+                buffer(3, storage),
+                buffer(4, storage),
+                buffer(5, BufferBindingType::Uniform),
+            ],
+        ),
+    ]
+}
+
+/// The bind groups of one [`LightingPass`] on the GPU.
+pub(crate) struct LightingDispatch {
+    seed: BindGroup,
+    jump_flood: Vec<BindGroup>,
+    light: BindGroup,
+    workgroups: UVec2,
+}
+
+pub(crate) fn prepare_lighting(
+    device: &RenderDevice,
+    layouts: &PixelMapShaderLayoutInput,
+    gpu_images: &RenderAssets<GpuImage>,
+    pass: &LightingPass,
+) -> Option<LightingDispatch> {
+    let occupancy = gpu_images.get(&pass.occupancy)?;
+    let light_map = gpu_images.get(&pass.light_map)?;
+    let seeds = [
+        gpu_images.get(&pass.seeds[0])?,
+        gpu_images.get(&pass.seeds[1])?,
+    ];
+    if occupancy.size != pass.size || light_map.size != pass.size {
+        return None;
+    }
+    let seed = device.create_bind_group(
+        "pixel map lighting seed bind group",
+        &layouts.lighting_seed_layout,
+        &BindGroupEntries::sequential((
+            occupancy.texture_view.into_binding(),
+            seeds[0].texture_view.into_binding(),
+        )),
+    );
+    let (jump_flood, result) = jump_flood_bind_groups(
+        device,
+        &layouts.jump_flood_layout,
+        [&seeds[0].texture_view, &seeds[1].texture_view],
+        pass.size,
+    );
+    // Storage buffers can't be empty.
+    let lights = device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("lights_buffer"),
+        contents: bytemuck::cast_slice(if pass.lights.is_empty() {
+            &[[0f32; 8]]
+        } else {
+            &pass.lights[..]
+        }),
+        usage: BufferUsages::STORAGE,
+    });
+    let emissive = device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("emissive_colors_buffer"),
+        contents: bytemuck::cast_slice(if pass.emissive.is_empty() {
+            &[[0u32; 8]]
+        } else {
+            &pass.emissive[..]
+        }),
+        usage: BufferUsages::STORAGE,
+    });
+    let params = device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("lighting_params_buffer"),
+        contents: bytemuck::cast_slice(&pass.params),
+        usage: BufferUsages::UNIFORM,
+    });
+    let light = device.create_bind_group(
+        "pixel map lighting bind group",
+        &layouts.lighting_layout,
+        &BindGroupEntries::sequential((
+            occupancy.texture_view.into_binding(),
+            seeds[result].texture_view.into_binding(),
+            light_map.texture_view.into_binding(),
+            lights.as_entire_binding(),
+            emissive.as_entire_binding(),
+            params.as_entire_binding(),
+        )),
+    );
+    Some(LightingDispatch {
+        seed,
+        jump_flood,
+        light,
+        workgroups: (pass.size + UVec2::splat(7)) / 8,
+    })
+}
+
+/// Seeds, floods and lights, after the minimaps the lighting reads were drawn.
+pub(crate) fn encode_lighting(
+    encoder: &mut CommandEncoder,
+    pipelines: [&ComputePipeline; 3],
+    dispatches: &[LightingDispatch],
+) {
+    if dispatches.is_empty() {
+        return;
+    }
+    let [seed, jump_flood, light] = pipelines;
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+    for dispatch in dispatches {
+        let workgroups = dispatch.workgroups;
+        pass.set_pipeline(seed);
+        pass.set_bind_group(0, &dispatch.seed, &[]);
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+        pass.set_pipeline(jump_flood);
+        for binds in dispatch.jump_flood.iter() {
+            pass.set_bind_group(0, binds, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+        }
+        pass.set_pipeline(light);
+        pass.set_bind_group(0, &dispatch.light, &[]);
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+    }
+}
+
+/// Multiplies what is drawn below it with a light map.
+#[derive(Asset, TypePath, AsBindGroup, Clone, Debug)]
+pub struct LightCompositeMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub light_map: Handle<Image>,
+}
+
+impl Material2d for LightCompositeMaterial {
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Path(AssetPath::from(ASSETS_PATH.join("light_composite.wgsl")))
+    }
+
+    fn alpha_mode(&self) -> AlphaMode2d {
+        AlphaMode2d::Blend
+    }
+
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: Material2dKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let multiply = BlendComponent {
+            src_factor: BlendFactor::Dst,
+            dst_factor: BlendFactor::Zero,
+            operation: BlendOperation::Add,
+        };
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            for target in fragment.targets.iter_mut().flatten() {
+                target.blend = Some(BlendState {
+                    color: multiply,
+                    alpha: BlendComponent::OVER,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Spawns the quads that composite light maps, and keeps them over their rect.
+pub(crate) fn composite_lighting(
+    mut commands: Commands,
+    mut lighting_query: Query<&mut PixelMapLighting>,
+    pixel_map_query: Query<&PixelMap>,
+    mut transforms: Query<&mut Transform>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<LightCompositeMaterial>>,
+) {
+    for mut lighting in lighting_query.iter_mut() {
+        let Ok(pixel_map) = pixel_map_query.get(lighting.map) else {
+            continue;
+        };
+        // Chunk sprites are centered on the origin of their chunk.
+        let center = (lighting.rect.min + lighting.rect.max).as_vec2() / 2.
+            - pixel_map.chunk_size.as_vec2() / 2.;
+        let transform = Transform::from_xyz(center.x, center.y, lighting.composite_z)
+            .with_scale(lighting.rect.size().as_vec2().extend(1.));
+        if let Some(composite) = lighting.composite {
+            if let Ok(mut current) = transforms.get_mut(composite) {
+                current.set_if_neq(transform);
+                continue;
+            }
+        }
+        let composite = commands
+            .spawn((
+                Mesh2d(meshes.add(Rectangle::new(1., 1.))),
+                MeshMaterial2d(materials.add(LightCompositeMaterial {
+                    light_map: lighting.light_map.clone(),
+                })),
+                transform,
+            ))
+            .id();
+        commands.entity(pixel_map.root_entity).add_child(composite);
+        lighting.bypass_change_detection().composite = Some(composite);
+    }
+}
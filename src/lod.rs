@@ -0,0 +1,389 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupLayout, BindGroupLayoutEntry, BindingType, BufferBindingType,
+    CommandEncoder, ComputePassDescriptor, ComputePipeline, ShaderStages, StorageTextureAccess,
+    TextureFormat, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+
+use crate::export::{copy_rows, downscale_image, filled_image};
+use crate::{ChunkDirty, PixelMap};
+
+/// Downsampled overview of a map, see [`PixelMap::enable_lod`].
+///
+/// At level `n`, each tile covers `2^n` by `2^n` chunks at the resolution of
+/// one chunk, so zooming out by a factor of two draws a quarter of the
+/// sprites. Tiles of the level in use are regenerated whenever a
+/// [`ChunkDirty`] rect reports a change to one of their chunks, the others
+/// once they are switched to.
+#[derive(Clone, Debug, Default)]
+pub struct PixelMapLod {
+    levels: u32,
+    level: u32,
+    forced_level: Option<u32>,
+    chunks: HashSet<IVec2>,
+    /// Tiles of levels `1..=levels`.
+    tiles: Vec<HashMap<IVec2, LodTile>>,
+    stale: Vec<HashSet<IVec2>>,
+    pub(crate) updates: Vec<LodUpdate>,
+}
+
+#[derive(Clone, Debug)]
+struct LodTile {
+    image: Handle<Image>,
+    entity: Entity,
+    chunks: u32,
+}
+
+/// Sprite drawing a tile of a [`PixelMapLod`] level instead of its chunks.
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct PixelLodTile {
+    pub level: u32,
+    pub position: IVec2,
+}
+
+/// Redraws a chunk's part of a tile, from the chunk image or, once the chunk
+/// is gone, in the default chunk color.
+#[derive(Clone, Debug)]
+pub(crate) struct LodUpdate {
+    pub source: Option<Handle<Image>>,
+    pub tile: Handle<Image>,
+    pub origin: UVec2,
+    pub factor: u32,
+}
+
+impl PixelMapLod {
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+
+    /// Level drawn, 0 when the chunks themselves are.
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn forced_level(&self) -> Option<u32> {
+        self.forced_level
+    }
+
+    pub fn tile_count(&self, level: u32) -> usize {
+        level
+            .checked_sub(1)
+            .and_then(|index| self.tiles.get(index as usize))
+            .map_or(0, HashMap::len)
+    }
+}
+
+impl PixelMap {
+    /// Keeps `levels` downsampled overviews of the map and draws them in place
+    /// of the chunks when the camera is zoomed out far enough that a screen
+    /// pixel covers `2^level` map pixels. Levels are capped so that the chunk
+    /// size divides evenly.
+    pub fn enable_lod(&mut self, levels: u32) {
+        let cap = self
+            .chunk_size
+            .x
+            .trailing_zeros()
+            .min(self.chunk_size.y.trailing_zeros());
+        self.lod.get_or_insert_with(PixelMapLod::default).levels = levels.min(cap);
+    }
+
+    /// Goes back to drawing the chunks. The tiles are removed in the next
+    /// frame.
+    pub fn disable_lod(&mut self) {
+        if let Some(lod) = self.lod.as_mut() {
+            lod.levels = 0;
+        }
+    }
+
+    pub fn lod(&self) -> Option<&PixelMapLod> {
+        self.lod.as_ref().filter(|lod| lod.levels > 0)
+    }
+
+    /// Draws `level` regardless of the camera, or picks it from the camera
+    /// again with `None`.
+    pub fn force_lod_level(&mut self, level: Option<u32>) {
+        if let Some(lod) = self.lod.as_mut() {
+            lod.forced_level = level;
+        }
+    }
+
+    fn spawn_lod_tile(
+        &self,
+        level: u32,
+        position: IVec2,
+        visible: bool,
+        commands: &mut Commands,
+        images: &mut Assets<Image>,
+    ) -> LodTile {
+        let factor: u32 = 1 << level;
+        let mut image = filled_image(self.chunk_size, self.default_chunk_color);
+        image.sampler = self.empty_texture.sampler.clone();
+        let image = images.add(image);
+        // Chunk sprites are centered on the origin of their chunk.
+        let center = (position * factor as i32 * self.chunk_size.as_ivec2()).as_vec2()
+            + (self.chunk_size * (factor - 1)).as_vec2() / 2.;
+        let entity = commands
+            .spawn((
+                Sprite {
+                    image: image.clone(),
+                    custom_size: Some((self.chunk_size * factor).as_vec2()),
+                    ..default()
+                },
+                Transform::from_xyz(center.x, center.y, 0.0),
+                lod_visibility(visible),
+                PixelLodTile { level, position },
+            ))
+            .id();
+        commands.entity(self.root_entity).add_child(entity);
+        LodTile {
+            image,
+            entity,
+            chunks: 0,
+        }
+    }
+
+    /// Keeps the tiles in step with the chunks, switches to `level` and queues
+    /// the tile updates of the level drawn, for the chunks in `changed` and
+    /// those added or removed.
+    fn update_lod(
+        &mut self,
+        level: u32,
+        mut changed: HashSet<IVec2>,
+        commands: &mut Commands,
+        images: &mut Assets<Image>,
+    ) {
+        let Some(mut lod) = self.lod.take() else {
+            return;
+        };
+        if lod.tiles.len() != lod.levels as usize {
+            for tile in lod.tiles.drain(..).flat_map(HashMap::into_values) {
+                commands.entity(tile.entity).despawn_recursive();
+                images.remove(tile.image.id());
+            }
+            lod.chunks.clear();
+            lod.tiles = vec![HashMap::new(); lod.levels as usize];
+            lod.stale = vec![HashSet::new(); lod.levels as usize];
+        }
+        let level = lod.forced_level.unwrap_or(level).min(lod.levels);
+
+        let added: Vec<IVec2> = self
+            .positions
+            .keys()
+            .filter(|chunk_pos| !lod.chunks.contains(*chunk_pos))
+            .copied()
+            .collect();
+        let removed: Vec<IVec2> = lod
+            .chunks
+            .iter()
+            .filter(|chunk_pos| !self.positions.contains_key(*chunk_pos))
+            .copied()
+            .collect();
+        for &chunk_pos in added.iter() {
+            lod.chunks.insert(chunk_pos);
+            changed.insert(chunk_pos);
+            for tile_level in 1..=lod.levels {
+                let position = chunk_pos.div_euclid(IVec2::splat(1 << tile_level));
+                let tiles = &mut lod.tiles[tile_level as usize - 1];
+                let tile = tiles.entry(position).or_insert_with(|| {
+                    self.spawn_lod_tile(tile_level, position, tile_level == level, commands, images)
+                });
+                tile.chunks += 1;
+            }
+            if level > 0 {
+                if let Some(entity) = self.chunk_entity(chunk_pos) {
+                    commands.entity(entity).insert(Visibility::Hidden);
+                }
+            }
+        }
+        for chunk_pos in removed {
+            lod.chunks.remove(&chunk_pos);
+            for tile_level in 1..=lod.levels {
+                let position = chunk_pos.div_euclid(IVec2::splat(1 << tile_level));
+                let tiles = &mut lod.tiles[tile_level as usize - 1];
+                let Some(tile) = tiles.get_mut(&position) else {
+                    continue;
+                };
+                tile.chunks -= 1;
+                if tile.chunks == 0 {
+                    commands.entity(tile.entity).despawn_recursive();
+                    images.remove(tile.image.id());
+                    tiles.remove(&position);
+                } else {
+                    changed.insert(chunk_pos);
+                }
+            }
+        }
+        for stale in lod.stale.iter_mut() {
+            stale.extend(changed.iter().copied());
+        }
+
+        if level != lod.level {
+            for &entity in self.chunk_entities.iter() {
+                commands.entity(entity).insert(lod_visibility(level == 0));
+            }
+            for (index, tiles) in lod.tiles.iter().enumerate() {
+                let tile_level = index as u32 + 1;
+                if tile_level == lod.level || tile_level == level {
+                    for tile in tiles.values() {
+                        commands
+                            .entity(tile.entity)
+                            .insert(lod_visibility(tile_level == level));
+                    }
+                }
+            }
+            lod.level = level;
+        }
+
+        if level > 0 {
+            let factor = 1 << level;
+            let cell = self.chunk_size / factor;
+            let index = level as usize - 1;
+            for chunk_pos in std::mem::take(&mut lod.stale[index]) {
+                let tile_pos = chunk_pos.div_euclid(IVec2::splat(factor as i32));
+                let Some(tile) = lod.tiles[index].get(&tile_pos) else {
+                    continue;
+                };
+                let local = (chunk_pos - tile_pos * factor as i32).as_uvec2();
+                lod.updates.push(LodUpdate {
+                    source: self
+                        .positions
+                        .get(&chunk_pos)
+                        .map(|&index| self.image_data[index].clone()),
+                    tile: tile.image.clone(),
+                    origin: UVec2::new(local.x, factor - 1 - local.y) * cell,
+                    factor,
+                });
+            }
+        }
+        self.lod = Some(lod);
+    }
+}
+
+fn lod_visibility(visible: bool) -> Visibility {
+    if visible {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    }
+}
+
+/// Map pixels covered by one screen pixel of the first active orthographic
+/// camera.
+fn map_pixels_per_screen_pixel(
+    cameras: &Query<(&Camera, &OrthographicProjection)>,
+    scale: f32,
+) -> Option<f32> {
+    cameras
+        .iter()
+        .filter(|(camera, _)| camera.is_active)
+        .find_map(|(camera, projection)| {
+            let viewport = camera.logical_viewport_size()?;
+            Some(projection.area.width() / viewport.x / scale)
+        })
+}
+
+pub(crate) fn update_lod(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut dirty: EventReader<ChunkDirty>,
+    transforms: Query<&GlobalTransform>,
+    cameras: Query<(&Camera, &OrthographicProjection)>,
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let dirty: Vec<&ChunkDirty> = dirty.read().collect();
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        if pixel_map.lod.is_none() {
+            continue;
+        }
+        let changed = dirty
+            .iter()
+            .filter(|event| event.map == map)
+            .map(|event| event.chunk_pos)
+            .collect();
+        let scale = transforms
+            .get(pixel_map.root_entity)
+            .map_or(1., |transform| transform.scale().x.abs());
+        let level = map_pixels_per_screen_pixel(&cameras, scale)
+            .filter(|density| density.is_finite() && *density >= 1.)
+            .map_or(0, |density| density.log2().floor() as u32);
+        pixel_map.update_lod(level, changed, &mut commands, &mut images);
+    }
+}
+
+pub(crate) fn downsample_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let texture = |binding, access| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access,
+            format: TextureFormat::Rgba8Unorm,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map downsample Bind Group Layout"),
+        &[
+            texture(0, StorageTextureAccess::ReadOnly),
+            texture(1, StorageTextureAccess::WriteOnly),
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    )
+}
+
+/// The CPU backend's tile updates, after this frame's edits.
+pub(crate) fn apply_lod_cpu(pixel_map: &mut PixelMap, images: &mut Assets<Image>) {
+    let Some(lod) = pixel_map.lod.as_mut() else {
+        return;
+    };
+    let chunk_size = pixel_map.chunk_size;
+    for update in std::mem::take(&mut lod.updates) {
+        let cell = chunk_size / update.factor;
+        let data = match update.source.as_ref().and_then(|source| images.get(source)) {
+            Some(source) => downscale_image(source, update.factor).data,
+            None => pixel_map
+                .default_chunk_color
+                .repeat(cell.element_product() as usize),
+        };
+        let Some(tile) = images.get_mut(&update.tile) else {
+            continue;
+        };
+        copy_rows(
+            &data,
+            cell.x,
+            URect::from_corners(UVec2::ZERO, cell),
+            &mut tile.data,
+            chunk_size.x,
+            update.origin,
+        );
+    }
+}
+
+/// Downsamples chunks into tiles or minimaps, after the chunks were edited.
+pub(crate) fn encode_downsamples(
+    encoder: &mut CommandEncoder,
+    pipeline: &ComputePipeline,
+    downsamples: &[(BindGroup, UVec2)],
+) {
+    if downsamples.is_empty() {
+        return;
+    }
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+    pass.set_pipeline(pipeline);
+    for (binds, workgroups) in downsamples {
+        pass.set_bind_group(0, binds, &[]);
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+    }
+}
@@ -0,0 +1,308 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy::utils::hashbrown::HashSet;
+
+use crate::clipboard::chunks_in_rect;
+use crate::export::filled_image;
+use crate::PixelMap;
+
+/// Colors a [`PixelMapMinimap`] is drawn in.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MinimapPalette {
+    /// The averaged colors of the map.
+    #[default]
+    Colors,
+    /// `solid` where at least half of the averaged pixels are opaque, `empty`
+    /// elsewhere.
+    Solid { solid: [u8; 4], empty: [u8; 4] },
+}
+
+impl MinimapPalette {
+    pub fn apply(&self, color: [u8; 4]) -> [u8; 4] {
+        match *self {
+            MinimapPalette::Colors => color,
+            MinimapPalette::Solid { solid, empty } => {
+                if color[3] >= 128 {
+                    solid
+                } else {
+                    empty
+                }
+            }
+        }
+    }
+
+    /// Mode and packed colors, as read by `minimap.wgsl`.
+    pub(crate) fn shader_params(&self) -> [u32; 3] {
+        match *self {
+            MinimapPalette::Colors => [0, 0, 0],
+            MinimapPalette::Solid { solid, empty } => {
+                [1, u32::from_le_bytes(solid), u32::from_le_bytes(empty)]
+            }
+        }
+    }
+}
+
+/// Live downsampled view of `rect` of a [`PixelMap`], drawn into `image`.
+///
+/// The image has its first row at the top and can be shown in an
+/// [`ImageNode`] or on a sprite. Each of its pixels averages a `downscale` by
+/// `downscale` block of the rect, limited to the chunk the center of the
+/// block lies in. Only the chunks that change are redrawn, on the GPU along
+/// with the edits of the map.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, from_reflect = false)]
+pub struct PixelMapMinimap {
+    pub map: Entity,
+    pub rect: IRect,
+    pub downscale: u32,
+    pub palette: MinimapPalette,
+    pub image: Handle<Image>,
+    #[reflect(ignore)]
+    drawn: Option<(Entity, IRect, u32, MinimapPalette)>,
+    #[reflect(ignore)]
+    chunks: HashSet<IVec2>,
+}
+
+/// Redraws the minimap pixels whose blocks are centered in a chunk, from the
+/// chunk image or in the default chunk color once the chunk is gone.
+#[derive(Clone, Debug)]
+pub(crate) struct MinimapUpdate {
+    pub source: Option<Handle<Image>>,
+    pub target: Handle<Image>,
+    pub chunk_pos: IVec2,
+    pub rect: IRect,
+    pub downscale: u32,
+    pub palette: MinimapPalette,
+    pub pixels: URect,
+}
+
+impl PixelMapMinimap {
+    pub fn new(map: Entity, rect: IRect, downscale: u32, images: &mut Assets<Image>) -> Self {
+        PixelMapMinimap {
+            map,
+            rect,
+            downscale,
+            palette: MinimapPalette::default(),
+            image: images.add(filled_image(UVec2::ONE, [0; 4])),
+            drawn: None,
+            chunks: HashSet::new(),
+        }
+    }
+
+    pub fn with_palette(mut self, palette: MinimapPalette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Size of the image, one pixel per started block of the rect.
+    pub fn size(&self) -> UVec2 {
+        let downscale = self.downscale.max(1);
+        (self.rect.size().as_uvec2() + UVec2::splat(downscale - 1)) / downscale
+    }
+}
+
+/// Minimap pixels along one axis whose blocks are centered within `lo..hi`,
+/// in texels of an image `len` long.
+fn centered_pixels(len: u32, downscale: u32, lo: i32, hi: i32) -> Range<u32> {
+    let count = len.div_ceil(downscale);
+    let center = |pixel: u32| {
+        let min = pixel * downscale;
+        let max = (min + downscale).min(len);
+        (min + (max - min) / 2) as i32
+    };
+    let first = lo.max(0) as u32 / downscale;
+    let start = (first.saturating_sub(1)..count)
+        .find(|&pixel| center(pixel) >= lo)
+        .unwrap_or(count);
+    let end = (start..count)
+        .find(|&pixel| center(pixel) >= hi)
+        .unwrap_or(count);
+    start..end
+}
+
+/// The minimap pixels [`MinimapUpdate`] redraws for a chunk.
+pub(crate) fn minimap_pixels(
+    rect: IRect,
+    downscale: u32,
+    chunk_pos: IVec2,
+    chunk_size: UVec2,
+) -> Option<URect> {
+    let size = rect.size().as_uvec2();
+    let origin = chunk_pos * chunk_size.as_ivec2();
+    let x_lo = origin.x - rect.min.x;
+    let y_lo = rect.max.y - origin.y - chunk_size.y as i32;
+    let x = centered_pixels(size.x, downscale, x_lo, x_lo + chunk_size.x as i32);
+    let y = centered_pixels(size.y, downscale, y_lo, y_lo + chunk_size.y as i32);
+    let pixels = URect::new(x.start, y.start, x.end, y.end);
+    (!pixels.is_empty()).then_some(pixels)
+}
+
+impl PixelMap {
+    fn queue_minimap_update(
+        &mut self,
+        minimap: &PixelMapMinimap,
+        chunk_pos: IVec2,
+        source: Option<Handle<Image>>,
+    ) {
+        let downscale = minimap.downscale.max(1);
+        let Some(pixels) = minimap_pixels(minimap.rect, downscale, chunk_pos, self.chunk_size)
+        else {
+            return;
+        };
+        self.minimap_updates.push(MinimapUpdate {
+            source,
+            target: minimap.image.clone(),
+            chunk_pos,
+            rect: minimap.rect,
+            downscale,
+            palette: minimap.palette,
+            pixels,
+        });
+    }
+}
+
+pub(crate) fn update_minimaps(
+    mut minimap_query: Query<&mut PixelMapMinimap>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for mut minimap in minimap_query.iter_mut() {
+        let Ok(mut pixel_map) = pixel_map_query.get_mut(minimap.map) else {
+            continue;
+        };
+        // Only the private drawing state changes here.
+        let minimap = minimap.bypass_change_detection();
+        let chunk_size = pixel_map.chunk_size;
+        let setup = (
+            minimap.map,
+            minimap.rect,
+            minimap.downscale,
+            minimap.palette,
+        );
+        if minimap.drawn != Some(setup) {
+            let color = minimap.palette.apply(pixel_map.default_chunk_color);
+            let mut image = filled_image(minimap.size().max(UVec2::ONE), color);
+            image.sampler = pixel_map.empty_texture.sampler.clone();
+            images.insert(&minimap.image, image);
+            minimap.chunks.clear();
+            minimap.drawn = Some(setup);
+        }
+        if minimap.rect.is_empty() {
+            continue;
+        }
+
+        let overlaps = |chunk_pos: IVec2| {
+            let origin = chunk_pos * chunk_size.as_ivec2();
+            !IRect::from_corners(origin, origin + chunk_size.as_ivec2())
+                .intersect(minimap.rect)
+                .is_empty()
+        };
+        let mut changed: HashSet<IVec2> = pixel_map
+            .changed_chunks()
+            .into_iter()
+            .filter(|chunk_pos| overlaps(*chunk_pos))
+            .collect();
+        let chunk_span = (minimap.rect.max - IVec2::ONE).div_euclid(chunk_size.as_ivec2())
+            - minimap.rect.min.div_euclid(chunk_size.as_ivec2())
+            + IVec2::ONE;
+        let added: Vec<IVec2> =
+            if chunk_span.as_uvec2().element_product() as usize > pixel_map.positions.len() {
+                pixel_map
+                    .positions
+                    .keys()
+                    .copied()
+                    .filter(|&chunk_pos| overlaps(chunk_pos))
+                    .filter(|chunk_pos| !minimap.chunks.contains(chunk_pos))
+                    .collect()
+            } else {
+                chunks_in_rect(minimap.rect, chunk_size)
+                    .filter(|chunk_pos| pixel_map.positions.contains_key(chunk_pos))
+                    .filter(|chunk_pos| !minimap.chunks.contains(chunk_pos))
+                    .collect()
+            };
+        changed.extend(added.iter().copied());
+        minimap.chunks.extend(added);
+        let removed: Vec<IVec2> = minimap
+            .chunks
+            .iter()
+            .filter(|chunk_pos| !pixel_map.positions.contains_key(*chunk_pos))
+            .copied()
+            .collect();
+        for chunk_pos in removed {
+            minimap.chunks.remove(&chunk_pos);
+            pixel_map.queue_minimap_update(minimap, chunk_pos, None);
+        }
+        for chunk_pos in changed {
+            let source = pixel_map
+                .positions
+                .get(&chunk_pos)
+                .map(|&index| pixel_map.image_data[index].clone());
+            if source.is_some() {
+                pixel_map.queue_minimap_update(minimap, chunk_pos, source);
+            }
+        }
+    }
+}
+
+/// The CPU backend's minimap updates, same as `minimap.wgsl`.
+pub(crate) fn apply_minimaps_cpu(pixel_map: &mut PixelMap, images: &mut Assets<Image>) {
+    let chunk_size = pixel_map.chunk_size;
+    for update in std::mem::take(&mut pixel_map.minimap_updates) {
+        let source = update
+            .source
+            .as_ref()
+            .and_then(|source| images.get(source))
+            .map(|image| image.data.clone());
+        let empty = update.palette.apply(pixel_map.default_chunk_color);
+        let Some(target) = images.get_mut(&update.target) else {
+            continue;
+        };
+        let size = update.rect.size().as_uvec2();
+        let width = target.size().x;
+        let origin = update.chunk_pos * chunk_size.as_ivec2();
+        for y in update.pixels.min.y..update.pixels.max.y {
+            for x in update.pixels.min.x..update.pixels.max.x {
+                let color = match source.as_ref() {
+                    Some(source) => {
+                        let block_min = UVec2::new(x, y) * update.downscale;
+                        let block_max = (block_min + UVec2::splat(update.downscale)).min(size);
+                        let mut sum = [0u32; 4];
+                        let mut count = 0;
+                        for by in block_min.y..block_max.y {
+                            for bx in block_min.x..block_max.x {
+                                let world = IVec2::new(
+                                    update.rect.min.x + bx as i32,
+                                    update.rect.max.y - 1 - by as i32,
+                                );
+                                let texel = IVec2::new(
+                                    world.x - origin.x,
+                                    origin.y + chunk_size.y as i32 - 1 - world.y,
+                                );
+                                if texel.cmplt(IVec2::ZERO).any()
+                                    || texel.cmpge(chunk_size.as_ivec2()).any()
+                                {
+                                    continue;
+                                }
+                                let ind = (texel.y as usize * chunk_size.x as usize
+                                    + texel.x as usize)
+                                    * 4;
+                                for (total, &channel) in sum.iter_mut().zip(&source[ind..ind + 4]) {
+                                    *total += channel as u32;
+                                }
+                                count += 1;
+                            }
+                        }
+                        let count = count.max(1);
+                        update
+                            .palette
+                            .apply(sum.map(|total| ((total + count / 2) / count) as u8))
+                    }
+                    None => empty,
+                };
+                let ind = (y * width + x) as usize * 4;
+                target.data[ind..ind + 4].copy_from_slice(&color);
+            }
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::utils::hashbrown::HashMap;
+
+use crate::{ChunkCreated, ChunkDirty, ChunkModified, ChunkRemoved, PixelMap, RegionExported};
+
+/// Cost of moving one cell sideways; the other costs are scaled to it.
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+/// Which cells of a [`PixelNavGrid`] can be moved through.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NavMode {
+    /// Every free cell, moving in eight directions without cutting corners.
+    /// For flying or top down agents.
+    #[default]
+    Open,
+    /// Free cells right above a solid one, for platformers. Agents walk
+    /// sideways, step up or down ledges of up to `max_step` cells and drop
+    /// off edges onto ground up to `max_drop` cells below. `clearance` free
+    /// cells are needed above a cell to stand in it.
+    Standable {
+        clearance: u32,
+        max_step: u32,
+        max_drop: u32,
+    },
+}
+
+/// Coarse grid of a [`PixelMap`] for pathfinding. A cell is solid when more
+/// than `solid_fraction` of its pixels are solid by the predicate, opaque
+/// pixels by default. Cells of chunks that don't exist or haven't been read
+/// yet are solid.
+///
+/// The grid is read from the GPU chunk by chunk as chunks are created,
+/// modified or report dirty rects, so it trails the map by a few frames.
+#[derive(Component, Reflect, Clone)]
+#[reflect(Component, from_reflect = false)]
+pub struct PixelNavGrid {
+    pub map: Entity,
+    pub cell_size: u32,
+    pub mode: NavMode,
+    pub solid_fraction: f32,
+    #[reflect(ignore)]
+    solid: Arc<dyn Fn([u8; 4]) -> bool + Send + Sync>,
+    #[reflect(ignore)]
+    cells: HashMap<IVec2, bool>,
+    #[reflect(ignore)]
+    scanned: bool,
+    /// Rects changed this frame, read in the next one once the changes are on
+    /// the GPU.
+    #[reflect(ignore)]
+    stale: HashMap<IVec2, IRect>,
+    #[reflect(ignore)]
+    due: HashMap<IVec2, IRect>,
+    #[reflect(ignore)]
+    pending: HashMap<u64, IRect>,
+    #[reflect(ignore)]
+    version: u64,
+}
+
+impl PixelNavGrid {
+    pub fn new(map: Entity, cell_size: u32) -> Self {
+        PixelNavGrid {
+            map,
+            cell_size: cell_size.max(1),
+            mode: NavMode::default(),
+            solid_fraction: 0.0,
+            solid: Arc::new(|color| color[3] > 0),
+            cells: HashMap::new(),
+            scanned: false,
+            stale: HashMap::new(),
+            due: HashMap::new(),
+            pending: HashMap::new(),
+            version: 0,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: NavMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_solid_fraction(mut self, solid_fraction: f32) -> Self {
+        self.solid_fraction = solid_fraction;
+        self
+    }
+
+    /// Which pixel colors are solid, for example to let agents swim through
+    /// water.
+    pub fn with_solid(mut self, solid: impl Fn([u8; 4]) -> bool + Send + Sync + 'static) -> Self {
+        self.solid = Arc::new(solid);
+        self
+    }
+
+    /// Bumped whenever cells change, to know when to recompute paths.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Whether reads of the grid are still on their way.
+    pub fn is_updating(&self) -> bool {
+        !self.pending.is_empty() || !self.due.is_empty() || !self.stale.is_empty()
+    }
+
+    /// Cell a world position lies in.
+    pub fn cell_at(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size as f32).floor().as_ivec2()
+    }
+
+    /// Center of a cell in world pixels.
+    pub fn cell_center(&self, cell: IVec2) -> Vec2 {
+        (cell.as_vec2() + 0.5) * self.cell_size as f32
+    }
+
+    /// `None` for cells that haven't been read.
+    pub fn is_solid(&self, cell: IVec2) -> Option<bool> {
+        self.cells.get(&cell).copied()
+    }
+
+    fn is_free(&self, cell: IVec2) -> bool {
+        self.cells.get(&cell) == Some(&false)
+    }
+
+    pub fn is_walkable(&self, cell: IVec2) -> bool {
+        match self.mode {
+            NavMode::Open => self.is_free(cell),
+            NavMode::Standable { clearance, .. } => {
+                self.is_solid(cell - IVec2::Y) == Some(true)
+                    && (0..clearance.max(1) as i32).all(|y| self.is_free(cell + IVec2::Y * y))
+            }
+        }
+    }
+
+    /// Cells reachable in one move from a walkable cell, with their cost.
+    fn neighbors(&self, cell: IVec2, out: &mut Vec<(IVec2, u32)>) {
+        out.clear();
+        match self.mode {
+            NavMode::Open => {
+                for y in -1..=1 {
+                    for x in -1..=1 {
+                        let offset = IVec2::new(x, y);
+                        if offset == IVec2::ZERO || !self.is_free(cell + offset) {
+                            continue;
+                        }
+                        if x != 0 && y != 0 {
+                            if self.is_free(cell + IVec2::new(x, 0))
+                                && self.is_free(cell + IVec2::new(0, y))
+                            {
+                                out.push((cell + offset, DIAGONAL));
+                            }
+                        } else {
+                            out.push((cell + offset, STRAIGHT));
+                        }
+                    }
+                }
+            }
+            NavMode::Standable {
+                max_step, max_drop, ..
+            } => {
+                for x in [-1, 1] {
+                    let side = cell + IVec2::X * x;
+                    if self.is_walkable(side) {
+                        out.push((side, STRAIGHT));
+                        continue;
+                    }
+                    // Steps up need head room above the current cell.
+                    if let Some(up) = (1..=max_step as i32)
+                        .take_while(|&y| self.is_free(cell + IVec2::Y * y))
+                        .map(|y| side + IVec2::Y * y)
+                        .find(|&up| self.is_walkable(up))
+                    {
+                        out.push((up, STRAIGHT * (1 + (up.y - cell.y) as u32)));
+                        continue;
+                    }
+                    if !self.is_free(side) {
+                        continue;
+                    }
+                    if let Some(down) = (1..=max_step.max(max_drop) as i32)
+                        .take_while(|&y| self.is_free(side - IVec2::Y * y))
+                        .map(|y| side - IVec2::Y * y)
+                        .find(|&down| self.is_walkable(down))
+                    {
+                        out.push((down, STRAIGHT + STRAIGHT / 2 * (cell.y - down.y) as u32));
+                    }
+                }
+            }
+        }
+    }
+
+    fn heuristic(&self, from: IVec2, to: IVec2) -> u32 {
+        let delta = (to - from).abs().as_uvec2();
+        match self.mode {
+            NavMode::Open => {
+                STRAIGHT * delta.max_element() + (DIAGONAL - STRAIGHT) * delta.min_element()
+            }
+            NavMode::Standable { .. } => STRAIGHT * delta.x + STRAIGHT / 2 * delta.y,
+        }
+    }
+
+    /// Shortest path between the cells of two world positions with A*,
+    /// both ends included. Searches at most `max_cells` cells.
+    pub fn find_path(&self, start: Vec2, goal: Vec2, max_cells: usize) -> Option<Vec<IVec2>> {
+        let start = self.cell_at(start);
+        let goal = self.cell_at(goal);
+        if !self.is_walkable(start) || !self.is_walkable(goal) {
+            return None;
+        }
+        let mut open =
+            BinaryHeap::from([Reverse((self.heuristic(start, goal), 0, start.to_array()))]);
+        let mut came_from: HashMap<IVec2, IVec2> = HashMap::new();
+        let mut costs: HashMap<IVec2, u32> = HashMap::from([(start, 0)]);
+        let mut neighbors = Vec::new();
+        let mut visited = 0;
+        while let Some(Reverse((_, cost, cell))) = open.pop() {
+            let cell = IVec2::from_array(cell);
+            if cell == goal {
+                let mut path = vec![goal];
+                while let Some(&previous) = came_from.get(path.last().unwrap()) {
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if cost > costs[&cell] {
+                continue;
+            }
+            visited += 1;
+            if visited > max_cells {
+                return None;
+            }
+            self.neighbors(cell, &mut neighbors);
+            for &(next, step) in neighbors.iter() {
+                let next_cost = cost + step;
+                if costs.get(&next).is_some_and(|&known| known <= next_cost) {
+                    continue;
+                }
+                costs.insert(next, next_cost);
+                came_from.insert(next, cell);
+                open.push(Reverse((
+                    next_cost + self.heuristic(next, goal),
+                    next_cost,
+                    next.to_array(),
+                )));
+            }
+        }
+        None
+    }
+
+    /// Costs to reach the cell of `goal` from every walkable cell within
+    /// `bounds`, in cells, for steering many agents towards one target.
+    pub fn flow_field(&self, goal: Vec2, bounds: IRect) -> PixelFlowField {
+        let goal = self.cell_at(goal);
+        let mut field = PixelFlowField {
+            cell_size: self.cell_size,
+            goal,
+            costs: HashMap::new(),
+            next: HashMap::new(),
+        };
+        if !self.is_walkable(goal) || !bounds.contains(goal) {
+            return field;
+        }
+        // Moves aren't symmetric when standing, so search backwards.
+        let mut incoming: HashMap<IVec2, Vec<(IVec2, u32)>> = HashMap::new();
+        let mut neighbors = Vec::new();
+        for y in bounds.min.y..bounds.max.y {
+            for x in bounds.min.x..bounds.max.x {
+                let cell = IVec2::new(x, y);
+                if !self.is_walkable(cell) {
+                    continue;
+                }
+                self.neighbors(cell, &mut neighbors);
+                for &(next, cost) in neighbors.iter() {
+                    if bounds.contains(next) {
+                        incoming.entry(next).or_default().push((cell, cost));
+                    }
+                }
+            }
+        }
+        let mut open = BinaryHeap::from([Reverse((0, goal.to_array()))]);
+        field.costs.insert(goal, 0);
+        while let Some(Reverse((cost, cell))) = open.pop() {
+            let cell = IVec2::from_array(cell);
+            if cost > field.costs[&cell] {
+                continue;
+            }
+            for &(previous, step) in incoming.get(&cell).map_or(&[][..], Vec::as_slice) {
+                let previous_cost = cost + step;
+                if field
+                    .costs
+                    .get(&previous)
+                    .is_some_and(|&known| known <= previous_cost)
+                {
+                    continue;
+                }
+                field.costs.insert(previous, previous_cost);
+                field.next.insert(previous, cell);
+                open.push(Reverse((previous_cost, previous.to_array())));
+            }
+        }
+        field
+    }
+
+    /// Marks the cells overlapping a world rect to be read again.
+    fn invalidate(&mut self, chunk_pos: IVec2, rect: IRect) {
+        let cell_size = self.cell_size as i32;
+        let cells = IRect::from_corners(
+            rect.min.div_euclid(IVec2::splat(cell_size)),
+            (rect.max + cell_size - 1).div_euclid(IVec2::splat(cell_size)),
+        );
+        let rect = IRect::from_corners(cells.min * cell_size, cells.max * cell_size);
+        self.stale
+            .entry(chunk_pos)
+            .and_modify(|stale| *stale = stale.union(rect))
+            .or_insert(rect);
+    }
+
+    /// Reads the cells of an exported rect.
+    fn apply_export(&mut self, rect: IRect, image: &Image) {
+        let cell_size = self.cell_size as i32;
+        let size = rect.size();
+        let cells = rect.size() / cell_size;
+        let mut changed = false;
+        for cy in 0..cells.y {
+            for cx in 0..cells.x {
+                let mut solid = 0;
+                for y in 0..cell_size {
+                    for x in 0..cell_size {
+                        // First row of the image at the top.
+                        let texel =
+                            IVec2::new(cx * cell_size + x, size.y - 1 - (cy * cell_size + y));
+                        let ind = (texel.y * size.x + texel.x) as usize * 4;
+                        let color: [u8; 4] = image.data[ind..ind + 4].try_into().unwrap();
+                        if (self.solid)(color) {
+                            solid += 1;
+                        }
+                    }
+                }
+                let solid = solid as f32 > self.solid_fraction * (cell_size * cell_size) as f32;
+                let cell = rect.min / cell_size + IVec2::new(cx, cy);
+                if self.cells.insert(cell, solid) != Some(solid) {
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.version += 1;
+        }
+    }
+}
+
+/// Result of [`PixelNavGrid::flow_field`].
+#[derive(Clone, Debug, Default)]
+pub struct PixelFlowField {
+    pub cell_size: u32,
+    pub goal: IVec2,
+    costs: HashMap<IVec2, u32>,
+    next: HashMap<IVec2, IVec2>,
+}
+
+impl PixelFlowField {
+    /// `None` where the goal can't be reached.
+    pub fn cost(&self, cell: IVec2) -> Option<u32> {
+        self.costs.get(&cell).copied()
+    }
+
+    /// The cell to move to next on the way to the goal.
+    pub fn next_cell(&self, cell: IVec2) -> Option<IVec2> {
+        self.next.get(&cell).copied()
+    }
+
+    /// Normalized direction from a world position towards the center of the
+    /// next cell.
+    pub fn direction(&self, position: Vec2) -> Option<Vec2> {
+        let cell = (position / self.cell_size as f32).floor().as_ivec2();
+        let next = self.next_cell(cell)?;
+        let center = (next.as_vec2() + 0.5) * self.cell_size as f32;
+        (center - position).try_normalize()
+    }
+}
+
+/// Marks the cells of created, modified and dirty chunks to be read.
+pub(crate) fn invalidate_nav_grids(
+    mut grids: Query<&mut PixelNavGrid>,
+    pixel_maps: Query<&PixelMap>,
+    mut created: EventReader<ChunkCreated>,
+    mut modified: EventReader<ChunkModified>,
+    mut dirty: EventReader<ChunkDirty>,
+    mut removed: EventReader<ChunkRemoved>,
+) {
+    let mut changes: Vec<(Entity, IVec2, Option<IRect>)> = created
+        .read()
+        .map(|event| (event.map, event.chunk_pos, None))
+        .chain(
+            modified
+                .read()
+                .map(|event| (event.map, event.chunk_pos, None)),
+        )
+        .chain(
+            removed
+                .read()
+                .map(|event| (event.map, event.chunk_pos, None)),
+        )
+        .collect();
+    changes.extend(
+        dirty
+            .read()
+            .map(|event| (event.map, event.chunk_pos, Some(event.rect))),
+    );
+    for mut grid in grids.iter_mut() {
+        let Ok(pixel_map) = pixel_maps.get(grid.map) else {
+            continue;
+        };
+        let chunk_size = pixel_map.chunk_size;
+        let chunk_rect = |chunk_pos: IVec2| {
+            let origin = chunk_pos * chunk_size.as_ivec2();
+            IRect::from_corners(origin, origin + chunk_size.as_ivec2())
+        };
+        if !grid.scanned {
+            grid.scanned = true;
+            for &chunk_pos in pixel_map.positions.keys() {
+                grid.invalidate(chunk_pos, chunk_rect(chunk_pos));
+            }
+        }
+        for &(map, chunk_pos, rect) in changes.iter() {
+            if map == grid.map {
+                grid.invalidate(chunk_pos, rect.unwrap_or_else(|| chunk_rect(chunk_pos)));
+            }
+        }
+    }
+}
+
+/// Reads the exported cells into the navigation grids and exports the cells
+/// changed last frame, now that the changes are on the GPU.
+pub(crate) fn read_nav_grids(
+    mut grids: Query<&mut PixelNavGrid>,
+    mut pixel_maps: Query<&mut PixelMap>,
+    mut exports: EventReader<RegionExported>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let exports: Vec<&RegionExported> = exports.read().collect();
+    for mut grid in grids.iter_mut() {
+        let grid = grid.as_mut();
+        let map = grid.map;
+        for export in exports.iter().filter(|export| export.map == map) {
+            if let Some(rect) = grid.pending.remove(&export.id) {
+                grid.apply_export(rect, &export.image);
+            }
+        }
+        let Ok(mut pixel_map) = pixel_maps.get_mut(grid.map) else {
+            continue;
+        };
+        for (_, rect) in std::mem::take(&mut grid.due) {
+            if let Some(id) = pixel_map.request_export(rect, 1, &mut images) {
+                grid.pending.insert(id, rect);
+            }
+        }
+        grid.due = std::mem::take(&mut grid.stale);
+    }
+}
@@ -0,0 +1,698 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType, Buffer,
+    BufferBindingType, BufferDescriptor, BufferInitDescriptor, BufferUsages, CommandEncoder,
+    ComputePassDescriptor, ComputePipeline, Extent3d, IntoBinding, ShaderStages,
+    StorageTextureAccess, Texture, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureViewDescriptor, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::texture::GpuImage;
+use bevy::utils::hashbrown::HashMap;
+
+use crate::clipboard::{chunk_copy_rect, chunks_in_rect, TextureCopy};
+use crate::events::ChunkEvent;
+use crate::explosion::Explosion;
+use crate::export::filled_image;
+use crate::{PixelMap, PixelMapShaderLayoutInput, UnrecordableEdit};
+
+/// Bytes of a particle in the buffers read by `particles.wgsl`.
+pub(crate) const PARTICLE_SIZE: u64 = 32;
+/// Bytes of the count and padding before the particles simulation shaders
+/// eject.
+const EJECT_HEADER: u64 = 8;
+/// Steps a settling particle may slide down like sand.
+const SETTLE_STEPS: u32 = 64;
+
+/// How particles of a map move.
+#[derive(Reflect, Clone, Debug, PartialEq)]
+pub struct ParticleSettings {
+    /// Pixels per second squared, pulling particles down.
+    pub gravity: f32,
+    /// Seconds particles fly before they settle wherever they are.
+    pub lifetime: f32,
+    /// Pixels particles may fly from where they were emitted.
+    pub max_reach: u32,
+    /// Most pixels simulations can eject into particles per frame.
+    pub eject_capacity: u32,
+}
+
+impl Default for ParticleSettings {
+    fn default() -> Self {
+        ParticleSettings {
+            gravity: 200.0,
+            lifetime: 2.0,
+            max_reach: 256,
+            eject_capacity: 1024,
+        }
+    }
+}
+
+impl ParticleSettings {
+    /// How far a particle thrown `speed` pixels per second fast can fly.
+    fn reach(&self, speed: f32) -> f32 {
+        let lifetime = self.lifetime.max(0.0);
+        (speed * lifetime + self.gravity.abs() * lifetime * lifetime / 2.0)
+            .min(self.max_reach as f32)
+    }
+}
+
+/// A pixel to throw over the map with [`PixelMap::emit_particles`], in world
+/// pixels and world pixels per second.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+pub struct PixelParticle {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub color: [u8; 4],
+}
+
+/// A pixel flying over the map, in world pixels per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Particle {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub color: [u8; 4],
+    pub flying: bool,
+}
+
+impl From<PixelParticle> for Particle {
+    fn from(particle: PixelParticle) -> Self {
+        Particle {
+            position: particle.position,
+            velocity: particle.velocity,
+            color: particle.color,
+            flying: true,
+        }
+    }
+}
+
+impl Particle {
+    /// Layout of `Particle` in `particles.wgsl`.
+    pub fn to_words(self) -> [u32; 8] {
+        [
+            self.position.x.to_bits(),
+            self.position.y.to_bits(),
+            self.velocity.x.to_bits(),
+            self.velocity.y.to_bits(),
+            u32::from_le_bytes(self.color),
+            self.flying as u32,
+            0,
+            0,
+        ]
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let word = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        Particle {
+            position: Vec2::new(f32::from_bits(word(0)), f32::from_bits(word(1))),
+            velocity: Vec2::new(f32::from_bits(word(2)), f32::from_bits(word(3))),
+            color: word(4).to_le_bytes(),
+            flying: word(5) != 0,
+        }
+    }
+}
+
+/// Particles emitted together, flying within `rect` until they land on a
+/// pixel of the map and settle into it. On the GPU they live in buffers of
+/// the render world, uploaded from `particles` in the first frame of the
+/// batch; on the CPU `particles` holds them until they settle.
+#[derive(Clone, Debug)]
+pub(crate) struct ParticleBatch {
+    pub id: u64,
+    pub rect: IRect,
+    pub capacity: u32,
+    pub age: f32,
+    /// Seconds simulated this frame.
+    pub step: f32,
+    pub overlay: Handle<Image>,
+    pub sprite: Entity,
+    pub explosions: Vec<Explosion>,
+    pub particles: Vec<Particle>,
+}
+
+/// Particles of a map and the explosions and emissions waiting to start
+/// batches.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PixelParticles {
+    pub settings: ParticleSettings,
+    pub batches: Vec<ParticleBatch>,
+    pub pending: Vec<Explosion>,
+    pub emitted: Vec<Vec<Particle>>,
+    pub next_id: u64,
+}
+
+impl ParticleBatch {
+    /// Particles settle wherever they are in the last step of the batch.
+    pub fn is_last_step(&self, settings: &ParticleSettings) -> bool {
+        self.age >= settings.lifetime
+    }
+}
+
+impl PixelMap {
+    /// Throws pixels over the map. They fall with the gravity of the
+    /// [`ParticleSettings`], collide with the pixels of the map and settle
+    /// into it where they land, piling up like sand. Nothing is drawn where
+    /// the particles start, so clear those pixels first when moving them.
+    pub fn emit_particles(&mut self, particles: impl IntoIterator<Item = PixelParticle>) {
+        let particles: Vec<Particle> = particles.into_iter().map(Particle::from).collect();
+        if !particles.is_empty() {
+            self.particles.emitted.push(particles);
+        }
+    }
+
+    pub fn particle_settings(&self) -> &ParticleSettings {
+        &self.particles.settings
+    }
+
+    pub fn particle_settings_mut(&mut self) -> &mut ParticleSettings {
+        &mut self.particles.settings
+    }
+
+    /// Batches of particles in flight.
+    pub fn particle_batch_count(&self) -> usize {
+        self.particles.batches.len()
+    }
+
+    /// Chunks particles may settle into this frame.
+    pub(crate) fn particle_chunks(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.particles
+            .batches
+            .iter()
+            .flat_map(|batch| chunks_in_rect(batch.rect, self.chunk_size))
+    }
+}
+
+/// Starts batches for new explosions and emitted particles, ages the others
+/// and removes the ones that have settled.
+pub(crate) fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let step = time.delta_secs();
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        if pixel_map.particles.batches.is_empty()
+            && pixel_map.particles.pending.is_empty()
+            && pixel_map.particles.emitted.is_empty()
+        {
+            continue;
+        }
+        let pixel_map = pixel_map.as_mut();
+        let settings = pixel_map.particles.settings.clone();
+        let mut batches = std::mem::take(&mut pixel_map.particles.batches);
+        batches.retain_mut(|batch| {
+            if batch.is_last_step(&settings) {
+                commands.entity(batch.sprite).despawn_recursive();
+                images.remove(&batch.overlay);
+                pixel_map.record_readback(batch.rect);
+                return false;
+            }
+            batch.age += step;
+            batch.step = step;
+            true
+        });
+
+        let mut started = Vec::new();
+        for explosion in std::mem::take(&mut pixel_map.particles.pending) {
+            let extent = (explosion.extent() + settings.reach(explosion.strength.abs())).ceil();
+            let rect =
+                IRect::from_center_half_size(explosion.center, IVec2::splat(extent as i32 + 1));
+            for chunk_pos in chunks_in_rect(explosion.rect(), pixel_map.chunk_size) {
+                if pixel_map.positions.contains_key(&chunk_pos) {
+                    pixel_map
+                        .chunk_events
+                        .push(ChunkEvent::Modified { chunk_pos });
+                }
+            }
+            started.push((rect, explosion.capacity(), vec![explosion], Vec::new()));
+        }
+        for particles in std::mem::take(&mut pixel_map.particles.emitted) {
+            let speed = particles
+                .iter()
+                .map(|particle| particle.velocity.length())
+                .fold(0.0, f32::max);
+            let (min, max) = particles.iter().fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), particle| (min.min(particle.position), max.max(particle.position)),
+            );
+            let reach = settings.reach(speed).ceil() as i32 + 1;
+            let rect = IRect::from_corners(
+                min.floor().as_ivec2() - reach,
+                max.floor().as_ivec2() + 1 + reach,
+            );
+            started.push((rect, particles.len() as u32, Vec::new(), particles));
+        }
+        if !started.is_empty() {
+            pixel_map.refuse_recording(UnrecordableEdit::Particles);
+        }
+        for (rect, capacity, explosions, particles) in started {
+            let mut overlay = filled_image(rect.size().as_uvec2(), [0; 4]);
+            overlay.sampler = pixel_map.empty_texture.sampler.clone();
+            let overlay = images.add(overlay);
+            // Chunk sprites are centered on the origin of their chunk.
+            let center = (rect.min + rect.max).as_vec2() / 2. - pixel_map.chunk_size.as_vec2() / 2.;
+            let sprite = commands
+                .spawn((
+                    Sprite {
+                        image: overlay.clone(),
+                        custom_size: Some(rect.size().as_vec2()),
+                        ..default()
+                    },
+                    Transform::from_xyz(center.x, center.y, 1.0),
+                ))
+                .id();
+            commands.entity(pixel_map.root_entity).add_child(sprite);
+            pixel_map.particles.next_id += 1;
+            batches.push(ParticleBatch {
+                id: pixel_map.particles.next_id,
+                rect,
+                capacity,
+                age: 0.0,
+                step,
+                overlay,
+                sprite,
+                explosions,
+                particles,
+            });
+        }
+
+        // Particles can only settle into chunks that exist.
+        for batch in batches.iter() {
+            for chunk_pos in chunks_in_rect(batch.rect, pixel_map.chunk_size) {
+                pixel_map.add_chunk(chunk_pos, &mut commands, &mut images);
+                pixel_map.wake_chunk(chunk_pos);
+            }
+        }
+        pixel_map.particles.batches = batches;
+    }
+}
+
+/// Pixels of a batch's rect, first row at the top, that particles collide
+/// with and settle into.
+struct Atlas {
+    rect: IRect,
+    size: UVec2,
+    data: Vec<u8>,
+}
+
+impl Atlas {
+    fn texel(&self, position: Vec2) -> IVec2 {
+        let pixel = position.floor().as_ivec2();
+        IVec2::new(pixel.x - self.rect.min.x, self.rect.max.y - 1 - pixel.y)
+    }
+
+    fn blocked(&self, texel: IVec2) -> bool {
+        if texel.cmplt(IVec2::ZERO).any() || texel.cmpge(self.size.as_ivec2()).any() {
+            return true;
+        }
+        self.data[(texel.y as u32 * self.size.x + texel.x as u32) as usize * 4 + 3] > 0
+    }
+
+    fn set(&mut self, texel: IVec2, color: [u8; 4]) {
+        let ind = (texel.y as u32 * self.size.x + texel.x as u32) as usize * 4;
+        self.data[ind..ind + 4].copy_from_slice(&color);
+    }
+
+    /// Slides down like sand from `texel` and leaves `color` where it stops,
+    /// climbing out of pixels that settled there first.
+    fn settle(&mut self, mut texel: IVec2, color: [u8; 4], index: usize) {
+        let side = if index.is_multiple_of(2) { 1 } else { -1 };
+        for _ in 0..SETTLE_STEPS {
+            if !self.blocked(texel) || texel.y <= 0 {
+                break;
+            }
+            texel.y -= 1;
+        }
+        for _ in 0..SETTLE_STEPS {
+            let next = [IVec2::new(0, 1), IVec2::new(-side, 1), IVec2::new(side, 1)]
+                .into_iter()
+                .map(|offset| texel + offset)
+                .find(|&next| !self.blocked(next));
+            match next {
+                Some(next) => texel = next,
+                None => break,
+            }
+        }
+        if !self.blocked(texel) {
+            self.set(texel, color);
+        }
+    }
+}
+
+/// One frame of a particle, like `particles.wgsl`.
+fn step_particle(
+    atlas: &mut Atlas,
+    particle: &mut Particle,
+    index: usize,
+    step: f32,
+    gravity: f32,
+    last: bool,
+) {
+    if !particle.flying {
+        return;
+    }
+    particle.velocity.y -= gravity * step;
+    let travel = particle.velocity * step;
+    let steps = travel.abs().max_element().ceil().max(1.0);
+    let delta = travel / steps;
+    let mut landed = last;
+    for _ in 0..steps as u32 {
+        let next = particle.position + delta;
+        if atlas.blocked(atlas.texel(next)) {
+            landed = true;
+            break;
+        }
+        particle.position = next;
+    }
+    if landed {
+        particle.flying = false;
+        let texel = atlas.texel(particle.position);
+        atlas.settle(texel, particle.color, index);
+    }
+}
+
+/// The CPU backend's particles, after this frame's chunk edits.
+pub(crate) fn apply_particles_cpu(pixel_map: &mut PixelMap, images: &mut Assets<Image>) {
+    let chunk_size = pixel_map.chunk_size;
+    let settings = pixel_map.particles.settings.clone();
+    let mut batches = std::mem::take(&mut pixel_map.particles.batches);
+    for batch in batches.iter_mut() {
+        let size = batch.rect.size().as_uvec2();
+        let mut atlas = Atlas {
+            rect: batch.rect,
+            size,
+            data: vec![0; size.element_product() as usize * 4],
+        };
+        let chunks: Vec<(IVec2, Handle<Image>)> = chunks_in_rect(batch.rect, chunk_size)
+            .filter_map(|chunk_pos| {
+                let index = *pixel_map.positions.get(&chunk_pos)?;
+                Some((chunk_pos, pixel_map.image_data[index].clone()))
+            })
+            .collect();
+        for (chunk_pos, image) in chunks.iter() {
+            let (Some(image), Some((rect, origin))) = (
+                images.get(image),
+                chunk_copy_rect(*chunk_pos, chunk_size, batch.rect),
+            ) else {
+                continue;
+            };
+            for y in 0..rect.height() {
+                let from = ((rect.min.y + y) * chunk_size.x + rect.min.x) as usize * 4;
+                let to = ((origin.y + y) * size.x + origin.x) as usize * 4;
+                let row = rect.width() as usize * 4;
+                atlas.data[to..to + row].copy_from_slice(&image.data[from..from + row]);
+            }
+        }
+
+        let last = batch.is_last_step(&settings);
+        for (index, particle) in batch.particles.iter_mut().enumerate() {
+            step_particle(
+                &mut atlas,
+                particle,
+                index,
+                batch.step,
+                settings.gravity,
+                last,
+            );
+        }
+        batch.particles.retain(|particle| particle.flying);
+
+        for (chunk_pos, image) in chunks.iter() {
+            let (Some(image), Some((rect, origin))) = (
+                images.get_mut(image),
+                chunk_copy_rect(*chunk_pos, chunk_size, batch.rect),
+            ) else {
+                continue;
+            };
+            for y in 0..rect.height() {
+                let to = ((rect.min.y + y) * chunk_size.x + rect.min.x) as usize * 4;
+                let from = ((origin.y + y) * size.x + origin.x) as usize * 4;
+                let row = rect.width() as usize * 4;
+                image.data[to..to + row].copy_from_slice(&atlas.data[from..from + row]);
+            }
+        }
+        if let Some(overlay) = images.get_mut(&batch.overlay) {
+            overlay.data.fill(0);
+            for particle in batch.particles.iter() {
+                let texel = atlas.texel(particle.position);
+                if texel.cmpge(IVec2::ZERO).all() && texel.cmplt(size.as_ivec2()).all() {
+                    let ind = (texel.y as u32 * size.x + texel.x as u32) as usize * 4;
+                    overlay.data[ind..ind + 4].copy_from_slice(&particle.color);
+                }
+            }
+        }
+    }
+    pixel_map.particles.batches = batches;
+}
+
+/// Bind group layouts of `particles.wgsl` and `particle_draw.wgsl`.
+pub(crate) fn particle_bind_group_layouts(device: &RenderDevice) -> [BindGroupLayout; 2] {
+    let texture = |binding, access| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access,
+            format: TextureFormat::Rgba8Unorm,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    let buffer = |binding, ty| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    [
+        device.create_bind_group_layout(
+            Some("pixel map particles Bind Group Layout"),
+            &[
+                texture(0, StorageTextureAccess::ReadWrite),
+                buffer(1, BufferBindingType::Storage { read_only: false }),
+                buffer(2, BufferBindingType::Uniform),
+            ],
+        ),
+        device.create_bind_group_layout(
+            Some("pixel map particle draw Bind Group Layout"),
+            &[
+                texture(0, StorageTextureAccess::WriteOnly),
+                buffer(1, BufferBindingType::Storage { read_only: true }),
+                buffer(2, BufferBindingType::Uniform),
+            ],
+        ),
+    ]
+}
+
+/// Buffers and collision texture of a batch, kept in the render world for
+/// as long as the batch flies.
+pub(crate) struct GpuParticleBatch {
+    pub particles: Buffer,
+    pub count: Buffer,
+    atlas: Texture,
+}
+
+impl GpuParticleBatch {
+    pub fn new(device: &RenderDevice, batch: &ParticleBatch) -> Self {
+        let size = batch.rect.size().as_uvec2();
+        let mut words = vec![0; batch.capacity.max(1) as usize * 8];
+        for (particle, target) in batch.particles.iter().zip(words.chunks_exact_mut(8)) {
+            target.copy_from_slice(&particle.to_words());
+        }
+        GpuParticleBatch {
+            particles: device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("particles_buffer"),
+                contents: bytemuck::cast_slice(&words),
+                usage: BufferUsages::STORAGE,
+            }),
+            count: device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("particle_count_buffer"),
+                contents: bytemuck::cast_slice(&[batch.particles.len() as u32]),
+                usage: BufferUsages::STORAGE,
+            }),
+            atlas: device.create_texture(&TextureDescriptor {
+                label: Some("pixel map particle atlas"),
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::STORAGE_BINDING
+                    | TextureUsages::COPY_DST
+                    | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            }),
+        }
+    }
+}
+
+/// Buffer simulation shaders eject pixels into, holding `capacity` particles.
+pub(crate) fn eject_buffer(device: &RenderDevice, capacity: u32) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("pixel map eject buffer"),
+        size: EJECT_HEADER + capacity as u64 * PARTICLE_SIZE,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Particles in an eject buffer read back from the GPU.
+pub(crate) fn read_ejected(data: &[u8]) -> Vec<Particle> {
+    let count = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+    data[EJECT_HEADER as usize..]
+        .chunks_exact(PARTICLE_SIZE as usize)
+        .take(count)
+        .map(Particle::from_bytes)
+        .collect()
+}
+
+/// One frame of a batch on the GPU: gathering the chunks into its atlas,
+/// moving and drawing the particles, and copying the atlas back.
+pub(crate) struct ParticleDispatch {
+    gather: Vec<TextureCopy>,
+    scatter: Vec<TextureCopy>,
+    step: BindGroup,
+    draw: BindGroup,
+    capacity: u32,
+    overlay_size: UVec2,
+}
+
+pub(crate) fn prepare_particles(
+    device: &RenderDevice,
+    layouts: &PixelMapShaderLayoutInput,
+    gpu_images: &RenderAssets<GpuImage>,
+    pixel_map: &PixelMap,
+    map: Entity,
+    gpu_batches: &mut HashMap<(Entity, u64), GpuParticleBatch>,
+) -> Vec<ParticleDispatch> {
+    let chunk_size = pixel_map.chunk_size;
+    let settings = &pixel_map.particles.settings;
+    let mut dispatches = Vec::new();
+    for batch in pixel_map.particles.batches.iter() {
+        let gpu_batch = gpu_batches
+            .entry((map, batch.id))
+            .or_insert_with(|| GpuParticleBatch::new(device, batch));
+        let Some(overlay) = gpu_images.get(&batch.overlay) else {
+            continue;
+        };
+        let mut gather = Vec::new();
+        let mut scatter = Vec::new();
+        for chunk_pos in chunks_in_rect(batch.rect, chunk_size) {
+            let Some(chunk) = pixel_map
+                .positions
+                .get(&chunk_pos)
+                .and_then(|&index| gpu_images.get(&pixel_map.image_data[index]))
+            else {
+                continue;
+            };
+            let Some((rect, origin)) = chunk_copy_rect(chunk_pos, chunk_size, batch.rect) else {
+                continue;
+            };
+            gather.push(TextureCopy {
+                source: chunk.texture.clone(),
+                target: gpu_batch.atlas.clone(),
+                source_rect: rect,
+                target_origin: origin,
+            });
+            scatter.push(TextureCopy {
+                source: gpu_batch.atlas.clone(),
+                target: chunk.texture.clone(),
+                source_rect: URect::from_corners(origin, origin + rect.size()),
+                target_origin: rect.min,
+            });
+        }
+        let params = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("particle_params_buffer"),
+            contents: bytemuck::cast_slice(&[
+                batch.rect.min.x as u32,
+                batch.rect.max.y as u32,
+                batch.step.to_bits(),
+                settings.gravity.to_bits(),
+                batch.is_last_step(settings) as u32,
+                batch.capacity,
+                0,
+                0,
+            ]),
+            usage: BufferUsages::UNIFORM,
+        });
+        let atlas = gpu_batch
+            .atlas
+            .create_view(&TextureViewDescriptor::default());
+        let step = device.create_bind_group(
+            "pixel map particles bind group",
+            &layouts.particle_layout,
+            &BindGroupEntries::sequential((
+                atlas.into_binding(),
+                gpu_batch.particles.as_entire_binding(),
+                params.as_entire_binding(),
+            )),
+        );
+        let draw = device.create_bind_group(
+            "pixel map particle draw bind group",
+            &layouts.particle_draw_layout,
+            &BindGroupEntries::sequential((
+                overlay.texture_view.into_binding(),
+                gpu_batch.particles.as_entire_binding(),
+                params.as_entire_binding(),
+            )),
+        );
+        dispatches.push(ParticleDispatch {
+            gather,
+            scatter,
+            step,
+            draw,
+            capacity: batch.capacity,
+            overlay_size: overlay.size,
+        });
+    }
+    dispatches
+}
+
+/// Moves, settles and draws particles, after the explosions that emitted
+/// them carved the chunks.
+pub(crate) fn encode_particles(
+    encoder: &mut CommandEncoder,
+    pipelines: [&ComputePipeline; 3],
+    dispatches: &[ParticleDispatch],
+) {
+    if dispatches.is_empty() {
+        return;
+    }
+    let [step, clear, draw] = pipelines;
+    for copy in dispatches
+        .iter()
+        .flat_map(|dispatch| dispatch.gather.iter())
+    {
+        copy.encode(encoder);
+    }
+    {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        for dispatch in dispatches {
+            let particles = dispatch.capacity.div_ceil(64).max(1);
+            pass.set_pipeline(step);
+            pass.set_bind_group(0, &dispatch.step, &[]);
+            pass.dispatch_workgroups(particles, 1, 1);
+            let workgroups = (dispatch.overlay_size + UVec2::splat(7)) / 8;
+            pass.set_pipeline(clear);
+            pass.set_bind_group(0, &dispatch.draw, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+            pass.set_pipeline(draw);
+            pass.dispatch_workgroups(particles, 1, 1);
+        }
+    }
+    for copy in dispatches
+        .iter()
+        .flat_map(|dispatch| dispatch.scatter.iter())
+    {
+        copy.encode(encoder);
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Extent3d, ImageCopyBuffer,
+    ImageCopyTexture, ImageDataLayout, MapMode, Origin3d, Texture, TextureAspect,
+};
+use bevy::render::renderer::RenderDevice;
+
+/// Work to run on the main world once a readback has completed.
+pub(crate) type ReadbackResult = Box<dyn FnOnce(&mut World) + Send>;
+
+#[derive(Resource, Clone)]
+pub(crate) struct ReadbackSender(Sender<ReadbackResult>);
+
+#[derive(Resource)]
+pub(crate) struct ReadbackReceiver(Mutex<Receiver<ReadbackResult>>);
+
+impl ReadbackSender {
+    pub(crate) fn send(&self, result: ReadbackResult) {
+        let _ = self.0.send(result);
+    }
+}
+
+pub(crate) fn readback_channel() -> (ReadbackSender, ReadbackReceiver) {
+    let (sender, receiver) = channel();
+    (
+        ReadbackSender(sender),
+        ReadbackReceiver(Mutex::new(receiver)),
+    )
+}
+
+/// A staging buffer that a copy was recorded into. Call [`PendingReadback::map`]
+/// after the command encoder holding the copy has been submitted.
+pub(crate) struct PendingReadback {
+    buffer: Buffer,
+    finish: Box<dyn FnOnce(Vec<u8>) -> ReadbackResult + Send>,
+}
+
+impl PendingReadback {
+    /// Reads the first `size` bytes of every buffer in `sources`, concatenated.
+    pub(crate) fn buffers(
+        render_device: &RenderDevice,
+        encoder: &mut CommandEncoder,
+        sources: &[&Buffer],
+        size: u64,
+        finish: impl FnOnce(Vec<u8>) -> ReadbackResult + Send + 'static,
+    ) -> Self {
+        let buffer = staging_buffer(render_device, size * sources.len() as u64);
+        for (i, source) in sources.iter().enumerate() {
+            encoder.copy_buffer_to_buffer(source, 0, &buffer, size * i as u64, size);
+        }
+        PendingReadback {
+            buffer,
+            finish: Box::new(finish),
+        }
+    }
+
+    /// Reads a region of a `texture` with 4 byte texels, handing `finish`
+    /// tightly packed rows.
+    pub(crate) fn texture(
+        render_device: &RenderDevice,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        rect: URect,
+        finish: impl FnOnce(Vec<u8>) -> ReadbackResult + Send + 'static,
+    ) -> Self {
+        let row = rect.width() as usize * 4;
+        let padded_row = RenderDevice::align_copy_bytes_per_row(row);
+        let buffer = staging_buffer(render_device, (padded_row * rect.height() as usize) as u64);
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: rect.min.x,
+                    y: rect.min.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row as u32),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: rect.width(),
+                height: rect.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+        PendingReadback {
+            buffer,
+            finish: Box::new(move |data: Vec<u8>| {
+                let rows = data
+                    .chunks_exact(padded_row)
+                    .flat_map(|padded| &padded[..row])
+                    .copied()
+                    .collect();
+                finish(rows)
+            }),
+        }
+    }
+
+    pub(crate) fn map(self, sender: &ReadbackSender) {
+        let PendingReadback { buffer, finish } = self;
+        let sender = sender.0.clone();
+        let mapped = buffer.clone();
+        buffer.slice(..).map_async(MapMode::Read, move |result| {
+            if result.is_err() {
+                warn!("pixel map readback failed");
+                return;
+            }
+            let data = mapped.slice(..).get_mapped_range().to_vec();
+            mapped.unmap();
+            let _ = sender.send(finish(data));
+        });
+    }
+}
+
+fn staging_buffer(render_device: &RenderDevice, size: u64) -> Buffer {
+    render_device.create_buffer(&BufferDescriptor {
+        label: Some("pixel map readback buffer"),
+        size,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    })
+}
+
+pub(crate) fn apply_readbacks(world: &mut World) {
+    let results: Vec<ReadbackResult> = {
+        let receiver = world.resource::<ReadbackReceiver>();
+        let receiver = receiver.0.lock().expect("readback receiver poisoned");
+        receiver.try_iter().collect()
+    };
+    for result in results {
+        result(world);
+    }
+}
@@ -0,0 +1,453 @@
+use bevy::prelude::*;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::is_gpu_only;
+use crate::deterministic::sorted_chunks;
+use crate::replication::StampTextures;
+use crate::{
+    DeterministicSettings, PixelEdit, PixelMap, PixelMapRecordingFailed, PixelMapRecordingReady,
+    PixelMapReplayVerified, PixelPositionedTexture, RegionExported,
+};
+
+/// The edits made on one tick of a [`PixelMapRecording`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RecordedFrame {
+    pub tick: u64,
+    pub edits: Vec<PixelEdit>,
+}
+
+/// The edits of a map over the ticks `start_tick..end_tick`, with its chunks
+/// before and after, to play back with [`PixelMapReplay`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PixelMapRecording {
+    pub chunk_size: UVec2,
+    pub deterministic: Option<DeterministicSettings>,
+    pub start_tick: u64,
+    pub end_tick: u64,
+    /// Chunk positions and their pixels at `start_tick`, rows from the top.
+    pub start_chunks: Vec<(IVec2, Vec<u8>)>,
+    /// Ticks with edits, in order.
+    pub frames: Vec<RecordedFrame>,
+    /// Chunk positions and their pixels at `end_tick`, rows from the top.
+    pub end_chunks: Vec<(IVec2, Vec<u8>)>,
+}
+
+/// An edit a [`PixelMapRecording`] can't hold, since only the GPU knows how
+/// it turns out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnrecordableEdit {
+    /// Particles started flying, from an explosion, a simulation eject or
+    /// [`PixelMap::emit_particles`].
+    Particles,
+    /// A [`PixelMap::copy_region`] clip was stamped before its pixels were
+    /// read back.
+    RegionCopy,
+}
+
+impl std::fmt::Display for UnrecordableEdit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnrecordableEdit::Particles => write!(f, "particles started flying"),
+            UnrecordableEdit::RegionCopy => {
+                write!(f, "a region copy was stamped before it was read back")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnrecordableEdit {}
+
+/// Chunks of a map being read back.
+#[derive(Clone, Debug, Default)]
+struct ChunkReadback {
+    exports: HashMap<u64, IVec2>,
+    chunks: Vec<(IVec2, Vec<u8>)>,
+}
+
+impl ChunkReadback {
+    fn new(exports: HashMap<u64, IVec2>) -> Self {
+        ChunkReadback {
+            exports,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Takes the chunks out of `exports`, true once all of them are in.
+    fn collect(&mut self, map: Entity, exports: &[&RegionExported]) -> bool {
+        for export in exports.iter().filter(|export| export.map == map) {
+            if let Some(chunk_pos) = self.exports.remove(&export.id) {
+                self.chunks.push((chunk_pos, export.image.data.clone()));
+            }
+        }
+        self.exports.is_empty()
+    }
+}
+
+/// See [`PixelMap::start_recording`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PixelRecorder {
+    stamps: StampTextures,
+    deterministic: Option<DeterministicSettings>,
+    /// Ticks of the frames the chunks were exported on, known once flushed.
+    start_tick: Option<u64>,
+    end_tick: Option<u64>,
+    start: ChunkReadback,
+    current: Vec<PixelEdit>,
+    frames: Vec<RecordedFrame>,
+    /// The chunks at the end once stopped.
+    end: Option<ChunkReadback>,
+    /// The edit the recording gave up at.
+    failed: Option<UnrecordableEdit>,
+}
+
+impl PixelRecorder {
+    fn is_recording(&self) -> bool {
+        self.end.is_none() && self.failed.is_none()
+    }
+
+    pub(crate) fn record(&mut self, edit: PixelEdit) {
+        if self.is_recording() {
+            self.current.push(edit);
+        }
+    }
+
+    pub(crate) fn record_stamps(
+        &mut self,
+        textures: &[PixelPositionedTexture],
+        images: &Assets<Image>,
+    ) {
+        if !self.is_recording() {
+            return;
+        }
+        if textures
+            .iter()
+            .any(|texture| is_gpu_only(&texture.image, images))
+        {
+            self.failed = Some(UnrecordableEdit::RegionCopy);
+            return;
+        }
+        let edits = self.stamps.stamp_edits(textures, images);
+        self.current.extend(edits);
+    }
+
+    pub(crate) fn forget_stamp_texture(&mut self, image: AssetId<Image>) {
+        self.stamps.forget(image);
+    }
+}
+
+impl PixelMap {
+    /// Records stamps, [`PixelMap::set_pixels_cpu`] writes, cuts, imports,
+    /// undo, redo and chunks being added or removed along with their
+    /// simulation tick, until [`PixelMap::stop_recording`]. The chunks are
+    /// read back as they are before this frame's edits, so start it before
+    /// making any. Replaces a recording in progress.
+    ///
+    /// Particles and stamps of [`PixelMap::copy_region`] clips still on the
+    /// GPU can't be replayed, so the recording stops at the first of them
+    /// with a [`PixelMapRecordingFailed`] event instead.
+    pub fn start_recording(&mut self, images: &mut ResMut<Assets<Image>>) {
+        let exports = self.export_chunks(images);
+        self.recorder = Some(PixelRecorder {
+            deterministic: self.deterministic_settings().copied(),
+            start: ChunkReadback::new(exports),
+            ..default()
+        });
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder
+            .as_ref()
+            .is_some_and(PixelRecorder::is_recording)
+    }
+
+    /// Stops the recording at an edit it can't hold.
+    pub(crate) fn refuse_recording(&mut self, edit: UnrecordableEdit) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if recorder.is_recording() {
+                recorder.failed = Some(edit);
+            }
+        }
+    }
+
+    /// Ends the recording before this frame's edits. A
+    /// [`PixelMapRecordingReady`] event carries it once the chunks have been
+    /// read back. False when not recording.
+    pub fn stop_recording(&mut self, images: &mut ResMut<Assets<Image>>) -> bool {
+        if !self.is_recording() {
+            return false;
+        }
+        let exports = self.export_chunks(images);
+        let recorder = self.recorder.as_mut().expect("recording");
+        recorder.current.clear();
+        recorder.end = Some(ChunkReadback::new(exports));
+        true
+    }
+}
+
+/// Turns the edits recorded this frame into a frame of their tick.
+pub(crate) fn flush_recordings(mut pixel_map_query: Query<&mut PixelMap>) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        let tick = pixel_map.simulation_tick;
+        let Some(recorder) = pixel_map.recorder.as_mut() else {
+            continue;
+        };
+        recorder.start_tick.get_or_insert(tick);
+        if recorder.end.is_some() {
+            recorder.end_tick.get_or_insert(tick);
+        }
+        if recorder.current.is_empty() {
+            continue;
+        }
+        let edits = std::mem::take(&mut recorder.current);
+        match recorder.frames.last_mut() {
+            // Paused maps make several frames on the same tick.
+            Some(frame) if frame.tick == tick => frame.edits.extend(edits),
+            _ => recorder.frames.push(RecordedFrame { tick, edits }),
+        }
+    }
+}
+
+pub(crate) fn collect_recordings(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut exports: EventReader<RegionExported>,
+    mut ready: EventWriter<PixelMapRecordingReady>,
+    mut failed: EventWriter<PixelMapRecordingFailed>,
+) {
+    let exports: Vec<&RegionExported> = exports.read().collect();
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        let chunk_size = pixel_map.chunk_size;
+        let Some(recorder) = pixel_map.recorder.as_mut() else {
+            continue;
+        };
+        if let Some(edit) = recorder.failed {
+            warn!("stopped recording pixel map {map}: {edit}");
+            pixel_map.recorder = None;
+            failed.send(PixelMapRecordingFailed { map, edit });
+            continue;
+        }
+        let started = recorder.start.collect(map, &exports);
+        let Some(end) = recorder.end.as_mut() else {
+            continue;
+        };
+        if !end.collect(map, &exports) || !started {
+            continue;
+        }
+        let (Some(start_tick), Some(end_tick)) = (recorder.start_tick, recorder.end_tick) else {
+            continue;
+        };
+        let recorder = pixel_map.recorder.take().expect("recording");
+        ready.send(PixelMapRecordingReady {
+            map,
+            recording: PixelMapRecording {
+                chunk_size,
+                deterministic: recorder.deterministic,
+                start_tick,
+                end_tick,
+                start_chunks: recorder.start.chunks,
+                frames: recorder.frames,
+                end_chunks: recorder.end.expect("stopped").chunks,
+            },
+        });
+    }
+}
+
+/// Plays a [`PixelMapRecording`] back against `map`, one recorded tick per
+/// frame unless fast forwarding, replacing its chunks with the recorded ones
+/// first. The simulation
+/// of the map is paused whenever the replay is. At the end the chunks are
+/// read back and compared with the recorded ones in a
+/// [`PixelMapReplayVerified`] event.
+#[derive(Resource, Clone, Debug)]
+pub struct PixelMapReplay {
+    pub map: Entity,
+    recording: PixelMapRecording,
+    stamps: StampTextures,
+    /// Tick played next, and the tick to pause at.
+    tick: u64,
+    target: u64,
+    ticks_per_frame: u32,
+    next_frame: usize,
+    reset: bool,
+    verification: Option<ChunkReadback>,
+    mismatched_chunks: Option<Vec<IVec2>>,
+}
+
+impl PixelMapReplay {
+    /// Plays the whole recording.
+    pub fn new(map: Entity, recording: PixelMapRecording) -> Self {
+        PixelMapReplay {
+            map,
+            tick: recording.start_tick,
+            target: recording.end_tick,
+            ticks_per_frame: 1,
+            recording,
+            stamps: StampTextures::default(),
+            next_frame: 0,
+            reset: true,
+            verification: None,
+            mismatched_chunks: None,
+        }
+    }
+
+    pub fn recording(&self) -> &PixelMapRecording {
+        &self.recording
+    }
+
+    /// The tick played next. While paused, the map is as the recorded one
+    /// was before that tick's edits.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.tick < self.target
+    }
+
+    pub fn play(&mut self) {
+        self.target = self.recording.end_tick;
+        self.ticks_per_frame = 1;
+    }
+
+    pub fn pause(&mut self) {
+        self.target = self.tick;
+    }
+
+    /// Plays up to `tick` and pauses there. Seeking backward starts over
+    /// from the recorded chunks, since ticks can't be undone; either way the
+    /// ticks in between are played one per frame.
+    pub fn seek(&mut self, tick: u64) {
+        let tick = tick.clamp(self.recording.start_tick, self.recording.end_tick);
+        if tick < self.tick {
+            self.tick = self.recording.start_tick;
+            self.next_frame = 0;
+            self.reset = true;
+            self.verification = None;
+            self.mismatched_chunks = None;
+        }
+        self.target = tick;
+        self.ticks_per_frame = 1;
+    }
+
+    /// Plays `ticks` more ticks and pauses there, simulating up to
+    /// `ticks_per_frame` of them each frame. Ticks with recorded edits still
+    /// start a frame of their own, so the edits land between the same ticks.
+    pub fn fast_forward(&mut self, ticks: u64, ticks_per_frame: u32) {
+        self.seek(self.tick.saturating_add(ticks));
+        self.ticks_per_frame = ticks_per_frame.max(1);
+    }
+
+    /// Chunks that differ from the recording once played to the end, empty if
+    /// none do.
+    pub fn mismatched_chunks(&self) -> Option<&[IVec2]> {
+        self.mismatched_chunks.as_deref()
+    }
+}
+
+pub(crate) fn play_replay(
+    mut commands: Commands,
+    replay: Option<ResMut<PixelMapReplay>>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let Some(mut replay) = replay else {
+        return;
+    };
+    let replay = replay.as_mut();
+    let Ok(mut pixel_map) = pixel_map_query.get_mut(replay.map) else {
+        return;
+    };
+    if replay.reset {
+        if replay.recording.chunk_size != pixel_map.chunk_size {
+            warn!(
+                "can't replay a recording of {} chunks on a map of {} chunks",
+                replay.recording.chunk_size, pixel_map.chunk_size
+            );
+            commands.remove_resource::<PixelMapReplay>();
+            return;
+        }
+        replay.reset = false;
+        match replay.recording.deterministic {
+            Some(settings) => pixel_map.enable_deterministic_simulation(settings),
+            None => pixel_map.disable_deterministic_simulation(),
+        }
+        let chunks = replay.recording.start_chunks.clone();
+        pixel_map.replace_chunks(chunks, &mut commands, &mut images);
+        pixel_map.simulation_tick = replay.tick;
+    }
+    if !replay.is_playing() {
+        pixel_map.pause_simulation();
+        if replay.tick == replay.recording.end_tick
+            && replay.verification.is_none()
+            && replay.mismatched_chunks.is_none()
+        {
+            let exports = pixel_map.export_chunks(&mut images);
+            replay.verification = Some(ChunkReadback::new(exports));
+        }
+        return;
+    }
+    pixel_map.resume_simulation();
+    pixel_map.simulation_tick = replay.tick;
+    while let Some(frame) = replay.recording.frames.get(replay.next_frame) {
+        if frame.tick > replay.tick {
+            break;
+        }
+        if frame.tick == replay.tick {
+            for edit in frame.edits.iter().cloned() {
+                pixel_map.apply_edit(&mut replay.stamps, edit, &mut commands, &mut images);
+            }
+        }
+        replay.next_frame += 1;
+    }
+    let next_edits = replay
+        .recording
+        .frames
+        .get(replay.next_frame)
+        .map_or(u64::MAX, |frame| frame.tick);
+    let steps = (replay.ticks_per_frame as u64)
+        .min(replay.target - replay.tick)
+        .min(next_edits - replay.tick);
+    pixel_map.simulation_steps = steps as u32;
+    replay.tick += steps;
+}
+
+pub(crate) fn verify_replay(
+    replay: Option<ResMut<PixelMapReplay>>,
+    mut exports: EventReader<RegionExported>,
+    mut verified: EventWriter<PixelMapReplayVerified>,
+) {
+    let exports: Vec<&RegionExported> = exports.read().collect();
+    let Some(mut replay) = replay else {
+        return;
+    };
+    let replay = replay.as_mut();
+    let map = replay.map;
+    let Some(verification) = replay.verification.as_mut() else {
+        return;
+    };
+    if !verification.collect(map, &exports) {
+        return;
+    }
+    let verification = replay.verification.take().expect("verifying");
+    let played: HashMap<IVec2, &Vec<u8>> = verification
+        .chunks
+        .iter()
+        .map(|(chunk_pos, data)| (*chunk_pos, data))
+        .collect();
+    let recorded: HashMap<IVec2, &Vec<u8>> = replay
+        .recording
+        .end_chunks
+        .iter()
+        .map(|(chunk_pos, data)| (*chunk_pos, data))
+        .collect();
+    let positions: HashSet<IVec2> = played.keys().chain(recorded.keys()).copied().collect();
+    let mismatched_chunks: Vec<IVec2> = sorted_chunks(positions)
+        .into_iter()
+        .filter(|chunk_pos| played.get(chunk_pos) != recorded.get(chunk_pos))
+        .collect();
+    replay.mismatched_chunks = Some(mismatched_chunks.clone());
+    verified.send(PixelMapReplayVerified {
+        map,
+        mismatched_chunks,
+    });
+}
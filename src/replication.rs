@@ -0,0 +1,692 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::utils::hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::{chunk_copy_rect, chunks_in_rect, is_gpu_only};
+use crate::export::copy_rows;
+use crate::import::RegionWrite;
+use crate::{PixelMap, PixelMapSnapshotReady, PixelPositionedTexture, RegionExported};
+
+/// One edit of a [`PixelEditBatch`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PixelEdit {
+    /// Pixels of a stamp texture, sent before the first stamp using it.
+    /// `id` is a hash of the size and pixels.
+    Texture {
+        id: u64,
+        size: UVec2,
+        data: Vec<u8>,
+    },
+    /// A [`PixelMap::set_pixels_gpu`] texture.
+    Stamp {
+        position: IVec2,
+        size: UVec2,
+        texture: u64,
+    },
+    /// A [`PixelMap::set_pixels_cpu`] call, or pixels cleared by debris.
+    Pixels(Vec<(IVec2, [u8; 4])>),
+    /// Pixels written over `rect` of a chunk, rows from the top. Cuts,
+    /// imports, undo and redo make these, and so do edits whose outcome only
+    /// the GPU knows once their pixels have been read back.
+    Region {
+        chunk_pos: IVec2,
+        rect: URect,
+        data: Vec<u8>,
+    },
+    AddChunk(IVec2),
+    RemoveChunk(IVec2),
+}
+
+/// The edits of one frame of a replicated map, to apply with
+/// [`PixelMap::receive_edits`] on the other side.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PixelEditBatch {
+    pub sequence: u64,
+    pub edits: Vec<PixelEdit>,
+}
+
+/// Every chunk and stamp texture of a replicated map, for late joiners.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PixelMapSnapshot {
+    /// Sequence of the first [`PixelEditBatch`] not contained in the
+    /// snapshot.
+    pub sequence: u64,
+    pub chunk_size: UVec2,
+    /// Chunk positions and their pixels, rows from the top.
+    pub chunks: Vec<(IVec2, Vec<u8>)>,
+    pub textures: Vec<(u64, UVec2, Vec<u8>)>,
+}
+
+/// Read backs of the rects of edits only the GPU knows the outcome of, to go
+/// in front of the batch of `sequence`.
+#[derive(Clone, Debug)]
+struct PendingRegions {
+    sequence: u64,
+    exports: HashMap<u64, IRect>,
+    edits: Vec<PixelEdit>,
+}
+
+#[derive(Clone, Debug)]
+struct PendingSnapshot {
+    id: u64,
+    sequence: u64,
+    exports: HashMap<u64, IVec2>,
+    chunks: Vec<(IVec2, Vec<u8>)>,
+}
+
+/// Stamp textures by id, sent or received, and the ids of the sent ones.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StampTextures {
+    textures: HashMap<u64, (Handle<Image>, UVec2)>,
+    /// Ids and sizes of the images stamped so far, until they are modified.
+    ids: HashMap<AssetId<Image>, (u64, UVec2)>,
+    sent: HashSet<u64>,
+}
+
+impl StampTextures {
+    fn id(&mut self, handle: &Handle<Image>, images: &Assets<Image>) -> Option<u64> {
+        let (id, size) = match self.ids.get(&handle.id()) {
+            Some(&cached) => cached,
+            None => {
+                let image = images.get(handle)?;
+                let size = image.size();
+                let id = texture_hash(size, &image.data);
+                self.ids.insert(handle.id(), (id, size));
+                (id, size)
+            }
+        };
+        self.textures
+            .entry(id)
+            .or_insert_with(|| (handle.clone(), size));
+        Some(id)
+    }
+
+    /// Drops the id of an image whose pixels changed, so its next stamp
+    /// hashes it again. The texture of the old id goes too if this image was
+    /// holding it, and is sent again should another image still use it.
+    pub(crate) fn forget(&mut self, image: AssetId<Image>) {
+        let Some((id, _)) = self.ids.remove(&image) else {
+            return;
+        };
+        if self
+            .textures
+            .get(&id)
+            .is_some_and(|(handle, _)| handle.id() == image)
+        {
+            self.textures.remove(&id);
+            self.sent.remove(&id);
+        }
+    }
+
+    /// The edits stamping `textures`, each texture's pixels going before its
+    /// first stamp.
+    pub(crate) fn stamp_edits<'a>(
+        &mut self,
+        textures: impl IntoIterator<Item = &'a PixelPositionedTexture>,
+        images: &Assets<Image>,
+    ) -> Vec<PixelEdit> {
+        let mut edits = Vec::new();
+        for texture in textures {
+            let Some(id) = self.id(&texture.image, images) else {
+                continue;
+            };
+            if self.sent.insert(id) {
+                let image = images.get(&texture.image).expect("hashed");
+                edits.push(PixelEdit::Texture {
+                    id,
+                    size: image.size(),
+                    data: image.data.clone(),
+                });
+            }
+            edits.push(PixelEdit::Stamp {
+                position: texture.position,
+                size: texture.size,
+                texture: id,
+            });
+        }
+        edits
+    }
+
+    fn receive(&mut self, id: u64, size: UVec2, data: Vec<u8>, images: &mut Assets<Image>) {
+        self.textures
+            .entry(id)
+            .or_insert_with(|| (images.add(stamp_image(size, data)), size));
+    }
+}
+
+/// Recorded and received edits of a map, see
+/// [`PixelMap::enable_replication`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PixelReplication {
+    recording: Vec<PixelEdit>,
+    /// Rects to read back once this frame's edits are in, and the ones of
+    /// the frame before, read back this frame.
+    readbacks: Vec<IRect>,
+    deferred_readbacks: Vec<IRect>,
+    /// Batches from the first of these on wait for them.
+    regions: Vec<PendingRegions>,
+    outgoing: VecDeque<PixelEditBatch>,
+    next_sequence: u64,
+    stamps: StampTextures,
+    incoming: BTreeMap<u64, PixelEditBatch>,
+    expected_sequence: u64,
+    incoming_snapshot: Option<PixelMapSnapshot>,
+    snapshots: Vec<PendingSnapshot>,
+    snapshot_count: u64,
+}
+
+/// FNV-1a, so ids match between builds.
+fn texture_hash(size: UVec2, data: &[u8]) -> u64 {
+    size.x
+        .to_le_bytes()
+        .iter()
+        .chain(size.y.to_le_bytes().iter())
+        .chain(data.iter())
+        .fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn stamp_image(size: UVec2, data: Vec<u8>) -> Image {
+    Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::all(),
+    )
+}
+
+impl PixelMap {
+    /// Records stamps, [`PixelMap::set_pixels_cpu`] writes, cuts, imports,
+    /// undo, redo and chunks being added or removed into batches, one per
+    /// frame, to send to other maps with [`PixelMap::drain_outgoing_edits`].
+    /// Particles, from explosions or otherwise, and stamps of
+    /// [`PixelMap::copy_region`] images still on the GPU are sent as the
+    /// pixels they leave behind once read back. Edits received from other
+    /// maps aren't sent on.
+    pub fn enable_replication(&mut self) {
+        self.replication
+            .get_or_insert_with(PixelReplication::default);
+    }
+
+    pub fn disable_replication(&mut self) {
+        self.replication = None;
+    }
+
+    pub fn is_replicating(&self) -> bool {
+        self.replication.is_some()
+    }
+
+    /// Hands an edit to replication and to the recording, if any.
+    pub(crate) fn record_edit(&mut self, edit: PixelEdit) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(edit.clone());
+        }
+        if let Some(replication) = self.replication.as_mut() {
+            replication.recording.push(edit);
+        }
+    }
+
+    pub(crate) fn record_stamps(
+        &mut self,
+        textures: &[PixelPositionedTexture],
+        images: &Assets<Image>,
+    ) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_stamps(textures, images);
+        }
+        if let Some(replication) = self.replication.as_mut() {
+            let (gpu_only, textures): (Vec<_>, Vec<_>) = textures
+                .iter()
+                .partition(|texture| is_gpu_only(&texture.image, images));
+            let edits = replication.stamps.stamp_edits(textures, images);
+            replication.recording.extend(edits);
+            replication
+                .readbacks
+                .extend(gpu_only.into_iter().map(|texture| {
+                    IRect::from_corners(
+                        texture.position,
+                        texture.position + texture.size.as_ivec2(),
+                    )
+                }));
+        }
+    }
+
+    /// Sends the pixels of `rect` once this frame's edits are in, for edits
+    /// whose outcome only the GPU knows.
+    pub(crate) fn record_readback(&mut self, rect: IRect) {
+        if let Some(replication) = self.replication.as_mut() {
+            replication.readbacks.push(rect);
+        }
+    }
+
+    /// Region edits writing the pixels read back for `rect`, rows from the
+    /// top, over the chunks of the map.
+    fn region_edits(&self, rect: IRect, data: &[u8]) -> Vec<PixelEdit> {
+        chunks_in_rect(rect, self.chunk_size)
+            .filter(|chunk_pos| self.positions.contains_key(chunk_pos))
+            .filter_map(|chunk_pos| {
+                let (texture_rect, origin) = chunk_copy_rect(chunk_pos, self.chunk_size, rect)?;
+                let size = texture_rect.size();
+                let mut region = vec![0; size.element_product() as usize * 4];
+                copy_rows(
+                    data,
+                    rect.width() as u32,
+                    URect::from_corners(origin, origin + size),
+                    &mut region,
+                    size.x,
+                    UVec2::ZERO,
+                );
+                Some(PixelEdit::Region {
+                    chunk_pos,
+                    rect: texture_rect,
+                    data: region,
+                })
+            })
+            .collect()
+    }
+
+    /// Batches recorded so far, oldest first. Each frame with edits makes
+    /// one batch. Batches wait here while pixels that go in front of them
+    /// are being read back.
+    pub fn drain_outgoing_edits(&mut self) -> impl Iterator<Item = PixelEditBatch> + '_ {
+        self.replication.iter_mut().flat_map(|replication| {
+            let held = replication
+                .regions
+                .iter()
+                .map(|regions| regions.sequence)
+                .min()
+                .unwrap_or(u64::MAX);
+            let ready = replication
+                .outgoing
+                .iter()
+                .take_while(|batch| batch.sequence < held)
+                .count();
+            replication.outgoing.drain(..ready)
+        })
+    }
+
+    /// Queues a batch of another map to be applied in sequence order. Batches
+    /// can arrive out of order; the ones before the last snapshot are
+    /// dropped. Enables replication.
+    pub fn receive_edits(&mut self, batch: PixelEditBatch) {
+        let replication = self
+            .replication
+            .get_or_insert_with(PixelReplication::default);
+        if batch.sequence >= replication.expected_sequence {
+            replication.incoming.insert(batch.sequence, batch);
+        }
+    }
+
+    /// Batches received but waiting for an earlier one.
+    pub fn pending_incoming_edits(&self) -> usize {
+        self.replication
+            .as_ref()
+            .map_or(0, |replication| replication.incoming.len())
+    }
+
+    /// Captures every chunk as it is on the GPU before this frame's edits.
+    /// A [`PixelMapSnapshotReady`] event with the returned id carries the
+    /// snapshot once the chunks have been read back. Request it during
+    /// [`Update`] so this frame's edits go to the batch the snapshot starts
+    /// at. `None` without replication.
+    pub fn request_replication_snapshot(
+        &mut self,
+        images: &mut ResMut<Assets<Image>>,
+    ) -> Option<u64> {
+        let mut replication = self.replication.take()?;
+        let exports = self.export_chunks(images);
+        replication.snapshot_count += 1;
+        let id = replication.snapshot_count;
+        replication.snapshots.push(PendingSnapshot {
+            id,
+            sequence: replication.next_sequence,
+            exports,
+            chunks: Vec::new(),
+        });
+        self.replication = Some(replication);
+        Some(id)
+    }
+
+    /// Exports every chunk, by export id.
+    pub(crate) fn export_chunks(
+        &mut self,
+        images: &mut ResMut<Assets<Image>>,
+    ) -> HashMap<u64, IVec2> {
+        let chunk_size = self.chunk_size.as_ivec2();
+        let positions: Vec<IVec2> = self.positions.keys().copied().collect();
+        positions
+            .into_iter()
+            .filter_map(|chunk_pos| {
+                let origin = chunk_pos * chunk_size;
+                let rect = IRect::from_corners(origin, origin + chunk_size);
+                Some((self.request_export(rect, 1, images)?, chunk_pos))
+            })
+            .collect()
+    }
+
+    /// Replaces the chunks of the map with the ones of `snapshot` next
+    /// frame, then applies the batches received from its sequence on.
+    /// Enables replication.
+    pub fn receive_snapshot(&mut self, snapshot: PixelMapSnapshot) {
+        let replication = self
+            .replication
+            .get_or_insert_with(PixelReplication::default);
+        replication
+            .incoming
+            .retain(|&sequence, _| sequence >= snapshot.sequence);
+        replication.expected_sequence = snapshot.sequence;
+        replication.incoming_snapshot = Some(snapshot);
+    }
+
+    fn apply_snapshot(
+        &mut self,
+        replication: &mut PixelReplication,
+        snapshot: PixelMapSnapshot,
+        commands: &mut Commands,
+        images: &mut ResMut<Assets<Image>>,
+    ) {
+        if snapshot.chunk_size != self.chunk_size {
+            warn!(
+                "ignoring a snapshot of {} chunks for a map of {} chunks",
+                snapshot.chunk_size, self.chunk_size
+            );
+            return;
+        }
+        for (id, size, data) in snapshot.textures {
+            replication.stamps.receive(id, size, data, images);
+        }
+        self.replace_chunks(snapshot.chunks, commands, images);
+    }
+
+    /// Removes every chunk but `chunks` and overwrites their pixels before
+    /// this frame's edits.
+    pub(crate) fn replace_chunks(
+        &mut self,
+        chunks: Vec<(IVec2, Vec<u8>)>,
+        commands: &mut Commands,
+        images: &mut ResMut<Assets<Image>>,
+    ) {
+        let kept: HashSet<IVec2> = chunks.iter().map(|(pos, _)| *pos).collect();
+        let removed: Vec<IVec2> = self
+            .positions
+            .keys()
+            .filter(|pos| !kept.contains(*pos))
+            .copied()
+            .collect();
+        for chunk_pos in removed {
+            self.remove_chunk(chunk_pos, commands, images);
+        }
+        for (chunk_pos, data) in chunks {
+            self.add_chunk(chunk_pos, commands, images);
+            self.queue_region_write(RegionWrite {
+                chunk_pos,
+                rect: URect::from_corners(UVec2::ZERO, self.chunk_size),
+                data: Arc::from(data),
+            });
+        }
+    }
+
+    pub(crate) fn apply_edit(
+        &mut self,
+        stamps: &mut StampTextures,
+        edit: PixelEdit,
+        commands: &mut Commands,
+        images: &mut ResMut<Assets<Image>>,
+    ) {
+        match edit {
+            PixelEdit::Texture { id, size, data } => stamps.receive(id, size, data, images),
+            PixelEdit::Stamp {
+                position,
+                size,
+                texture,
+            } => {
+                let Some((image, _)) = stamps.textures.get(&texture) else {
+                    warn!("stamp of an unknown texture {texture:x}");
+                    return;
+                };
+                self.set_pixels_gpu(
+                    vec![PixelPositionedTexture {
+                        position,
+                        image: image.clone(),
+                        size,
+                    }],
+                    images,
+                );
+            }
+            PixelEdit::Pixels(pixels) => self.set_pixels_cpu(&pixels, commands, images),
+            PixelEdit::Region {
+                chunk_pos,
+                rect,
+                data,
+            } => {
+                let fits = rect.max.cmple(self.chunk_size).all()
+                    && data.len() == rect.size().element_product() as usize * 4;
+                if !fits {
+                    warn!(
+                        "region write of {} bytes over {rect:?} doesn't fit a chunk",
+                        data.len()
+                    );
+                    return;
+                }
+                self.add_chunk(chunk_pos, commands, images);
+                self.queue_region_write(RegionWrite {
+                    chunk_pos,
+                    rect,
+                    data: Arc::from(data),
+                });
+            }
+            PixelEdit::AddChunk(chunk_pos) => self.add_chunk(chunk_pos, commands, images),
+            PixelEdit::RemoveChunk(chunk_pos) => self.remove_chunk(chunk_pos, commands, images),
+        }
+    }
+}
+
+/// Forgets the stamp texture ids of modified images, see
+/// [`StampTextures::forget`].
+pub(crate) fn forget_modified_stamp_textures(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut pixel_map_query: Query<&mut PixelMap>,
+) {
+    let modified: Vec<AssetId<Image>> = events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    if modified.is_empty() {
+        return;
+    }
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        if pixel_map.replication.is_none() && pixel_map.recorder.is_none() {
+            continue;
+        }
+        let pixel_map = pixel_map.as_mut();
+        for &image in modified.iter() {
+            if let Some(replication) = pixel_map.replication.as_mut() {
+                replication.stamps.forget(image);
+            }
+            if let Some(recorder) = pixel_map.recorder.as_mut() {
+                recorder.forget_stamp_texture(image);
+            }
+        }
+    }
+}
+
+/// Applies received snapshots and batches in order. Replication is taken
+/// out of the map meanwhile so the edits aren't recorded again.
+pub(crate) fn apply_incoming_edits(
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        let Some(mut replication) = pixel_map.replication.take() else {
+            continue;
+        };
+        if let Some(snapshot) = replication.incoming_snapshot.take() {
+            pixel_map.apply_snapshot(&mut replication, snapshot, &mut commands, &mut images);
+        }
+        while let Some(batch) = replication.incoming.remove(&replication.expected_sequence) {
+            replication.expected_sequence += 1;
+            for edit in batch.edits {
+                pixel_map.apply_edit(&mut replication.stamps, edit, &mut commands, &mut images);
+            }
+        }
+        pixel_map.replication = Some(replication);
+    }
+}
+
+/// Reads back the rects recorded the frame before, as they are after it,
+/// for this frame's batch.
+pub(crate) fn request_replication_readbacks(
+    mut pixel_map_query: Query<&mut PixelMap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        let waiting = pixel_map
+            .replication
+            .as_ref()
+            .is_some_and(|replication| !replication.deferred_readbacks.is_empty());
+        if !waiting {
+            continue;
+        }
+        let mut replication = pixel_map.replication.take().expect("waiting");
+        let exports = std::mem::take(&mut replication.deferred_readbacks)
+            .into_iter()
+            .filter_map(|rect| Some((pixel_map.request_export(rect, 1, &mut images)?, rect)))
+            .collect();
+        replication.regions.push(PendingRegions {
+            sequence: replication.next_sequence,
+            exports,
+            edits: Vec::new(),
+        });
+        pixel_map.replication = Some(replication);
+    }
+}
+
+/// Puts the pixels read back for a batch in front of it.
+pub(crate) fn collect_replication_readbacks(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut exports: EventReader<RegionExported>,
+) {
+    let exports: Vec<&RegionExported> = exports.read().collect();
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        let reading = pixel_map
+            .replication
+            .as_ref()
+            .is_some_and(|replication| !replication.regions.is_empty());
+        if !reading {
+            continue;
+        }
+        let mut replication = pixel_map.replication.take().expect("reading");
+        for export in exports.iter().filter(|export| export.map == map) {
+            for regions in replication.regions.iter_mut() {
+                if let Some(rect) = regions.exports.remove(&export.id) {
+                    regions
+                        .edits
+                        .extend(pixel_map.region_edits(rect, &export.image.data));
+                }
+            }
+        }
+        replication.regions.retain_mut(|regions| {
+            if !regions.exports.is_empty() {
+                return true;
+            }
+            let Some(batch) = replication
+                .outgoing
+                .iter_mut()
+                .find(|batch| batch.sequence == regions.sequence)
+            else {
+                return true;
+            };
+            batch.edits.splice(0..0, regions.edits.drain(..));
+            false
+        });
+        pixel_map.replication = Some(replication);
+    }
+}
+
+/// Turns the edits recorded this frame into a batch.
+pub(crate) fn flush_replication(mut pixel_map_query: Query<&mut PixelMap>) {
+    for mut pixel_map in pixel_map_query.iter_mut() {
+        let Some(replication) = pixel_map.replication.as_mut() else {
+            continue;
+        };
+        let readbacks = std::mem::take(&mut replication.readbacks);
+        replication.deferred_readbacks.extend(readbacks);
+        let reading = replication
+            .regions
+            .iter()
+            .any(|regions| regions.sequence == replication.next_sequence);
+        if replication.recording.is_empty() && !reading {
+            continue;
+        }
+        let batch = PixelEditBatch {
+            sequence: replication.next_sequence,
+            edits: std::mem::take(&mut replication.recording),
+        };
+        replication.next_sequence += 1;
+        replication.outgoing.push_back(batch);
+    }
+}
+
+pub(crate) fn collect_replication_snapshots(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut exports: EventReader<RegionExported>,
+    mut ready: EventWriter<PixelMapSnapshotReady>,
+    images: Res<Assets<Image>>,
+) {
+    let exports: Vec<&RegionExported> = exports.read().collect();
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        let chunk_size = pixel_map.chunk_size;
+        let Some(replication) = pixel_map.replication.as_mut() else {
+            continue;
+        };
+        if replication.snapshots.is_empty() {
+            continue;
+        }
+        for export in exports.iter().filter(|export| export.map == map) {
+            for snapshot in replication.snapshots.iter_mut() {
+                if let Some(chunk_pos) = snapshot.exports.remove(&export.id) {
+                    snapshot.chunks.push((chunk_pos, export.image.data.clone()));
+                }
+            }
+        }
+        let (done, pending) = std::mem::take(&mut replication.snapshots)
+            .into_iter()
+            .partition(|snapshot| snapshot.exports.is_empty());
+        replication.snapshots = pending;
+        for snapshot in done {
+            let textures = replication
+                .stamps
+                .textures
+                .iter()
+                .filter_map(|(&id, (handle, size))| {
+                    Some((id, *size, images.get(handle)?.data.clone()))
+                })
+                .collect();
+            ready.send(PixelMapSnapshotReady {
+                map,
+                id: snapshot.id,
+                snapshot: PixelMapSnapshot {
+                    sequence: snapshot.sequence,
+                    chunk_size,
+                    chunks: snapshot.chunks,
+                    textures,
+                },
+            });
+        }
+    }
+}
@@ -0,0 +1,577 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::{RenderAssetUsages, RenderAssets};
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType,
+    BufferBindingType, BufferInitDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor,
+    ComputePipeline, Extent3d, IntoBinding, ShaderStages, StorageTextureAccess, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
+    TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::texture::GpuImage;
+use bevy::utils::hashbrown::HashMap;
+
+use crate::clipboard::{chunk_copy_rect, chunks_in_rect, TextureCopy};
+use crate::history::restore_texture_region;
+use crate::jump_flood::{jump_flood_bind_groups, jump_flood_cpu, pack_seed, unpack_seed, NO_SEED};
+use crate::readback::PendingReadback;
+use crate::{
+    get_chunk_index_i, get_chunk_outer_i, ChunkDirty, PixelMap, PixelMapShaderLayoutInput,
+};
+
+/// Signed distance field of a map, see [`PixelMap::enable_sdf`].
+///
+/// Every chunk has an `R32Float` field image of its size, with the first row
+/// at the top like the chunk image. Texels hold the distance in pixels from
+/// the pixel center to the nearest edge between solid pixels, with an alpha of
+/// at least 128, and empty ones: positive outside, negative inside, clamped to
+/// `max_distance`. Pixels of the neighboring chunks up to `max_distance` away
+/// count, so fields are recomputed for the chunks a [`ChunkDirty`] rect comes
+/// within `max_distance` of.
+#[derive(Clone, Debug, Default)]
+pub struct PixelMapSdf {
+    max_distance: u32,
+    halo: u32,
+    stale: bool,
+    fields: HashMap<IVec2, Handle<Image>>,
+    /// Copies of the fields for [`PixelMap::sdf_distance`], read back from
+    /// the GPU.
+    distances: HashMap<IVec2, Arc<[f32]>>,
+    pub(crate) updates: Vec<SdfUpdate>,
+}
+
+/// Recomputes the field of a chunk from the chunks around it.
+#[derive(Clone, Debug)]
+pub(crate) struct SdfUpdate {
+    pub chunk_pos: IVec2,
+    pub field: Handle<Image>,
+    pub halo: u32,
+    pub max_distance: u32,
+    /// Texels whose distances may have changed.
+    pub rect: URect,
+    pub pieces: Vec<SdfPiece>,
+}
+
+/// Part of a chunk image copied into the padded grid a field is flooded in,
+/// or filled with the default chunk color when there is no chunk.
+#[derive(Clone, Debug)]
+pub(crate) struct SdfPiece {
+    pub source: Option<Handle<Image>>,
+    pub rect: URect,
+    pub origin: UVec2,
+}
+
+impl PixelMapSdf {
+    pub fn max_distance(&self) -> u32 {
+        self.max_distance
+    }
+
+    /// Field image of a chunk, to read with `textureLoad` in shaders.
+    pub fn field(&self, chunk_position: IVec2) -> Option<&Handle<Image>> {
+        self.fields.get(&chunk_position)
+    }
+}
+
+impl PixelMap {
+    /// Keeps a signed distance field of every chunk, up to `max_distance`
+    /// pixels. Distances can reach into neighboring chunks, at most one chunk
+    /// far.
+    pub fn enable_sdf(&mut self, max_distance: u32) {
+        let max_distance = max_distance.max(1);
+        let sdf = self.sdf.get_or_insert_with(PixelMapSdf::default);
+        sdf.max_distance = max_distance;
+        sdf.halo = max_distance.min(self.chunk_size.min_element());
+        sdf.stale = true;
+    }
+
+    pub fn disable_sdf(&mut self) {
+        self.sdf = None;
+    }
+
+    pub fn sdf(&self) -> Option<&PixelMapSdf> {
+        self.sdf.as_ref()
+    }
+
+    /// Signed distance of a world pixel as of the latest field computed, or
+    /// read back from the GPU. `None` without a field for its chunk yet.
+    pub fn sdf_distance(&self, world_position: IVec2) -> Option<f32> {
+        let chunk_pos = get_chunk_outer_i(world_position, self.chunk_size);
+        let distances = self.sdf.as_ref()?.distances.get(&chunk_pos)?;
+        distances
+            .get(get_chunk_index_i(world_position, self.chunk_size))
+            .copied()
+    }
+
+    /// Where the chunks around a chunk go in its padded grid.
+    fn sdf_pieces(&self, chunk_pos: IVec2, halo: u32) -> Vec<SdfPiece> {
+        let size = self.chunk_size;
+        // Texture rows go down while chunk positions go up.
+        let span = |offset: i32, len: u32| match offset {
+            -1 => (len - halo..len, 0),
+            0 => (0..len, halo),
+            _ => (0..halo, halo + len),
+        };
+        let mut pieces = Vec::with_capacity(9);
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let (x_range, origin_x) = span(x, size.x);
+                let (y_range, origin_y) = span(-y, size.y);
+                let source = self
+                    .positions
+                    .get(&(chunk_pos + IVec2::new(x, y)))
+                    .map(|&index| self.image_data[index].clone());
+                pieces.push(SdfPiece {
+                    source,
+                    rect: URect::new(x_range.start, y_range.start, x_range.end, y_range.end),
+                    origin: UVec2::new(origin_x, origin_y),
+                });
+            }
+        }
+        pieces
+    }
+}
+
+fn field_image(size: UVec2, max_distance: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &(max_distance as f32).to_le_bytes(),
+        TextureFormat::R32Float,
+        RenderAssetUsages::all(),
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::TEXTURE_BINDING
+        | TextureUsages::STORAGE_BINDING;
+    image
+}
+
+pub(crate) fn update_sdf(
+    mut pixel_map_query: Query<(Entity, &mut PixelMap)>,
+    mut dirty: EventReader<ChunkDirty>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let dirty: Vec<&ChunkDirty> = dirty.read().collect();
+    for (map, mut pixel_map) in pixel_map_query.iter_mut() {
+        let Some(mut sdf) = pixel_map.sdf.take() else {
+            continue;
+        };
+        let chunk_size = pixel_map.chunk_size;
+        // Pixels whose distances may have changed, in world coordinates.
+        let mut changed: Vec<IRect> = dirty
+            .iter()
+            .filter(|event| event.map == map)
+            .map(|event| event.rect.inflate(sdf.max_distance as i32))
+            .collect();
+        let chunk_rect = |chunk_pos: IVec2| {
+            let origin = chunk_pos * chunk_size.as_ivec2();
+            IRect::from_corners(origin, origin + chunk_size.as_ivec2())
+        };
+        if sdf.stale {
+            changed.extend(pixel_map.positions.keys().map(|&pos| chunk_rect(pos)));
+            sdf.stale = false;
+        }
+        let added: Vec<IVec2> = pixel_map
+            .positions
+            .keys()
+            .filter(|chunk_pos| !sdf.fields.contains_key(*chunk_pos))
+            .copied()
+            .collect();
+        for chunk_pos in added {
+            let field = images.add(field_image(chunk_size, sdf.max_distance));
+            sdf.fields.insert(chunk_pos, field);
+            changed.push(chunk_rect(chunk_pos));
+        }
+        let removed: Vec<IVec2> = sdf
+            .fields
+            .keys()
+            .filter(|chunk_pos| !pixel_map.positions.contains_key(*chunk_pos))
+            .copied()
+            .collect();
+        for chunk_pos in removed {
+            sdf.fields.remove(&chunk_pos);
+            sdf.distances.remove(&chunk_pos);
+            changed.push(chunk_rect(chunk_pos).inflate(sdf.max_distance as i32));
+        }
+
+        // Only the chunks the changes reach are flooded again, and only the
+        // part within reach is read back.
+        let mut stale: HashMap<IVec2, URect> = HashMap::new();
+        for rect in changed {
+            for chunk_pos in chunks_in_rect(rect, chunk_size) {
+                if !sdf.fields.contains_key(&chunk_pos) {
+                    continue;
+                }
+                let Some((texels, _)) = chunk_copy_rect(chunk_pos, chunk_size, rect) else {
+                    continue;
+                };
+                stale
+                    .entry(chunk_pos)
+                    .and_modify(|existing| *existing = existing.union(texels))
+                    .or_insert(texels);
+            }
+        }
+        for (chunk_pos, rect) in stale {
+            sdf.updates.push(SdfUpdate {
+                chunk_pos,
+                field: sdf.fields[&chunk_pos].clone(),
+                halo: sdf.halo,
+                max_distance: sdf.max_distance,
+                rect,
+                pieces: pixel_map.sdf_pieces(chunk_pos, sdf.halo),
+            });
+        }
+        pixel_map.sdf = Some(sdf);
+    }
+}
+
+/// Seeds of the empty texels to flood outside and of the solid ones to flood
+/// inside, like `sdf_seed.wgsl`.
+fn seeds_cpu(solid: &[bool], size: UVec2) -> [Vec<u32>; 2] {
+    let seeds = |inside: bool| {
+        solid
+            .iter()
+            .enumerate()
+            .map(|(ind, &solid)| {
+                let texel = UVec2::new(ind as u32 % size.x, ind as u32 / size.x);
+                if solid != inside {
+                    pack_seed(texel)
+                } else {
+                    NO_SEED
+                }
+            })
+            .collect()
+    };
+    [seeds(false), seeds(true)]
+}
+
+/// Same as `sdf.wgsl`.
+fn resolve_cpu(
+    solid: &[bool],
+    [outside, inside]: [&[u32]; 2],
+    padded_size: UVec2,
+    update: &SdfUpdate,
+    chunk_size: UVec2,
+) -> Vec<f32> {
+    let max_distance = update.max_distance as f32;
+    let mut distances = Vec::with_capacity(chunk_size.element_product() as usize);
+    for y in 0..chunk_size.y {
+        for x in 0..chunk_size.x {
+            let texel = UVec2::new(x, y) + update.halo;
+            let ind = (texel.y * padded_size.x + texel.x) as usize;
+            let seed = if solid[ind] {
+                inside[ind]
+            } else {
+                outside[ind]
+            };
+            let distance = if seed == NO_SEED {
+                max_distance
+            } else {
+                (unpack_seed(seed).as_vec2().distance(texel.as_vec2()) - 0.5).min(max_distance)
+            };
+            distances.push(if solid[ind] { -distance } else { distance });
+        }
+    }
+    distances
+}
+
+/// The CPU backend's field updates, after the chunks changed this frame.
+pub(crate) fn apply_sdf_cpu(pixel_map: &mut PixelMap, images: &mut Assets<Image>) {
+    let Some(sdf) = pixel_map.sdf.as_mut() else {
+        return;
+    };
+    let chunk_size = pixel_map.chunk_size;
+    let default_solid = pixel_map.default_chunk_color[3] >= 128;
+    for update in std::mem::take(&mut sdf.updates) {
+        let padded_size = chunk_size + UVec2::splat(update.halo * 2);
+        let mut solid = vec![default_solid; padded_size.element_product() as usize];
+        for piece in update.pieces.iter() {
+            let Some(source) = piece.source.as_ref().and_then(|source| images.get(source)) else {
+                continue;
+            };
+            for y in 0..piece.rect.height() {
+                for x in 0..piece.rect.width() {
+                    let from = (piece.rect.min.y + y) * chunk_size.x + piece.rect.min.x + x;
+                    let to = (piece.origin.y + y) * padded_size.x + piece.origin.x + x;
+                    solid[to as usize] = source.data[from as usize * 4 + 3] >= 128;
+                }
+            }
+        }
+        let [outside, inside] =
+            seeds_cpu(&solid, padded_size).map(|seeds| jump_flood_cpu(seeds, padded_size));
+        let distances = resolve_cpu(
+            &solid,
+            [&outside, &inside],
+            padded_size,
+            &update,
+            chunk_size,
+        );
+        if let Some(field) = images.get_mut(&update.field) {
+            field.data = bytemuck::cast_slice(&distances).to_vec();
+        }
+        sdf.distances.insert(update.chunk_pos, distances.into());
+    }
+}
+
+/// Bind group layouts of `sdf_seed.wgsl` and `sdf.wgsl`.
+pub(crate) fn sdf_bind_group_layouts(device: &RenderDevice) -> [BindGroupLayout; 2] {
+    let texture = |binding, access, format| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    let read = StorageTextureAccess::ReadOnly;
+    let write = StorageTextureAccess::WriteOnly;
+    [
+        device.create_bind_group_layout(
+            Some("pixel map sdf seed Bind Group Layout"),
+            &[
+                texture(0, read, TextureFormat::Rgba8Unorm),
+                texture(1, write, TextureFormat::R32Uint),
+                texture(2, write, TextureFormat::R32Uint),
+            ],
+        ),
+        device.create_bind_group_layout(
+            Some("pixel map sdf Bind Group Layout"),
+            &[
+                texture(0, read, TextureFormat::Rgba8Unorm),
+                texture(1, read, TextureFormat::R32Uint),
+                texture(2, read, TextureFormat::R32Uint),
+                texture(3, write, TextureFormat::R32Float),
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        ),
+    ]
+}
+
+/// One [`SdfUpdate`] on the GPU, flooded in textures of its own.
+pub(crate) struct SdfDispatch {
+    map: Entity,
+    chunk_pos: IVec2,
+    copies: Vec<TextureCopy>,
+    fills: Vec<(URect, Vec<u8>)>,
+    padded: Texture,
+    seed: BindGroup,
+    floods: [Vec<BindGroup>; 2],
+    resolve: BindGroup,
+    padded_size: UVec2,
+    field: Texture,
+    rect: URect,
+    chunk_size: UVec2,
+}
+
+fn scratch_texture(device: &RenderDevice, size: UVec2, format: TextureFormat) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("pixel map sdf texture"),
+        size: Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+pub(crate) fn prepare_sdf(
+    device: &RenderDevice,
+    layouts: &PixelMapShaderLayoutInput,
+    gpu_images: &RenderAssets<GpuImage>,
+    pixel_map: &PixelMap,
+    map: Entity,
+    update: &SdfUpdate,
+) -> Option<SdfDispatch> {
+    let field = gpu_images.get(&update.field)?;
+    let chunk_size = pixel_map.chunk_size;
+    let padded_size = chunk_size + UVec2::splat(update.halo * 2);
+    let padded = scratch_texture(device, padded_size, TextureFormat::Rgba8Unorm);
+    let mut copies = Vec::new();
+    let mut fills = Vec::new();
+    for piece in update.pieces.iter() {
+        let source = piece
+            .source
+            .as_ref()
+            .and_then(|source| gpu_images.get(source));
+        match source {
+            Some(source) => copies.push(TextureCopy {
+                source: source.texture.clone(),
+                target: padded.clone(),
+                source_rect: piece.rect,
+                target_origin: piece.origin,
+            }),
+            None => fills.push((
+                URect::from_corners(piece.origin, piece.origin + piece.rect.size()),
+                pixel_map
+                    .default_chunk_color
+                    .repeat(piece.rect.size().element_product() as usize),
+            )),
+        }
+    }
+    let view = |texture: &Texture| texture.create_view(&TextureViewDescriptor::default());
+    let padded_view = view(&padded);
+    let seeds = [0; 4].map(|_| {
+        view(&scratch_texture(
+            device,
+            padded_size,
+            TextureFormat::R32Uint,
+        ))
+    });
+    let seed = device.create_bind_group(
+        "pixel map sdf seed bind group",
+        &layouts.sdf_seed_layout,
+        &BindGroupEntries::sequential((
+            padded_view.into_binding(),
+            seeds[0].into_binding(),
+            seeds[2].into_binding(),
+        )),
+    );
+    let (outside, outside_result) = jump_flood_bind_groups(
+        device,
+        &layouts.jump_flood_layout,
+        [&seeds[0], &seeds[1]],
+        padded_size,
+    );
+    let (inside, inside_result) = jump_flood_bind_groups(
+        device,
+        &layouts.jump_flood_layout,
+        [&seeds[2], &seeds[3]],
+        padded_size,
+    );
+    let params = device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("sdf_params_buffer"),
+        contents: bytemuck::cast_slice(&[
+            update.halo,
+            (update.max_distance as f32).to_bits(),
+            0,
+            0,
+        ]),
+        usage: BufferUsages::UNIFORM,
+    });
+    let resolve = device.create_bind_group(
+        "pixel map sdf bind group",
+        &layouts.sdf_layout,
+        &BindGroupEntries::sequential((
+            padded_view.into_binding(),
+            seeds[outside_result].into_binding(),
+            seeds[2 + inside_result].into_binding(),
+            field.texture_view.into_binding(),
+            params.as_entire_binding(),
+        )),
+    );
+    Some(SdfDispatch {
+        map,
+        chunk_pos: update.chunk_pos,
+        copies,
+        fills,
+        padded,
+        seed,
+        floods: [outside, inside],
+        resolve,
+        padded_size,
+        field: field.texture.clone(),
+        rect: update.rect,
+        chunk_size,
+    })
+}
+
+/// Gathers, floods and resolves the fields, then reads back the part that may
+/// have changed for [`PixelMap::sdf_distance`].
+pub(crate) fn encode_sdf(
+    device: &RenderDevice,
+    encoder: &mut CommandEncoder,
+    pipelines: [&ComputePipeline; 3],
+    dispatches: &[SdfDispatch],
+) -> Vec<PendingReadback> {
+    if dispatches.is_empty() {
+        return Vec::new();
+    }
+    let [seed, jump_flood, resolve] = pipelines;
+    for dispatch in dispatches {
+        for copy in dispatch.copies.iter() {
+            copy.encode(encoder);
+        }
+        for (rect, data) in dispatch.fills.iter() {
+            restore_texture_region(device, encoder, &dispatch.padded, *rect, data);
+        }
+    }
+    {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        for dispatch in dispatches {
+            let workgroups = (dispatch.padded_size + UVec2::splat(7)) / 8;
+            pass.set_pipeline(seed);
+            pass.set_bind_group(0, &dispatch.seed, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+            pass.set_pipeline(jump_flood);
+            for binds in dispatch.floods.iter().flatten() {
+                pass.set_bind_group(0, binds, &[]);
+                pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+            }
+            let workgroups = (dispatch.chunk_size + UVec2::splat(7)) / 8;
+            pass.set_pipeline(resolve);
+            pass.set_bind_group(0, &dispatch.resolve, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+        }
+    }
+    dispatches
+        .iter()
+        .map(|dispatch| {
+            let (map, chunk_pos, rect) = (dispatch.map, dispatch.chunk_pos, dispatch.rect);
+            let chunk_size = dispatch.chunk_size;
+            PendingReadback::texture(device, encoder, &dispatch.field, rect, move |data| {
+                Box::new(move |world| {
+                    let Some(mut pixel_map) = world.get_mut::<PixelMap>(map) else {
+                        return;
+                    };
+                    let Some(sdf) = pixel_map.sdf.as_mut() else {
+                        return;
+                    };
+                    if !sdf.fields.contains_key(&chunk_pos) {
+                        return;
+                    }
+                    let mut distances = match sdf.distances.get(&chunk_pos) {
+                        Some(distances) => distances.to_vec(),
+                        None => {
+                            vec![sdf.max_distance as f32; chunk_size.element_product() as usize]
+                        }
+                    };
+                    let width = rect.width() as usize;
+                    for (y, row) in data.chunks_exact(width * 4).enumerate() {
+                        let start =
+                            (rect.min.y as usize + y) * chunk_size.x as usize + rect.min.x as usize;
+                        for (distance, bytes) in distances[start..start + width]
+                            .iter_mut()
+                            .zip(row.chunks_exact(4))
+                        {
+                            *distance = f32::from_le_bytes(bytes.try_into().unwrap());
+                        }
+                    }
+                    sdf.distances.insert(chunk_pos, distances.into());
+                })
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+
+use crate::{ChunkEvent, PixelMap};
+
+/// Simulation state of a chunk, updated from the pixel write counts read back
+/// from the GPU.
+///
+/// Awake chunks run the simulation shaders every frame. A chunk whose
+/// simulation ran `sleep_after` ticks without any pixel writes goes to sleep
+/// until it is edited or a neighbor changes pixels within `simulation_margin`
+/// of it. Ticks without a simulation pass, while the pipelines compile or the
+/// simulation is paused, don't count.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkActivity {
+    pub sleeping: bool,
+    /// Simulated ticks in a row without a pixel write.
+    pub idle_ticks: u32,
+    /// Pixel writes in the latest tick read back.
+    pub changed_pixels: u32,
+}
+
+impl ChunkActivity {
+    pub(crate) const AWAKE: ChunkActivity = ChunkActivity {
+        sleeping: false,
+        idle_ticks: 0,
+        changed_pixels: 0,
+    };
+}
+
+const NEIGHBORS: [IVec2; 8] = [
+    IVec2::new(-1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 0),
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+];
+
+impl PixelMap {
+    pub fn chunk_activity(&self, chunk_position: IVec2) -> Option<ChunkActivity> {
+        self.chunk_activity.get(&chunk_position).copied()
+    }
+
+    pub fn is_chunk_sleeping(&self, chunk_position: IVec2) -> bool {
+        self.chunk_activity
+            .get(&chunk_position)
+            .is_some_and(|activity| activity.sleeping)
+    }
+
+    pub fn awake_chunks(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.chunk_activity
+            .iter()
+            .filter(|(_, activity)| !activity.sleeping)
+            .map(|(&pos, _)| pos)
+    }
+
+    pub fn sleep_after(&self) -> u32 {
+        self.sleep_after
+    }
+
+    /// Resets the idle ticks of a chunk, waking it if it was sleeping. The
+    /// next simulation pass of a woken chunk covers all of it.
+    pub fn wake_chunk(&mut self, chunk_position: IVec2) {
+        let Some(activity) = self.chunk_activity.get_mut(&chunk_position) else {
+            return;
+        };
+        activity.idle_ticks = 0;
+        if activity.sleeping {
+            activity.sleeping = false;
+            self.woken_chunks.push(chunk_position);
+            self.chunk_events.push(ChunkEvent::Woke {
+                chunk_pos: chunk_position,
+            });
+        }
+    }
+
+    pub(crate) fn record_activity(
+        &mut self,
+        chunk_pos: IVec2,
+        changed_pixels: u32,
+        dirty_rect: Option<IRect>,
+        simulated: bool,
+    ) {
+        let sleep_after = self.sleep_after;
+        let Some(activity) = self.chunk_activity.get_mut(&chunk_pos) else {
+            return;
+        };
+        activity.changed_pixels = changed_pixels;
+        if changed_pixels > 0 {
+            self.wake_chunk(chunk_pos);
+        } else if !activity.sleeping && simulated {
+            activity.idle_ticks += 1;
+            if activity.idle_ticks >= sleep_after && self.deterministic.is_none() {
+                activity.sleeping = true;
+                self.chunk_events.push(ChunkEvent::Slept { chunk_pos });
+            }
+        }
+
+        let Some(rect) = dirty_rect else {
+            return;
+        };
+        let reach = rect.inflate(self.simulation_margin as i32);
+        let size = self.chunk_size.as_ivec2();
+        for neighbor in NEIGHBORS.map(|offset| chunk_pos + offset) {
+            let bounds = IRect::from_corners(neighbor * size, (neighbor + IVec2::ONE) * size);
+            if self.is_chunk_sleeping(neighbor) && !reach.intersect(bounds).is_empty() {
+                self.wake_chunk(neighbor);
+            }
+        }
+    }
+}
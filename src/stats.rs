@@ -0,0 +1,375 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType, Buffer,
+    BufferBindingType, BufferInitDescriptor, BufferUsages, CommandEncoder, ComputePassDescriptor,
+    ComputePipeline, IntoBinding, ShaderStages, StorageTextureAccess, TextureFormat,
+    TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::texture::GpuImage;
+
+use crate::clipboard::{chunk_copy_rect, chunks_in_rect};
+use crate::readback::PendingReadback;
+use crate::{PixelMap, RegionStats};
+
+/// Words of the result buffer before the histogram: the count and the
+/// bounds.
+const RESULT_HEADER: usize = 5;
+
+/// Which pixels a [`PixelStatsQuery`] counts and bounds.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelPredicate {
+    /// Every pixel with a non zero alpha.
+    #[default]
+    Opaque,
+    /// Pixels whose channels are all within `tolerance` of `color`.
+    Color { color: [u8; 4], tolerance: u8 },
+}
+
+impl PixelPredicate {
+    pub fn matches(&self, color: [u8; 4]) -> bool {
+        match *self {
+            PixelPredicate::Opaque => color[3] > 0,
+            PixelPredicate::Color {
+                color: target,
+                tolerance,
+            } => within(color, target, tolerance),
+        }
+    }
+
+    /// Mode, packed color and tolerance, as read by `stats.wgsl`.
+    fn shader_params(&self) -> [u32; 3] {
+        match *self {
+            PixelPredicate::Opaque => [0, 0, 0],
+            PixelPredicate::Color { color, tolerance } => {
+                [1, u32::from_le_bytes(color), tolerance as u32]
+            }
+        }
+    }
+}
+
+fn within(color: [u8; 4], target: [u8; 4], tolerance: u8) -> bool {
+    color
+        .iter()
+        .zip(target.iter())
+        .all(|(a, b)| a.abs_diff(*b) <= tolerance)
+}
+
+/// What to measure in a region of a map with [`PixelMap::request_stats`].
+#[derive(Reflect, Clone, Debug, Default, PartialEq)]
+pub struct PixelStatsQuery {
+    pub predicate: PixelPredicate,
+    /// Colors to count pixels of, each pixel counting towards the first one
+    /// it is within `tolerance` of, like materials.
+    pub palette: Vec<[u8; 4]>,
+    pub tolerance: u8,
+}
+
+impl PixelStatsQuery {
+    pub fn new(predicate: PixelPredicate) -> Self {
+        PixelStatsQuery {
+            predicate,
+            ..default()
+        }
+    }
+
+    pub fn with_palette(mut self, palette: Vec<[u8; 4]>, tolerance: u8) -> Self {
+        self.palette = palette;
+        self.tolerance = tolerance;
+        self
+    }
+
+    fn histogram_bin(&self, color: [u8; 4]) -> Option<usize> {
+        self.palette
+            .iter()
+            .position(|&entry| within(color, entry, self.tolerance))
+    }
+}
+
+/// Result of a [`PixelStatsQuery`].
+#[derive(Reflect, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PixelStats {
+    /// Pixels matching the predicate.
+    pub count: u32,
+    /// Pixels of each palette color.
+    pub histogram: Vec<u32>,
+    /// World pixels of the matching pixels, with an exclusive max.
+    pub bounds: Option<IRect>,
+}
+
+impl PixelStats {
+    fn from_words(words: &[i32]) -> Self {
+        let bounds = (words[0] > 0).then(|| {
+            IRect::from_corners(
+                IVec2::new(words[1], words[2]),
+                IVec2::new(words[3], words[4]) + 1,
+            )
+        });
+        PixelStats {
+            count: words[0] as u32,
+            histogram: words[RESULT_HEADER..]
+                .iter()
+                .map(|&count| count as u32)
+                .collect(),
+            bounds,
+        }
+    }
+}
+
+/// A [`PixelMap::request_stats`] to measure after this frame's edits.
+#[derive(Clone, Debug)]
+pub(crate) struct StatsRequest {
+    pub id: u64,
+    pub rect: IRect,
+    pub query: PixelStatsQuery,
+}
+
+impl PixelMap {
+    /// Measures the pixels of `rect` after this frame's edits, on the GPU or
+    /// with the [`PixelMapCpuPlugin`](crate::PixelMapCpuPlugin). A
+    /// [`RegionStats`] event with the returned id carries the result once it
+    /// has been read back. Pixels of chunks that don't exist aren't counted.
+    pub fn request_stats(&mut self, rect: IRect, query: PixelStatsQuery) -> Option<u64> {
+        if rect.is_empty() {
+            return None;
+        }
+        self.export_count += 1;
+        let id = self.export_count;
+        self.stats_requests.push(StatsRequest { id, rect, query });
+        Some(id)
+    }
+
+    /// [`PixelMap::request_stats`] over a whole chunk.
+    pub fn request_chunk_stats(&mut self, chunk_pos: IVec2, query: PixelStatsQuery) -> Option<u64> {
+        let origin = chunk_pos * self.chunk_size.as_ivec2();
+        self.request_stats(
+            IRect::from_corners(origin, origin + self.chunk_size.as_ivec2()),
+            query,
+        )
+    }
+}
+
+/// The CPU backend's measurements, after this frame's edits.
+pub(crate) fn apply_stats_cpu(
+    map: Entity,
+    pixel_map: &mut PixelMap,
+    images: &Assets<Image>,
+) -> Vec<RegionStats> {
+    let chunk_size = pixel_map.chunk_size;
+    std::mem::take(&mut pixel_map.stats_requests)
+        .into_iter()
+        .map(|request| {
+            let mut words = vec![0, i32::MAX, i32::MAX, i32::MIN, i32::MIN];
+            words.resize(RESULT_HEADER + request.query.palette.len(), 0);
+            for chunk_pos in chunks_in_rect(request.rect, chunk_size) {
+                let (Some(image), Some((rect, _))) = (
+                    pixel_map
+                        .positions
+                        .get(&chunk_pos)
+                        .and_then(|&index| images.get(&pixel_map.image_data[index])),
+                    chunk_copy_rect(chunk_pos, chunk_size, request.rect),
+                ) else {
+                    continue;
+                };
+                let origin = chunk_pos * chunk_size.as_ivec2();
+                for y in rect.min.y..rect.max.y {
+                    for x in rect.min.x..rect.max.x {
+                        let ind = (y * chunk_size.x + x) as usize * 4;
+                        let color: [u8; 4] = image.data[ind..ind + 4].try_into().unwrap();
+                        if let Some(bin) = request.query.histogram_bin(color) {
+                            words[RESULT_HEADER + bin] += 1;
+                        }
+                        if !request.query.predicate.matches(color) {
+                            continue;
+                        }
+                        let world = IVec2::new(
+                            origin.x + x as i32,
+                            origin.y + (chunk_size.y - 1 - y) as i32,
+                        );
+                        words[0] += 1;
+                        words[1] = words[1].min(world.x);
+                        words[2] = words[2].min(world.y);
+                        words[3] = words[3].max(world.x);
+                        words[4] = words[4].max(world.y);
+                    }
+                }
+            }
+            RegionStats {
+                map,
+                id: request.id,
+                stats: PixelStats::from_words(&words),
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn stats_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let buffer = |binding, ty| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(
+        Some("pixel map stats Bind Group Layout"),
+        &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            buffer(1, BufferBindingType::Uniform),
+            buffer(2, BufferBindingType::Storage { read_only: true }),
+            buffer(3, BufferBindingType::Storage { read_only: false }),
+        ],
+    )
+}
+
+/// A stats request on the GPU: one dispatch per chunk of its rect, all
+/// adding up into `result`.
+pub(crate) struct StatsDispatch {
+    map: Entity,
+    id: u64,
+    result: Buffer,
+    words: usize,
+    chunks: Vec<(BindGroup, UVec2)>,
+}
+
+pub(crate) fn prepare_stats(
+    device: &RenderDevice,
+    layout: &BindGroupLayout,
+    gpu_images: &RenderAssets<GpuImage>,
+    pixel_map: &PixelMap,
+    map: Entity,
+) -> Vec<StatsDispatch> {
+    let chunk_size = pixel_map.chunk_size;
+    pixel_map
+        .stats_requests
+        .iter()
+        .map(|request| {
+            let query = &request.query;
+            let mut initial = vec![0, i32::MAX, i32::MAX, i32::MIN, i32::MIN];
+            initial.resize(RESULT_HEADER + query.palette.len(), 0);
+            let result = device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("stats_result_buffer"),
+                contents: bytemuck::cast_slice(&initial),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            });
+            // Bindings can't be empty.
+            let mut palette: Vec<u32> = query
+                .palette
+                .iter()
+                .map(|&color| u32::from_le_bytes(color))
+                .collect();
+            palette.push(0);
+            let palette = device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("stats_palette_buffer"),
+                contents: bytemuck::cast_slice(&palette),
+                usage: BufferUsages::STORAGE,
+            });
+            let [mode, color, tolerance] = query.predicate.shader_params();
+            let chunks = chunks_in_rect(request.rect, chunk_size)
+                .filter_map(|chunk_pos| {
+                    let chunk = pixel_map
+                        .positions
+                        .get(&chunk_pos)
+                        .and_then(|&index| gpu_images.get(&pixel_map.image_data[index]))?;
+                    let (rect, _) = chunk_copy_rect(chunk_pos, chunk_size, request.rect)?;
+                    let origin = chunk_pos * chunk_size.as_ivec2();
+                    let params = device.create_buffer_with_data(&BufferInitDescriptor {
+                        label: Some("stats_params_buffer"),
+                        contents: bytemuck::cast_slice(&[
+                            origin.x as u32,
+                            origin.y as u32,
+                            rect.min.x,
+                            rect.min.y,
+                            rect.max.x,
+                            rect.max.y,
+                            chunk_size.y,
+                            mode,
+                            color,
+                            tolerance,
+                            query.palette.len() as u32,
+                            query.tolerance as u32,
+                        ]),
+                        usage: BufferUsages::UNIFORM,
+                    });
+                    let binds = device.create_bind_group(
+                        "pixel map stats bind group",
+                        layout,
+                        &BindGroupEntries::sequential((
+                            chunk.texture_view.into_binding(),
+                            params.as_entire_binding(),
+                            palette.as_entire_binding(),
+                            result.as_entire_binding(),
+                        )),
+                    );
+                    Some((binds, (rect.size() + UVec2::splat(7)) / 8))
+                })
+                .collect();
+            StatsDispatch {
+                map,
+                id: request.id,
+                result,
+                words: initial.len(),
+                chunks,
+            }
+        })
+        .collect()
+}
+
+/// Measures the requested regions after this frame's edits and reads the
+/// results back.
+pub(crate) fn encode_stats(
+    render_device: &RenderDevice,
+    encoder: &mut CommandEncoder,
+    pipeline: &ComputePipeline,
+    dispatches: Vec<StatsDispatch>,
+) -> Vec<PendingReadback> {
+    if dispatches.is_empty() {
+        return Vec::new();
+    }
+    {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(pipeline);
+        for (binds, workgroups) in dispatches
+            .iter()
+            .flat_map(|dispatch| dispatch.chunks.iter())
+        {
+            pass.set_bind_group(0, binds, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+        }
+    }
+    dispatches
+        .into_iter()
+        .map(|dispatch| {
+            let StatsDispatch { map, id, .. } = dispatch;
+            PendingReadback::buffers(
+                render_device,
+                encoder,
+                &[&dispatch.result],
+                (dispatch.words * 4) as u64,
+                move |data| {
+                    let words: Vec<i32> = bytemuck::cast_slice(&data).to_vec();
+                    Box::new(move |world| {
+                        world.send_event(RegionStats {
+                            map,
+                            id,
+                            stats: PixelStats::from_words(&words),
+                        });
+                    })
+                },
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,311 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{fill_chunk, ChunkGenerator, GpuChunkGenerator, ASSETS_PATH};
+
+pub const MAX_NOISE_LAYERS: usize = 8;
+pub const MAX_MATERIAL_BANDS: usize = 16;
+
+/// Perlin and simplex noise are roughly in `-1.0..=1.0`, Worley noise is the
+/// distance to the nearest feature point in `0.0..=1.0`.
+#[derive(Reflect, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+}
+
+impl NoiseKind {
+    pub fn sample(self, p: Vec2, seed: u32) -> f32 {
+        match self {
+            NoiseKind::Perlin => perlin(p, seed),
+            NoiseKind::Simplex => simplex(p, seed),
+            NoiseKind::Worley => worley(p, seed),
+        }
+    }
+}
+
+/// Fractal noise: each octave doubles the frequency and halves the amplitude.
+#[derive(Reflect, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct NoiseLayer {
+    pub kind: NoiseKind,
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub octaves: u32,
+}
+
+/// Material starting `depth` pixels below the surface.
+#[derive(Reflect, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MaterialBand {
+    pub depth: f32,
+    pub color: [u8; 4],
+}
+
+/// Noise based terrain, usable as a [`ChunkGenerator`] on the CPU or through
+/// [`TerrainGenerator::gpu_generator`] on the GPU.
+///
+/// The surface is `surface_height` plus the sum of `surface_layers` sampled
+/// along x. Below it the summed `cave_layers` carve out `sky_color` wherever
+/// they exceed `cave_threshold`, and everything else takes the color of the
+/// deepest band in `bands` (sorted by depth) that has started.
+#[derive(Reflect, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TerrainGenerator {
+    pub seed: u32,
+    pub surface_height: f32,
+    pub surface_layers: Vec<NoiseLayer>,
+    pub cave_layers: Vec<NoiseLayer>,
+    pub cave_threshold: f32,
+    pub cave_min_depth: f32,
+    pub bands: Vec<MaterialBand>,
+    pub sky_color: [u8; 4],
+}
+
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        TerrainGenerator {
+            seed: 0,
+            surface_height: 0.0,
+            surface_layers: vec![
+                NoiseLayer {
+                    kind: NoiseKind::Perlin,
+                    frequency: 0.002,
+                    amplitude: 200.0,
+                    octaves: 4,
+                },
+                NoiseLayer {
+                    kind: NoiseKind::Simplex,
+                    frequency: 0.02,
+                    amplitude: 8.0,
+                    octaves: 2,
+                },
+            ],
+            cave_layers: vec![
+                NoiseLayer {
+                    kind: NoiseKind::Worley,
+                    frequency: 0.01,
+                    amplitude: -1.0,
+                    octaves: 1,
+                },
+                NoiseLayer {
+                    kind: NoiseKind::Simplex,
+                    frequency: 0.03,
+                    amplitude: 0.15,
+                    octaves: 2,
+                },
+            ],
+            cave_threshold: -0.2,
+            cave_min_depth: 40.0,
+            bands: vec![
+                MaterialBand {
+                    depth: 0.0,
+                    color: [86, 160, 60, 255],
+                },
+                MaterialBand {
+                    depth: 6.0,
+                    color: [120, 85, 55, 255],
+                },
+                MaterialBand {
+                    depth: 80.0,
+                    color: [110, 110, 115, 255],
+                },
+                MaterialBand {
+                    depth: 400.0,
+                    color: [70, 65, 75, 255],
+                },
+            ],
+            sky_color: [0, 0, 0, 0],
+        }
+    }
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u32) -> Self {
+        TerrainGenerator { seed, ..default() }
+    }
+
+    pub fn surface_height_at(&self, x: i32) -> f32 {
+        self.surface_layers
+            .iter()
+            .take(MAX_NOISE_LAYERS)
+            .enumerate()
+            .fold(self.surface_height, |height, (i, layer)| {
+                height + sample_layer(layer, self.seed, i as u32, Vec2::new(x as f32, 0.5))
+            })
+    }
+
+    pub fn sample(&self, world_position: IVec2) -> [u8; 4] {
+        let p = world_position.as_vec2();
+        let depth = self.surface_height_at(world_position.x) - p.y;
+        if depth < 0.0 {
+            return self.sky_color;
+        }
+        if !self.cave_layers.is_empty() && depth >= self.cave_min_depth {
+            let density = self
+                .cave_layers
+                .iter()
+                .take(MAX_NOISE_LAYERS)
+                .enumerate()
+                .fold(0.0, |density, (i, layer)| {
+                    density + sample_layer(layer, self.seed, (MAX_NOISE_LAYERS + i) as u32, p)
+                });
+            if density > self.cave_threshold {
+                return self.sky_color;
+            }
+        }
+        self.bands
+            .iter()
+            .take(MAX_MATERIAL_BANDS)
+            .enumerate()
+            .rev()
+            .find(|(i, band)| *i == 0 || band.depth <= depth)
+            .map_or(self.sky_color, |(_, band)| band.color)
+    }
+
+    /// The same terrain generated by `terrain.wgsl`.
+    pub fn gpu_generator(&self) -> GpuChunkGenerator {
+        // Matches the std430 layout of `TerrainParams` in `terrain.wgsl`.
+        let mut words = vec![
+            self.surface_height.to_bits(),
+            self.cave_threshold.to_bits(),
+            self.cave_min_depth.to_bits(),
+            u32::from_le_bytes(self.sky_color),
+            self.surface_layers.len().min(MAX_NOISE_LAYERS) as u32,
+            self.cave_layers.len().min(MAX_NOISE_LAYERS) as u32,
+            self.bands.len().min(MAX_MATERIAL_BANDS) as u32,
+        ];
+        for layers in [&self.surface_layers, &self.cave_layers] {
+            for i in 0..MAX_NOISE_LAYERS {
+                words.extend(layers.get(i).map_or([0; 4], |layer| {
+                    [
+                        layer.kind as u32,
+                        layer.octaves,
+                        layer.frequency.to_bits(),
+                        layer.amplitude.to_bits(),
+                    ]
+                }));
+            }
+        }
+        for i in 0..MAX_MATERIAL_BANDS {
+            words.extend(self.bands.get(i).map_or([0; 2], |band| {
+                [band.depth.to_bits(), u32::from_le_bytes(band.color)]
+            }));
+        }
+        GpuChunkGenerator {
+            shader: ASSETS_PATH
+                .join("terrain.wgsl")
+                .to_string_lossy()
+                .into_owned(),
+            seed: self.seed,
+            params: bytemuck::cast_slice(&words).to_vec(),
+        }
+    }
+}
+
+impl ChunkGenerator for TerrainGenerator {
+    fn generate(&self, chunk_position: IVec2, chunk_size: UVec2, pixels: &mut [u8]) {
+        fill_chunk(chunk_position, chunk_size, pixels, |world| {
+            self.sample(world)
+        });
+    }
+}
+
+pub(crate) fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+pub(crate) fn hash2(x: i32, y: i32, seed: u32) -> u32 {
+    pcg(pcg(pcg(seed) ^ x as u32) ^ y as u32)
+}
+
+pub(crate) fn unit(h: u32) -> f32 {
+    (h >> 8) as f32 / 16777216.0
+}
+
+fn grad(h: u32, d: Vec2) -> f32 {
+    match h & 7 {
+        0 => d.x + d.y,
+        1 => -d.x + d.y,
+        2 => d.x - d.y,
+        3 => -d.x - d.y,
+        4 => d.x,
+        5 => -d.x,
+        6 => d.y,
+        _ => -d.y,
+    }
+}
+
+fn perlin(p: Vec2, seed: u32) -> f32 {
+    let i = p.floor();
+    let f = p - i;
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let (ix, iy) = (i.x as i32, i.y as i32);
+    let n00 = grad(hash2(ix, iy, seed), f);
+    let n10 = grad(hash2(ix + 1, iy, seed), f - Vec2::new(1.0, 0.0));
+    let n01 = grad(hash2(ix, iy + 1, seed), f - Vec2::new(0.0, 1.0));
+    let n11 = grad(hash2(ix + 1, iy + 1, seed), f - Vec2::new(1.0, 1.0));
+    let x0 = n00 + (n10 - n00) * u.x;
+    let x1 = n01 + (n11 - n01) * u.x;
+    x0 + (x1 - x0) * u.y
+}
+
+fn simplex_corner(x: Vec2, ix: i32, iy: i32, seed: u32) -> f32 {
+    let t = 0.5 - x.dot(x);
+    if t <= 0.0 {
+        return 0.0;
+    }
+    t * t * t * t * grad(hash2(ix, iy, seed), x)
+}
+
+fn simplex(p: Vec2, seed: u32) -> f32 {
+    const F2: f32 = 0.366_025_42;
+    const G2: f32 = 0.211_324_87;
+    let skew = (p.x + p.y) * F2;
+    let i = (p + Vec2::splat(skew)).floor();
+    let unskew = (i.x + i.y) * G2;
+    let x0 = p - (i - Vec2::splat(unskew));
+    let o = if x0.x > x0.y {
+        Vec2::new(1.0, 0.0)
+    } else {
+        Vec2::new(0.0, 1.0)
+    };
+    let x1 = x0 - o + Vec2::splat(G2);
+    let x2 = x0 - Vec2::ONE + Vec2::splat(2.0 * G2);
+    let (ix, iy) = (i.x as i32, i.y as i32);
+    let n = simplex_corner(x0, ix, iy, seed)
+        + simplex_corner(x1, ix + o.x as i32, iy + o.y as i32, seed)
+        + simplex_corner(x2, ix + 1, iy + 1, seed);
+    70.0 * n
+}
+
+fn worley(p: Vec2, seed: u32) -> f32 {
+    let i = p.floor();
+    let f = p - i;
+    let (ix, iy) = (i.x as i32, i.y as i32);
+    let mut min_d: f32 = 8.0;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let h = hash2(ix + dx, iy + dy, seed);
+            let point = Vec2::new(dx as f32, dy as f32) + Vec2::new(unit(h), unit(pcg(h)));
+            min_d = min_d.min((point - f).length());
+        }
+    }
+    min_d.min(1.0)
+}
+
+fn sample_layer(layer: &NoiseLayer, seed: u32, index: u32, p: Vec2) -> f32 {
+    let layer_seed = pcg(seed.wrapping_add(index));
+    let mut sum = 0.0;
+    let mut frequency = layer.frequency;
+    let mut amplitude = layer.amplitude;
+    for octave in 0..layer.octaves {
+        sum += amplitude
+            * layer
+                .kind
+                .sample(p * frequency, pcg(layer_seed.wrapping_add(octave)));
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+    sum
+}
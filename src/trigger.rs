@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+
+use crate::{
+    ChunkDirty, PixelMap, PixelPredicate, PixelStatsQuery, PixelTriggerEntered, PixelTriggerExited,
+    RegionStats,
+};
+
+/// Requests a trigger has on their way at most.
+const MAX_PENDING: usize = 4;
+
+/// Area of a [`PixelMap`] that fires [`PixelTriggerEntered`] once at least
+/// `threshold` of its pixels match `predicate`, and [`PixelTriggerExited`]
+/// once fewer do, like water reaching a pressure plate.
+///
+/// Evaluated with [`PixelMap::request_stats`] when it is added or changed and
+/// whenever a [`ChunkDirty`] rect overlaps it, so only the count is read back
+/// from the GPU and the events trail the map by a few frames.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component)]
+pub struct PixelTrigger {
+    pub map: Entity,
+    pub rect: IRect,
+    pub predicate: PixelPredicate,
+    pub threshold: u32,
+    #[reflect(ignore)]
+    count: Option<u32>,
+    #[reflect(ignore)]
+    active: bool,
+    /// Requests on their way, oldest first.
+    #[reflect(ignore)]
+    pending: Vec<u64>,
+    /// The area and predicate of the last request.
+    #[reflect(ignore)]
+    requested: Option<(IRect, PixelPredicate)>,
+    /// Pixels in the rect changed since the last request.
+    #[reflect(ignore)]
+    outdated: bool,
+}
+
+impl PixelTrigger {
+    pub fn new(map: Entity, rect: IRect, predicate: PixelPredicate, threshold: u32) -> Self {
+        PixelTrigger {
+            map,
+            rect,
+            predicate,
+            threshold: threshold.max(1),
+            count: None,
+            active: false,
+            pending: Vec::new(),
+            requested: None,
+            outdated: true,
+        }
+    }
+
+    /// Whether the trigger was entered and hasn't been exited since.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Matching pixels at the last evaluation, `None` before the first one.
+    pub fn count(&self) -> Option<u32> {
+        self.count
+    }
+}
+
+pub(crate) fn update_pixel_triggers(
+    mut triggers: Query<(Entity, &mut PixelTrigger)>,
+    mut pixel_maps: Query<&mut PixelMap>,
+    mut stats: EventReader<RegionStats>,
+    mut dirty: EventReader<ChunkDirty>,
+    mut entered: EventWriter<PixelTriggerEntered>,
+    mut exited: EventWriter<PixelTriggerExited>,
+) {
+    let stats: Vec<&RegionStats> = stats.read().collect();
+    let dirty: Vec<&ChunkDirty> = dirty.read().collect();
+    for (entity, mut trigger) in triggers.iter_mut() {
+        let trigger = trigger.as_mut();
+        let map = trigger.map;
+        trigger.outdated |= trigger.requested != Some((trigger.rect, trigger.predicate))
+            || dirty
+                .iter()
+                .any(|event| event.map == map && !event.rect.intersect(trigger.rect).is_empty());
+        for event in stats.iter().filter(|event| event.map == map) {
+            let Some(index) = trigger.pending.iter().position(|&id| id == event.id) else {
+                continue;
+            };
+            trigger.pending.drain(..=index);
+            let count = event.stats.count;
+            trigger.count = Some(count);
+            let active = count >= trigger.threshold;
+            if active == trigger.active {
+                continue;
+            }
+            trigger.active = active;
+            if active {
+                entered.send(PixelTriggerEntered {
+                    trigger: entity,
+                    map,
+                    count,
+                });
+            } else {
+                exited.send(PixelTriggerExited {
+                    trigger: entity,
+                    map,
+                    count,
+                });
+            }
+        }
+        if !trigger.outdated || trigger.pending.len() >= MAX_PENDING {
+            continue;
+        }
+        let Ok(mut pixel_map) = pixel_maps.get_mut(map) else {
+            continue;
+        };
+        trigger.outdated = false;
+        trigger.requested = Some((trigger.rect, trigger.predicate));
+        if let Some(id) =
+            pixel_map.request_stats(trigger.rect, PixelStatsQuery::new(trigger.predicate))
+        {
+            trigger.pending.push(id);
+        }
+    }
+}
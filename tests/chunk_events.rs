@@ -0,0 +1,58 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+
+fn drain<E: Event>(app: &mut App) -> Vec<E> {
+    app.world_mut()
+        .resource_mut::<Events<E>>()
+        .drain()
+        .collect()
+}
+
+#[test]
+fn chunks_announce_their_lifecycle() {
+    let mut app = app();
+    let map = spawn_map(&mut app, UVec2::new(16, 16), |builder| {
+        builder.with_cpu_simulation(|_: &mut CpuChunk| {})
+    });
+    queue_pixels(&mut app, map, vec![(IVec2::new(3, -4), RED)]);
+    app.update();
+
+    let created = drain::<ChunkCreated>(&mut app);
+    assert_eq!(created.len(), 1);
+    let ChunkCreated {
+        map: created_map,
+        chunk_pos,
+        entity,
+    } = created[0];
+    assert_eq!((created_map, chunk_pos), (map, IVec2::new(0, -1)));
+    assert_eq!(
+        app.world()
+            .get::<PixelChunk>(entity)
+            .map(|chunk| chunk.position),
+        Some(chunk_pos)
+    );
+    assert!(drain::<ChunkModified>(&mut app).contains(&ChunkModified { map, chunk_pos }));
+    assert!(drain::<ChunkSimulated>(&mut app).contains(&ChunkSimulated { map, chunk_pos }));
+
+    // Untouched chunks aren't reported as modified.
+    app.update();
+    assert!(drain::<ChunkCreated>(&mut app).is_empty());
+    assert!(drain::<ChunkModified>(&mut app).is_empty());
+
+    remove_chunk(&mut app, map, chunk_pos);
+    app.update();
+    assert_eq!(
+        drain::<ChunkRemoved>(&mut app),
+        [ChunkRemoved {
+            map,
+            chunk_pos,
+            entity
+        }]
+    );
+    assert!(app.world().get_entity(entity).is_err());
+}
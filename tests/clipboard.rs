@@ -0,0 +1,82 @@
+mod common;
+
+use bevy::prelude::*;
+use common::*;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+
+/// Two maps with different chunk sizes, the first one with history.
+fn app() -> (App, Entity, Entity) {
+    let mut app = common::app();
+    let source = spawn_map(&mut app, UVec2::new(5, 7), |builder| {
+        builder.with_history(1 << 20)
+    });
+    let target = spawn_map(&mut app, UVec2::new(16, 16), |builder| builder);
+    (app, source, target)
+}
+
+#[test]
+fn copies_stitch_chunks_with_the_top_row_first() {
+    let (mut app, source, _) = app();
+    let rect = IRect::new(-3, -4, 9, 6);
+    let written = IRect::new(-1, -2, 8, 5);
+    fill_with(&mut app, source, written, color);
+
+    let clip = with_images(&mut app, source, move |map, images| {
+        map.copy_region(rect, images)
+    })
+    .unwrap();
+    app.update();
+    assert_eq!(clip.position, rect.min);
+    assert_eq!(clip.size, UVec2::new(12, 10));
+
+    let images = app.world().resource::<Assets<Image>>();
+    let data = &images.get(&clip.image).unwrap().data;
+    for (i, texel) in data.chunks_exact(4).enumerate() {
+        let world = IVec2::new(
+            rect.min.x + (i % 12) as i32,
+            rect.max.y - 1 - (i / 12) as i32,
+        );
+        let expected = if world.cmpge(written.min).all() && world.cmplt(written.max).all() {
+            color(world)
+        } else {
+            [0; 4]
+        };
+        assert_eq!(texel, expected, "texel {i} at {world}");
+    }
+}
+
+#[test]
+fn cut_and_paste_moves_pixels_between_maps() {
+    let (mut app, source, target) = app();
+    let rect = IRect::new(2, 3, 8, 10);
+    fill_with(&mut app, source, rect, color);
+    set_pixels(&mut app, target, vec![(IVec2::new(40, 40), RED)]);
+
+    let clip = with_images(&mut app, source, move |map, images| {
+        map.cut_region(rect, images)
+    })
+    .unwrap();
+    app.update();
+    assert_eq!(
+        get_pixels(&mut app, source, rect_positions(rect)),
+        vec![[0; 4]; rect_positions(rect).len()]
+    );
+
+    let offset = IVec2::new(38, 33);
+    with_images(&mut app, target, move |map, images| {
+        map.paste(&clip, rect.min + offset, images)
+    });
+    app.update();
+    let moved: Vec<IVec2> = rect_positions(rect)
+        .into_iter()
+        .map(|p| p + offset)
+        .collect();
+    let expected: Vec<[u8; 4]> = rect_positions(rect).into_iter().map(color).collect();
+    assert_eq!(get_pixels(&mut app, target, moved), expected);
+
+    // The cut is an edit like any other.
+    assert!(pixel_map_mut(&mut app, source).undo());
+    app.update();
+    assert_eq!(get_pixels(&mut app, source, rect_positions(rect)), expected);
+}
@@ -0,0 +1,173 @@
+//! Fixtures shared by the integration tests: a headless app on the CPU backend
+//! and helpers that edit and read the maps in it.
+#![allow(dead_code)]
+
+use bevy::ecs::system::RunSystemOnce;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_pixelmap::*;
+
+/// An app running pixel maps on the CPU backend, without a window or GPU.
+pub fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default(), PixelMapCpuPlugin));
+    app
+}
+
+/// Spawns a map with chunks of `chunk_size`, set up by `build`.
+pub fn spawn_map(
+    app: &mut App,
+    chunk_size: UVec2,
+    build: impl FnOnce(PixelMapBuilder) -> PixelMapBuilder,
+) -> Entity {
+    let root = app.world_mut().spawn_empty().id();
+    let pixel_map = build(PixelMap::builder(chunk_size, root)).build();
+    app.world_mut().entity_mut(root).insert(pixel_map);
+    root
+}
+
+pub fn pixel_map(app: &App, map: Entity) -> &PixelMap {
+    app.world().get::<PixelMap>(map).unwrap()
+}
+
+pub fn pixel_map_mut(app: &mut App, map: Entity) -> Mut<'_, PixelMap> {
+    app.world_mut().get_mut::<PixelMap>(map).unwrap()
+}
+
+/// Runs `f` on a map along with the images, for the methods that create or
+/// read them.
+pub fn with_images<T: Send + 'static>(
+    app: &mut App,
+    map: Entity,
+    f: impl FnOnce(&mut PixelMap, &mut ResMut<Assets<Image>>) -> T + Send + Sync + 'static,
+) -> T {
+    let mut f = Some(f);
+    app.world_mut()
+        .run_system_once(
+            move |mut query: Query<&mut PixelMap>, mut images: ResMut<Assets<Image>>| {
+                let f = f.take().unwrap();
+                f(&mut query.get_mut(map).unwrap(), &mut images)
+            },
+        )
+        .unwrap()
+}
+
+/// Queues `set_pixels_cpu` writes without running a frame.
+pub fn queue_pixels(app: &mut App, map: Entity, pixels: Vec<(IVec2, [u8; 4])>) {
+    app.world_mut()
+        .run_system_once(
+            move |mut query: Query<&mut PixelMap>,
+                  mut commands: Commands,
+                  mut textures: ResMut<Assets<Image>>| {
+                query
+                    .get_mut(map)
+                    .unwrap()
+                    .set_pixels_cpu(&pixels, &mut commands, &mut textures);
+            },
+        )
+        .unwrap();
+}
+
+/// Writes `pixels` and runs a frame, so they are in the chunk images.
+pub fn set_pixels(app: &mut App, map: Entity, pixels: Vec<(IVec2, [u8; 4])>) {
+    queue_pixels(app, map, pixels);
+    app.update();
+}
+
+/// Queues `color` over `rect` without running a frame.
+pub fn queue_fill(app: &mut App, map: Entity, rect: IRect, color: [u8; 4]) {
+    let pixels = rect_positions(rect)
+        .into_iter()
+        .map(|position| (position, color))
+        .collect();
+    queue_pixels(app, map, pixels);
+}
+
+/// Writes `color` over `rect` and runs a frame.
+pub fn fill(app: &mut App, map: Entity, rect: IRect, color: [u8; 4]) {
+    queue_fill(app, map, rect, color);
+    app.update();
+}
+
+/// Writes the color of every position in `rect` and runs a frame.
+pub fn fill_with(app: &mut App, map: Entity, rect: IRect, color: impl Fn(IVec2) -> [u8; 4]) {
+    let pixels = rect_positions(rect)
+        .into_iter()
+        .map(|position| (position, color(position)))
+        .collect();
+    set_pixels(app, map, pixels);
+}
+
+pub fn get_pixels(app: &mut App, map: Entity, positions: Vec<IVec2>) -> Vec<[u8; 4]> {
+    app.world_mut()
+        .run_system_once(
+            move |query: Query<&PixelMap>, textures: Res<Assets<Image>>| {
+                query
+                    .get(map)
+                    .unwrap()
+                    .get_pixels_cpu(&positions, &textures)
+            },
+        )
+        .unwrap()
+}
+
+/// Removes the chunk at `chunk_pos`, without running a frame.
+pub fn remove_chunk(app: &mut App, map: Entity, chunk_pos: IVec2) {
+    app.world_mut()
+        .run_system_once(
+            move |mut query: Query<&mut PixelMap>,
+                  mut commands: Commands,
+                  mut textures: ResMut<Assets<Image>>| {
+                query
+                    .get_mut(map)
+                    .unwrap()
+                    .remove_chunk(chunk_pos, &mut commands, &mut textures);
+            },
+        )
+        .unwrap();
+}
+
+/// Every position in `rect`, row by row from the bottom.
+pub fn rect_positions(rect: IRect) -> Vec<IVec2> {
+    (rect.min.y..rect.max.y)
+        .flat_map(|y| (rect.min.x..rect.max.x).map(move |x| IVec2::new(x, y)))
+        .collect()
+}
+
+/// A color that tells positions apart.
+pub fn color(position: IVec2) -> [u8; 4] {
+    [position.x as u8, position.y as u8, 7, 255]
+}
+
+/// Adds an `Rgba8Unorm` image of texel colors, rows from the top.
+pub fn image(app: &mut App, size: UVec2, texels: &[[u8; 4]]) -> Handle<Image> {
+    app.world_mut()
+        .resource_mut::<Assets<Image>>()
+        .add(Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            texels.concat(),
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::all(),
+        ))
+}
+
+/// Stamps `image` with its bottom left corner at `position`, without running
+/// a frame.
+pub fn stamp(app: &mut App, map: Entity, image: Handle<Image>, position: IVec2, size: UVec2) {
+    with_images(app, map, move |pixel_map, images| {
+        pixel_map.set_pixels_gpu(
+            vec![PixelPositionedTexture {
+                position,
+                image,
+                size,
+            }],
+            images,
+        );
+    });
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+fn config() -> PixelMapConfig {
+    PixelMapConfig {
+        chunk_size: UVec2::new(16, 16),
+        default_chunk_color: [0; 4],
+        simulation_shaders: vec![],
+        simulation_margin: 2,
+        sleep_after: 30,
+        history_budget: 0,
+        lod_levels: 0,
+        sdf_distance: 0,
+    }
+}
+
+#[test]
+fn reloaded_assets_reach_spawned_maps() {
+    let mut app = app();
+    let handle = app
+        .world_mut()
+        .resource_mut::<Assets<PixelMapConfig>>()
+        .add(config());
+    let map = app
+        .world_mut()
+        .spawn(PixelMapConfigHandle(handle.clone()))
+        .id();
+    app.update();
+    app.update();
+    assert_eq!(pixel_map(&app, map).config(), config());
+
+    let reloaded = PixelMapConfig {
+        simulation_margin: 5,
+        sleep_after: 4,
+        history_budget: 1 << 20,
+        sdf_distance: 3,
+        ..config()
+    };
+    *app.world_mut()
+        .resource_mut::<Assets<PixelMapConfig>>()
+        .get_mut(&handle)
+        .unwrap() = reloaded.clone();
+    // Asset events go out at the end of the frame.
+    app.update();
+    app.update();
+    let spawned = pixel_map(&app, map);
+    assert_eq!(spawned.config(), reloaded);
+    assert_eq!(spawned.history().unwrap().budget(), 1 << 20);
+
+    *app.world_mut()
+        .resource_mut::<Assets<PixelMapConfig>>()
+        .get_mut(&handle)
+        .unwrap() = config();
+    app.update();
+    app.update();
+    let spawned = pixel_map(&app, map);
+    assert_eq!(spawned.config(), config());
+    assert!(spawned.history().is_none());
+}
+
+#[test]
+fn changed_components_reach_spawned_maps() {
+    let mut app = app();
+    let map = app.world_mut().spawn(config()).id();
+    app.update();
+    app.update();
+    app.world_mut()
+        .get_mut::<PixelMapConfig>(map)
+        .unwrap()
+        .sleep_after = 7;
+    app.update();
+    let spawned = pixel_map(&app, map);
+    assert_eq!(spawned.sleep_after(), 7);
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const SAND: [u8; 4] = [200, 180, 90, 255];
+
+fn app(build: impl FnOnce(PixelMapBuilder) -> PixelMapBuilder) -> (App, Entity) {
+    let mut app = common::app();
+    let map = spawn_map(&mut app, UVec2::new(16, 16), build);
+    (app, map)
+}
+
+#[test]
+fn cpu_writes_are_applied() {
+    let (mut app, map) = app(|builder| builder);
+    let positions = vec![IVec2::new(3, 4), IVec2::new(-1, -20), IVec2::new(15, 15)];
+    queue_pixels(&mut app, map, positions.iter().map(|&p| (p, RED)).collect());
+    app.update();
+    assert_eq!(get_pixels(&mut app, map, positions), vec![RED; 3]);
+    assert_eq!(
+        get_pixels(&mut app, map, vec![IVec2::new(4, 4)]),
+        vec![[0; 4]]
+    );
+    assert_eq!(
+        pixel_map(&app, map).dirty_rect(IVec2::new(0, 0)),
+        Some(IRect::new(3, 4, 16, 16))
+    );
+}
+
+#[test]
+fn stamps_skip_transparent_texels() {
+    let (mut app, map) = app(|builder| builder);
+    let image = image(&mut app, UVec2::new(2, 2), &[[255, 0, 0, 0], RED, RED, RED]);
+    stamp(&mut app, map, image, IVec2::new(7, 7), UVec2::new(2, 2));
+    app.update();
+    let around = IRect::new(5, 5, 11, 11);
+    let positions: Vec<IVec2> = (around.min.x..around.max.x)
+        .flat_map(|x| (around.min.y..around.max.y).map(move |y| IVec2::new(x, y)))
+        .collect();
+    let written = get_pixels(&mut app, map, positions)
+        .into_iter()
+        .filter(|&color| color == RED)
+        .count();
+    assert_eq!(written, 3);
+}
+
+fn fall(chunk: &mut CpuChunk) {
+    for y in (chunk.dispatch.min.y as i32..chunk.dispatch.max.y as i32).rev() {
+        for x in chunk.dispatch.min.x as i32..chunk.dispatch.max.x as i32 {
+            let coords = IVec2::new(x, y);
+            let below = coords + IVec2::Y;
+            if chunk.get(coords) == SAND && chunk.contains(below) && chunk.get(below)[3] == 0 {
+                chunk.set(below, SAND);
+                chunk.set(coords, [0; 4]);
+            }
+        }
+    }
+}
+
+#[test]
+fn cpu_simulation_moves_pixels_until_the_chunk_sleeps() {
+    let (mut app, map) = app(|builder| builder.with_cpu_simulation(fall).with_sleep_after(3));
+    queue_pixels(&mut app, map, vec![(IVec2::new(5, 12), SAND)]);
+    for _ in 0..40 {
+        app.update();
+    }
+    assert_eq!(
+        get_pixels(&mut app, map, vec![IVec2::new(5, 0)]),
+        vec![SAND]
+    );
+    assert_eq!(
+        get_pixels(&mut app, map, vec![IVec2::new(5, 12)]),
+        vec![[0; 4]]
+    );
+    assert!(pixel_map(&app, map).is_chunk_sleeping(IVec2::ZERO));
+
+    queue_pixels(&mut app, map, vec![(IVec2::new(9, 9), SAND)]);
+    app.update();
+    assert!(!pixel_map(&app, map).is_chunk_sleeping(IVec2::ZERO));
+}
+
+#[test]
+fn activity_near_an_edge_wakes_the_neighbor() {
+    let (mut app, map) = app(|builder| builder.with_cpu_simulation(fall).with_sleep_after(2));
+    queue_pixels(
+        &mut app,
+        map,
+        vec![(IVec2::new(5, 5), RED), (IVec2::new(5, -5), RED)],
+    );
+    for _ in 0..5 {
+        app.update();
+    }
+    assert!(pixel_map(&app, map).is_chunk_sleeping(IVec2::new(0, 0)));
+    assert!(pixel_map(&app, map).is_chunk_sleeping(IVec2::new(0, -1)));
+
+    queue_pixels(&mut app, map, vec![(IVec2::new(8, 0), RED)]);
+    app.update();
+    assert!(!pixel_map(&app, map).is_chunk_sleeping(IVec2::new(0, -1)));
+}
+
+#[test]
+fn chunks_only_idle_while_simulated() {
+    let (mut app, map) = app(|builder| builder.with_cpu_simulation(fall).with_sleep_after(2));
+    pixel_map_mut(&mut app, map).pause_simulation();
+    queue_pixels(&mut app, map, vec![(IVec2::new(5, 5), RED)]);
+    for _ in 0..5 {
+        app.update();
+    }
+    let activity = pixel_map(&app, map).chunk_activity(IVec2::ZERO).unwrap();
+    assert!(!activity.sleeping);
+    assert_eq!(activity.idle_ticks, 0);
+
+    pixel_map_mut(&mut app, map).resume_simulation();
+    for _ in 0..3 {
+        app.update();
+    }
+    assert!(pixel_map(&app, map).is_chunk_sleeping(IVec2::ZERO));
+}
+
+#[test]
+fn simulation_is_dispatched_over_the_dirty_rect_and_margin() {
+    let dispatches = Arc::new(Mutex::new(Vec::new()));
+    let recorded = dispatches.clone();
+    let (mut app, map) = app(move |builder| {
+        builder
+            .with_simulation_margin(3)
+            .with_cpu_simulation(move |chunk: &mut CpuChunk| {
+                if chunk.position == IVec2::ZERO {
+                    recorded.lock().unwrap().push(chunk.dispatch);
+                }
+            })
+    });
+    // New chunks are simulated in full once. The write in the corner is
+    // simulated again the frame after, with the margin clamped to the chunk.
+    queue_pixels(&mut app, map, vec![(IVec2::new(15, 0), RED)]);
+    for _ in 0..3 {
+        app.update();
+    }
+    assert_eq!(
+        dispatches.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        [URect::new(0, 0, 16, 16), URect::new(12, 12, 16, 16)]
+    );
+
+    let image = image(&mut app, UVec2::new(2, 2), &[RED; 4]);
+    stamp(&mut app, map, image, IVec2::new(4, 10), UVec2::new(2, 2));
+    app.update();
+    assert_eq!(
+        pixel_map(&app, map).dirty_rect(IVec2::ZERO),
+        Some(IRect::new(4, 10, 6, 12))
+    );
+    for _ in 0..2 {
+        app.update();
+    }
+    // Texel rows count from the top, so world rows 10 and 11 are rows 5 and
+    // 4. The rect of the stamp frame is simulated once more the frame after.
+    assert_eq!(dispatches.lock().unwrap()[..], [URect::new(1, 1, 9, 9); 2]);
+}
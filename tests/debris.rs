@@ -0,0 +1,166 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+const ROCK: [u8; 4] = [90, 90, 90, 255];
+const BEDROCK: [u8; 4] = [20, 20, 20, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, UVec2::new(8, 8), |builder| {
+        builder.with_default_chunk_color([0; 4])
+    });
+    fill(&mut app, root, IRect::new(0, 0, 24, 4), GROUND);
+    (app, root)
+}
+
+fn detach(app: &mut App, map: Entity, rect: IRect, selection: DebrisSelection) {
+    with_images(app, map, move |pixel_map, images| {
+        pixel_map.detach_debris(rect, selection, images);
+    });
+    for _ in 0..3 {
+        app.update();
+    }
+}
+
+fn debris(app: &mut App) -> Vec<(Entity, PixelDebris, Transform, DebrisCollider)> {
+    app.world_mut()
+        .query::<(Entity, &PixelDebris, &Transform, &DebrisCollider)>()
+        .iter(app.world())
+        .map(|(entity, debris, transform, collider)| {
+            (entity, debris.clone(), *transform, collider.clone())
+        })
+        .collect()
+}
+
+fn set_rest_seconds(app: &mut App, root: Entity, seconds: f32) {
+    pixel_map_mut(app, root).debris_settings_mut().rest_seconds = seconds;
+}
+
+#[test]
+fn detaches_floating_pixels() {
+    let (mut app, root) = app();
+    set_rest_seconds(&mut app, root, 1000.0);
+    fill(&mut app, root, IRect::new(10, 10, 13, 13), ROCK);
+    detach(
+        &mut app,
+        root,
+        IRect::new(0, 0, 24, 20),
+        DebrisSelection::Disconnected,
+    );
+
+    let debris = debris(&mut app);
+    assert_eq!(debris.len(), 1);
+    let (entity, piece, transform, collider) = &debris[0];
+    assert_eq!(piece.size, UVec2::new(3, 3));
+    assert_eq!(piece.pixel_count, 9);
+    assert_eq!(piece.map, root);
+    assert_eq!(transform.translation, Vec3::new(7.5, 7.5, 0.0));
+    assert_eq!(collider.rects, vec![Rect::new(-1.5, -1.5, 1.5, 1.5)]);
+    assert_eq!(app.world().get::<Parent>(*entity).unwrap().get(), root);
+    let image = app
+        .world()
+        .resource::<Assets<Image>>()
+        .get(&piece.image)
+        .unwrap();
+    assert!(image.data.chunks_exact(4).all(|color| color == ROCK));
+
+    let rock = get_pixels(&mut app, root, rect_positions(IRect::new(10, 10, 13, 13)));
+    assert!(rock.iter().all(|&color| color == [0; 4]));
+    let ground = get_pixels(&mut app, root, rect_positions(IRect::new(0, 0, 24, 4)));
+    assert!(ground.iter().all(|&color| color == GROUND));
+}
+
+#[test]
+fn anchors_and_selection() {
+    let (mut app, root) = app();
+    set_rest_seconds(&mut app, root, 1000.0);
+    {
+        let mut map = pixel_map_mut(&mut app, root);
+        map.debris_settings_mut().anchors.push(BEDROCK);
+    }
+    fill(&mut app, root, IRect::new(4, 10, 6, 12), ROCK);
+    fill(&mut app, root, IRect::new(5, 11, 6, 12), BEDROCK);
+    // Too small to come loose.
+    fill(&mut app, root, IRect::new(16, 16, 17, 17), ROCK);
+    detach(
+        &mut app,
+        root,
+        IRect::new(0, 0, 24, 20),
+        DebrisSelection::Disconnected,
+    );
+    assert!(debris(&mut app).is_empty());
+    assert_eq!(
+        get_pixels(&mut app, root, vec![IVec2::new(4, 10)]),
+        vec![ROCK]
+    );
+
+    // Everything in the rect, with the ground cut at its edge.
+    detach(
+        &mut app,
+        root,
+        IRect::new(2, 2, 22, 20),
+        DebrisSelection::All,
+    );
+    let mut sizes: Vec<UVec2> = debris(&mut app)
+        .into_iter()
+        .map(|(_, piece, _, _)| piece.size)
+        .collect();
+    sizes.sort_by_key(|size| size.x);
+    assert_eq!(sizes, vec![UVec2::new(2, 2), UVec2::new(20, 2)]);
+    assert_eq!(
+        get_pixels(&mut app, root, vec![IVec2::new(1, 3), IVec2::new(2, 3)]),
+        vec![GROUND, [0; 4]]
+    );
+}
+
+#[test]
+fn settles_back_where_it_rests() {
+    let (mut app, root) = app();
+    set_rest_seconds(&mut app, root, 1000.0);
+    // An L shape, lying on its side after a quarter turn.
+    fill(&mut app, root, IRect::new(10, 10, 13, 11), ROCK);
+    fill(&mut app, root, IRect::new(10, 11, 11, 12), ROCK);
+    detach(
+        &mut app,
+        root,
+        IRect::new(0, 0, 24, 20),
+        DebrisSelection::Disconnected,
+    );
+    let (entity, _, transform, collider) = debris(&mut app).remove(0);
+    assert_eq!(
+        collider.rects,
+        vec![
+            Rect::new(-1.5, 0.0, -0.5, 1.0),
+            Rect::new(-1.5, -1.0, 1.5, 0.0)
+        ]
+    );
+    let moved = transform
+        .with_translation(transform.translation + Vec3::new(6.5, -2.5, 0.0))
+        .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+    app.world_mut().entity_mut(entity).insert(moved);
+    set_rest_seconds(&mut app, root, 0.0);
+    for _ in 0..4 {
+        app.update();
+    }
+
+    assert!(debris(&mut app).is_empty());
+    assert!(app.world().get_entity(entity).is_err());
+    // Turned a quarter to the left, the bar stands upright with its foot at
+    // the bottom pointing left.
+    let placed = get_pixels(
+        &mut app,
+        root,
+        vec![
+            IVec2::new(18, 7),
+            IVec2::new(18, 8),
+            IVec2::new(18, 9),
+            IVec2::new(17, 7),
+            IVec2::new(17, 8),
+        ],
+    );
+    assert_eq!(placed, vec![ROCK, ROCK, ROCK, ROCK, [0; 4]]);
+}
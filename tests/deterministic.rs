@@ -0,0 +1,170 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const SAND: [u8; 4] = [220, 200, 120, 255];
+
+/// Moves pixels sideways at random, so runs only agree with the same seed.
+fn jitter(chunk: &mut CpuChunk) {
+    for y in chunk.dispatch.min.y as i32..chunk.dispatch.max.y as i32 {
+        for x in chunk.dispatch.min.x as i32..chunk.dispatch.max.x as i32 {
+            let coords = IVec2::new(x, y);
+            let color = chunk.get(coords);
+            if color[3] == 0 {
+                continue;
+            }
+            let side = if chunk.random(coords) & 1 == 0 { -1 } else { 1 };
+            let target = coords + IVec2::new(side, 0);
+            if chunk.contains(target) && chunk.get(target)[3] == 0 {
+                chunk.set(target, color);
+                chunk.set(coords, [0; 4]);
+            }
+        }
+    }
+}
+
+fn spawn_seeded_map(app: &mut App, seed: u32) -> Entity {
+    let map = spawn_map(app, UVec2::new(16, 16), |builder| {
+        builder
+            .with_default_chunk_color([0; 4])
+            .with_sleep_after(2)
+            .with_cpu_simulation(jitter)
+    });
+    pixel_map_mut(app, map)
+        .enable_deterministic_simulation(DeterministicSettings { seed, ..default() });
+    map
+}
+
+/// Queues `color` over `rect` on every map, without running a frame.
+fn set_pixels(app: &mut App, rect: IRect, color: [u8; 4]) {
+    let maps: Vec<Entity> = app
+        .world_mut()
+        .query_filtered::<Entity, With<PixelMap>>()
+        .iter(app.world())
+        .collect();
+    for map in maps {
+        queue_fill(app, map, rect, color);
+    }
+}
+
+/// Runs `ticks` updates, returning the checksums each map reported.
+fn run(app: &mut App, ticks: usize) -> Vec<ChunkChecksums> {
+    let mut checksums = Vec::new();
+    for _ in 0..ticks {
+        app.update();
+        checksums.extend(
+            app.world_mut()
+                .resource_mut::<Events<ChunkChecksums>>()
+                .drain(),
+        );
+    }
+    checksums
+}
+
+fn map_checksums(events: &[ChunkChecksums], map: Entity) -> Vec<(u64, Vec<(IVec2, u32)>)> {
+    events
+        .iter()
+        .filter(|event| event.map == map)
+        .map(|event| (event.tick, event.checksums.clone()))
+        .collect()
+}
+
+#[test]
+fn same_seed_runs_agree_every_tick() {
+    let mut app = app();
+    let a = spawn_seeded_map(&mut app, 7);
+    let b = spawn_seeded_map(&mut app, 7);
+    let c = spawn_seeded_map(&mut app, 8);
+    set_pixels(&mut app, IRect::new(0, 0, 32, 6), SAND);
+    set_pixels(&mut app, IRect::new(4, 0, 12, 4), [0; 4]);
+
+    let events = run(&mut app, 20);
+    let (a, b, c) = (
+        map_checksums(&events, a),
+        map_checksums(&events, b),
+        map_checksums(&events, c),
+    );
+    assert_eq!(a.len(), 20);
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+    // Ticks go up by one, chunks come in a fixed order.
+    assert!(a.windows(2).all(|pair| pair[1].0 == pair[0].0 + 1));
+    assert_eq!(
+        a[0].1.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+        vec![IVec2::ZERO, IVec2::X]
+    );
+}
+
+#[test]
+fn checksums_follow_the_pixels() {
+    let mut app = app();
+    let map = spawn_seeded_map(&mut app, 1);
+    pixel_map_mut(&mut app, map).disable_deterministic_simulation();
+    let empty = {
+        let mut pixel_map = pixel_map_mut(&mut app, map);
+        pixel_map.enable_deterministic_simulation(default());
+        pixel_map.deterministic_settings().copied()
+    };
+    assert_eq!(empty, Some(DeterministicSettings::default()));
+
+    set_pixels(&mut app, IRect::new(0, 0, 1, 1), [0; 4]);
+    let before = run(&mut app, 1);
+    set_pixels(&mut app, IRect::new(0, 0, 1, 1), SAND);
+    let after = run(&mut app, 1);
+    assert_ne!(before[0].checksums, after[0].checksums);
+    set_pixels(&mut app, IRect::new(0, 0, 16, 16), [0; 4]);
+    let cleared = run(&mut app, 1);
+    assert_eq!(before[0].checksums, cleared[0].checksums);
+
+    let spawned = pixel_map(&app, map);
+    assert_eq!(
+        spawned.chunk_checksum(IVec2::ZERO),
+        Some((cleared[0].tick, cleared[0].checksums[0].1))
+    );
+    assert_eq!(spawned.simulation_tick(), cleared[0].tick);
+}
+
+#[test]
+fn deterministic_chunks_never_sleep() {
+    let mut app = app();
+    let map = spawn_seeded_map(&mut app, 3);
+    set_pixels(&mut app, IRect::new(0, 0, 16, 16), SAND);
+    run(&mut app, 10);
+    assert!(!pixel_map(&app, map).is_chunk_sleeping(IVec2::ZERO));
+
+    pixel_map_mut(&mut app, map).disable_deterministic_simulation();
+    run(&mut app, 10);
+    assert!(pixel_map(&app, map).is_chunk_sleeping(IVec2::ZERO));
+}
+
+#[test]
+fn integer_storage_is_opt_in() {
+    // Settings saved before integer storage still load, without it.
+    let settings: DeterministicSettings = ron::from_str("(seed: 3, phases: (2, 2))").unwrap();
+    assert_eq!(
+        settings,
+        DeterministicSettings {
+            seed: 3,
+            phases: UVec2::splat(2),
+            integer_storage: false,
+        }
+    );
+
+    // CPU simulations already work on bytes, so it doesn't change them.
+    let mut app = app();
+    let float = spawn_seeded_map(&mut app, 7);
+    let integer = spawn_seeded_map(&mut app, 7);
+    pixel_map_mut(&mut app, integer).enable_deterministic_simulation(DeterministicSettings {
+        seed: 7,
+        integer_storage: true,
+        ..default()
+    });
+    set_pixels(&mut app, IRect::new(0, 0, 32, 6), SAND);
+    let events = run(&mut app, 10);
+    assert_eq!(
+        map_checksums(&events, float),
+        map_checksums(&events, integer)
+    );
+}
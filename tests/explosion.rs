@@ -0,0 +1,105 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+
+fn app(step: Duration) -> (App, Entity) {
+    let mut app = common::app();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(step));
+    let root = spawn_map(&mut app, UVec2::new(16, 16), |builder| {
+        builder.with_default_chunk_color([0; 4])
+    });
+    pixel_map_mut(&mut app, root).explosion_settings_mut().noise = 0.0;
+    fill(&mut app, root, IRect::new(0, 0, 48, 16), GROUND);
+    (app, root)
+}
+
+fn overlay_pixels(app: &mut App) -> usize {
+    let sprites: Vec<Handle<Image>> = app
+        .world_mut()
+        .query_filtered::<&Sprite, Without<PixelChunk>>()
+        .iter(app.world())
+        .map(|sprite| sprite.image.clone())
+        .collect();
+    let images = app.world().resource::<Assets<Image>>();
+    sprites
+        .iter()
+        .filter_map(|image| images.get(image))
+        .map(|image| image.data.chunks_exact(4).filter(|p| p[3] > 0).count())
+        .sum()
+}
+
+#[test]
+fn explosion_carves_a_hole_with_a_scorched_edge() {
+    let (mut app, map) = app(Duration::ZERO);
+    pixel_map_mut(&mut app, map).explode(IVec2::new(24, 8), 4.0, 0.0);
+    app.update();
+
+    let hole = rect_positions(IRect::new(21, 5, 28, 12))
+        .into_iter()
+        .filter(|p| (*p - IVec2::new(24, 8)).as_vec2().length() < 4.0)
+        .collect::<Vec<_>>();
+    let carved = get_pixels(&mut app, map, hole.clone());
+    assert!(carved.iter().all(|color| color[3] == 0));
+
+    let [edge, far] = get_pixels(&mut app, map, vec![IVec2::new(29, 8), IVec2::new(40, 8)])[..]
+    else {
+        unreachable!()
+    };
+    assert_eq!(edge[3], 255);
+    assert!(edge[0] < GROUND[0] && edge[0] > 32);
+    assert_eq!(far, GROUND);
+
+    // Without time passing the particles hang where they were thrown from.
+    assert_eq!(pixel_map(&app, map).particle_batch_count(), 1);
+    assert_eq!(overlay_pixels(&mut app), hole.len());
+}
+
+#[test]
+fn particles_settle_back_into_the_map() {
+    let (mut app, map) = app(Duration::from_millis(16));
+    pixel_map_mut(&mut app, map)
+        .particle_settings_mut()
+        .lifetime = 0.5;
+    let region = rect_positions(IRect::new(-64, -64, 112, 80));
+    let before = get_pixels(&mut app, map, region.clone())
+        .iter()
+        .filter(|color| color[3] > 0)
+        .count();
+
+    pixel_map_mut(&mut app, map).explode(IVec2::new(24, 14), 5.0, 80.0);
+    app.update();
+    assert!(overlay_pixels(&mut app) > 0);
+    for _ in 0..40 {
+        app.update();
+    }
+
+    assert_eq!(pixel_map(&app, map).particle_batch_count(), 0);
+    assert_eq!(overlay_pixels(&mut app), 0);
+    let after = get_pixels(&mut app, map, region);
+    assert_eq!(after.iter().filter(|color| color[3] > 0).count(), before);
+    // Some of the thrown pixels landed on top of the ground.
+    let above = get_pixels(&mut app, map, rect_positions(IRect::new(0, 16, 48, 32)));
+    assert!(above.iter().any(|color| color[3] > 0));
+}
+
+#[test]
+fn explosion_in_empty_space_leaves_nothing_behind() {
+    let (mut app, map) = app(Duration::from_millis(16));
+    pixel_map_mut(&mut app, map)
+        .particle_settings_mut()
+        .lifetime = 0.1;
+    pixel_map_mut(&mut app, map).explode(IVec2::new(24, 60), 6.0, 50.0);
+    for _ in 0..10 {
+        app.update();
+    }
+    assert_eq!(pixel_map(&app, map).particle_batch_count(), 0);
+    let air = get_pixels(&mut app, map, rect_positions(IRect::new(10, 40, 40, 80)));
+    assert!(air.iter().all(|color| color[3] == 0));
+}
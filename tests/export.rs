@@ -0,0 +1,95 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const BACKGROUND: [u8; 4] = [10, 20, 30, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let map = spawn_map(&mut app, UVec2::new(6, 4), |builder| {
+        builder.with_default_chunk_color(BACKGROUND)
+    });
+    (app, map)
+}
+
+fn export_region(app: &mut App, map: Entity, rect: IRect) -> Image {
+    with_images(app, map, move |pixel_map, images| {
+        pixel_map.export_region(rect, images)
+    })
+    .unwrap()
+}
+
+fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+    let ind = (y * image.width() + x) as usize * 4;
+    image.data[ind..ind + 4].try_into().unwrap()
+}
+
+#[test]
+fn exports_stitch_chunks_and_fill_missing_ones() {
+    let (mut app, map) = app();
+    let written = [IVec2::new(0, 0), IVec2::new(-1, -1), IVec2::new(7, 5)];
+    set_pixels(
+        &mut app,
+        map,
+        written.iter().map(|&p| (p, color(p))).collect(),
+    );
+
+    let rect = IRect::new(-8, -6, 14, 9);
+    let image = export_region(&mut app, map, rect);
+    assert_eq!(image.size(), UVec2::new(22, 15));
+    let positions: Vec<IVec2> = (-6..9)
+        .rev()
+        .flat_map(|y| (-8..14).map(move |x| IVec2::new(x, y)))
+        .collect();
+    let colors = get_pixels(&mut app, map, positions);
+    assert_eq!(image.data, colors.concat());
+    for p in written {
+        let texel = (p - IVec2::new(rect.min.x, rect.max.y - 1)) * IVec2::new(1, -1);
+        assert_eq!(pixel(&image, texel.x as u32, texel.y as u32), color(p));
+    }
+    // Chunk (2, 2) was never created.
+    assert_eq!(pixel(&image, 21, 0), BACKGROUND);
+}
+
+#[test]
+fn requested_exports_arrive_downscaled() {
+    let (mut app, map) = app();
+    set_pixels(
+        &mut app,
+        map,
+        vec![
+            (IVec2::new(0, 1), [200, 0, 0, 255]),
+            (IVec2::new(1, 1), [100, 0, 0, 255]),
+        ],
+    );
+    let id = with_images(&mut app, map, |pixel_map, images| {
+        pixel_map.request_export(IRect::new(0, 0, 4, 2), 2, images)
+    })
+    .unwrap();
+    app.update();
+
+    let events = app.world().resource::<Events<RegionExported>>();
+    let mut cursor = events.get_cursor();
+    let exported: Vec<&RegionExported> = cursor.read(events).collect();
+    assert_eq!(exported.len(), 1);
+    assert_eq!(exported[0].id, id);
+    let image = &exported[0].image;
+    assert_eq!(image.size(), UVec2::new(2, 1));
+    assert_eq!(pixel(image, 0, 0), [80, 10, 15, 255]);
+    assert_eq!(pixel(image, 1, 0), BACKGROUND);
+}
+
+#[test]
+fn pngs_round_trip() {
+    let (mut app, map) = app();
+    set_pixels(&mut app, map, vec![(IVec2::new(2, 3), [1, 2, 3, 4])]);
+    let image = export_region(&mut app, map, IRect::new(0, 0, 6, 4));
+    let path = std::env::temp_dir().join(format!("pixelmap_export_{}.png", std::process::id()));
+    save_png(&image, &path).unwrap();
+    let decoded = image::open(&path).unwrap().into_rgba8();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(decoded.dimensions(), (6, 4));
+    assert_eq!(decoded.into_raw(), image.data);
+}
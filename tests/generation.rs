@@ -0,0 +1,57 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+
+#[test]
+fn new_chunks_are_generated_under_their_edits() {
+    let mut app = app();
+    let map = spawn_map(&mut app, UVec2::new(16, 8), |builder| {
+        builder.with_generator(PixelGenerator(color))
+    });
+    queue_pixels(&mut app, map, vec![(IVec2::new(-3, 5), RED)]);
+    app.update();
+
+    let rect = IRect::new(-16, 0, 0, 8);
+    let mut expected: Vec<[u8; 4]> = rect_positions(rect).into_iter().map(color).collect();
+    let written = rect_positions(rect)
+        .iter()
+        .position(|&position| position == IVec2::new(-3, 5))
+        .unwrap();
+    expected[written] = RED;
+    assert_eq!(get_pixels(&mut app, map, rect_positions(rect)), expected);
+}
+
+#[test]
+fn chunks_are_generated_once_per_creation() {
+    let generated = Arc::new(Mutex::new(Vec::new()));
+    let calls = generated.clone();
+    let mut app = app();
+    let map = spawn_map(&mut app, UVec2::new(8, 8), move |builder| {
+        builder.with_generator(move |chunk_pos: IVec2, _: UVec2, pixels: &mut [u8]| {
+            calls.lock().unwrap().push(chunk_pos);
+            pixels.fill(chunk_pos.x as u8);
+        })
+    });
+    queue_pixels(&mut app, map, vec![(IVec2::new(9, 1), RED)]);
+    app.update();
+    queue_pixels(&mut app, map, vec![(IVec2::new(10, 1), RED)]);
+    app.update();
+    assert_eq!(generated.lock().unwrap()[..], [IVec2::new(1, 0)]);
+
+    // A chunk created again starts from its generated pixels.
+    remove_chunk(&mut app, map, IVec2::new(1, 0));
+    app.update();
+    queue_pixels(&mut app, map, vec![(IVec2::new(12, 1), RED)]);
+    app.update();
+    assert_eq!(generated.lock().unwrap().len(), 2);
+    assert_eq!(
+        get_pixels(&mut app, map, vec![IVec2::new(9, 1), IVec2::new(12, 1)]),
+        [[1; 4], RED]
+    );
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use bevy::prelude::*;
+use common::*;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+fn app(budget: usize) -> (App, Entity) {
+    let mut app = common::app();
+    let map = spawn_map(&mut app, UVec2::new(8, 8), |builder| {
+        builder.with_history(budget)
+    });
+    (app, map)
+}
+
+fn undo(app: &mut App, map: Entity) -> bool {
+    let undone = pixel_map_mut(app, map).undo();
+    app.update();
+    undone
+}
+
+fn redo(app: &mut App, map: Entity) -> bool {
+    let redone = pixel_map_mut(app, map).redo();
+    app.update();
+    redone
+}
+
+#[test]
+fn undo_and_redo_restore_pixels() {
+    let (mut app, map) = app(1 << 20);
+    let a = IVec2::new(1, 1);
+    let b = IVec2::new(9, -3);
+    set_pixels(&mut app, map, vec![(a, RED), (b, RED)]);
+    set_pixels(&mut app, map, vec![(a, BLUE)]);
+    assert_eq!(get_pixels(&mut app, map, vec![a, b]), vec![BLUE, RED]);
+
+    assert!(undo(&mut app, map));
+    assert_eq!(get_pixels(&mut app, map, vec![a, b]), vec![RED, RED]);
+    assert!(undo(&mut app, map));
+    assert_eq!(get_pixels(&mut app, map, vec![a, b]), vec![[0; 4], [0; 4]]);
+    assert!(!undo(&mut app, map));
+
+    assert!(redo(&mut app, map));
+    assert_eq!(get_pixels(&mut app, map, vec![a, b]), vec![RED, RED]);
+    assert!(redo(&mut app, map));
+    assert_eq!(get_pixels(&mut app, map, vec![a, b]), vec![BLUE, RED]);
+    assert!(!redo(&mut app, map));
+}
+
+#[test]
+fn new_edits_clear_the_redo_stack() {
+    let (mut app, map) = app(1 << 20);
+    set_pixels(&mut app, map, vec![(IVec2::ZERO, RED)]);
+    assert!(undo(&mut app, map));
+    assert!(pixel_map(&app, map).can_redo());
+    set_pixels(&mut app, map, vec![(IVec2::ONE, BLUE)]);
+    assert!(!pixel_map(&app, map).can_redo());
+}
+
+#[test]
+fn transactions_undo_as_one_entry() {
+    let (mut app, map) = app(1 << 20);
+    let a = IVec2::new(2, 3);
+    set_pixels(&mut app, map, vec![(a, RED)]);
+    pixel_map_mut(&mut app, map).begin_transaction();
+    set_pixels(&mut app, map, vec![(a, BLUE)]);
+    set_pixels(
+        &mut app,
+        map,
+        vec![(a, [9, 9, 9, 255]), (IVec2::new(20, 20), BLUE)],
+    );
+    pixel_map_mut(&mut app, map).end_transaction();
+    assert_eq!(pixel_map(&app, map).history().unwrap().undo_len(), 2);
+
+    assert!(undo(&mut app, map));
+    assert_eq!(
+        get_pixels(&mut app, map, vec![a, IVec2::new(20, 20)]),
+        vec![RED, [0; 4]]
+    );
+}
+
+#[test]
+fn undo_restores_stamped_regions_exactly() {
+    let (mut app, map) = app(1 << 20);
+    let under = IVec2::new(4, 4);
+    set_pixels(&mut app, map, vec![(under, RED)]);
+    let mut texels = [BLUE; 9];
+    // The middle texel is transparent, so the stamp keeps the pixel under it.
+    texels[4][3] = 0;
+    let image = image(&mut app, UVec2::new(3, 3), &texels);
+    stamp(&mut app, map, image, IVec2::new(3, 3), UVec2::new(3, 3));
+    app.update();
+    let area: Vec<IVec2> = (2..7)
+        .flat_map(|x| (2..7).map(move |y| IVec2::new(x, y)))
+        .collect();
+    let stamped = get_pixels(&mut app, map, area.clone());
+    assert_eq!(stamped.iter().filter(|&&color| color == BLUE).count(), 8);
+    assert_eq!(get_pixels(&mut app, map, vec![under]), vec![RED]);
+
+    assert!(undo(&mut app, map));
+    let restored = get_pixels(&mut app, map, area.clone());
+    let expected: Vec<[u8; 4]> = area
+        .iter()
+        .map(|&p| if p == under { RED } else { [0; 4] })
+        .collect();
+    assert_eq!(restored, expected);
+}
+
+#[test]
+fn the_budget_drops_the_oldest_entries() {
+    // Each single pixel snapshot takes 4 bytes.
+    let (mut app, map) = app(12);
+    for x in 0..5 {
+        set_pixels(&mut app, map, vec![(IVec2::new(x, 0), RED)]);
+    }
+    let history = pixel_map(&app, map).history().cloned().unwrap();
+    assert_eq!(history.undo_len(), 3);
+    assert_eq!(history.memory_usage(), 12);
+
+    for _ in 0..3 {
+        assert!(undo(&mut app, map));
+    }
+    assert!(!undo(&mut app, map));
+    let row: Vec<IVec2> = (0..5).map(|x| IVec2::new(x, 0)).collect();
+    assert_eq!(
+        get_pixels(&mut app, map, row),
+        vec![RED, RED, [0; 4], [0; 4], [0; 4]]
+    );
+}
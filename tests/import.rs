@@ -0,0 +1,210 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_pixelmap::*;
+use common::*;
+
+const CHUNK_SIZE: UVec2 = UVec2::new(8, 6);
+
+fn spawn_chunked_map(app: &mut App) -> Entity {
+    spawn_map(app, CHUNK_SIZE, |builder| builder)
+}
+
+fn import(app: &mut App, map: Entity, image: ChunkedImage) {
+    pixel_map_mut(app, map).import(&image).unwrap();
+    while pixel_map(app, map).pending_import_pieces() > 0 {
+        app.update();
+    }
+    app.update();
+}
+
+/// World pixels of an image at `position`, in image data order.
+fn footprint(position: IVec2, size: UVec2) -> Vec<IVec2> {
+    (0..size.y as i32)
+        .rev()
+        .flat_map(|y| (0..size.x as i32).map(move |x| position + IVec2::new(x, y)))
+        .collect()
+}
+
+fn rgba_image(position: IVec2, size: UVec2) -> Image {
+    let data = footprint(position, size)
+        .into_iter()
+        .flat_map(color)
+        .collect();
+    Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::all(),
+    )
+}
+
+fn rgb_png(position: IVec2, size: UVec2) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, size.x, size.y);
+    encoder.set_color(png::ColorType::Rgb);
+    let data: Vec<u8> = footprint(position, size)
+        .into_iter()
+        .flat_map(|p| color(p)[..3].to_vec())
+        .collect();
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&data)
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn images_are_split_along_chunk_borders() {
+    let mut app = app();
+    let map = spawn_chunked_map(&mut app);
+    let position = IVec2::new(-11, -5);
+    let size = UVec2::new(30, 17);
+    let mut image = ChunkedImage::new(CHUNK_SIZE);
+    image
+        .add_image(&rgba_image(position, size), position)
+        .unwrap();
+    // 30 columns from x = -11 touch chunk columns -2..=2, 17 rows from
+    // y = -5 touch chunk rows -1..=1.
+    assert_eq!(image.chunk_positions().count(), 15);
+    import(&mut app, map, image);
+
+    let pixels = footprint(position, size);
+    let expected: Vec<[u8; 4]> = pixels.iter().copied().map(color).collect();
+    assert_eq!(get_pixels(&mut app, map, pixels), expected);
+    assert_eq!(
+        get_pixels(
+            &mut app,
+            map,
+            vec![position - IVec2::ONE, position + size.as_ivec2()]
+        ),
+        vec![[0; 4]; 2]
+    );
+}
+
+#[test]
+fn png_tiles_stream_into_one_map() {
+    let mut app = app();
+    let map = spawn_chunked_map(&mut app);
+    let size = UVec2::new(13, 9);
+    let tiles = [IVec2::new(0, 0), IVec2::new(13, 0), IVec2::new(0, 9)];
+    let mut image = ChunkedImage::new(CHUNK_SIZE);
+    for tile in tiles {
+        image.add_png(&rgb_png(tile, size)[..], tile).unwrap();
+    }
+    import(&mut app, map, image);
+
+    for tile in tiles {
+        let pixels = footprint(tile, size);
+        let expected: Vec<[u8; 4]> = pixels.iter().copied().map(color).collect();
+        assert_eq!(get_pixels(&mut app, map, pixels), expected);
+    }
+}
+
+#[test]
+fn imports_are_spread_over_frames() {
+    let mut app = app();
+    let map = spawn_chunked_map(&mut app);
+    let size = CHUNK_SIZE * UVec2::new(10, 10);
+    let mut image = ChunkedImage::new(CHUNK_SIZE);
+    image
+        .add_image(&rgba_image(IVec2::ZERO, size), IVec2::ZERO)
+        .unwrap();
+    app.world_mut()
+        .get_mut::<PixelMap>(map)
+        .unwrap()
+        .import(&image)
+        .unwrap();
+    app.update();
+    let pending = app
+        .world()
+        .get::<PixelMap>(map)
+        .unwrap()
+        .pending_import_pieces();
+    assert!(pending > 0 && pending < 100);
+}
+
+#[test]
+fn chunk_sizes_have_to_match() {
+    let mut app = app();
+    let map = spawn_chunked_map(&mut app);
+    let image = ChunkedImage::new(CHUNK_SIZE * 2);
+    let result = app
+        .world_mut()
+        .get_mut::<PixelMap>(map)
+        .unwrap()
+        .import(&image);
+    assert!(matches!(
+        result,
+        Err(PixelMapImportError::ChunkSizeMismatch { .. })
+    ));
+}
+
+#[test]
+fn image_handles_become_maps() {
+    let mut app = app();
+    let position = IVec2::new(3, -2);
+    let size = UVec2::new(20, 7);
+    let image = ChunkedImage::from_png(&rgb_png(position, size)[..], CHUNK_SIZE, position).unwrap();
+    let handle = app
+        .world_mut()
+        .resource_mut::<Assets<ChunkedImage>>()
+        .add(image);
+    let map = app.world_mut().spawn(PixelMapImageHandle(handle)).id();
+    for _ in 0..3 {
+        app.update();
+    }
+    assert!(app.world().get::<PixelMapImageHandle>(map).is_none());
+    assert_eq!(pixel_map(&app, map).config().chunk_size, CHUNK_SIZE);
+    let pixels = footprint(position, size);
+    let expected: Vec<[u8; 4]> = pixels.iter().copied().map(color).collect();
+    assert_eq!(get_pixels(&mut app, map, pixels), expected);
+}
+
+#[test]
+fn chunked_png_files_load_through_the_asset_server() {
+    use bevy::asset::io::memory::{Dir, MemoryAssetReader};
+    use bevy::asset::io::{AssetSource, AssetSourceId};
+
+    let position = IVec2::new(-4, 5);
+    let size = UVec2::new(13, 9);
+    let dir = Dir::default();
+    dir.insert_asset(
+        std::path::Path::new("level.chunked.png"),
+        rgb_png(position, size),
+    );
+    let mut app = App::new();
+    app.register_asset_source(
+        AssetSourceId::from("memory"),
+        AssetSource::build().with_reader(move || Box::new(MemoryAssetReader { root: dir.clone() })),
+    );
+    app.add_plugins((MinimalPlugins, AssetPlugin::default(), PixelMapCpuPlugin));
+    let handle = app.world().resource::<AssetServer>().load_with_settings(
+        "memory://level.chunked.png",
+        move |settings: &mut ChunkedImageSettings| {
+            settings.chunk_size = CHUNK_SIZE;
+            settings.position = position;
+        },
+    );
+    let map = app.world_mut().spawn(PixelMapImageHandle(handle)).id();
+    for _ in 0..100 {
+        app.update();
+        if app.world().get::<PixelMapImageHandle>(map).is_none() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    app.update();
+    app.update();
+    let pixels = footprint(position, size);
+    let expected: Vec<[u8; 4]> = pixels.iter().copied().map(color).collect();
+    assert_eq!(get_pixels(&mut app, map, pixels), expected);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const WALL: [u8; 4] = [90, 90, 90, 255];
+const LAVA: [u8; 4] = [250, 60, 0, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, UVec2::new(8, 8), |builder| {
+        builder.with_default_chunk_color([0, 0, 0, 0])
+    });
+    (app, root)
+}
+
+fn spawn_lighting(
+    app: &mut App,
+    map: Entity,
+    build: impl FnOnce(PixelMapLighting) -> PixelMapLighting,
+) -> Entity {
+    let lighting = app
+        .world_mut()
+        .resource_scope(|_, mut images: Mut<Assets<Image>>| {
+            PixelMapLighting::new(map, IRect::new(0, 0, 32, 32), 1, &mut images)
+        });
+    let entity = app.world_mut().spawn(build(lighting)).id();
+    app.update();
+    app.update();
+    entity
+}
+
+/// A light over the world pixel, given chunk sprites centered on their origin.
+fn spawn_light(app: &mut App, pixel: IVec2) {
+    let translation = pixel.as_vec2() + 0.5 - Vec2::splat(4.);
+    app.world_mut().spawn((
+        PixelLight2d {
+            radius: 48.0,
+            ..default()
+        },
+        GlobalTransform::from_translation(translation.extend(0.)),
+    ));
+}
+
+/// Light map color over a world pixel.
+fn light_at(app: &App, lighting: Entity, pixel: IVec2) -> [u8; 4] {
+    let lighting = app.world().get::<PixelMapLighting>(lighting).unwrap();
+    let image = app
+        .world()
+        .resource::<Assets<Image>>()
+        .get(&lighting.light_map)
+        .unwrap();
+    let texel = UVec2::new(
+        (pixel.x - lighting.rect.min.x) as u32,
+        (lighting.rect.max.y - 1 - pixel.y) as u32,
+    );
+    let ind = (texel.y * image.size().x + texel.x) as usize * 4;
+    image.data[ind..ind + 4].try_into().unwrap()
+}
+
+#[test]
+fn ambient_only_without_lights() {
+    let (mut app, root) = app();
+    let lighting = spawn_lighting(&mut app, root, |lighting| {
+        lighting.with_ambient(LinearRgba::rgb(0.2, 0.4, 0.6))
+    });
+    assert_eq!(
+        app.world()
+            .get::<PixelMapLighting>(lighting)
+            .unwrap()
+            .size(),
+        UVec2::new(32, 32)
+    );
+    for pixel in [IVec2::new(0, 0), IVec2::new(16, 16), IVec2::new(31, 31)] {
+        assert_eq!(light_at(&app, lighting, pixel), [51, 102, 153, 255]);
+    }
+}
+
+#[test]
+fn lights_open_space_with_falloff() {
+    let (mut app, root) = app();
+    spawn_light(&mut app, IVec2::new(16, 16));
+    let lighting = spawn_lighting(&mut app, root, |lighting| {
+        lighting.with_ambient(LinearRgba::BLACK)
+    });
+    let near = light_at(&app, lighting, IVec2::new(18, 16));
+    let far = light_at(&app, lighting, IVec2::new(30, 16));
+    assert!(near[0] > 200, "{near:?}");
+    assert!(far[0] > 0 && far[0] < near[0], "{far:?}");
+    assert_eq!(near[0], near[1]);
+}
+
+#[test]
+fn walls_cast_shadows() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(16, 0, 18, 32), WALL);
+    spawn_light(&mut app, IVec2::new(8, 16));
+    let lighting = spawn_lighting(&mut app, root, |lighting| {
+        lighting.with_ambient(LinearRgba::BLACK)
+    });
+    assert!(light_at(&app, lighting, IVec2::new(12, 16))[0] > 100);
+    // The side of the wall facing the light is lit, the far side is not.
+    assert!(light_at(&app, lighting, IVec2::new(16, 16))[0] > 50);
+    assert_eq!(light_at(&app, lighting, IVec2::new(17, 16))[0], 0);
+    assert_eq!(light_at(&app, lighting, IVec2::new(24, 16))[0], 0);
+
+    // Removing the wall lets the light through.
+    fill(&mut app, root, IRect::new(16, 0, 18, 32), [0; 4]);
+    app.update();
+    assert!(light_at(&app, lighting, IVec2::new(24, 16))[0] > 20);
+}
+
+#[test]
+fn emissive_pixels_glow() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(4, 4, 8, 8), LAVA);
+    let lighting = spawn_lighting(&mut app, root, |lighting| {
+        lighting
+            .with_ambient(LinearRgba::BLACK)
+            .with_emissive([255, 64, 0, 255], LinearRgba::RED)
+    });
+    assert_eq!(light_at(&app, lighting, IVec2::new(5, 5)), [255, 0, 0, 255]);
+    let glow = light_at(&app, lighting, IVec2::new(10, 6));
+    assert!(glow[0] > 0 && glow[1] == 0, "{glow:?}");
+    assert_eq!(light_at(&app, lighting, IVec2::new(30, 30)), [0, 0, 0, 255]);
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const CHUNK_SIZE: UVec2 = UVec2::new(8, 8);
+
+fn color(position: IVec2) -> [u8; 4] {
+    [(position.x * 9) as u8, (position.y * 5) as u8, 7, 255]
+}
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, CHUNK_SIZE, |builder| {
+        builder.with_default_chunk_color([1, 2, 3, 255]).with_lod(2)
+    });
+    (app, root)
+}
+
+fn tiles(app: &mut App) -> Vec<(PixelLodTile, Handle<Image>, Visibility)> {
+    app.world_mut()
+        .query::<(&PixelLodTile, &Sprite, &Visibility)>()
+        .iter(app.world())
+        .map(|(tile, sprite, visibility)| (*tile, sprite.image.clone(), *visibility))
+        .collect()
+}
+
+fn tile_data(app: &mut App, level: u32, position: IVec2) -> Vec<u8> {
+    let (_, image, _) = tiles(app)
+        .into_iter()
+        .find(|(tile, _, _)| tile.level == level && tile.position == position)
+        .unwrap();
+    app.world()
+        .resource::<Assets<Image>>()
+        .get(&image)
+        .unwrap()
+        .data
+        .clone()
+}
+
+/// What a tile should hold: the world area it covers, averaged down.
+fn expected_tile(app: &mut App, map: Entity, level: u32, position: IVec2) -> Vec<u8> {
+    let span = CHUNK_SIZE.as_ivec2() * (1 << level);
+    let rect = IRect::from_corners(position * span, (position + IVec2::ONE) * span);
+    let export = with_images(app, map, move |pixel_map, images| {
+        pixel_map.export_region(rect, images).unwrap()
+    });
+    downscale_image(&export, 1 << level).data
+}
+
+#[test]
+fn tiles_average_their_chunks() {
+    let (mut app, root) = app();
+    fill_with(&mut app, root, IRect::new(-13, -3, 21, 30), color);
+    pixel_map_mut(&mut app, root).force_lod_level(Some(1));
+    app.update();
+
+    for position in [IVec2::new(-1, -1), IVec2::ZERO, IVec2::new(1, 1)] {
+        assert_eq!(
+            tile_data(&mut app, 1, position),
+            expected_tile(&mut app, root, 1, position),
+            "tile {position}"
+        );
+    }
+
+    // New chunks reach the tiles of the level drawn right away.
+    fill_with(&mut app, root, IRect::new(40, 40, 42, 41), color);
+    assert_eq!(
+        tile_data(&mut app, 1, IVec2::new(2, 2)),
+        expected_tile(&mut app, root, 1, IVec2::new(2, 2))
+    );
+
+    // Edits to existing chunks once they are reported dirty.
+    fill(&mut app, root, IRect::new(2, 3, 6, 5), [250, 0, 0, 255]);
+    app.update();
+    assert_eq!(
+        tile_data(&mut app, 1, IVec2::ZERO),
+        expected_tile(&mut app, root, 1, IVec2::ZERO)
+    );
+
+    // Other levels catch up once they are drawn.
+    pixel_map_mut(&mut app, root).force_lod_level(Some(2));
+    app.update();
+    for position in [IVec2::new(-1, -1), IVec2::ZERO, IVec2::new(1, 1)] {
+        assert_eq!(
+            tile_data(&mut app, 2, position),
+            expected_tile(&mut app, root, 2, position),
+            "tile {position}"
+        );
+    }
+}
+
+#[test]
+fn tiles_replace_chunk_sprites() {
+    let (mut app, root) = app();
+    fill_with(&mut app, root, IRect::new(0, 0, 32, 16), color);
+    let lod = pixel_map(&app, root).lod().cloned().unwrap();
+    assert_eq!(lod.level(), 0);
+    assert_eq!(lod.tile_count(1), 2);
+    assert_eq!(lod.tile_count(2), 1);
+    assert!(tiles(&mut app)
+        .iter()
+        .all(|(_, _, visibility)| *visibility == Visibility::Hidden));
+
+    pixel_map_mut(&mut app, root).force_lod_level(Some(1));
+    app.update();
+    app.update();
+    let chunk = pixel_map(&app, root).chunk_entity(IVec2::ZERO).unwrap();
+    assert_eq!(
+        app.world().get::<Visibility>(chunk),
+        Some(&Visibility::Hidden)
+    );
+    for (tile, _, visibility) in tiles(&mut app) {
+        let expected = if tile.level == 1 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        assert_eq!(visibility, expected, "{tile:?}");
+    }
+    let mut parents = app
+        .world_mut()
+        .query_filtered::<&Parent, With<PixelLodTile>>();
+    assert!(parents.iter(app.world()).all(|parent| parent.get() == root));
+
+    pixel_map_mut(&mut app, root).disable_lod();
+    app.update();
+    app.update();
+    assert!(tiles(&mut app).is_empty());
+    assert_eq!(
+        app.world().get::<Visibility>(chunk),
+        Some(&Visibility::Inherited)
+    );
+}
+
+#[test]
+fn removed_chunks_leave_their_tiles() {
+    let (mut app, root) = app();
+    fill_with(&mut app, root, IRect::new(0, 0, 16, 8), color);
+    pixel_map_mut(&mut app, root).force_lod_level(Some(1));
+    app.update();
+    remove_chunk(&mut app, root, IVec2::new(1, 0));
+    app.update();
+    assert_eq!(
+        tile_data(&mut app, 1, IVec2::ZERO),
+        expected_tile(&mut app, root, 1, IVec2::ZERO)
+    );
+
+    remove_chunk(&mut app, root, IVec2::ZERO);
+    app.update();
+    assert!(tiles(&mut app).is_empty());
+}
+
+#[test]
+fn levels_are_capped_by_the_chunk_size() {
+    let mut app = App::new();
+    let root = app.world_mut().spawn_empty().id();
+    let map = PixelMap::builder(UVec2::new(12, 40), root)
+        .with_lod(5)
+        .build();
+    assert_eq!(map.lod().unwrap().levels(), 2);
+    assert_eq!(map.config().lod_levels, 2);
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const DEFAULT: [u8; 4] = [1, 2, 3, 255];
+
+fn color(position: IVec2) -> [u8; 4] {
+    [(position.x * 9) as u8, (position.y * 5) as u8, 7, 255]
+}
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let map = spawn_map(&mut app, UVec2::new(8, 8), |builder| {
+        builder.with_default_chunk_color(DEFAULT)
+    });
+    (app, map)
+}
+
+fn spawn_minimap(app: &mut App, map: Entity, rect: IRect, downscale: u32) -> Entity {
+    let minimap = app
+        .world_mut()
+        .resource_scope(|_, mut images: Mut<Assets<Image>>| {
+            PixelMapMinimap::new(map, rect, downscale, &mut images)
+        });
+    let entity = app.world_mut().spawn(minimap).id();
+    app.update();
+    entity
+}
+
+fn minimap_image(app: &App, minimap: Entity) -> Image {
+    let handle = &app.world().get::<PixelMapMinimap>(minimap).unwrap().image;
+    app.world()
+        .resource::<Assets<Image>>()
+        .get(handle)
+        .unwrap()
+        .clone()
+}
+
+fn expected(app: &mut App, map: Entity, rect: IRect, downscale: u32) -> Vec<u8> {
+    let export = with_images(app, map, move |pixel_map, images| {
+        pixel_map.export_region(rect, images).unwrap()
+    });
+    downscale_image(&export, downscale).data
+}
+
+#[test]
+fn chunk_aligned_minimaps_average_the_map() {
+    let (mut app, map) = app();
+    fill_with(&mut app, map, IRect::new(-5, -7, 13, 9), color);
+    let rect = IRect::new(-8, -8, 24, 16);
+    let minimap = spawn_minimap(&mut app, map, rect, 4);
+    let image = minimap_image(&app, minimap);
+    assert_eq!(image.size(), UVec2::new(8, 6));
+    assert_eq!(image.data, expected(&mut app, map, rect, 4));
+
+    // Only edited chunks are redrawn, and they are.
+    fill_with(&mut app, map, IRect::new(14, 2, 23, 3), |_| {
+        [200, 0, 0, 255]
+    });
+    assert_eq!(
+        minimap_image(&app, minimap).data,
+        expected(&mut app, map, rect, 4)
+    );
+}
+
+#[test]
+fn every_pixel_comes_from_one_chunk() {
+    let (mut app, map) = app();
+    let rect = IRect::new(-11, -6, 20, 15);
+    fill_with(&mut app, map, IRect::new(-16, -8, 24, 16), color);
+    let minimap = spawn_minimap(&mut app, map, rect, 3);
+    let image = minimap_image(&app, minimap);
+    assert_eq!(image.size(), UVec2::new(11, 7));
+    assert!(image.data.chunks_exact(4).all(|pixel| pixel[2] == 7));
+
+    remove_chunk(&mut app, map, IVec2::ZERO);
+    app.update();
+    let image = minimap_image(&app, minimap);
+    let cleared = image
+        .data
+        .chunks_exact(4)
+        .filter(|pixel| **pixel == DEFAULT)
+        .count();
+    // The 8 by 8 chunk covers about 3 by 3 blocks of 3 pixels.
+    assert!((4..=9).contains(&cleared), "{cleared} pixels cleared");
+    assert!(image
+        .data
+        .chunks_exact(4)
+        .all(|pixel| pixel == DEFAULT || pixel[2] == 7));
+}
+
+#[test]
+fn palettes_and_rects_can_change() {
+    let (mut app, map) = app();
+    fill_with(&mut app, map, IRect::new(0, 0, 8, 4), color);
+    let minimap = spawn_minimap(&mut app, map, IRect::new(0, 0, 8, 8), 4);
+    let handle = app
+        .world()
+        .get::<PixelMapMinimap>(minimap)
+        .unwrap()
+        .image
+        .clone();
+    let solid = [255, 255, 255, 255];
+    let empty = [0, 0, 0, 0];
+    {
+        let mut component = app.world_mut().get_mut::<PixelMapMinimap>(minimap).unwrap();
+        component.palette = MinimapPalette::Solid { solid, empty };
+        component.rect = IRect::new(0, -8, 8, 8);
+    }
+    // Missing chunks count as the opaque default color.
+    app.update();
+    let image = minimap_image(&app, minimap);
+    assert_eq!(
+        app.world().get::<PixelMapMinimap>(minimap).unwrap().image,
+        handle
+    );
+    assert_eq!(image.size(), UVec2::new(2, 4));
+    assert!(image.data.chunks_exact(4).all(|pixel| pixel == solid));
+
+    // A map with a transparent default color is empty.
+    let other = app.world_mut().spawn_empty().id();
+    let pixel_map = PixelMap::builder(UVec2::new(8, 8), other).build();
+    app.world_mut().entity_mut(other).insert(pixel_map);
+    app.world_mut()
+        .get_mut::<PixelMapMinimap>(minimap)
+        .unwrap()
+        .map = other;
+    app.update();
+    let image = minimap_image(&app, minimap);
+    assert!(image.data.chunks_exact(4).all(|pixel| pixel == empty));
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, UVec2::new(16, 16), |builder| {
+        builder.with_default_chunk_color([0; 4])
+    });
+    fill(&mut app, root, IRect::new(0, 0, 64, 48), [0; 4]);
+    fill(&mut app, root, IRect::new(0, 0, 64, 8), GROUND);
+    (app, root)
+}
+
+fn spawn_grid(app: &mut App, grid: PixelNavGrid) -> Entity {
+    let entity = app.world_mut().spawn(grid).id();
+    settle(app, entity);
+    entity
+}
+
+fn settle(app: &mut App, entity: Entity) {
+    for _ in 0..4 {
+        app.update();
+    }
+    assert!(!grid(app, entity).is_updating());
+}
+
+fn grid(app: &App, entity: Entity) -> &PixelNavGrid {
+    app.world().get::<PixelNavGrid>(entity).unwrap()
+}
+
+#[test]
+fn open_path_goes_around_walls_and_follows_edits() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(28, 8, 36, 40), GROUND);
+    let entity = spawn_grid(&mut app, PixelNavGrid::new(root, 4));
+
+    let nav = grid(&app, entity);
+    assert_eq!(nav.is_solid(IVec2::new(7, 5)), Some(true));
+    assert_eq!(nav.is_solid(IVec2::new(7, 11)), Some(false));
+    assert_eq!(nav.is_solid(IVec2::new(7, 12)), None);
+    let path = nav
+        .find_path(Vec2::new(10.0, 20.0), Vec2::new(50.0, 20.0), 10_000)
+        .unwrap();
+    assert_eq!(path.first(), Some(&IVec2::new(2, 5)));
+    assert_eq!(path.last(), Some(&IVec2::new(12, 5)));
+    assert!(path.iter().all(|&cell| nav.is_walkable(cell)));
+    assert!(path.iter().any(|cell| cell.y >= 10));
+    let version = nav.version();
+
+    // Blowing a hole through the wall opens a shortcut.
+    fill(&mut app, root, IRect::new(28, 16, 36, 24), [0; 4]);
+    settle(&mut app, entity);
+    let nav = grid(&app, entity);
+    assert!(nav.version() > version);
+    let path = nav
+        .find_path(Vec2::new(10.0, 20.0), Vec2::new(50.0, 20.0), 10_000)
+        .unwrap();
+    assert_eq!(path.len(), 11);
+    assert!(path.iter().all(|cell| cell.y == 5));
+}
+
+#[test]
+fn standing_agents_climb_steps_they_can_reach() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(32, 8, 40, 12), GROUND);
+    let standing = |max_step| {
+        PixelNavGrid::new(root, 4).with_mode(NavMode::Standable {
+            clearance: 2,
+            max_step,
+            max_drop: 4,
+        })
+    };
+    let climber = spawn_grid(&mut app, standing(1));
+    let walker = spawn_grid(&mut app, standing(0));
+
+    let nav = grid(&app, climber);
+    assert!(nav.is_walkable(IVec2::new(4, 2)));
+    assert!(!nav.is_walkable(IVec2::new(4, 3)));
+    let path = nav
+        .find_path(Vec2::new(10.0, 10.0), Vec2::new(50.0, 10.0), 10_000)
+        .unwrap();
+    assert!(path.contains(&IVec2::new(8, 3)));
+    assert!(path.iter().all(|&cell| nav.is_walkable(cell)));
+
+    let nav = grid(&app, walker);
+    assert!(nav
+        .find_path(Vec2::new(10.0, 10.0), Vec2::new(50.0, 10.0), 10_000)
+        .is_none());
+    // Dropping off the step works without climbing.
+    assert!(nav
+        .find_path(Vec2::new(34.0, 14.0), Vec2::new(50.0, 10.0), 10_000)
+        .is_some());
+}
+
+#[test]
+fn flow_field_leads_to_the_goal() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(28, 8, 36, 40), GROUND);
+    let entity = spawn_grid(&mut app, PixelNavGrid::new(root, 4));
+    let nav = grid(&app, entity);
+
+    let field = nav.flow_field(Vec2::new(50.0, 20.0), IRect::new(0, 0, 16, 12));
+    assert_eq!(field.cost(IVec2::new(12, 5)), Some(0));
+    assert_eq!(field.cost(IVec2::new(8, 5)), None);
+    assert!(field.cost(IVec2::new(2, 5)).unwrap() > field.cost(IVec2::new(14, 5)).unwrap());
+
+    // Following the field from the far side of the wall reaches the goal.
+    let mut cell = IVec2::new(2, 5);
+    for _ in 0..100 {
+        let Some(next) = field.next_cell(cell) else {
+            break;
+        };
+        cell = next;
+    }
+    assert_eq!(cell, IVec2::new(12, 5));
+    let direction = field.direction(Vec2::new(10.0, 20.0)).unwrap();
+    assert!(direction.length() > 0.99);
+}
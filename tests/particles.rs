@@ -0,0 +1,106 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+const SAND: [u8; 4] = [220, 200, 120, 255];
+const SPARK: [u8; 4] = [250, 120, 20, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+        16,
+    )));
+    let map = spawn_map(&mut app, UVec2::new(16, 16), |builder| {
+        builder.with_default_chunk_color([0; 4])
+    });
+    pixel_map_mut(&mut app, map)
+        .particle_settings_mut()
+        .lifetime = 1.5;
+    fill(&mut app, map, IRect::new(0, 0, 48, 16), GROUND);
+    (app, map)
+}
+
+/// The positions and colors of the opaque pixels in `rect`.
+fn opaque_pixels(app: &mut App, map: Entity, rect: IRect) -> Vec<(IVec2, [u8; 4])> {
+    let positions = rect_positions(rect);
+    let colors = get_pixels(app, map, positions.clone());
+    positions
+        .into_iter()
+        .zip(colors)
+        .filter(|(_, color)| color[3] > 0)
+        .collect()
+}
+
+fn run_until_settled(app: &mut App, map: Entity) {
+    for _ in 0..200 {
+        app.update();
+        if pixel_map(app, map).particle_batch_count() == 0 {
+            return;
+        }
+    }
+    panic!("particles never settled");
+}
+
+#[test]
+fn emitted_particle_falls_onto_the_ground() {
+    let (mut app, map) = app();
+    pixel_map_mut(&mut app, map).emit_particles([PixelParticle {
+        position: Vec2::new(24.5, 40.5),
+        velocity: Vec2::ZERO,
+        color: SAND,
+    }]);
+    app.update();
+    assert_eq!(pixel_map(&app, map).particle_batch_count(), 1);
+    run_until_settled(&mut app, map);
+
+    assert_eq!(
+        opaque_pixels(&mut app, map, IRect::new(0, 16, 48, 64)),
+        vec![(IVec2::new(24, 16), SAND)]
+    );
+}
+
+#[test]
+fn particles_pile_up_like_sand() {
+    let (mut app, map) = app();
+    pixel_map_mut(&mut app, map).emit_particles((0..5).map(|i| PixelParticle {
+        position: Vec2::new(24.5, 30.5 + i as f32 * 2.0),
+        velocity: Vec2::ZERO,
+        color: SAND,
+    }));
+    run_until_settled(&mut app, map);
+
+    let landed = opaque_pixels(&mut app, map, IRect::new(0, 16, 48, 64));
+    assert_eq!(landed.len(), 5);
+    assert!(landed.iter().all(|(position, color)| {
+        *color == SAND && position.y <= 17 && (position.x - 24).abs() <= 2
+    }));
+}
+
+#[test]
+fn simulations_eject_pixels_as_particles() {
+    let (mut app, map) = app();
+    pixel_map_mut(&mut app, map).add_cpu_simulation(|chunk: &mut CpuChunk| {
+        for y in chunk.dispatch.min.y as i32..chunk.dispatch.max.y as i32 {
+            for x in chunk.dispatch.min.x as i32..chunk.dispatch.max.x as i32 {
+                let coords = IVec2::new(x, y);
+                if chunk.get(coords) == SPARK && chunk.world_position(coords).x < 24 {
+                    chunk.eject(coords, Vec2::new(60.0, 40.0));
+                }
+            }
+        }
+    });
+    set_pixels(&mut app, map, vec![(IVec2::new(10, 16), SPARK)]);
+    run_until_settled(&mut app, map);
+
+    let landed = opaque_pixels(&mut app, map, IRect::new(0, 16, 48, 64));
+    assert_eq!(landed.len(), 1);
+    let (position, color) = landed[0];
+    assert_eq!(color, SPARK);
+    assert!(position.x >= 24 && position.y == 16);
+}
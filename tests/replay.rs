@@ -0,0 +1,219 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const SAND: [u8; 4] = [220, 200, 120, 255];
+const RED: [u8; 4] = [220, 30, 30, 255];
+
+/// Moves pixels sideways at random, so a replay only matches on the same
+/// ticks.
+fn jitter(chunk: &mut CpuChunk) {
+    for y in chunk.dispatch.min.y as i32..chunk.dispatch.max.y as i32 {
+        for x in chunk.dispatch.min.x as i32..chunk.dispatch.max.x as i32 {
+            let coords = IVec2::new(x, y);
+            let color = chunk.get(coords);
+            if color[3] == 0 {
+                continue;
+            }
+            let side = if chunk.random(coords) & 1 == 0 { -1 } else { 1 };
+            let target = coords + IVec2::new(side, 0);
+            if chunk.contains(target) && chunk.get(target)[3] == 0 {
+                chunk.set(target, color);
+                chunk.set(coords, [0; 4]);
+            }
+        }
+    }
+}
+
+fn spawn_jittery_map(app: &mut App) -> Entity {
+    spawn_map(app, UVec2::new(16, 16), |builder| {
+        builder
+            .with_default_chunk_color([0; 4])
+            .with_cpu_simulation(jitter)
+    })
+}
+
+/// Records a few ticks of edits on a deterministic map.
+fn record(app: &mut App) -> PixelMapRecording {
+    let map = spawn_jittery_map(app);
+    pixel_map_mut(app, map).enable_deterministic_simulation(DeterministicSettings {
+        seed: 11,
+        ..default()
+    });
+    fill(app, map, IRect::new(-8, 0, 24, 4), SAND);
+    app.update();
+
+    with_images(app, map, |pixel_map, images| {
+        pixel_map.start_recording(images)
+    });
+    fill(app, map, IRect::new(0, 0, 4, 2), [0; 4]);
+    let image = image(app, UVec2::new(2, 2), &[RED; 4]);
+    stamp(app, map, image, IVec2::new(10, 6), UVec2::new(2, 2));
+    for _ in 0..3 {
+        app.update();
+    }
+    fill(app, map, IRect::new(20, 4, 22, 5), SAND);
+    for _ in 0..3 {
+        app.update();
+    }
+    assert!(with_images(app, map, |pixel_map, images| {
+        pixel_map.stop_recording(images)
+    }));
+
+    for _ in 0..4 {
+        app.update();
+        let mut events = app
+            .world_mut()
+            .resource_mut::<Events<PixelMapRecordingReady>>();
+        let event = events.drain().find(|event| event.map == map);
+        if let Some(event) = event {
+            return ron::from_str(&ron::to_string(&event.recording).unwrap()).unwrap();
+        }
+    }
+    panic!("the recording never finished");
+}
+
+/// Runs the replay to the end and returns the verified event.
+fn verify(app: &mut App) -> PixelMapReplayVerified {
+    for _ in 0..40 {
+        app.update();
+        let mut events = app
+            .world_mut()
+            .resource_mut::<Events<PixelMapReplayVerified>>();
+        let event = events.drain().next();
+        if let Some(event) = event {
+            return event;
+        }
+    }
+    panic!("the replay never finished");
+}
+
+#[test]
+fn replays_match_the_recording() {
+    let mut app = app();
+    let recording = record(&mut app);
+    assert_eq!(recording.end_tick - recording.start_tick, 8);
+    assert_eq!(recording.start_chunks.len(), 3);
+    assert_eq!(recording.frames.len(), 3);
+    assert!(recording.frames[0].tick == recording.start_tick);
+    assert_ne!(recording.start_chunks, recording.end_chunks);
+
+    let fresh = spawn_jittery_map(&mut app);
+    app.insert_resource(PixelMapReplay::new(fresh, recording.clone()));
+    let verified = verify(&mut app);
+    assert_eq!(verified.map, fresh);
+    assert_eq!(verified.mismatched_chunks, Vec::<IVec2>::new());
+    let replay = app.world().resource::<PixelMapReplay>();
+    assert_eq!(replay.tick(), recording.end_tick);
+    assert_eq!(replay.mismatched_chunks(), Some(&[][..]));
+    assert!(pixel_map(&app, fresh).is_simulation_paused());
+}
+
+#[test]
+fn altered_edits_are_reported() {
+    let mut app = app();
+    let mut recording = record(&mut app);
+    let frame = recording.frames.last_mut().unwrap();
+    let PixelEdit::Pixels(pixels) = &mut frame.edits[0] else {
+        panic!("expected a pixel write");
+    };
+    pixels[0].1 = RED;
+
+    let fresh = spawn_jittery_map(&mut app);
+    app.insert_resource(PixelMapReplay::new(fresh, recording));
+    let verified = verify(&mut app);
+    assert_eq!(verified.mismatched_chunks, vec![IVec2::new(1, 0)]);
+}
+
+#[test]
+fn seeking_pauses_and_rewinds() {
+    let mut app = app();
+    let recording = record(&mut app);
+    let start = recording.start_tick;
+    let fresh = spawn_jittery_map(&mut app);
+    let mut replay = PixelMapReplay::new(fresh, recording.clone());
+    replay.seek(start + 3);
+    app.insert_resource(replay);
+    for _ in 0..6 {
+        app.update();
+    }
+    let replay = app.world().resource::<PixelMapReplay>();
+    assert_eq!(replay.tick(), start + 3);
+    assert!(!replay.is_playing());
+    assert!(pixel_map(&app, fresh).is_simulation_paused());
+    assert_eq!(pixel_map(&app, fresh).simulation_tick(), start + 3);
+
+    // Back to the start, then on to the end.
+    let mut replay = app.world_mut().resource_mut::<PixelMapReplay>();
+    replay.seek(start + 1);
+    assert_eq!(replay.tick(), start);
+    replay.fast_forward(100, 1);
+    let verified = verify(&mut app);
+    assert_eq!(verified.mismatched_chunks, Vec::<IVec2>::new());
+}
+
+#[test]
+fn fast_forward_plays_several_ticks_per_frame() {
+    let mut app = app();
+    let recording = record(&mut app);
+    let fresh = spawn_jittery_map(&mut app);
+    let mut replay = PixelMapReplay::new(fresh, recording.clone());
+    replay.fast_forward(recording.end_tick - recording.start_tick, 4);
+    app.insert_resource(replay);
+    let mut frames = 0;
+    while app.world().resource::<PixelMapReplay>().is_playing() {
+        app.update();
+        frames += 1;
+    }
+    // Ticks 0, 1 to 3 and 4 to 7, each frame starting at recorded edits.
+    assert_eq!(frames, 3);
+    let verified = verify(&mut app);
+    assert_eq!(verified.mismatched_chunks, Vec::<IVec2>::new());
+    assert_eq!(pixel_map(&app, fresh).simulation_tick(), recording.end_tick);
+}
+
+fn recording_failure(app: &mut App, map: Entity) -> Option<UnrecordableEdit> {
+    app.update();
+    let mut events = app
+        .world_mut()
+        .resource_mut::<Events<PixelMapRecordingFailed>>();
+    let event = events.drain().find(|event| event.map == map);
+    event.map(|event| event.edit)
+}
+
+#[test]
+fn unrecordable_edits_stop_the_recording() {
+    let mut app = app();
+    let map = spawn_jittery_map(&mut app);
+    fill(&mut app, map, IRect::new(0, 0, 16, 4), SAND);
+    with_images(&mut app, map, |pixel_map, images| {
+        pixel_map.start_recording(images)
+    });
+    app.update();
+
+    pixel_map_mut(&mut app, map).explode(IVec2::new(8, 2), 3.0, 20.0);
+    assert_eq!(
+        recording_failure(&mut app, map),
+        Some(UnrecordableEdit::Particles)
+    );
+    assert!(!pixel_map(&app, map).is_recording());
+    assert!(app
+        .world()
+        .resource::<Events<PixelMapRecordingReady>>()
+        .is_empty());
+
+    // Clips are only read back by the end of the frame they're copied in.
+    with_images(&mut app, map, |pixel_map, images| {
+        pixel_map.start_recording(images);
+        let clip = pixel_map
+            .copy_region(IRect::new(0, 0, 4, 4), images)
+            .unwrap();
+        pixel_map.paste(&clip, IVec2::new(8, 8), images);
+    });
+    assert_eq!(
+        recording_failure(&mut app, map),
+        Some(UnrecordableEdit::RegionCopy)
+    );
+}
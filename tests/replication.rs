@@ -0,0 +1,288 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+const RED: [u8; 4] = [220, 30, 30, 255];
+
+fn spawn_empty_map(app: &mut App) -> Entity {
+    spawn_map(app, UVec2::new(16, 16), |builder| {
+        builder
+            .with_default_chunk_color([0; 4])
+            .with_history(1 << 20)
+    })
+}
+
+fn app() -> (App, Entity, Entity) {
+    let mut app = common::app();
+    let host = spawn_empty_map(&mut app);
+    let client = spawn_empty_map(&mut app);
+    pixel_map_mut(&mut app, host).enable_replication();
+    (app, host, client)
+}
+
+/// Batches sent by the host, through the same round trip a transport would
+/// make.
+fn outgoing(app: &mut App, host: Entity) -> Vec<PixelEditBatch> {
+    pixel_map_mut(app, host)
+        .drain_outgoing_edits()
+        .map(|batch| ron::from_str(&ron::to_string(&batch).unwrap()).unwrap())
+        .collect()
+}
+
+/// Runs the frames the host needs to read back pixels only the GPU knows,
+/// then hands its batches to the client.
+fn sync(app: &mut App, host: Entity, client: Entity) {
+    for _ in 0..3 {
+        app.update();
+    }
+    for batch in outgoing(app, host) {
+        pixel_map_mut(app, client).receive_edits(batch);
+    }
+    app.update();
+}
+
+fn pixels(app: &mut App, map: Entity, rect: IRect) -> Vec<[u8; 4]> {
+    get_pixels(app, map, rect_positions(rect))
+}
+
+fn chunk_positions(app: &App, map: Entity) -> Vec<IVec2> {
+    let mut positions: Vec<IVec2> = pixel_map(app, map).chunk_positions().collect();
+    positions.sort_by_key(|pos| (pos.x, pos.y));
+    positions
+}
+
+fn assert_in_sync(app: &mut App, host: Entity, client: Entity) {
+    assert_eq!(chunk_positions(app, host), chunk_positions(app, client));
+    let rect = IRect::new(-32, -32, 48, 48);
+    assert_eq!(pixels(app, host, rect), pixels(app, client, rect));
+}
+
+#[test]
+fn pixel_writes_and_chunks_replicate_in_order() {
+    let (mut app, host, client) = app();
+    queue_fill(&mut app, host, IRect::new(0, 0, 32, 4), GROUND);
+    sync(&mut app, host, client);
+    assert_in_sync(&mut app, host, client);
+    assert_eq!(pixels(&mut app, client, IRect::new(20, 2, 21, 3)), [GROUND]);
+
+    // Later batches wait for the ones before them.
+    queue_fill(&mut app, host, IRect::new(4, 4, 6, 6), RED);
+    app.update();
+    let first = outgoing(&mut app, host);
+    queue_fill(&mut app, host, IRect::new(4, 4, 6, 6), GROUND);
+    remove_chunk(&mut app, host, IVec2::new(1, 0));
+    app.update();
+    let second = outgoing(&mut app, host);
+    assert_eq!((first.len(), second.len()), (1, 1));
+    assert!(first[0].sequence < second[0].sequence);
+
+    pixel_map_mut(&mut app, client).receive_edits(second[0].clone());
+    app.update();
+    assert_eq!(pixel_map(&app, client).pending_incoming_edits(), 1);
+    assert_eq!(chunk_positions(&app, client).len(), 2);
+    pixel_map_mut(&mut app, client).receive_edits(first[0].clone());
+    app.update();
+    app.update();
+    assert_eq!(pixel_map(&app, client).pending_incoming_edits(), 0);
+    assert_in_sync(&mut app, host, client);
+    assert_eq!(chunk_positions(&app, client), vec![IVec2::ZERO]);
+
+    // The client's own edits aren't sent back.
+    assert!(pixel_map_mut(&mut app, client)
+        .drain_outgoing_edits()
+        .next()
+        .is_none());
+}
+
+#[test]
+fn stamps_send_their_texture_once() {
+    let (mut app, host, client) = app();
+    let image = image(&mut app, UVec2::new(2, 2), &[RED; 4]);
+    stamp(
+        &mut app,
+        host,
+        image.clone(),
+        IVec2::new(3, 3),
+        UVec2::new(2, 2),
+    );
+    app.update();
+    stamp(&mut app, host, image, IVec2::new(-8, 9), UVec2::new(2, 2));
+    app.update();
+
+    let batches = outgoing(&mut app, host);
+    let edits: Vec<&PixelEdit> = batches.iter().flat_map(|batch| &batch.edits).collect();
+    let textures = edits
+        .iter()
+        .filter(|edit| matches!(edit, PixelEdit::Texture { .. }))
+        .count();
+    let stamps = edits
+        .iter()
+        .filter(|edit| matches!(edit, PixelEdit::Stamp { .. }))
+        .count();
+    assert_eq!((textures, stamps), (1, 2));
+
+    for batch in batches {
+        pixel_map_mut(&mut app, client).receive_edits(batch);
+    }
+    app.update();
+    app.update();
+    assert_in_sync(&mut app, host, client);
+    assert_eq!(
+        pixels(&mut app, client, IRect::new(-8, 9, -6, 10)),
+        [RED; 2]
+    );
+}
+
+#[test]
+fn modified_textures_are_sent_again() {
+    let (mut app, host, client) = app();
+    let image = image(&mut app, UVec2::new(2, 2), &[RED; 4]);
+    stamp(&mut app, host, image.clone(), IVec2::ZERO, UVec2::new(2, 2));
+    sync(&mut app, host, client);
+
+    app.world_mut()
+        .resource_mut::<Assets<Image>>()
+        .get_mut(&image)
+        .unwrap()
+        .data = [GROUND; 4].concat();
+    app.update();
+    stamp(&mut app, host, image, IVec2::new(4, 0), UVec2::new(2, 2));
+    app.update();
+    let batches = outgoing(&mut app, host);
+    assert!(matches!(
+        batches[0].edits[..],
+        [PixelEdit::Texture { .. }, PixelEdit::Stamp { .. }]
+    ));
+    for batch in batches {
+        pixel_map_mut(&mut app, client).receive_edits(batch);
+    }
+    app.update();
+    assert_in_sync(&mut app, host, client);
+    assert_eq!(
+        pixels(&mut app, client, IRect::new(4, 0, 6, 1)),
+        [GROUND; 2]
+    );
+}
+
+#[test]
+fn cuts_imports_and_undo_replicate() {
+    let (mut app, host, client) = app();
+    fill_with(&mut app, host, IRect::new(0, 0, 32, 16), color);
+    sync(&mut app, host, client);
+
+    // The clip is only on the GPU when it's pasted, so it's read back.
+    with_images(&mut app, host, |pixel_map, images| {
+        let clip = pixel_map
+            .cut_region(IRect::new(4, 2, 20, 10), images)
+            .unwrap();
+        pixel_map.paste(&clip, IVec2::new(10, 12), images);
+    });
+    sync(&mut app, host, client);
+    assert_in_sync(&mut app, host, client);
+    assert_eq!(
+        pixels(&mut app, client, IRect::new(10, 12, 11, 13)),
+        [color(IVec2::new(4, 2))]
+    );
+
+    let mut image = ChunkedImage::new(UVec2::new(16, 16));
+    let texels = [RED; 6 * 5];
+    let red = Image::new(
+        bevy::render::render_resource::Extent3d {
+            width: 6,
+            height: 5,
+            depth_or_array_layers: 1,
+        },
+        bevy::render::render_resource::TextureDimension::D2,
+        texels.concat(),
+        bevy::render::render_resource::TextureFormat::Rgba8Unorm,
+        default(),
+    );
+    image.add_image(&red, IVec2::new(-3, 14)).unwrap();
+    pixel_map_mut(&mut app, host).import(&image).unwrap();
+    while pixel_map(&app, host).pending_import_pieces() > 0 {
+        app.update();
+    }
+    sync(&mut app, host, client);
+    assert_in_sync(&mut app, host, client);
+
+    assert!(pixel_map_mut(&mut app, host).undo());
+    sync(&mut app, host, client);
+    assert_in_sync(&mut app, host, client);
+    assert!(pixel_map_mut(&mut app, host).undo());
+    sync(&mut app, host, client);
+    assert_in_sync(&mut app, host, client);
+    assert_eq!(
+        pixels(&mut app, client, IRect::new(4, 2, 5, 3)),
+        [color(IVec2::new(4, 2))]
+    );
+}
+
+#[test]
+fn explosions_replicate_once_settled() {
+    let (mut app, host, client) = app();
+    app.insert_resource(bevy::time::TimeUpdateStrategy::ManualDuration(
+        std::time::Duration::from_millis(16),
+    ));
+    pixel_map_mut(&mut app, host)
+        .particle_settings_mut()
+        .lifetime = 0.2;
+    queue_fill(&mut app, host, IRect::new(0, 0, 48, 16), GROUND);
+    sync(&mut app, host, client);
+
+    pixel_map_mut(&mut app, host).explode(IVec2::new(24, 14), 5.0, 60.0);
+    for _ in 0..20 {
+        sync(&mut app, host, client);
+    }
+    assert_eq!(pixel_map(&app, host).particle_batch_count(), 0);
+    assert_eq!(pixel_map(&app, client).particle_batch_count(), 0);
+    assert_in_sync(&mut app, host, client);
+    // Some of the thrown pixels landed on top of the ground.
+    let above = pixels(&mut app, client, IRect::new(0, 16, 48, 32));
+    assert!(above.iter().any(|color| color[3] > 0));
+}
+
+#[test]
+fn late_joiners_start_from_a_snapshot() {
+    let (mut app, host, _) = app();
+    queue_fill(&mut app, host, IRect::new(-16, 0, 32, 8), GROUND);
+    app.update();
+    // Nobody was listening to the first batches.
+    outgoing(&mut app, host);
+
+    let id = with_images(&mut app, host, |pixel_map, images| {
+        pixel_map.request_replication_snapshot(images)
+    })
+    .unwrap();
+    queue_fill(&mut app, host, IRect::new(0, 8, 4, 12), RED);
+    let mut snapshot = None;
+    for _ in 0..4 {
+        app.update();
+        let events = app.world().resource::<Events<PixelMapSnapshotReady>>();
+        if let Some(event) = events
+            .get_cursor()
+            .read(events)
+            .find(|event| event.id == id)
+        {
+            snapshot = Some(event.snapshot.clone());
+            break;
+        }
+    }
+    let snapshot: PixelMapSnapshot =
+        ron::from_str(&ron::to_string(&snapshot.unwrap()).unwrap()).unwrap();
+    assert_eq!(snapshot.chunks.len(), 3);
+
+    let late = spawn_empty_map(&mut app);
+    queue_fill(&mut app, late, IRect::new(40, 40, 41, 41), RED);
+    app.update();
+    pixel_map_mut(&mut app, late).receive_snapshot(snapshot);
+    for batch in outgoing(&mut app, host) {
+        pixel_map_mut(&mut app, late).receive_edits(batch);
+    }
+    app.update();
+    app.update();
+    assert_in_sync(&mut app, host, late);
+    assert_eq!(pixels(&mut app, late, IRect::new(0, 8, 1, 9)), [RED]);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+use common::*;
+
+const SOLID: [u8; 4] = [200, 150, 100, 255];
+const EMPTY: [u8; 4] = [0, 0, 0, 0];
+
+fn app(max_distance: u32) -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, UVec2::new(8, 8), |builder| {
+        builder
+            .with_default_chunk_color(EMPTY)
+            .with_sdf(max_distance)
+    });
+    (app, root)
+}
+
+/// Writes `pixels` and runs the frames that report the change and rebuild
+/// the field.
+fn set(app: &mut App, root: Entity, pixels: Vec<(IVec2, [u8; 4])>) {
+    set_pixels(app, root, pixels);
+    app.update();
+}
+
+fn distance(app: &App, root: Entity, x: i32, y: i32) -> Option<f32> {
+    pixel_map(app, root).sdf_distance(IVec2::new(x, y))
+}
+
+#[test]
+fn distances_around_a_solid_pixel() {
+    let (mut app, root) = app(6);
+    set(&mut app, root, vec![(IVec2::new(3, 3), SOLID)]);
+    assert_eq!(distance(&app, root, 3, 3), Some(-0.5));
+    assert_eq!(distance(&app, root, 4, 3), Some(0.5));
+    assert_eq!(distance(&app, root, 6, 3), Some(2.5));
+    assert_eq!(distance(&app, root, 3, 7), Some(3.5));
+    assert_eq!(distance(&app, root, 7, 7), Some(32f32.sqrt() - 0.5));
+    // No field without a chunk.
+    assert_eq!(distance(&app, root, 9, 3), None);
+}
+
+#[test]
+fn distances_reach_across_chunks() {
+    let (mut app, root) = app(6);
+    set(
+        &mut app,
+        root,
+        vec![(IVec2::new(7, 3), SOLID), (IVec2::new(12, 3), EMPTY)],
+    );
+    assert_eq!(distance(&app, root, 9, 3), Some(1.5));
+    assert_eq!(distance(&app, root, 14, 3), Some(6.0));
+
+    // A change in one chunk updates its neighbor too.
+    set(&mut app, root, vec![(IVec2::new(8, 3), SOLID)]);
+    assert_eq!(distance(&app, root, 5, 3), Some(1.5));
+    assert_eq!(distance(&app, root, 8, 3), Some(-0.5));
+
+    set(
+        &mut app,
+        root,
+        vec![(IVec2::new(7, 3), EMPTY), (IVec2::new(8, 3), EMPTY)],
+    );
+    assert_eq!(distance(&app, root, 5, 3), Some(6.0));
+    assert_eq!(distance(&app, root, 9, 3), Some(6.0));
+}
+
+#[test]
+fn changes_reach_fields_within_max_distance() {
+    let (mut app, root) = app(3);
+    set(
+        &mut app,
+        root,
+        vec![(IVec2::new(4, 3), EMPTY), (IVec2::new(12, 3), EMPTY)],
+    );
+    assert_eq!(distance(&app, root, 5, 3), Some(3.0));
+
+    // The dirty pixel is in the next chunk, but close enough.
+    set(&mut app, root, vec![(IVec2::new(8, 3), SOLID)]);
+    assert_eq!(distance(&app, root, 5, 3), Some(2.5));
+    assert_eq!(distance(&app, root, 4, 3), Some(3.0));
+
+    remove_chunk(&mut app, root, IVec2::new(1, 0));
+    app.update();
+    assert_eq!(distance(&app, root, 5, 3), Some(3.0));
+}
+
+#[test]
+fn inside_distances_are_negative_and_clamped() {
+    let (mut app, root) = app(2);
+    let pixels = (0..8)
+        .flat_map(|y| (0..8).map(move |x| (IVec2::new(x, y), SOLID)))
+        .collect();
+    set(&mut app, root, pixels);
+    assert_eq!(distance(&app, root, 0, 5), Some(-0.5));
+    assert_eq!(distance(&app, root, 1, 5), Some(-1.5));
+    assert_eq!(distance(&app, root, 4, 4), Some(-2.0));
+}
+
+#[test]
+fn field_images_match_the_query() {
+    let (mut app, root) = app(4);
+    set(&mut app, root, vec![(IVec2::new(2, 5), SOLID)]);
+    let map = pixel_map(&app, root);
+    let sdf = map.sdf().unwrap();
+    assert_eq!(sdf.max_distance(), 4);
+    assert_eq!(map.config().sdf_distance, 4);
+    let field = app
+        .world()
+        .resource::<Assets<Image>>()
+        .get(sdf.field(IVec2::ZERO).unwrap())
+        .unwrap();
+    assert_eq!(field.texture_descriptor.format, TextureFormat::R32Float);
+    // First row at the top, like the chunk images.
+    let texel = |x: u32, y: u32| {
+        let ind = (y * 8 + x) as usize * 4;
+        f32::from_le_bytes(field.data[ind..ind + 4].try_into().unwrap())
+    };
+    for (x, y) in [(2, 5), (0, 0), (7, 7), (3, 4)] {
+        assert_eq!(
+            Some(texel(x, 7 - y)),
+            map.sdf_distance(IVec2::new(x as i32, y as i32))
+        );
+    }
+
+    pixel_map_mut(&mut app, root).disable_sdf();
+    assert!(distance(&app, root, 2, 5).is_none());
+}
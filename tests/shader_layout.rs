@@ -0,0 +1,89 @@
+use bevy_pixelmap::*;
+
+const HEADER: &str = "
+@group(0) @binding(0) var input_texture: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(1) var<uniform> input_texture_pos: vec2<i32>;
+@group(0) @binding(2) var<uniform> input_texture_size: vec2<u32>;
+";
+
+#[test]
+fn current_layouts_pass() {
+    let sand = include_str!("../examples/simple_example/assets/shaders/sand_sim.wgsl");
+    assert_eq!(check_simulation_shader(sand), Ok(()));
+
+    let full = format!(
+        "{HEADER}
+struct SimulationTick {{ tick: u32, seed: u32, phase: u32, phases: u32 }}
+// @group(0) @binding(9) var<uniform> commented_out: u32;
+@group(0) @binding(3) var<uniform> dispatch_offset: vec2<u32>;
+@binding(4) @group(0) var<storage, read_write> bounds: array<atomic<i32>, 5>;
+@group(0) @binding(5) var<storage, read_write> ejected: array<u32>;
+@group(0) @binding(6) var<uniform> tick: SimulationTick;
+@group(1) @binding(0) var<uniform> other_group: u32;
+
+fn main() {{
+    var local = 0;
+}}"
+    );
+    assert_eq!(check_simulation_shader(&full), Ok(()));
+}
+
+#[test]
+fn old_layouts_name_the_missing_binding() {
+    let err = check_simulation_shader(HEADER).unwrap_err();
+    assert_eq!(
+        err,
+        SimulationLayoutError::Missing {
+            binding: 3,
+            expected: "var<uniform> dispatch_offset: vec2<u32>",
+        }
+    );
+    assert!(err.to_string().contains("binding 3 is missing"));
+}
+
+#[test]
+fn wrong_kinds_are_reported() {
+    let swapped = format!(
+        "{HEADER}
+@group(0) @binding(3) var<storage, read_write> dirty_bounds: array<atomic<i32>, 4>;
+@group(0) @binding(4) var<uniform> dispatch_offset: vec2<u32>;"
+    );
+    let err = check_simulation_shader(&swapped).unwrap_err();
+    assert!(matches!(
+        err,
+        SimulationLayoutError::Mismatch { binding: 3, .. }
+    ));
+    assert!(err.to_string().contains("found `var<storage, read_write>"));
+
+    let extra = format!(
+        "{HEADER}
+@group(0) @binding(3) var<uniform> dispatch_offset: vec2<u32>;
+@group(0) @binding(4) var<storage, read_write> dirty_bounds: array<atomic<i32>, 5>;
+@group(0) @binding(7) var<uniform> mine: u32;"
+    );
+    assert!(matches!(
+        check_simulation_shader(&extra),
+        Err(SimulationLayoutError::Unknown { binding: 7, .. })
+    ));
+}
+
+#[test]
+fn integer_storage_shaders_bind_an_integer_texture() {
+    let integer = "
+@group(0) @binding(0) var input_texture: texture_storage_2d<rgba8uint, read_write>;
+@group(0) @binding(1) var<uniform> input_texture_pos: vec2<i32>;
+@group(0) @binding(2) var<uniform> input_texture_size: vec2<u32>;
+@group(0) @binding(3) var<uniform> dispatch_offset: vec2<u32>;
+@group(0) @binding(4) var<storage, read_write> dirty_bounds: array<atomic<i32>, 5>;";
+    assert_eq!(check_integer_simulation_shader(integer), Ok(()));
+    assert!(matches!(
+        check_simulation_shader(integer),
+        Err(SimulationLayoutError::Mismatch { binding: 0, .. })
+    ));
+
+    let sand = include_str!("../examples/simple_example/assets/shaders/sand_sim.wgsl");
+    let err = check_integer_simulation_shader(sand).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("should be `var input_texture: texture_storage_2d<rgba8uint, read_write>`"));
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use common::*;
+use proptest::prelude::*;
+
+fn app(chunk_size: UVec2) -> (App, Entity) {
+    let mut app = common::app();
+    let map = spawn_map(&mut app, chunk_size, |builder| builder);
+    (app, map)
+}
+
+fn dirty_rects(app: &App, map: Entity) -> Vec<IRect> {
+    pixel_map(app, map)
+        .dirty_rects()
+        .map(|(_, rect)| rect)
+        .collect()
+}
+
+/// Texel colors of a stamp, rows from the top like image data. Some texels are
+/// transparent so holes in the stamp are covered too.
+fn texels(size: UVec2, seed: u32) -> Vec<[u8; 4]> {
+    (0..size.x * size.y)
+        .map(|i| {
+            let h = (i ^ seed).wrapping_mul(2654435761).to_le_bytes();
+            let alpha = if h[3] < 50 { 0 } else { 255 };
+            [h[0], h[1], h[2].max(1), alpha]
+        })
+        .collect()
+}
+
+fn stamp_texels(app: &mut App, map: Entity, position: IVec2, size: UVec2, texels: &[[u8; 4]]) {
+    let image = image(app, size, texels);
+    stamp(app, map, image, position, size);
+    app.update();
+}
+
+/// The world pixels a stamp covers and their texel colors. Counted from the
+/// bottom, so the last texel row lands on `position.y` and the first on the top
+/// row of the footprint.
+fn footprint(position: IVec2, size: UVec2, texels: &[[u8; 4]]) -> HashMap<IVec2, [u8; 4]> {
+    texels
+        .chunks(size.x as usize)
+        .rev()
+        .enumerate()
+        .flat_map(|(row, colors)| {
+            colors.iter().enumerate().map(move |(column, color)| {
+                (position + IVec2::new(column as i32, row as i32), *color)
+            })
+        })
+        .collect()
+}
+
+fn chunk_sizes() -> impl Strategy<Value = UVec2> {
+    (1u32..24, 1u32..24).prop_map(|(x, y)| UVec2::new(x, y))
+}
+
+fn positions() -> impl Strategy<Value = IVec2> {
+    (-80i32..80, -80i32..80).prop_map(|(x, y)| IVec2::new(x, y))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn stamps_land_on_their_footprint(
+        chunk_size in chunk_sizes(),
+        position in positions(),
+        size in (1u32..20, 1u32..20).prop_map(|(x, y)| UVec2::new(x, y)),
+        seed in any::<u32>(),
+    ) {
+        let (mut app, map) = app(chunk_size);
+        let texels = texels(size, seed);
+        stamp_texels(&mut app, map, position, size, &texels);
+
+        let covered = footprint(position, size, &texels);
+        let around = IRect::from_corners(position, position + size.as_ivec2()).inflate(1);
+        let mut expected = Vec::new();
+        let mut world_positions = Vec::new();
+        for y in around.min.y..around.max.y {
+            for x in around.min.x..around.max.x {
+                let world = IVec2::new(x, y);
+                let color = covered
+                    .get(&world)
+                    .filter(|color| color[3] > 0)
+                    .copied()
+                    .unwrap_or([0; 4]);
+                world_positions.push(world);
+                expected.push(color);
+            }
+        }
+        prop_assert_eq!(get_pixels(&mut app, map, world_positions), expected);
+    }
+
+    #[test]
+    fn dirty_rects_cover_the_opaque_texels(
+        chunk_size in chunk_sizes(),
+        position in positions(),
+        size in (1u32..20, 1u32..20).prop_map(|(x, y)| UVec2::new(x, y)),
+        seed in any::<u32>(),
+    ) {
+        let (mut app, map) = app(chunk_size);
+        let texels = texels(size, seed);
+        stamp_texels(&mut app, map, position, size, &texels);
+
+        let opaque = footprint(position, size, &texels)
+            .into_iter()
+            .filter(|(_, color)| color[3] > 0)
+            .map(|(world, _)| world)
+            .fold(None, |rect: Option<IRect>, world| {
+                let pixel = IRect::from_corners(world, world + IVec2::ONE);
+                Some(rect.map_or(pixel, |rect| rect.union(pixel)))
+            });
+        let dirty = dirty_rects(&app, map)
+            .into_iter()
+            .reduce(|a, b| a.union(b));
+        prop_assert_eq!(dirty, opaque);
+    }
+
+    #[test]
+    fn cpu_writes_read_back_across_seams(
+        chunk_size in chunk_sizes(),
+        writes in prop::collection::hash_map(positions(), any::<[u8; 3]>(), 1..40),
+    ) {
+        let (mut app, map) = app(chunk_size);
+        let writes: Vec<(IVec2, [u8; 4])> = writes
+            .into_iter()
+            .map(|(position, [r, g, b])| (position, [r, g, b, 255]))
+            .collect();
+        set_pixels(&mut app, map, writes.clone());
+        let (positions, colors): (Vec<IVec2>, Vec<[u8; 4]>) = writes.into_iter().unzip();
+        prop_assert_eq!(get_pixels(&mut app, map, positions), colors);
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+const GOLD: [u8; 4] = [250, 210, 40, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, UVec2::new(16, 16), |builder| {
+        builder.with_default_chunk_color([0; 4])
+    });
+    fill(&mut app, root, IRect::new(0, 0, 32, 8), GROUND);
+    (app, root)
+}
+
+fn read_stats(app: &mut App, id: u64) -> PixelStats {
+    app.update();
+    let events = app.world().resource::<Events<RegionStats>>();
+    let mut reader = events.get_cursor();
+    let event = reader
+        .read(events)
+        .find(|event| event.id == id)
+        .expect("no stats for the request");
+    event.stats.clone()
+}
+
+#[test]
+fn counts_and_bounds_opaque_pixels() {
+    let (mut app, root) = app();
+    let id = pixel_map_mut(&mut app, root)
+        .request_stats(
+            IRect::new(-16, -16, 48, 48),
+            PixelStatsQuery::new(PixelPredicate::Opaque),
+        )
+        .unwrap();
+    let stats = read_stats(&mut app, id);
+    assert_eq!(stats.count, 32 * 8);
+    assert_eq!(stats.bounds, Some(IRect::new(0, 0, 32, 8)));
+    assert!(stats.histogram.is_empty());
+
+    let id = pixel_map_mut(&mut app, root)
+        .request_stats(
+            IRect::new(10, 20, 30, 40),
+            PixelStatsQuery::new(PixelPredicate::Opaque),
+        )
+        .unwrap();
+    let stats = read_stats(&mut app, id);
+    assert_eq!(stats.count, 0);
+    assert_eq!(stats.bounds, None);
+    let events = app.world().resource::<Events<RegionStats>>();
+    assert!(events
+        .get_cursor()
+        .read(events)
+        .all(|event| event.map == root));
+}
+
+#[test]
+fn matches_colors_within_tolerance_after_edits() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(20, 2, 24, 5), GOLD);
+    fill(&mut app, root, IRect::new(3, 3, 4, 4), [252, 208, 44, 255]);
+    let id = pixel_map_mut(&mut app, root)
+        .request_stats(
+            IRect::new(0, 0, 32, 16),
+            PixelStatsQuery::new(PixelPredicate::Color {
+                color: GOLD,
+                tolerance: 4,
+            }),
+        )
+        .unwrap();
+    let stats = read_stats(&mut app, id);
+    assert_eq!(stats.count, 13);
+    assert_eq!(stats.bounds, Some(IRect::new(3, 2, 24, 5)));
+}
+
+#[test]
+fn histogram_counts_each_pixel_once() {
+    let (mut app, root) = app();
+    fill(&mut app, root, IRect::new(16, 0, 20, 2), GOLD);
+    let id = pixel_map_mut(&mut app, root)
+        .request_chunk_stats(
+            IVec2::new(1, 0),
+            PixelStatsQuery::new(PixelPredicate::Opaque)
+                .with_palette(vec![GOLD, GROUND, [0; 4], GOLD], 0),
+        )
+        .unwrap();
+    let stats = read_stats(&mut app, id);
+    assert_eq!(stats.count, 16 * 8);
+    assert_eq!(stats.histogram, vec![8, 16 * 8 - 8, 16 * 8, 0]);
+    assert_eq!(stats.bounds, Some(IRect::new(16, 0, 32, 8)));
+}
@@ -0,0 +1,84 @@
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+
+fn generate(generator: &TerrainGenerator, chunk_position: IVec2, chunk_size: UVec2) -> Vec<u8> {
+    let mut pixels = vec![0; (chunk_size.x * chunk_size.y * 4) as usize];
+    generator.generate(chunk_position, chunk_size, &mut pixels);
+    pixels
+}
+
+#[test]
+fn same_seed_generates_same_chunks() {
+    let chunk_size = UVec2::new(64, 48);
+    for chunk_position in [IVec2::new(0, 0), IVec2::new(-3, -2), IVec2::new(5, -7)] {
+        assert_eq!(
+            generate(&TerrainGenerator::new(42), chunk_position, chunk_size),
+            generate(&TerrainGenerator::new(42), chunk_position, chunk_size),
+        );
+    }
+}
+
+#[test]
+fn different_seeds_generate_different_surfaces() {
+    let a = TerrainGenerator::new(1);
+    let b = TerrainGenerator::new(2);
+    assert!((0..256).any(|x| a.surface_height_at(x) != b.surface_height_at(x)));
+}
+
+#[test]
+fn chunks_agree_with_sample() {
+    let generator = TerrainGenerator::new(7);
+    let chunk_size = UVec2::new(32, 32);
+    let chunk_position = IVec2::new(-1, -2);
+    let pixels = generate(&generator, chunk_position, chunk_size);
+    let origin = chunk_position * chunk_size.as_ivec2();
+    for (x, y) in [(0, 0), (31, 0), (0, 31), (17, 5)] {
+        let world = origin + IVec2::new(x, y);
+        let row = chunk_size.y as i32 - 1 - y;
+        let ind = ((row * chunk_size.x as i32 + x) * 4) as usize;
+        assert_eq!(pixels[ind..ind + 4], generator.sample(world));
+    }
+}
+
+#[test]
+fn sky_above_surface_and_bands_below() {
+    let generator = TerrainGenerator {
+        cave_layers: vec![],
+        ..TerrainGenerator::new(3)
+    };
+    for x in [-500, 0, 123, 9000] {
+        let surface = generator.surface_height_at(x).floor() as i32;
+        assert_eq!(
+            generator.sample(IVec2::new(x, surface + 1)),
+            generator.sky_color
+        );
+        assert_eq!(
+            generator.sample(IVec2::new(x, surface - 1)),
+            generator.bands[0].color
+        );
+        assert_eq!(
+            generator.sample(IVec2::new(x, surface - 1000)),
+            generator.bands.last().unwrap().color
+        );
+    }
+}
+
+#[test]
+fn noise_stays_in_range() {
+    for i in 0..1000 {
+        let p = Vec2::new(i as f32 * 0.37 - 150.0, i as f32 * -0.91 + 40.0);
+        assert!(NoiseKind::Perlin.sample(p, 9).abs() <= 1.0);
+        assert!(NoiseKind::Simplex.sample(p, 9).abs() <= 1.0);
+        assert!((0.0..=1.0).contains(&NoiseKind::Worley.sample(p, 9)));
+    }
+}
+
+#[test]
+fn gpu_params_match_shader_layout() {
+    let generator = TerrainGenerator::new(11).gpu_generator();
+    assert_eq!(generator.seed, 11);
+    assert_eq!(
+        generator.params.len(),
+        (7 + 2 * MAX_NOISE_LAYERS * 4 + MAX_MATERIAL_BANDS * 2) * 4
+    );
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_pixelmap::*;
+use common::*;
+
+const GROUND: [u8; 4] = [120, 80, 40, 255];
+const WATER: [u8; 4] = [40, 90, 220, 255];
+
+fn app() -> (App, Entity) {
+    let mut app = common::app();
+    let root = spawn_map(&mut app, UVec2::new(16, 16), |builder| {
+        builder.with_default_chunk_color([0; 4])
+    });
+    fill(&mut app, root, IRect::new(0, 0, 32, 8), GROUND);
+    (app, root)
+}
+
+fn water_trigger(root: Entity, threshold: u32) -> PixelTrigger {
+    PixelTrigger::new(
+        root,
+        IRect::new(8, 8, 16, 12),
+        PixelPredicate::Color {
+            color: WATER,
+            tolerance: 0,
+        },
+        threshold,
+    )
+}
+
+/// Runs a few frames, returning the triggers entered and exited meanwhile.
+fn run(app: &mut App) -> (Vec<PixelTriggerEntered>, Vec<PixelTriggerExited>) {
+    let mut entered = Vec::new();
+    let mut exited = Vec::new();
+    for _ in 0..3 {
+        app.update();
+        entered.extend(
+            app.world_mut()
+                .resource_mut::<Events<PixelTriggerEntered>>()
+                .drain(),
+        );
+        exited.extend(
+            app.world_mut()
+                .resource_mut::<Events<PixelTriggerExited>>()
+                .drain(),
+        );
+    }
+    (entered, exited)
+}
+
+fn trigger(app: &App, entity: Entity) -> &PixelTrigger {
+    app.world().get::<PixelTrigger>(entity).unwrap()
+}
+
+#[test]
+fn water_reaching_a_plate_enters_and_leaving_exits() {
+    let (mut app, root) = app();
+    let plate = app.world_mut().spawn(water_trigger(root, 1)).id();
+    assert_eq!(run(&mut app), (vec![], vec![]));
+    assert_eq!(trigger(&app, plate).count(), Some(0));
+
+    fill(&mut app, root, IRect::new(10, 8, 12, 9), WATER);
+    let (entered, exited) = run(&mut app);
+    assert_eq!(
+        entered,
+        vec![PixelTriggerEntered {
+            trigger: plate,
+            map: root,
+            count: 2,
+        }]
+    );
+    assert!(exited.is_empty());
+    assert!(trigger(&app, plate).is_active());
+
+    fill(&mut app, root, IRect::new(10, 8, 12, 9), [0; 4]);
+    let (entered, exited) = run(&mut app);
+    assert!(entered.is_empty());
+    assert_eq!(
+        exited,
+        vec![PixelTriggerExited {
+            trigger: plate,
+            map: root,
+            count: 0,
+        }]
+    );
+    assert!(!trigger(&app, plate).is_active());
+}
+
+#[test]
+fn threshold_needs_enough_matching_pixels() {
+    let (mut app, root) = app();
+    let plate = app.world_mut().spawn(water_trigger(root, 6)).id();
+    fill(&mut app, root, IRect::new(8, 8, 12, 9), WATER);
+    let (entered, _) = run(&mut app);
+    assert!(entered.is_empty());
+    assert_eq!(trigger(&app, plate).count(), Some(4));
+
+    fill(&mut app, root, IRect::new(8, 9, 12, 10), WATER);
+    let (entered, _) = run(&mut app);
+    assert_eq!(entered.len(), 1);
+    assert_eq!(entered[0].count, 8);
+
+    // Other colors inside the rect don't count.
+    fill(&mut app, root, IRect::new(8, 8, 12, 9), GROUND);
+    let (_, exited) = run(&mut app);
+    assert_eq!(exited.len(), 1);
+    assert_eq!(trigger(&app, plate).count(), Some(4));
+}
+
+#[test]
+fn triggers_fire_once_while_the_area_stays_filled() {
+    let (mut app, root) = app();
+    let plates = [
+        app.world_mut().spawn(water_trigger(root, 1)).id(),
+        app.world_mut()
+            .spawn(PixelTrigger::new(
+                root,
+                IRect::new(20, 8, 24, 12),
+                PixelPredicate::Opaque,
+                1,
+            ))
+            .id(),
+    ];
+    fill(&mut app, root, IRect::new(8, 8, 16, 12), WATER);
+    let (entered, _) = run(&mut app);
+    assert_eq!(entered.len(), 1);
+    assert_eq!(entered[0].trigger, plates[0]);
+    assert_eq!(entered[0].count, 32);
+
+    let (entered, exited) = run(&mut app);
+    assert!(entered.is_empty() && exited.is_empty());
+    assert!(trigger(&app, plates[0]).is_active());
+    assert!(!trigger(&app, plates[1]).is_active());
+}
+
+#[test]
+fn triggers_only_request_stats_after_changes() {
+    let (mut app, root) = app();
+    app.world_mut().spawn(water_trigger(root, 1));
+    let requests = |app: &mut App| {
+        run(app);
+        app.world_mut()
+            .resource_mut::<Events<RegionStats>>()
+            .drain()
+            .count()
+    };
+    assert!(requests(&mut app) > 0);
+    assert_eq!(requests(&mut app), 0);
+
+    // Edits elsewhere leave the trigger alone.
+    fill(&mut app, root, IRect::new(24, 0, 26, 2), WATER);
+    assert_eq!(requests(&mut app), 0);
+
+    fill(&mut app, root, IRect::new(15, 11, 17, 13), WATER);
+    assert!(requests(&mut app) > 0);
+    assert_eq!(requests(&mut app), 0);
+}